@@ -0,0 +1,43 @@
+use std::hint::black_box;
+use std::ops::{AddAssign, SubAssign};
+use std::time::Instant;
+
+use payements_engine::engine::amount::MinorUnits;
+use rust_decimal::Decimal;
+
+const OPERATIONS: i64 = 10_000_000;
+
+// Mimic the engine hot path: deposits and withdrawals moving a running balance.
+fn measure<T, F>(name: &str, to_amount: F)
+where
+    T: Copy + Default + AddAssign + SubAssign,
+    F: Fn(i64) -> T,
+{
+    let amounts: Vec<T> = (0..1024).map(|i| to_amount(i * 1234 + 1)).collect();
+
+    let start = Instant::now();
+    let mut balance = T::default();
+    for i in 0..OPERATIONS as usize {
+        let amount = black_box(amounts[i % amounts.len()]);
+        if i % 3 == 0 {
+            balance -= amount;
+        } else {
+            balance += amount;
+        }
+    }
+    black_box(balance);
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<12} {:>10} operations in {:>8.2?} ({:>14.0} operations/s)",
+        name,
+        OPERATIONS,
+        elapsed,
+        OPERATIONS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    measure("decimal", |units| Decimal::new(units, 4));
+    measure("minor-units", MinorUnits::new);
+}
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+use payements_engine::engine::Transaction;
+use payements_engine::parser::ByteRecordParser;
+
+const RECORDS: u32 = 1_000_000;
+
+fn generate_input(records: u32) -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 0..records {
+        let client = tx % 1000;
+        match tx % 10 {
+            9 => input.push_str(&format!("dispute,{},{},\n", client, tx - 9)),
+            n if n % 2 == 0 => input.push_str(&format!(
+                "deposit,{},{},{}.{:04}\n",
+                client,
+                tx,
+                tx % 500,
+                tx % 10_000
+            )),
+            _ => input.push_str(&format!(
+                "withdrawal,{},{},{}.{:04}\n",
+                client,
+                tx,
+                tx % 50,
+                tx % 10_000
+            )),
+        }
+    }
+    input
+}
+
+fn reader(input: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_bytes())
+}
+
+fn measure<F: Fn() -> usize>(name: &str, parse: F) -> Duration {
+    let start = Instant::now();
+    let parsed = parse();
+    let elapsed = start.elapsed();
+    println!(
+        "{:<12} {:>10} records in {:>8.2?} ({:>12.0} records/s)",
+        name,
+        parsed,
+        elapsed,
+        parsed as f64 / elapsed.as_secs_f64()
+    );
+    elapsed
+}
+
+fn main() {
+    let input = generate_input(RECORDS);
+
+    let serde = measure("serde", || {
+        reader(&input)
+            .into_deserialize::<Transaction>()
+            .filter(Result::is_ok)
+            .count()
+    });
+    let fast = measure("byte-record", || {
+        ByteRecordParser::new(reader(&input))
+            .unwrap()
+            .filter(Result::is_ok)
+            .count()
+    });
+
+    println!("speedup: {:.2}x", serde.as_secs_f64() / fast.as_secs_f64());
+}
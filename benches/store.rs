@@ -0,0 +1,64 @@
+// Identifier widths depend on enabled features
+#![allow(clippy::unnecessary_cast)]
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use payements_engine::engine::store::{AccountStore, BTreeMapStore, DashMapStore, ShardedStore};
+use payements_engine::engine::{ClientId, TransactionId};
+use rust_decimal::Decimal;
+
+const THREADS: u64 = 8;
+const DEPOSITS_PER_THREAD: u64 = 250_000;
+const CLIENTS: u64 = 10_000;
+
+// Concurrent deposits from several workers, each worker owning a disjoint set of clients as it
+// would under a client-partitioned pipeline.
+fn measure<S: AccountStore<Decimal> + Default + 'static>(name: &str) {
+    let store = Arc::new(S::default());
+    let client_ids: Arc<Vec<ClientId>> = Arc::new(
+        (0..CLIENTS)
+            .map(|id| id.to_string().parse().unwrap())
+            .collect(),
+    );
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..THREADS)
+        .map(|worker| {
+            let store = Arc::clone(&store);
+            let client_ids = Arc::clone(&client_ids);
+            thread::spawn(move || {
+                for i in 0..DEPOSITS_PER_THREAD {
+                    let client_id = client_ids[((i * THREADS + worker) % CLIENTS) as usize];
+                    let tx = (worker * DEPOSITS_PER_THREAD + i) as TransactionId;
+                    store
+                        .with_account(client_id, |account_manager| {
+                            account_manager.deposit(tx, Decimal::new(i as i64, 2))
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    workers
+        .into_iter()
+        .for_each(|worker| worker.join().unwrap());
+    let elapsed = start.elapsed();
+
+    let operations = THREADS * DEPOSITS_PER_THREAD;
+    println!(
+        "{:<10} {:>10} deposits on {} threads in {:>8.2?} ({:>12.0} deposits/s)",
+        name,
+        operations,
+        THREADS,
+        elapsed,
+        operations as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    measure::<DashMapStore<Decimal>>("dashmap");
+    measure::<ShardedStore<Decimal>>("sharded");
+    measure::<BTreeMapStore<Decimal>>("btree");
+}
@@ -0,0 +1,57 @@
+/*
+ * C ABI of payements-engine, built with:
+ *
+ *   cargo build --release --lib --features ffi
+ *
+ * into target/release/libpayements_engine.so (.dylib on macOS, payements_engine.dll on Windows).
+ *
+ * All strings are NUL-terminated UTF-8. An engine may be shared between threads, as long as
+ * transactions of one client are submitted in order.
+ *
+ * Panics of the library never unwind into the caller. A function which panicked returns
+ * PE_PANICKED or NULL; the engine it was given may be left in any state and should only be
+ * released with pe_engine_free.
+ */
+#ifndef PAYEMENTS_ENGINE_H
+#define PAYEMENTS_ENGINE_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PE_OK 0
+#define PE_REJECTED 1
+#define PE_INVALID_ARGUMENT (-1)
+#define PE_PANICKED (-2)
+
+typedef struct PeEngine PeEngine;
+
+/* Create an engine with the default options, release it with pe_engine_free. NULL on a panic. */
+PeEngine *pe_engine_new(void);
+
+/* Release an engine created by pe_engine_new, NULL is ignored. The engine must not be used
+ * afterwards. */
+void pe_engine_free(PeEngine *engine);
+
+/* Process one transaction. type is one of deposit, withdrawal, dispute, resolve, chargeback.
+ * amount may be NULL for disputes, resolves and chargebacks. All non-NULL strings must be valid
+ * for the duration of the call.
+ *
+ * Returns PE_OK, PE_REJECTED if the engine refused the transaction, PE_INVALID_ARGUMENT for
+ * NULL or unparsable arguments or PE_PANICKED. */
+int pe_engine_submit(const PeEngine *engine, const char *type, const char *client,
+                     const char *tx, const char *amount);
+
+/* Accounts sorted by client id as a JSON array of
+ * {"client", "available", "held", "total", "locked"} with amounts as strings. Release the result
+ * with pe_string_free. Returns NULL if engine is NULL or on a panic. */
+char *pe_engine_accounts_json(const PeEngine *engine);
+
+/* Release a string returned by the library, NULL is ignored. */
+void pe_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,430 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{
+    ClientId, Engine, EngineError, Note, Timestamp, Transaction, TransactionId, TxStatus,
+    SUBMITTER_COLUMN,
+};
+use crate::server::{Request, Response};
+
+// How often a drain checks whether the batches being processed finished.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+// How often paused ingestion checks whether it was resumed.
+const RESUME_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Admin endpoints of `serve`, enabled by a token.
+//
+// * POST /admin/accounts/{id}/unlock - unlock a locked or quarantined account
+// * GET /admin/accounts/{id}/notes - notes left on the account
+// * POST /admin/accounts/{id}/notes - leave a note, `{"text": ..., "case": ..., "tx": ...}` with
+//   the case and transaction optional
+// * POST /admin/accounts/{id}/transactions/{tx}/resolve, .../chargeback - settle a dispute even
+//   of a locked or closed account
+// * POST /admin/snapshot - write a snapshot to the snapshot path
+// * POST /admin/compact - drop stored transactions which can't be referenced anymore
+// * GET /admin/ingestion - whether ingestion is paused and the number of batches in flight
+// * POST /admin/ingestion/pause, .../resume - stop and restart accepting `POST /transactions`
+// * POST /admin/ingestion/drain - pause and wait until the batches in flight are processed, then
+//   write a snapshot to the snapshot path with `{"snapshot": true}`
+// * GET /admin/approvals - transactions held for approval, see `ServeOptions::approval_threshold`
+// * POST /admin/approvals/{id}/approve, .../reject - apply or discard a held transaction, with the
+//   token of an approver other than the one who submitted it
+//
+// Requests have to carry the token as `Authorization: Bearer <token>`, the approvals endpoints
+// also take the token of an approver.
+pub struct AdminOptions {
+    pub token: String,
+    // Where `POST /admin/snapshot` writes, replacing the previous snapshot
+    pub snapshot_path: Option<PathBuf>,
+    // Who may decide on held transactions
+    pub approvers: Vec<Approver>,
+}
+
+// Someone deciding on held transactions, identified by their token. Transactions posted with the
+// token are held as submitted by them, see `SUBMITTER_COLUMN`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Approver {
+    pub name: String,
+    pub token: String,
+}
+
+impl AdminOptions {
+    // Name of the approver whose token the request carries.
+    pub(crate) fn approver(&self, request: &Request) -> Option<&str> {
+        let token = request.bearer_token()?;
+        self.approvers
+            .iter()
+            .find(|approver| constant_time_eq(token.as_bytes(), approver.token.as_bytes()))
+            .map(|approver| approver.name.as_str())
+    }
+}
+
+// Approvers from lines of `<name> <token>`, empty lines are skipped. None if a line has no token.
+pub fn parse_approvers(input: &str) -> Option<Vec<Approver>> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (name, token) = line.split_once(char::is_whitespace)?;
+            Some(Approver {
+                name: name.to_string(),
+                token: token.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+// Admission of transaction batches, paused by the admin endpoints or the signals of
+// `crate::signals`. Shared through `ServeOptions::ingestion`, or by the processing of an input
+// waiting in `wait_resumed` between transactions.
+#[derive(Default)]
+pub struct Ingestion {
+    paused: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+// A batch admitted for processing, counted in flight until dropped.
+pub(crate) struct Admitted<'a>(&'a Ingestion);
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Ingestion {
+    // Admit a batch, None while ingestion is paused. The batch is counted in flight before
+    // checking the pause, so a drain either waits for it or it is turned away.
+    pub(crate) fn admit(&self) -> Option<Admitted<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let admitted = Admitted(self);
+        (!self.paused.load(Ordering::SeqCst)).then_some(admitted)
+    }
+
+    pub fn pause(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // Block while ingestion is paused.
+    pub fn wait_resumed(&self) {
+        while self.is_paused() {
+            thread::sleep(RESUME_POLL_INTERVAL);
+        }
+    }
+
+    // Pause and wait until the batches in flight are processed.
+    pub fn drain(&self) {
+        self.pause(true);
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+    }
+
+    fn status(&self) -> Response {
+        Response::json(
+            200,
+            &json!({
+                "paused": self.is_paused(),
+                "in_flight": self.in_flight.load(Ordering::SeqCst),
+            }),
+        )
+    }
+}
+
+fn approvals_response(held: &[(u64, Transaction)]) -> Response {
+    let approvals: Vec<_> = held
+        .iter()
+        .map(|(id, transaction)| {
+            json!({
+                "id": id,
+                "type": transaction.r#type.name(),
+                "client": transaction.client_id,
+                "tx": transaction.transaction_id,
+                "amount": transaction.amount,
+                "metadata": transaction.metadata,
+            })
+        })
+        .collect();
+    Response::json(200, &json!({ "approvals": approvals }))
+}
+
+// Body of `POST /admin/accounts/{id}/notes`.
+#[derive(Deserialize)]
+struct NoteRequest {
+    text: String,
+    case: Option<String>,
+    tx: Option<TransactionId>,
+}
+
+// Body of `POST /admin/ingestion/drain`, optional.
+#[derive(Deserialize, Default)]
+struct DrainRequest {
+    #[serde(default)]
+    snapshot: bool,
+}
+
+fn notes_response(notes: &[Note]) -> Response {
+    let notes: Vec<_> = notes
+        .iter()
+        .map(|note| {
+            json!({
+                "tx": note.tx,
+                "case": note.case,
+                "text": note.text,
+                "at": note.at.map(|at| at.to_string()),
+            })
+        })
+        .collect();
+    Response::json(200, &json!({ "notes": notes }))
+}
+
+// Answer an admin request. Approved transactions are applied with `apply` once admitted by
+// `ingestion`, like posted ones.
+pub(crate) fn route<A, S>(
+    engine: &Engine<A, S>,
+    options: &AdminOptions,
+    ingestion: &Ingestion,
+    apply: &dyn Fn(Transaction) -> Result<(), EngineError>,
+    request: &Request,
+    segments: &[&str],
+) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let approver = options.approver(request);
+    let admin = request
+        .bearer_token()
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), options.token.as_bytes()));
+    let approval = approver.is_some() && segments.first() == Some(&"approvals");
+    if !admin && !approval {
+        return Response::error(401, "Unauthorized");
+    }
+
+    match (request.method.as_str(), segments) {
+        ("POST", ["accounts", client_id, "unlock"]) => {
+            let Ok(client_id) = client_id.parse::<ClientId>() else {
+                return Response::error(400, "Invalid client id");
+            };
+            match engine.unlock(client_id) {
+                Some(locked) => Response::json(200, &json!({ "was_locked": locked })),
+                None => Response::error(404, "Account not found"),
+            }
+        }
+        ("GET", ["accounts", client_id, "notes"]) => {
+            let Ok(client_id) = client_id.parse::<ClientId>() else {
+                return Response::error(400, "Invalid client id");
+            };
+            match engine.notes(client_id) {
+                Some(notes) => notes_response(&notes),
+                None => Response::error(404, "Account not found"),
+            }
+        }
+        ("POST", ["accounts", client_id, "notes"]) => {
+            let Ok(client_id) = client_id.parse::<ClientId>() else {
+                return Response::error(400, "Invalid client id");
+            };
+            let note = match serde_json::from_slice::<NoteRequest>(&request.body) {
+                Ok(note) if !note.text.trim().is_empty() => note,
+                Ok(_) => return Response::error(400, "Note text is missing"),
+                Err(e) => return Response::error(400, &e.to_string()),
+            };
+            let note = Note {
+                tx: note.tx,
+                case: note.case.filter(|case| !case.is_empty()),
+                text: note.text,
+                at: Some(Timestamp(SystemTime::now())),
+            };
+            if !engine.add_note(client_id, note.clone()) {
+                return Response::error(404, "Account not found");
+            }
+            notes_response(&[note])
+        }
+        ("POST", ["accounts", client_id, "transactions", transaction_id, outcome]) => {
+            let outcome = match *outcome {
+                "resolve" => TxStatus::Resolved,
+                "chargeback" => TxStatus::ChargedBack,
+                _ => return Response::error(404, "Not found"),
+            };
+            let (Ok(client_id), Ok(transaction_id)) = (
+                client_id.parse::<ClientId>(),
+                transaction_id.parse::<TransactionId>(),
+            ) else {
+                return Response::error(400, "Invalid client or transaction id");
+            };
+            match engine.force_settle(client_id, transaction_id, outcome) {
+                Some(Ok(())) => Response::json(200, &json!({ "status": outcome.as_str() })),
+                Some(Err(e)) => {
+                    Response::json(409, &json!({ "error": e.to_string(), "code": e.code() }))
+                }
+                None => Response::error(404, "Account not found"),
+            }
+        }
+        ("POST", ["snapshot"]) => snapshot(engine, options),
+        ("POST", ["compact"]) => {
+            let stats = engine.compact();
+            info!(target: "audit", "Compacted: {:?}", stats);
+            Response::json(
+                200,
+                &json!({
+                    "accounts": stats.accounts,
+                    "dropped_transactions": stats.dropped_transactions,
+                }),
+            )
+        }
+        ("GET", ["ingestion"]) => ingestion.status(),
+        ("POST", ["ingestion", action]) => {
+            let drain = if *action == "drain" && !request.body.is_empty() {
+                match serde_json::from_slice::<DrainRequest>(&request.body) {
+                    Ok(drain) => drain,
+                    Err(e) => return Response::error(400, &e.to_string()),
+                }
+            } else {
+                DrainRequest::default()
+            };
+            let snapshot_path = match (drain.snapshot, &options.snapshot_path) {
+                (false, _) => None,
+                (true, Some(path)) => Some(path),
+                (true, None) => return Response::error(400, "No snapshot path configured"),
+            };
+            match *action {
+                "pause" => ingestion.pause(true),
+                "resume" => ingestion.pause(false),
+                "drain" => ingestion.drain(),
+                _ => return Response::error(404, "Not found"),
+            }
+            info!(target: "audit", "Ingestion {}", action);
+            if let Some(path) = snapshot_path {
+                if let Err(e) = write_snapshot(engine, path) {
+                    return Response::error(500, &e.to_string());
+                }
+                info!(target: "audit", "Wrote snapshot to {}", path.display());
+            }
+            ingestion.status()
+        }
+        ("GET", ["approvals"]) => approvals_response(&engine.held()),
+        ("POST", ["approvals", id, decision @ ("approve" | "reject")]) => {
+            let Some(approver) = approver else {
+                return Response::error(403, "Deciding on approvals needs an approver token");
+            };
+            let Ok(id) = id.parse::<u64>() else {
+                return Response::error(400, "Invalid approval id");
+            };
+            if *decision == "reject" {
+                let Some(transaction) = engine.take_held(id) else {
+                    return Response::error(404, "Approval not found");
+                };
+                info!(
+                    target: "audit",
+                    "{} rejected held transaction {} of client {} ({})",
+                    approver,
+                    transaction.transaction_id,
+                    transaction.client_id,
+                    id
+                );
+                return Response::json(200, &json!({ "status": "rejected" }));
+            }
+            approve(engine, ingestion, apply, id, approver)
+        }
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+// Apply a held transaction, if it was submitted by someone else and ingestion isn't paused.
+// Otherwise it stays held.
+fn approve<A, S>(
+    engine: &Engine<A, S>,
+    ingestion: &Ingestion,
+    apply: &dyn Fn(Transaction) -> Result<(), EngineError>,
+    id: u64,
+    approver: &str,
+) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let Some((_, held)) = engine.held().into_iter().find(|(held, _)| *held == id) else {
+        return Response::error(404, "Approval not found");
+    };
+    if held.metadata.get(SUBMITTER_COLUMN).map(String::as_str) == Some(approver) {
+        return Response::error(403, "Transactions can't be approved by their submitter");
+    }
+    let Some(_admitted) = ingestion.admit() else {
+        return Response::error(503, "Ingestion paused");
+    };
+    let Some(transaction) = engine.take_held(id) else {
+        return Response::error(404, "Approval not found");
+    };
+    let (transaction_id, client_id) = (transaction.transaction_id, transaction.client_id);
+    let result = apply(transaction);
+    info!(
+        target: "audit",
+        "{} approved held transaction {} of client {} ({}): {}",
+        approver,
+        transaction_id,
+        client_id,
+        id,
+        result.as_ref().map_or_else(|e| e.to_string(), |()| "applied".to_string())
+    );
+    if let Err(e) = engine.flush_record() {
+        warn!("Error flushing event log: {}", e);
+    }
+    match result {
+        Ok(()) => Response::json(200, &json!({ "status": "applied" })),
+        Err(e) => Response::json(409, &json!({ "error": e.to_string(), "code": e.code() })),
+    }
+}
+
+fn snapshot<A, S>(engine: &Engine<A, S>, options: &AdminOptions) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let Some(path) = &options.snapshot_path else {
+        return Response::error(400, "No snapshot path configured");
+    };
+    match write_snapshot(engine, path) {
+        Ok(()) => {
+            info!(target: "audit", "Wrote snapshot to {}", path.display());
+            Response::json(200, &json!({ "path": path }))
+        }
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+// Write the snapshot next to the path and move it in place, so a failed write keeps the previous
+// snapshot.
+pub fn write_snapshot<A, S>(engine: &Engine<A, S>, path: &Path) -> io::Result<()>
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let temporary = path.with_extension("tmp");
+    let mut file = File::create(&temporary)?;
+    engine
+        .write_snapshot(&mut file)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+// Compare tokens in time independent of where they differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
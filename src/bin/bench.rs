@@ -0,0 +1,156 @@
+// Standardized workloads for comparing engine performance across changes and machines.
+//
+// cargo run --release --features bench --bin bench -- [records]
+
+use std::env;
+use std::fmt::Write;
+use std::time::Instant;
+
+use payements_engine::engine::store::{AccountStore, DashMapStore, ShardedStore};
+use payements_engine::engine::Engine;
+use payements_engine::parser::ByteRecordParser;
+use rust_decimal::Decimal;
+
+const DEFAULT_RECORDS: usize = 1_000_000;
+const SEED: u64 = 0x5eed;
+
+struct Workload {
+    name: &'static str,
+    // Probability of a deposit or withdrawal going to a new client, as in scripts/generate.py
+    new_client_rate: f64,
+    dispute_rate: f64,
+}
+
+const WORKLOADS: [Workload; 3] = [
+    Workload {
+        name: "generator",
+        new_client_rate: 0.75,
+        dispute_rate: 0.1,
+    },
+    Workload {
+        name: "hot-clients",
+        new_client_rate: 0.001,
+        dispute_rate: 0.1,
+    },
+    Workload {
+        name: "disputes",
+        new_client_rate: 0.75,
+        dispute_rate: 0.5,
+    },
+];
+
+struct Rng(u64);
+
+impl Rng {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_f64() * bound as f64) as usize
+    }
+}
+
+// Port of scripts/generate.py with a fixed seed, so every run processes the same input.
+fn generate(workload: &Workload, records: usize) -> String {
+    let mut rng = Rng(SEED);
+    let mut output = String::from("type,client,tx,amount\n");
+    let (mut client_id, mut max_client_id) = (0, 0);
+    let mut disputed = Vec::new();
+
+    for tx in 0..records {
+        if rng.next_f64() < workload.new_client_rate {
+            // Wrap around to stay within the default u16 client ids
+            client_id = (client_id + 1) % usize::from(u16::MAX);
+            max_client_id = max_client_id.max(client_id);
+        } else {
+            client_id = rng.below(max_client_id + 1);
+        }
+        let r#type = if rng.next_f64() < 0.5 {
+            "deposit"
+        } else {
+            "withdrawal"
+        };
+        let amount = (rng.next_f64() + 1.0) * 100.0;
+        let _ = writeln!(output, "{},{},{},{:.4}", r#type, client_id, tx, amount);
+
+        if rng.next_f64() < workload.dispute_rate {
+            let _ = writeln!(output, "dispute,{},{},", client_id, tx);
+            disputed.push((client_id, tx));
+        }
+        for r#type in ["resolve", "chargeback"] {
+            if !disputed.is_empty() && rng.next_f64() < 0.1 {
+                let (client_id, tx) = disputed.swap_remove(rng.below(disputed.len()));
+                let _ = writeln!(output, "{},{},{},", r#type, client_id, tx);
+            }
+        }
+    }
+    output
+}
+
+// Peak resident set size of the process so far in KiB, it never decreases between workloads.
+#[cfg(unix)]
+fn peak_rss_kib() -> Option<i64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage only writes into the provided struct
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: initialized by the successful call above
+    let max_rss = unsafe { usage.assume_init() }.ru_maxrss;
+    // Reported in bytes on macOS, KiB elsewhere
+    Some(if cfg!(target_os = "macos") {
+        max_rss / 1024
+    } else {
+        max_rss
+    })
+}
+
+#[cfg(not(unix))]
+fn peak_rss_kib() -> Option<i64> {
+    None
+}
+
+fn measure<S: AccountStore<Decimal> + Default>(workload: &str, store: &str, input: &str) {
+    let engine: Engine<Decimal, S> = Engine::new();
+    let reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(input.as_bytes());
+
+    let start = Instant::now();
+    let mut rows = 0;
+    for transaction in ByteRecordParser::new(reader)
+        .expect("valid headers")
+        .flatten()
+    {
+        let _ = engine.process(transaction);
+        rows += 1;
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<12} {:<8} {:>10} rows in {:>8.2?} ({:>12.0} rows/s), peak RSS {}",
+        workload,
+        store,
+        rows,
+        elapsed,
+        rows as f64 / elapsed.as_secs_f64(),
+        peak_rss_kib().map_or("n/a".to_string(), |kib| format!("{} KiB", kib))
+    );
+}
+
+fn main() {
+    let records = env::args()
+        .nth(1)
+        .and_then(|records| records.parse().ok())
+        .unwrap_or(DEFAULT_RECORDS);
+
+    for workload in &WORKLOADS {
+        let input = generate(workload, records);
+        measure::<DashMapStore<Decimal>>(workload.name, "dashmap", &input);
+        measure::<ShardedStore<Decimal>>(workload.name, "sharded", &input);
+    }
+}
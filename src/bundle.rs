@@ -0,0 +1,368 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{to_hex, Sha256};
+
+// Version of the bundle layout, bumped when entries change meaning.
+pub const BUNDLE_VERSION: u32 = 1;
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+// Entries of a bundle of `export`
+pub const REPORT_ENTRY: &str = "report.csv";
+pub const JOURNAL_ENTRY: &str = "journal.csv";
+pub const STATS_ENTRY: &str = "stats.json";
+pub const REJECTED_ENTRY: &str = "rejected.csv";
+// Engine state at the end of the run, see `Engine::write_snapshot`
+pub const SNAPSHOT_ENTRY: &str = "snapshot.csv";
+
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+const TAR_BLOCK: usize = 512;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BundleError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Not a bundle: {0}")]
+    Invalid(&'static str),
+    #[error("Invalid zstd frame: {0}")]
+    Zstd(String),
+    #[error("Unsupported bundle version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Missing entry {0}")]
+    MissingEntry(String),
+    #[error("Entry {0} doesn't match its digest in the manifest")]
+    DigestMismatch(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub name: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+// First entry of a bundle, describing the others.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    pub engine_version: String,
+    // RFC 3339 in UTC
+    pub created_at: String,
+    // Command line arguments of the run
+    pub parameters: Vec<String>,
+    pub entries: Vec<BundleEntry>,
+    // Input files of the run by path, none in bundles of earlier releases
+    #[serde(default)]
+    pub inputs: Vec<BundleEntry>,
+}
+
+// Outputs of a run packaged into one file for retention: a zstd-compressed tar archive, with
+// `manifest.json` first and the entries in the order they were added. Readable with
+// `zstd -d | tar x`, and bundles recompressed by the `zstd` tool are read too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub manifest: BundleManifest,
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    pub fn new(parameters: Vec<String>) -> Bundle {
+        Bundle {
+            manifest: BundleManifest {
+                version: BUNDLE_VERSION,
+                engine_version: env!("CARGO_PKG_VERSION").to_string(),
+                created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                parameters,
+                entries: Vec::new(),
+                inputs: Vec::new(),
+            },
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, data: Vec<u8>) {
+        self.manifest.entries.push(BundleEntry {
+            name: name.to_string(),
+            bytes: data.len() as u64,
+            sha256: to_hex(&Sha256::digest(&data)),
+        });
+        self.entries.push((name.to_string(), data));
+    }
+
+    // Replace the data of an entry in place, or add it.
+    pub fn set(&mut self, name: &str, data: Vec<u8>) {
+        let Some(index) = self.entries.iter().position(|(entry, _)| entry == name) else {
+            return self.add(name, data);
+        };
+        if let Some(entry) = self
+            .manifest
+            .entries
+            .iter_mut()
+            .find(|entry| entry.name == name)
+        {
+            entry.bytes = data.len() as u64;
+            entry.sha256 = to_hex(&Sha256::digest(&data));
+        }
+        self.entries[index].1 = data;
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), BundleError> {
+        let mut archive = Vec::new();
+        let manifest = serde_json::to_vec_pretty(&self.manifest)?;
+        write_tar_entry(&mut archive, MANIFEST_ENTRY, &manifest);
+        for (name, data) in &self.entries {
+            write_tar_entry(&mut archive, name, data);
+        }
+        archive.resize(archive.len() + 2 * TAR_BLOCK, 0);
+        write_zstd_frame(writer, &archive)?;
+        Ok(())
+    }
+
+    // Read a bundle, checking its version and the digests of its entries.
+    pub fn read<R: Read>(mut reader: R) -> Result<Bundle, BundleError> {
+        let mut frame = Vec::new();
+        reader.read_to_end(&mut frame)?;
+        let archive = read_zstd_frame(&frame)?;
+        let mut entries = read_tar_entries(&archive)?.into_iter();
+        let manifest: BundleManifest = match entries.next() {
+            Some((name, data)) if name == MANIFEST_ENTRY => serde_json::from_slice(&data)?,
+            _ => return Err(BundleError::MissingEntry(MANIFEST_ENTRY.to_string())),
+        };
+        if manifest.version != BUNDLE_VERSION {
+            return Err(BundleError::UnsupportedVersion(manifest.version));
+        }
+        let entries: Vec<_> = entries.collect();
+        for expected in &manifest.entries {
+            let (_, data) = entries
+                .iter()
+                .find(|(name, _)| *name == expected.name)
+                .ok_or_else(|| BundleError::MissingEntry(expected.name.clone()))?;
+            if data.len() as u64 != expected.bytes
+                || to_hex(&Sha256::digest(data)) != expected.sha256
+            {
+                return Err(BundleError::DigestMismatch(expected.name.clone()));
+            }
+        }
+        Ok(Bundle { manifest, entries })
+    }
+}
+
+// Whether the data starts like a bundle, with a zstd frame.
+pub fn is_bundle(data: &[u8]) -> bool {
+    data.starts_with(&ZSTD_MAGIC.to_le_bytes())
+}
+
+// Writer collecting into a buffer shared with its clones, for outputs handed to the engine as a
+// writer, e.g. the journal.
+#[derive(Debug, Default, Clone)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// ustar header and data of a regular file, padded to the block size.
+fn write_tar_entry(archive: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let mut header = [0u8; TAR_BLOCK];
+    let name = name.as_bytes();
+    header[..name.len().min(100)].copy_from_slice(&name[..name.len().min(100)]);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is summed with its own field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(TAR_BLOCK), 0);
+}
+
+fn read_tar_entries(archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>, BundleError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = archive.get(offset..offset + TAR_BLOCK) {
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            let end = field
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).trim().to_string()
+        };
+        let size = usize::from_str_radix(&field(124..136), 8)
+            .map_err(|_| BundleError::Invalid("invalid tar entry size"))?;
+        let start = offset + TAR_BLOCK;
+        let data = archive
+            .get(start..start + size)
+            .ok_or(BundleError::Invalid("truncated tar entry"))?;
+        entries.push((field(0..100), data.to_vec()));
+        offset = start + size.next_multiple_of(TAR_BLOCK);
+    }
+    Ok(entries)
+}
+
+// The pure Rust encoder, so bundles are written on every target including wasm. It only has the
+// fastest level, recompress with `zstd` for smaller bundles.
+fn write_zstd_frame<W: Write>(mut writer: W, data: &[u8]) -> io::Result<()> {
+    let frame =
+        ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+// Frames of any zstd encoder, concatenated frames included.
+fn read_zstd_frame(frame: &[u8]) -> Result<Vec<u8>, BundleError> {
+    if !is_bundle(frame) {
+        return Err(BundleError::Invalid("not a zstd frame"));
+    }
+    let mut input = frame;
+    let mut data = Vec::new();
+    while !input.is_empty() {
+        let mut decoder = ruzstd::decoding::StreamingDecoder::new(&mut input)
+            .map_err(|e| BundleError::Zstd(e.to_string()))?;
+        decoder
+            .read_to_end(&mut data)
+            .map_err(|e| BundleError::Zstd(e.to_string()))?;
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ErrorCode;
+    use crate::parser::ByteRecordParser;
+    use crate::Engine;
+
+    #[test]
+    fn test_bundle_snapshot() {
+        let process = |engine: &Engine, input: &str| {
+            ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+                .unwrap()
+                .map(|transaction| engine.process(transaction.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let engine: Engine = Engine::new();
+        process(&engine, "type,client,tx,amount\ndeposit,1,1,5.0\n");
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let mut bundle = Bundle::new(Vec::new());
+        bundle.add(SNAPSHOT_ENTRY, snapshot);
+        let mut written = Vec::new();
+        bundle.write(&mut written).unwrap();
+
+        // The next period starts from the bundle and still has the transactions before it
+        let next: Engine = Engine::new();
+        let bundle = Bundle::read(written.as_slice()).unwrap();
+        next.restore_snapshot(bundle.entry(SNAPSHOT_ENTRY).unwrap())
+            .unwrap();
+        let results = process(
+            &next,
+            "type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,\n",
+        );
+        assert_eq!(
+            results[0].as_ref().err().map(|e| e.code()),
+            Some(ErrorCode::TransactionExists)
+        );
+        assert!(results[1].is_ok());
+        assert_eq!(next.accounts().unwrap()[0].held, "5.0".parse().unwrap());
+    }
+
+    #[test]
+    fn test_bundle() {
+        let mut bundle = Bundle::new(vec!["export".to_string()]);
+        bundle.add("report.csv", b"client,available\n1,1.0\n".to_vec());
+        bundle.add("journal.csv", vec![b'x'; 400_000]);
+        bundle.add("rejected.csv", Vec::new());
+        let mut written = Vec::new();
+        bundle.write(&mut written).unwrap();
+
+        // Compressed
+        assert!(written.len() < 100_000);
+
+        let read = Bundle::read(written.as_slice()).unwrap();
+        assert_eq!(read, bundle);
+        assert_eq!(
+            read.entry("report.csv"),
+            Some(b"client,available\n1,1.0\n".as_slice())
+        );
+
+        // Entry data not matching the manifest
+        let mut corrupted = bundle.clone();
+        corrupted.entries[0].1 = b"client,available\n2,1.0\n".to_vec();
+        let mut written = Vec::new();
+        corrupted.write(&mut written).unwrap();
+        assert!(matches!(
+            Bundle::read(written.as_slice()),
+            Err(BundleError::DigestMismatch(name)) if name == "report.csv"
+        ));
+        // Truncated frame
+        assert!(matches!(
+            Bundle::read(&written[..written.len() / 2]),
+            Err(BundleError::Zstd(_))
+        ));
+        assert!(matches!(
+            Bundle::read(b"client,available\n".as_slice()),
+            Err(BundleError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_zstd_frame() {
+        // Written by `zstd -19`, with a compressed block and a checksum
+        let frame = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x68, 0xed, 0x00, 0x00, 0xb8, 0x63, 0x6c, 0x69, 0x65,
+            0x6e, 0x74, 0x2c, 0x61, 0x76, 0x61, 0x69, 0x6c, 0x61, 0x62, 0x6c, 0x65, 0x0a, 0x31,
+            0x2c, 0x31, 0x2e, 0x30, 0x0a, 0x01, 0x00, 0xe3, 0x95, 0x26, 0xe5, 0x6c, 0x84, 0xd8,
+        ];
+        assert_eq!(
+            read_zstd_frame(&frame).unwrap(),
+            b"client,available\n1,1.0\n1,1.0\n1,1.0\n1,1.0\n"
+        );
+        let mut written = Vec::new();
+        write_zstd_frame(&mut written, b"client,available\n1,1.0\n").unwrap();
+        assert_eq!(
+            read_zstd_frame(&written).unwrap(),
+            b"client,available\n1,1.0\n"
+        );
+    }
+}
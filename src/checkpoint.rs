@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{Engine, SnapshotError};
+
+const CHECKPOINT_FILE: &str = "checkpoint.csv";
+
+// Next offset to consume by source, e.g. by `<topic>/<partition>` of a Kafka consumer.
+pub type Offsets = BTreeMap<String, u64>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CheckpointError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error("Malformed checkpoint offset on line {0}")]
+    Malformed(u64),
+    #[error("Offset commit failed: {0}")]
+    Commit(Box<dyn Error + Send + Sync>),
+}
+
+// Engine snapshots tied to the input offsets they cover, for effectively-once processing of
+// at-least-once feeds across restarts.
+//
+// The offsets and the snapshot are written into a single file which atomically replaces the
+// previous checkpoint, so they always match. Offsets are committed upstream only once the
+// checkpoint is durable. After a crash the engine resumes from the offsets of the checkpoint,
+// which are never behind the committed ones; records consumed after it are consumed again and
+// applied to the state from before them.
+//
+// There's no consumer here. The guarantee holds only if the caller's consumer commits offsets
+// solely from the `commit` callback, with automatic commits disabled, and seeks to the offsets
+// returned by `restore` on start.
+//
+// File format: `offset,<source>,<next>` records followed by the snapshot records.
+pub struct Checkpointer {
+    dir: PathBuf,
+}
+
+impl Checkpointer {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Checkpointer {
+        Checkpointer {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // Restore the engine from the last checkpoint, returning the offsets to resume consuming
+    // from. Empty offsets and an untouched engine if there is no checkpoint yet.
+    pub fn restore<A: Amount, S: AccountStore<A>>(
+        &self,
+        engine: &Engine<A, S>,
+    ) -> Result<Offsets, CheckpointError> {
+        let content = match fs::read(self.dir.join(CHECKPOINT_FILE)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Offsets::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let (offsets, snapshot) = read_checkpoint(&content)?;
+        engine.restore_snapshot(snapshot)?;
+        Ok(offsets)
+    }
+
+    // Durably record the engine state together with the offsets following the last processed
+    // records, then commit the offsets upstream. Nothing is committed if the checkpoint can't be
+    // written.
+    pub fn checkpoint<A, S, F, E>(
+        &self,
+        engine: &Engine<A, S>,
+        offsets: &Offsets,
+        commit: F,
+    ) -> Result<(), CheckpointError>
+    where
+        A: Amount,
+        S: AccountStore<A>,
+        F: FnOnce(&Offsets) -> Result<(), E>,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(CHECKPOINT_FILE);
+        let temporary = path.with_extension("tmp");
+
+        let mut file = File::create(&temporary)?;
+        write_offsets(&mut file, offsets)?;
+        engine.write_snapshot(&mut file)?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
+        // Persist the rename itself
+        #[cfg(unix)]
+        File::open(&self.dir)?.sync_all()?;
+
+        commit(offsets).map_err(|e| CheckpointError::Commit(e.into()))
+    }
+}
+
+// Offsets of the content of a checkpoint file and the snapshot following them.
+pub fn read_checkpoint(content: &[u8]) -> Result<(Offsets, &[u8]), CheckpointError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(content);
+
+    let mut offsets = Offsets::new();
+    let mut snapshot_start = content.len();
+    let mut record = csv::StringRecord::new();
+    while reader.read_record(&mut record)? {
+        if record.get(0) != Some("offset") {
+            snapshot_start = record.position().map_or(0, |p| p.byte() as usize);
+            break;
+        }
+        let line = record.position().map_or(0, |p| p.line());
+        match (record.get(1), record.get(2).map(str::parse)) {
+            (Some(source), Some(Ok(next))) => offsets.insert(source.to_string(), next),
+            _ => return Err(CheckpointError::Malformed(line)),
+        };
+    }
+    Ok((offsets, &content[snapshot_start..]))
+}
+
+// Offset records of a checkpoint file, to be followed by the snapshot.
+pub fn write_offsets<W: Write>(writer: W, offsets: &Offsets) -> Result<(), CheckpointError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for (source, next) in offsets {
+        writer.write_record(["offset", source, &next.to_string()])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::Transaction;
+
+    fn process(engine: &mut Engine, input: &str) {
+        engine
+            .process_transactions_blocking(
+                csv::Reader::from_reader(input.as_bytes()).into_deserialize::<Transaction>(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("checkpoint-test-{}", std::process::id()));
+        let checkpointer = Checkpointer::new(&dir);
+        let mut engine: Engine = Engine::new();
+        assert_eq!(checkpointer.restore(&engine).unwrap(), Offsets::new());
+
+        process(&mut engine, "type,client,tx,amount\ndeposit,1,1,2.0\n");
+        let offsets = Offsets::from([("payments/0".to_string(), 1)]);
+        let mut committed = None;
+        checkpointer
+            .checkpoint(&engine, &offsets, |offsets| {
+                committed = Some(offsets.clone());
+                Ok::<_, io::Error>(())
+            })
+            .unwrap();
+        assert_eq!(committed.as_ref(), Some(&offsets));
+
+        process(&mut engine, "type,client,tx,amount\ndeposit,1,2,3.0\n");
+        let failed = checkpointer.checkpoint(
+            &engine,
+            &Offsets::from([("payments/0".to_string(), 2)]),
+            |_| Err("broker unavailable"),
+        );
+        assert!(matches!(failed, Err(CheckpointError::Commit(_))));
+
+        let restored: Engine = Engine::new();
+        let offsets = checkpointer.restore(&restored).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The checkpoint is durable even though its commit failed
+        assert_eq!(offsets, Offsets::from([("payments/0".to_string(), 2)]));
+        assert_eq!(
+            restored
+                .account(1.to_string().parse().unwrap())
+                .unwrap()
+                .available,
+            Decimal::new(5, 0)
+        );
+    }
+}
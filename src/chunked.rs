@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+use log::error;
+
+use crate::engine::Transaction;
+use crate::parser::{ByteRecordParser, ParseError, SerdeParser};
+
+// Default size of a chunk handed to a single parser thread.
+pub const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+type ParsedChunk = vec::IntoIter<Result<Transaction, ParseError>>;
+
+// Parallel parser over an in-memory (or memory-mapped) input.
+//
+// The input is split into byte chunks ending at record boundaries, which are parsed by a pool of
+// threads. Parsed chunks are merged back by their index, so records are yielded in exactly the
+// same order as in the input. Boundaries are found by reading the records, so line breaks in
+// quoted fields, e.g. of a memo column, stay within their record.
+//
+// A panicking thread ends the records with `ParseError::ParserPanicked`.
+pub struct ChunkedParser {
+    receiver: Receiver<(usize, Vec<Result<Transaction, ParseError>>)>,
+    threads: Vec<JoinHandle<()>>,
+    panicked: bool,
+    pending: BTreeMap<usize, ParsedChunk>,
+    current: Option<ParsedChunk>,
+    next_chunk: usize,
+    chunks: usize,
+}
+
+impl ChunkedParser {
+    pub fn spawn<D>(
+        data: Arc<D>,
+        threads: usize,
+        chunk_size: usize,
+        fast_parse: bool,
+    ) -> Result<ChunkedParser, ParseError>
+    where
+        D: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let bytes = (*data).as_ref();
+        let mut reader = csv_reader(bytes);
+        let headers = reader.byte_headers()?.clone();
+        let header_end = reader.position().byte() as usize;
+        let chunks = Arc::new(split_records(bytes, header_end, chunk_size)?);
+
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::sync_channel(threads * 2);
+        let next_index = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let data = Arc::clone(&data);
+            let chunks = Arc::clone(&chunks);
+            let next_index = Arc::clone(&next_index);
+            let headers = headers.clone();
+            let sender = sender.clone();
+            handles.push(thread::spawn(move || loop {
+                let index = next_index.fetch_add(1, Ordering::Relaxed);
+                let Some(range) = chunks.get(index) else {
+                    break;
+                };
+                let chunk = &(*data).as_ref()[range.clone()];
+                if sender
+                    .send((index, parse_chunk(chunk, &headers, fast_parse)))
+                    .is_err()
+                {
+                    break;
+                }
+            }));
+        }
+
+        Ok(ChunkedParser {
+            receiver,
+            threads: handles,
+            panicked: false,
+            pending: BTreeMap::new(),
+            current: None,
+            next_chunk: 0,
+            chunks: chunks.len(),
+        })
+    }
+}
+
+impl Iterator for ChunkedParser {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(current) = &mut self.current {
+                if let Some(record) = current.next() {
+                    return Some(record);
+                }
+                self.current = None;
+                self.next_chunk += 1;
+            }
+            if self.next_chunk == self.chunks || self.panicked {
+                return None;
+            }
+
+            // Chunks complete out of order, park them until their turn comes
+            match self.pending.remove(&self.next_chunk) {
+                Some(chunk) => self.current = Some(chunk),
+                None => match self.receiver.recv() {
+                    Ok((index, records)) => {
+                        self.pending.insert(index, records.into_iter());
+                    }
+                    // All threads are gone with chunks left, only if one of them panicked
+                    Err(_) => {
+                        for handle in self.threads.drain(..) {
+                            if handle.join().is_err() {
+                                error!("Parser thread panicked");
+                            }
+                        }
+                        self.panicked = true;
+                        return Some(Err(ParseError::ParserPanicked));
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn csv_reader(bytes: &[u8]) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(bytes)
+}
+
+// Ranges of about `chunk_size` bytes from `start` on, each ending after a record.
+fn split_records(
+    bytes: &[u8],
+    start: usize,
+    chunk_size: usize,
+) -> Result<Vec<Range<usize>>, ParseError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(&bytes[start..]);
+    let mut record = csv::ByteRecord::new();
+    let mut chunks = Vec::new();
+    let mut chunk_start = start;
+    while reader.read_byte_record(&mut record)? {
+        let record_end = start + reader.position().byte() as usize;
+        if record_end - chunk_start >= chunk_size {
+            chunks.push(chunk_start..record_end);
+            chunk_start = record_end;
+        }
+    }
+    if chunk_start < bytes.len() {
+        chunks.push(chunk_start..bytes.len());
+    }
+    Ok(chunks)
+}
+
+fn parse_chunk(
+    chunk: &[u8],
+    headers: &csv::ByteRecord,
+    fast_parse: bool,
+) -> Vec<Result<Transaction, ParseError>> {
+    let reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(chunk);
+
+    if fast_parse {
+        return match ByteRecordParser::with_headers(reader, headers) {
+            Ok(parser) => parser.collect(),
+            Err(e) => vec![Err(e)],
+        };
+    }
+
+    SerdeParser::with_headers(reader, headers).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preserves_input_order() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..1000 {
+            input.push_str(&format!("deposit,{},{},{}.5\n", tx % 7, tx, tx));
+        }
+        input.push_str("dispute,1,1");
+        let expected: Vec<Transaction> = csv_reader(input.as_bytes())
+            .into_deserialize()
+            .map(Result::unwrap)
+            .collect();
+
+        for fast_parse in [false, true] {
+            let parsed: Vec<Transaction> =
+                ChunkedParser::spawn(Arc::new(input.clone()), 4, 64, fast_parse)
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect();
+
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn test_split_records_at_line_boundaries() {
+        let input = b"h\naaaa\nbb\ncccccc\n";
+
+        let chunks = split_records(input, 2, 3).unwrap();
+
+        assert_eq!(chunks, vec![2..7, 7..10, 10..17]);
+    }
+
+    #[test]
+    fn test_quoted_line_breaks_across_chunks() {
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,10.0,\"first\nwithdrawal,1,2,5.0,x\nlast\"\n\
+                     deposit,1,3,1.0,\n";
+        let expected: Vec<Transaction> = csv_reader(input.as_bytes())
+            .into_deserialize()
+            .map(Result::unwrap)
+            .collect();
+
+        // Every chunk size, so boundaries fall anywhere within the quoted memo
+        for chunk_size in 1..input.len() {
+            let parsed: Vec<Transaction> =
+                ChunkedParser::spawn(Arc::new(input.to_string()), 2, chunk_size, true)
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect();
+
+            assert_eq!(parsed.len(), 2, "chunk size {}", chunk_size);
+            assert_eq!(
+                parsed[0].metadata["memo"],
+                "first\nwithdrawal,1,2,5.0,x\nlast"
+            );
+            assert_eq!(
+                parsed.iter().map(|t| t.transaction_id).collect::<Vec<_>>(),
+                expected
+                    .iter()
+                    .map(|t| t.transaction_id)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,888 @@
+use std::env;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use payements_engine::directory::MergeOrder;
+use payements_engine::engine::store::RetryPolicy;
+use payements_engine::engine::{
+    AccountFilter, AccountQuery, AmlThresholds, AmountFormat, BucketWidth, ChargebackDisputes,
+    ClientId, DunningPolicy, MaintenanceFees, RiskThresholds, StructuringPolicy, TransactionFilter,
+    TxStatus,
+};
+use payements_engine::pipeline::DEFAULT_CHANNEL_CAPACITY;
+use payements_engine::reorder::ReorderKey;
+use payements_engine::sequence::GapPolicy;
+use payements_engine::statement::{Period, StatementFormat};
+use payements_engine::storage;
+use rust_decimal::Decimal;
+
+// Amount representation used by the engine, see `payements_engine::engine::amount`.
+pub enum AmountKind {
+    Decimal,
+    MinorUnits,
+    Float,
+}
+
+impl FromStr for AmountKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<AmountKind> {
+        match s {
+            "decimal" => Ok(AmountKind::Decimal),
+            "minor-units" => Ok(AmountKind::MinorUnits),
+            "float" => Ok(AmountKind::Float),
+            _ => Err(anyhow::anyhow!("Unknown amount representation: {}", s)),
+        }
+    }
+}
+
+// Account store backing the engine, see `payements_engine::engine::store`.
+pub enum StoreKind {
+    DashMap,
+    Sharded,
+    BTreeMap,
+    Spilling,
+}
+
+impl FromStr for StoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<StoreKind> {
+        match s {
+            "dashmap" => Ok(StoreKind::DashMap),
+            "sharded" => Ok(StoreKind::Sharded),
+            "btree" => Ok(StoreKind::BTreeMap),
+            "spill" => Ok(StoreKind::Spilling),
+            _ => Err(anyhow::anyhow!("Unknown account store: {}", s)),
+        }
+    }
+}
+
+pub enum Command {
+    // Process the input file and print the report
+    Process,
+    // Process the optional input file, then serve the engine, see `payements_engine::server`
+    Serve(SocketAddr),
+    // Purge stored transactions of a client from the snapshot given as the file, printing the
+    // purged snapshot
+    Purge(ClientId),
+    // Print the stored transactions of a client, or of all clients without one, from the
+    // snapshot given as the file
+    ExportTransactions(Option<ClientId>),
+    // Compare the final accounts of the bundle given as the file with the other bundle, both
+    // exported from the input, see `payements_engine::compare`
+    CompareRuns(PathBuf, PathBuf),
+    // Close the period of the snapshot given as the file, archiving it to the path and printing
+    // the snapshot of the next period
+    ClosePeriod(PathBuf),
+    // Print a statement of a client for a period from the transaction history given as the file
+    Statement(ClientId, Period, StatementFormat),
+    // Split the input file into the number of shard files in the directory, by client
+    Partition(usize, PathBuf),
+    // Process jobs of a coordinator, see `payements_engine::distributed`
+    Worker(SocketAddr),
+    // Process the input file on the workers and print the report
+    Coordinate(Vec<SocketAddr>),
+    // Apply the batch to a copy of the base snapshot and print the changed balances, see
+    // `payements_engine::simulate`
+    Simulate(PathBuf, PathBuf),
+    // Process the event log given as the file, see `payements_engine::replay`, and print the
+    // report
+    Replay,
+    // Run commands from stdin against the optional snapshot given as the file, see
+    // `payements_engine::repl`
+    Repl,
+    // Check the engine against the reference engine on generated dispute sequences of the
+    // number of seeds from the seed on, each of the length, see `payements_engine::stress`
+    Stress(u64, u64, usize),
+    // Process the input file and package the report and the other outputs of the run into the
+    // bundle, see `payements_engine::bundle`
+    Export(PathBuf),
+    // Print the entries of the bundle given as the file, or the content of the entry
+    Inspect(Option<String>),
+    // Check the manifest given as the file is signed by the trusted public key, and the report and
+    // inputs still match it, see `payements_engine::manifest`
+    VerifyManifest(PathBuf),
+    // Print the snapshot, checkpoint or bundle given as the file with the snapshot in the current
+    // layout, see `payements_engine::migrate`
+    Migrate,
+    // Print the steps producing the final funds of a client from the snapshot or journal given
+    // as the file, see `payements_engine::explain`
+    Explain(ClientId),
+    // Print the accounts of the snapshot given as the file matching `AccountFilter::query`, see
+    // `AccountQuery`
+    Query,
+    // Print the result of the SQL statement over the snapshot given as the file, and the journal
+    // given with `--journal`, see `payements_engine::sql`
+    Sql(String),
+}
+
+pub struct Options {
+    pub command: Command,
+    // Instances of `serve` sharing the clients, see `payements_engine::routing`
+    pub peers: Option<Vec<SocketAddr>>,
+    // Address of this instance in `peers`, the listen address if not given
+    pub advertise: Option<SocketAddr>,
+    // File with the token enabling the admin endpoints of `serve`, see `payements_engine::admin`
+    pub admin_token_file: Option<PathBuf>,
+    // File with the secret shared by workers and coordinators, see
+    // `payements_engine::distributed::run_job`
+    pub secret_file: Option<PathBuf>,
+    // File with the approvers of held transactions, see `payements_engine::admin::Approver`
+    pub approvers_file: Option<PathBuf>,
+    // Amount above which `serve` holds posted transactions for approval, see
+    // `ServeOptions::approval_threshold`
+    pub approval_threshold: Option<Decimal>,
+    // Input file, optional when serving
+    pub file_path: Option<String>,
+    // TOML file of policies applied at start and reloaded on changes, see `payements_engine::config`
+    pub config: Option<PathBuf>,
+    // TOML file of the policies of the shadow engine of `serve`, see `payements_engine::shadow`
+    pub shadow_config: Option<PathBuf>,
+    // Snapshot to start from and to write after processing
+    pub restore: Option<PathBuf>,
+    pub snapshot_out: Option<PathBuf>,
+    // Bundle of an earlier `export` to start from, its snapshot
+    pub from_bundle: Option<PathBuf>,
+    // Where to write the risk report, see `Engine::risk_report`
+    pub risk_report: Option<PathBuf>,
+    // Where to write the posting journal, see `engine::journal`
+    pub journal: Option<PathBuf>,
+    // Where to write the transactions as applied, see `EngineBuilder::record`
+    pub record: Option<PathBuf>,
+    // Where to write the extended report as JSON, see `Engine::extended_report`
+    pub extended_report: Option<PathBuf>,
+    pub loss_account: Option<String>,
+    // Where to write disputes left in suspense, see `EngineBuilder::dispute_suspense`
+    pub dispute_suspense: Option<PathBuf>,
+    // Where to write transactions left queued on locked accounts, see
+    // `EngineBuilder::queue_locked`
+    pub queue_locked: Option<PathBuf>,
+    pub risk_thresholds: RiskThresholds,
+    // When clients are flagged for structuring, see `EngineBuilder::structuring`, and where to
+    // write the evidence
+    pub structuring: Option<StructuringPolicy>,
+    pub structuring_evidence: Option<PathBuf>,
+    // Where to write the large transaction register, see `Engine::aml_register`
+    pub aml_register: Option<PathBuf>,
+    pub aml_thresholds: AmlThresholds,
+    // Where to write the analytics report as JSON, see `Engine::analytics_report`
+    pub analytics: Option<PathBuf>,
+    // Where to write the clients processing spent the most time on, see `Engine::hot_accounts`
+    pub hot_accounts: Option<PathBuf>,
+    // How often `serve` logs `Engine::memory_stats`, never if not set
+    pub memory_log_interval: Option<Duration>,
+    // Age after which open disputes are settled as `expire_disputes_as`, see
+    // `Engine::expire_disputes`
+    pub dispute_window: Option<Duration>,
+    pub expire_disputes_as: TxStatus,
+    // Where to write the disputes expired at the end of the input
+    pub expired_disputes: Option<PathBuf>,
+    // Where to write held funds by age at the end of the input, see `Engine::held_aging`
+    pub held_aging: Option<PathBuf>,
+    // How often `serve` expires disputes
+    pub dispute_sweep_interval: Duration,
+    // Idle time after which accounts are reported and dropped, see
+    // `EngineBuilder::finalize_idle_accounts`
+    pub finalize_after: Option<Duration>,
+    pub top_clients: usize,
+    // Where to write time buckets, as JSON if the path ends with .json and CSV otherwise
+    pub time_buckets: Option<PathBuf>,
+    pub bucket_width: BucketWidth,
+    pub fast_parse: bool,
+    // Rejected on platforms other than unix
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub mmap: bool,
+    pub parse_threads: usize,
+    // Parsed records buffered ahead of the engine
+    pub queue_capacity: usize,
+    pub amount: AmountKind,
+    pub store: StoreKind,
+    pub duplicate_filter: Option<usize>,
+    pub duplicate_fp_rate: f64,
+    pub compaction_interval: Option<u64>,
+    pub enforce_balance_invariants: bool,
+    pub reject_zero_amounts: bool,
+    pub negative_deposits_as_reversals: bool,
+    pub close_with_held_funds: bool,
+    pub chargeback_disputes: ChargebackDisputes,
+    pub idempotent: bool,
+    // Client whose transactions are logged, see `EngineBuilder::trace_client`
+    pub trace_client: Option<ClientId>,
+    pub max_balance: Option<Decimal>,
+    // Fees charged by `close-period`, see `EngineBuilder::maintenance_fees`
+    pub maintenance_fees: Option<MaintenanceFees>,
+    // Idle periods after which `close-period` marks accounts dormant, see
+    // `EngineBuilder::dormancy`, and where to archive the history of dormant accounts
+    pub dormancy: Option<u32>,
+    pub dormant_archive: Option<PathBuf>,
+    // Percentage of deposits held back, and for how long, see `EngineBuilder::rolling_reserve`
+    pub rolling_reserve: Option<Decimal>,
+    pub reserve_period: Duration,
+    // TOML file of business-day calendars for reserve periods and dispute windows, see
+    // `payements_engine::config::load_calendars`
+    pub calendar: Option<PathBuf>,
+    // When to restrict withdrawals and lock accounts with negative funds, see
+    // `EngineBuilder::dunning`
+    pub dunning: Option<DunningPolicy>,
+    pub output_format: AmountFormat,
+    pub closed_column: bool,
+    // Parents of clients, adding a `parent` column to the report, and where to write the report's
+    // balances rolled up by parent
+    pub parents: Option<PathBuf>,
+    pub parent_report: Option<PathBuf>,
+    // Accounts file the report is merged into instead of being printed
+    pub upsert: Option<PathBuf>,
+    // File or object the report is written to instead of being printed
+    pub report_out: Option<PathBuf>,
+    // Where to write the manifest of the report, and the key to sign it with
+    pub manifest: Option<PathBuf>,
+    pub manifest_key: Option<PathBuf>,
+    pub report_filter: AccountFilter,
+    // Names of the ledgers to route transactions to, the first is the default
+    pub ledgers: Option<Vec<String>>,
+    pub blocklist: Option<PathBuf>,
+    pub allowlist: Option<PathBuf>,
+    pub input_filter: TransactionFilter,
+    // Transactions held back to restore their order, 0 to apply them in input order
+    pub reorder_window: usize,
+    pub reorder_by: ReorderKey,
+    // Transactions applied per second at most, see `payements_engine::throttle`
+    pub max_tps: Option<NonZeroU32>,
+    // Order of the records of an input directory's files
+    pub merge_order: MergeOrder,
+    // What to do on a gap or regression in a source's sequence numbers, unchecked if not set
+    pub gap_policy: Option<GapPolicy>,
+    pub differential: bool,
+    pub spill_dir: PathBuf,
+    pub max_resident_accounts: usize,
+    // Shards of the `dashmap` and `sharded` stores, their default if not set
+    pub store_shards: Option<usize>,
+    // Accounts the `dashmap` and `sharded` stores are pre-sized for
+    pub expected_clients: usize,
+    // Retries of failed spill file reads
+    pub store_retry: RetryPolicy,
+}
+
+impl Options {
+    pub fn from_args() -> anyhow::Result<Options> {
+        let mut file_path = None;
+        let mut command = None;
+        let mut listen = SocketAddr::from(([127, 0, 0, 1], 8080));
+        let mut client = None;
+        let mut period = None;
+        let mut statement_format = StatementFormat::Markdown;
+        let mut archive = None;
+        let mut bundle = None;
+        let mut entry = None;
+        let mut all_clients = false;
+        let mut other_bundle = None;
+        let mut statement = None;
+        let mut input = None;
+        let mut base = None;
+        let mut apply = None;
+        let mut seed = None;
+        let mut rounds = 1;
+        let mut length = 1000;
+        let mut shards = None;
+        let mut out = None;
+        let mut workers = None;
+        let mut peers = None;
+        let mut advertise = None;
+        let mut admin_token_file = None;
+        let mut secret_file = None;
+        let mut approvers_file = None;
+        let mut approval_threshold = None;
+        let mut config = None;
+        let mut shadow_config = None;
+        let mut restore = None;
+        let mut from_bundle = None;
+        let mut snapshot_out = None;
+        let mut report_out = None;
+        let mut risk_report = None;
+        let mut journal = None;
+        let mut record = None;
+        let mut extended_report = None;
+        let mut loss_account = None;
+        let mut dispute_suspense = None;
+        let mut queue_locked = None;
+        let mut risk_thresholds = RiskThresholds::default();
+        let mut analytics = None;
+        let mut hot_accounts = None;
+        let mut memory_log_interval = None;
+        let mut finalize_after = None;
+        let mut dispute_window = None;
+        let mut expire_disputes_as = TxStatus::Resolved;
+        let mut expired_disputes = None;
+        let mut held_aging = None;
+        let mut dispute_sweep_interval = Duration::from_secs(3600);
+        let mut top_clients = 10;
+        let mut time_buckets = None;
+        let mut bucket_width = BucketWidth::Day;
+        let mut fast_parse = false;
+        let mut mmap = false;
+        let mut parse_threads = 1;
+        let mut queue_capacity = DEFAULT_CHANNEL_CAPACITY;
+        let mut amount = AmountKind::Decimal;
+        let mut store = StoreKind::DashMap;
+        let mut duplicate_filter = None;
+        let mut duplicate_fp_rate = 0.0001;
+        let mut compaction_interval = None;
+        let mut enforce_balance_invariants = false;
+        let mut reject_zero_amounts = false;
+        let mut negative_deposits_as_reversals = false;
+        let mut close_with_held_funds = false;
+        let mut chargeback_disputes = ChargebackDisputes::default();
+        let mut idempotent = false;
+        let mut trace_client = None;
+        let mut max_balance = None;
+        let mut maintenance_fees = None;
+        let mut dormancy = None;
+        let mut dormant_archive = None;
+        let mut rolling_reserve = None;
+        let mut reserve_period = Duration::from_secs(90 * 24 * 3600);
+        let mut calendar = None;
+        let mut dunning = None;
+        let mut structuring = None;
+        let mut structuring_evidence = None;
+        let mut aml_register = None;
+        let mut aml_thresholds = AmlThresholds::default();
+        let mut output_format = AmountFormat::default();
+        let mut closed_column = false;
+        let mut parents = None;
+        let mut parent_report = None;
+        let mut upsert = None;
+        let mut manifest = None;
+        let mut manifest_key = None;
+        let mut trusted_key = None;
+        let mut report_filter = AccountFilter::default();
+        let mut ledgers = None;
+        let mut blocklist = None;
+        let mut allowlist = None;
+        let mut input_filter = TransactionFilter::default();
+        let mut reorder_window = 0;
+        let mut reorder_by = ReorderKey::Timestamp;
+        let mut max_tps = None;
+        let mut merge_order = MergeOrder::Timestamp;
+        let mut gap_policy = None;
+        let mut differential = false;
+        let mut spill_dir = env::temp_dir().join(format!("payements-engine-{}", process::id()));
+        let mut max_resident_accounts = 1_000_000;
+        let mut store_shards = None;
+        let mut expected_clients = 0;
+        let mut store_retry = RetryPolicy::default();
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--fast-parse" => fast_parse = true,
+                "--mmap" => mmap = true,
+                "--parse-threads" => parse_threads = value(&arg, args.next())?,
+                "--queue-capacity" => queue_capacity = value(&arg, args.next())?,
+                "--amount" => amount = value(&arg, args.next())?,
+                "--store" => store = value(&arg, args.next())?,
+                "--duplicate-filter" => duplicate_filter = Some(value(&arg, args.next())?),
+                "--duplicate-fp-rate" => duplicate_fp_rate = value(&arg, args.next())?,
+                "--compaction-interval" => compaction_interval = Some(value(&arg, args.next())?),
+                "--enforce-balance-invariants" => enforce_balance_invariants = true,
+                "--reject-zero-amounts" => reject_zero_amounts = true,
+                "--negative-deposits-as-reversals" => negative_deposits_as_reversals = true,
+                "--close-with-held-funds" => close_with_held_funds = true,
+                "--chargeback-disputes" => chargeback_disputes = value(&arg, args.next())?,
+                "--idempotent" => idempotent = true,
+                "--trace-client" => trace_client = Some(value(&arg, args.next())?),
+                "--max-balance" => max_balance = Some(value(&arg, args.next())?),
+                "--maintenance-fee" => maintenance_fees = Some(value(&arg, args.next())?),
+                "--dormancy" => {
+                    let periods: u32 = value(&arg, args.next())?;
+                    if periods == 0 {
+                        return Err(anyhow::anyhow!("--dormancy must be at least one period"));
+                    }
+                    dormancy = Some(periods)
+                }
+                "--dormant-archive" => dormant_archive = Some(value(&arg, args.next())?),
+                "--include-dormant" => report_filter.include_dormant = true,
+                "--query" => report_filter.query = Some(query(args.next())?),
+                "--rolling-reserve" => {
+                    let percent: Decimal = value(&arg, args.next())?;
+                    if !(Decimal::ZERO..=Decimal::ONE_HUNDRED).contains(&percent) {
+                        return Err(anyhow::anyhow!("--rolling-reserve is a percentage"));
+                    }
+                    rolling_reserve = Some(percent);
+                }
+                "--reserve-period" => {
+                    reserve_period = value::<humantime::Duration>(&arg, args.next())?.into()
+                }
+                "--calendar" => calendar = Some(value(&arg, args.next())?),
+                "--dunning" => dunning = Some(value(&arg, args.next())?),
+                "--structuring" => structuring = Some(value(&arg, args.next())?),
+                "--aml-register" => aml_register = Some(value(&arg, args.next())?),
+                "--aml-threshold" => aml_thresholds.single = Some(value(&arg, args.next())?),
+                "--aml-daily-threshold" => aml_thresholds.daily = Some(value(&arg, args.next())?),
+                "--structuring-evidence" => structuring_evidence = Some(value(&arg, args.next())?),
+                "--output-format" => output_format = value(&arg, args.next())?,
+                "--closed-column" => closed_column = true,
+                "--parents" => parents = Some(value(&arg, args.next())?),
+                "--parent-report" => parent_report = Some(value(&arg, args.next())?),
+                "--upsert" => upsert = Some(value(&arg, args.next())?),
+                "--manifest" => manifest = Some(value(&arg, args.next())?),
+                "--manifest-key" => manifest_key = Some(value(&arg, args.next())?),
+                "--trusted-key" => trusted_key = Some(value(&arg, args.next())?),
+                "--filter-clients" => report_filter.clients = Some(value(&arg, args.next())?),
+                "--only-locked" => report_filter.only_locked = true,
+                "--ledgers" => ledgers = Some(list(&arg, args.next())?),
+                "--blocklist" => blocklist = Some(value(&arg, args.next())?),
+                "--allowlist" => allowlist = Some(value(&arg, args.next())?),
+                "--types" => input_filter.types = Some(list(&arg, args.next())?),
+                "--from" => input_filter.from = Some(value(&arg, args.next())?),
+                "--to" => input_filter.to = Some(value(&arg, args.next())?),
+                "--reorder-window" => reorder_window = value(&arg, args.next())?,
+                "--max-tps" => max_tps = Some(value(&arg, args.next())?),
+                "--reorder-by" => reorder_by = value(&arg, args.next())?,
+                "--merge-order" => merge_order = value(&arg, args.next())?,
+                "--sequence-gaps" => gap_policy = Some(value(&arg, args.next())?),
+                "--min-balance" => report_filter.min_balance = Some(value(&arg, args.next())?),
+                "--differential" => differential = true,
+                "--spill-dir" => spill_dir = value(&arg, args.next())?,
+                "--max-resident-accounts" => max_resident_accounts = value(&arg, args.next())?,
+                "--store-shards" => store_shards = Some(value(&arg, args.next())?),
+                "--expected-clients" => expected_clients = value(&arg, args.next())?,
+                "--store-retries" => store_retry.max_attempts = value(&arg, args.next())?,
+                "--store-retry-backoff" => {
+                    store_retry.initial_backoff =
+                        value::<humantime::Duration>(&arg, args.next())?.into()
+                }
+                "--listen" => listen = value(&arg, args.next())?,
+                "--client" => client = Some(value(&arg, args.next())?),
+                "--all" => all_clients = true,
+                "--input" => input = Some(value(&arg, args.next())?),
+                "--period" => period = Some(value(&arg, args.next())?),
+                "--archive" => archive = Some(value(&arg, args.next())?),
+                "--bundle" => bundle = Some(value(&arg, args.next())?),
+                "--entry" => entry = Some(value(&arg, args.next())?),
+                "--base" => base = Some(value(&arg, args.next())?),
+                "--apply" => apply = Some(value(&arg, args.next())?),
+                "--seed" => seed = Some(value(&arg, args.next())?),
+                "--rounds" => rounds = value(&arg, args.next())?,
+                "--length" => length = value(&arg, args.next())?,
+                "--shards" => shards = Some(value(&arg, args.next())?),
+                "--out" => out = Some(value(&arg, args.next())?),
+                "--workers" => workers = Some(list(&arg, args.next())?),
+                "--peers" => peers = Some(list(&arg, args.next())?),
+                "--advertise" => advertise = Some(value(&arg, args.next())?),
+                "--statement-format" => statement_format = value(&arg, args.next())?,
+                "--admin-token-file" => admin_token_file = Some(value(&arg, args.next())?),
+                "--secret-file" => secret_file = Some(value(&arg, args.next())?),
+                "--approvers-file" => approvers_file = Some(value(&arg, args.next())?),
+                "--approval-threshold" => approval_threshold = Some(value(&arg, args.next())?),
+                "--config" => config = Some(value(&arg, args.next())?),
+                "--shadow-config" => shadow_config = Some(value(&arg, args.next())?),
+                "--restore" => restore = Some(value(&arg, args.next())?),
+                "--from-bundle" => from_bundle = Some(value(&arg, args.next())?),
+                "--snapshot-out" => snapshot_out = Some(value(&arg, args.next())?),
+                "--report-out" => report_out = Some(value(&arg, args.next())?),
+                "--journal" => journal = Some(value(&arg, args.next())?),
+                "--record" => record = Some(value(&arg, args.next())?),
+                "--extended-report" => extended_report = Some(value(&arg, args.next())?),
+                "--loss-account" => loss_account = Some(value(&arg, args.next())?),
+                "--dispute-suspense" => dispute_suspense = Some(value(&arg, args.next())?),
+                "--queue-locked" => queue_locked = Some(value(&arg, args.next())?),
+                "--risk-report" => risk_report = Some(value(&arg, args.next())?),
+                "--max-dispute-rate" => risk_thresholds.dispute_rate = value(&arg, args.next())?,
+                "--max-chargeback-rate" => {
+                    risk_thresholds.chargeback_rate = value(&arg, args.next())?
+                }
+                "--analytics" => analytics = Some(value(&arg, args.next())?),
+                "--hot-accounts" => hot_accounts = Some(value(&arg, args.next())?),
+                "--memory-log-interval" => {
+                    memory_log_interval =
+                        Some(value::<humantime::Duration>(&arg, args.next())?.into())
+                }
+                "--dispute-window" => {
+                    dispute_window = Some(value::<humantime::Duration>(&arg, args.next())?.into())
+                }
+                "--expire-disputes-as" => {
+                    expire_disputes_as = value(&arg, args.next())?;
+                    if !matches!(
+                        expire_disputes_as,
+                        TxStatus::Resolved | TxStatus::ChargedBack
+                    ) {
+                        return Err(anyhow::anyhow!(
+                            "--expire-disputes-as is either resolved or chargedback"
+                        ));
+                    }
+                }
+                "--expired-disputes" => expired_disputes = Some(value(&arg, args.next())?),
+                "--held-aging" => held_aging = Some(value(&arg, args.next())?),
+                "--dispute-sweep-interval" => {
+                    dispute_sweep_interval = value::<humantime::Duration>(&arg, args.next())?.into()
+                }
+                "--finalize-after" => {
+                    finalize_after = Some(value::<humantime::Duration>(&arg, args.next())?.into())
+                }
+                "--top-clients" => top_clients = value(&arg, args.next())?,
+                "--time-buckets" => time_buckets = Some(value(&arg, args.next())?),
+                "--bucket" => bucket_width = value(&arg, args.next())?,
+                "serve"
+                | "purge"
+                | "export-transactions"
+                | "compare-runs"
+                | "statement"
+                | "close-period"
+                | "partition"
+                | "worker"
+                | "coordinator"
+                | "repl"
+                | "simulate"
+                | "replay"
+                | "stress"
+                | "export"
+                | "inspect"
+                | "verify-manifest"
+                | "migrate"
+                | "explain"
+                | "query"
+                | "sql"
+                    if command.is_none() && file_path.is_none() =>
+                {
+                    command = Some(arg)
+                }
+                _ if file_path.is_none() => file_path = Some(arg),
+                // `query <snapshot.csv> <query>`
+                _ if command.as_deref() == Some("query") && report_filter.query.is_none() => {
+                    report_filter.query = Some(query(Some(arg))?)
+                }
+                // `sql <snapshot.csv> <statement>`
+                _ if command.as_deref() == Some("sql") && statement.is_none() => {
+                    statement = Some(arg)
+                }
+                // `compare-runs <old.tar.zst> <new.tar.zst>`
+                _ if command.as_deref() == Some("compare-runs") && other_bundle.is_none() => {
+                    other_bundle = Some(PathBuf::from(arg))
+                }
+                _ => return Err(anyhow::anyhow!("Unexpected argument: {}", arg)),
+            }
+        }
+
+        if aml_register.is_some() && aml_thresholds == AmlThresholds::default() {
+            return Err(anyhow::anyhow!(
+                "--aml-register needs --aml-threshold or --aml-daily-threshold"
+            ));
+        }
+
+        if parent_report.is_some() && parents.is_none() {
+            return Err(anyhow::anyhow!("--parent-report needs --parents"));
+        }
+        if manifest_key.is_some() && manifest.is_none() {
+            return Err(anyhow::anyhow!("--manifest-key needs --manifest"));
+        }
+        if approvers_file.is_some() && admin_token_file.is_none() {
+            return Err(anyhow::anyhow!("--approvers-file needs --admin-token-file"));
+        }
+        if from_bundle.is_some() && restore.is_some() {
+            return Err(anyhow::anyhow!(
+                "--from-bundle and --restore both give the state to start from"
+            ));
+        }
+        if from_bundle.is_some() && ledgers.is_some() {
+            return Err(anyhow::anyhow!("--from-bundle doesn't apply to --ledgers"));
+        }
+        if parents.is_some() && ledgers.is_some() {
+            return Err(anyhow::anyhow!("--parents doesn't apply to --ledgers"));
+        }
+
+        let paths = [
+            &admin_token_file,
+            &secret_file,
+            &approvers_file,
+            &config,
+            &calendar,
+            &shadow_config,
+            &from_bundle,
+            &risk_report,
+            &structuring_evidence,
+            &aml_register,
+            &journal,
+            &record,
+            &extended_report,
+            &dispute_suspense,
+            &queue_locked,
+            &analytics,
+            &hot_accounts,
+            &expired_disputes,
+            &held_aging,
+            &time_buckets,
+            &blocklist,
+            &allowlist,
+            &parents,
+            &parent_report,
+            &dormant_archive,
+            &upsert,
+            &manifest,
+            &manifest_key,
+            &trusted_key,
+            &archive,
+            &bundle,
+            &base,
+            &apply,
+            &out,
+        ];
+        // Object storage URIs would otherwise be taken for relative paths
+        let paths = paths.into_iter().flatten().map(PathBuf::as_path);
+        for path in paths.chain([spill_dir.as_path()]) {
+            if storage::is_object_uri(path) {
+                return Err(anyhow::anyhow!(
+                    "Only the input, --restore, --snapshot-out and --report-out can be object storage URIs: {}",
+                    path.display()
+                ));
+            }
+        }
+        if upsert.is_some() && report_out.is_some() {
+            return Err(anyhow::anyhow!("Use either --upsert or --report-out"));
+        }
+
+        let command = match command.as_deref() {
+            Some("serve") => Command::Serve(listen),
+            Some("worker") => Command::Worker(listen),
+            Some("repl") => Command::Repl,
+            Some("replay") if reorder_window > 0 => {
+                return Err(anyhow::anyhow!(
+                    "Events are replayed in the recorded order, --reorder-window doesn't apply"
+                ))
+            }
+            Some("replay") => Command::Replay,
+            Some("simulate") => Command::Simulate(
+                base.ok_or_else(|| anyhow::anyhow!("Missing --base snapshot to simulate on"))?,
+                apply.ok_or_else(|| anyhow::anyhow!("Missing --apply batch to simulate"))?,
+            ),
+            Some("stress") => Command::Stress(
+                seed.ok_or_else(|| anyhow::anyhow!("Missing --seed to stress with"))?,
+                rounds,
+                length,
+            ),
+            Some("coordinator") => Command::Coordinate(
+                workers
+                    .filter(|workers: &Vec<_>| !workers.is_empty())
+                    .ok_or_else(|| anyhow::anyhow!("Missing --workers to coordinate"))?,
+            ),
+            Some("export-transactions") => match (client, all_clients) {
+                (Some(_), true) => {
+                    return Err(anyhow::anyhow!("Use either --client or --all to export"))
+                }
+                (None, false) => {
+                    return Err(anyhow::anyhow!("Missing --client or --all to export"))
+                }
+                (client, _) => Command::ExportTransactions(client),
+            },
+            Some("compare-runs") => Command::CompareRuns(
+                other_bundle.ok_or_else(|| anyhow::anyhow!("Missing the new bundle to compare"))?,
+                input.ok_or_else(|| anyhow::anyhow!("Missing --input of the compared runs"))?,
+            ),
+            Some("purge") => {
+                Command::Purge(client.ok_or_else(|| anyhow::anyhow!("Missing --client to purge"))?)
+            }
+            Some("partition") => Command::Partition(
+                shards
+                    .filter(|&shards| shards > 0)
+                    .ok_or_else(|| anyhow::anyhow!("Missing or zero --shards to partition into"))?,
+                out.ok_or_else(|| anyhow::anyhow!("Missing --out directory of the shards"))?,
+            ),
+            Some("export") if journal.is_some() => {
+                return Err(anyhow::anyhow!(
+                    "The journal of export is in the bundle, --journal doesn't apply"
+                ))
+            }
+            Some("export") if ledgers.is_some() => {
+                return Err(anyhow::anyhow!("export doesn't support --ledgers"))
+            }
+            Some("export") => Command::Export(
+                bundle.ok_or_else(|| anyhow::anyhow!("Missing --bundle to export to"))?,
+            ),
+            Some("inspect") => Command::Inspect(entry),
+            Some("verify-manifest") => Command::VerifyManifest(
+                trusted_key
+                    .ok_or_else(|| anyhow::anyhow!("Missing --trusted-key to verify with"))?,
+            ),
+            Some("migrate") => Command::Migrate,
+            Some("query") if report_filter.query.is_none() => {
+                return Err(anyhow::anyhow!("Missing query of the accounts"))
+            }
+            Some("query") => Command::Query,
+            Some("sql") => Command::Sql(
+                statement.ok_or_else(|| anyhow::anyhow!("Missing SQL statement to run"))?,
+            ),
+            Some("explain") => Command::Explain(
+                client.ok_or_else(|| anyhow::anyhow!("Missing --client to explain"))?,
+            ),
+            Some("close-period") => Command::ClosePeriod(
+                archive.ok_or_else(|| anyhow::anyhow!("Missing --archive of the closed period"))?,
+            ),
+            Some(_) => Command::Statement(
+                client.ok_or_else(|| anyhow::anyhow!("Missing --client of the statement"))?,
+                period.ok_or_else(|| anyhow::anyhow!("Missing --period of the statement"))?,
+                statement_format,
+            ),
+            None => Command::Process,
+        };
+        let objects = [&restore, &snapshot_out, &report_out]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+            .chain(file_path.as_deref().map(Path::new))
+            .filter(|path| storage::is_object_uri(path));
+        for path in objects {
+            if !cfg!(feature = "object-store") {
+                return Err(anyhow::anyhow!(
+                    "Object storage URIs need a build with the object-store feature: {}",
+                    path.display()
+                ));
+            }
+            if !matches!(command, Command::Process) {
+                return Err(anyhow::anyhow!(
+                    "Only plain runs read and write object storage URIs: {}",
+                    path.display()
+                ));
+            }
+        }
+        // Jobs are plain TCP, anyone reaching the worker could otherwise submit them
+        if let Command::Worker(address) = command {
+            if secret_file.is_none() && !address.ip().is_loopback() {
+                return Err(anyhow::anyhow!(
+                    "A worker listening on {} needs --secret-file",
+                    address
+                ));
+            }
+        }
+        if mmap && cfg!(not(unix)) {
+            return Err(anyhow::anyhow!("--mmap is only supported on unix"));
+        }
+        if mmap
+            && file_path
+                .as_deref()
+                .is_some_and(|path| storage::is_object_uri(Path::new(path)))
+        {
+            return Err(anyhow::anyhow!("--mmap needs a local input file"));
+        }
+        if file_path.is_none()
+            && !matches!(
+                command,
+                Command::Serve(_)
+                    | Command::Worker(_)
+                    | Command::Repl
+                    | Command::Simulate(..)
+                    | Command::Stress(..)
+            )
+        {
+            return Err(anyhow::anyhow!("Expecting one argument"));
+        }
+
+        Ok(Options {
+            command,
+            peers,
+            advertise,
+            admin_token_file,
+            secret_file,
+            approvers_file,
+            approval_threshold,
+            file_path,
+            config,
+            shadow_config,
+            restore,
+            snapshot_out,
+            report_out,
+            from_bundle,
+            risk_report,
+            journal,
+            record,
+            extended_report,
+            loss_account,
+            dispute_suspense,
+            queue_locked,
+            risk_thresholds,
+            analytics,
+            hot_accounts,
+            memory_log_interval,
+            dispute_window,
+            expire_disputes_as,
+            expired_disputes,
+            held_aging,
+            dispute_sweep_interval,
+            finalize_after,
+            top_clients,
+            time_buckets,
+            bucket_width,
+            fast_parse,
+            mmap,
+            parse_threads,
+            queue_capacity,
+            amount,
+            store,
+            duplicate_filter,
+            duplicate_fp_rate,
+            compaction_interval,
+            enforce_balance_invariants,
+            reject_zero_amounts,
+            negative_deposits_as_reversals,
+            close_with_held_funds,
+            chargeback_disputes,
+            idempotent,
+            trace_client,
+            max_balance,
+            maintenance_fees,
+            dormancy,
+            dormant_archive,
+            rolling_reserve,
+            reserve_period,
+            calendar,
+            dunning,
+            structuring,
+            structuring_evidence,
+            aml_register,
+            aml_thresholds,
+            output_format,
+            closed_column,
+            parents,
+            parent_report,
+            upsert,
+            manifest,
+            manifest_key,
+            report_filter,
+            ledgers,
+            blocklist,
+            allowlist,
+            input_filter,
+            reorder_window,
+            reorder_by,
+            max_tps,
+            merge_order,
+            gap_policy,
+            differential,
+            spill_dir,
+            max_resident_accounts,
+            store_shards,
+            expected_clients,
+            store_retry,
+        })
+    }
+}
+
+fn value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> anyhow::Result<T> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid or missing value for {}", flag))
+}
+
+fn query(value: Option<String>) -> anyhow::Result<AccountQuery> {
+    let value = value.ok_or_else(|| anyhow::anyhow!("Missing value for --query"))?;
+    AccountQuery::new(&value).map_err(|e| anyhow::anyhow!("Invalid query {}: {}", value, e))
+}
+
+fn list<T: std::str::FromStr>(flag: &str, value: Option<String>) -> anyhow::Result<Vec<T>> {
+    value
+        .and_then(|v| v.split(',').map(|item| item.trim().parse().ok()).collect())
+        .ok_or_else(|| anyhow::anyhow!("Invalid or missing value for {}", flag))
+}
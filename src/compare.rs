@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::bundle::{Bundle, SNAPSHOT_ENTRY};
+use crate::engine::{Account, ClientId, Engine, SnapshotError};
+use crate::manifest::FileDigest;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompareError {
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error("No {SNAPSHOT_ENTRY} in the {0} bundle")]
+    MissingSnapshot(&'static str),
+    #[error("The {0} bundle doesn't record its inputs, it was exported by an earlier release")]
+    NoInputs(&'static str),
+    #[error("The {0} bundle wasn't run on {1}, the SHA-256 of its inputs differ")]
+    InputMismatch(&'static str, String),
+}
+
+// Field of an account whose final state differs between two runs. A client with an account in
+// only one of them has the single field `account`, `present` in that run and `missing` in the
+// other.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountChange {
+    pub client: ClientId,
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+// Accounts whose final state differs between the runs of two bundles of `export`, in client
+// order, after checking both runs were made on the input, e.g. the same file processed by two
+// engine versions. Inputs are matched by their SHA-256, not their path.
+pub fn compare_runs(
+    old: &Bundle,
+    new: &Bundle,
+    input: &FileDigest,
+) -> Result<Vec<AccountChange>, CompareError> {
+    for (name, bundle) in [("old", old), ("new", new)] {
+        if bundle.manifest.inputs.is_empty() {
+            return Err(CompareError::NoInputs(name));
+        }
+        if !bundle
+            .manifest
+            .inputs
+            .iter()
+            .any(|entry| entry.sha256 == input.sha256)
+        {
+            return Err(CompareError::InputMismatch(name, input.path.clone()));
+        }
+    }
+    let old = accounts(old, "old")?;
+    let new = accounts(new, "new")?;
+
+    let mut clients: Vec<ClientId> = old.keys().chain(new.keys()).copied().collect();
+    clients.sort();
+    clients.dedup();
+    let mut changes = Vec::new();
+    for client in clients {
+        match (old.get(&client), new.get(&client)) {
+            (Some(old), Some(new)) => {
+                for ((field, old), (_, new)) in fields(old).into_iter().zip(fields(new)) {
+                    if old != new {
+                        changes.push(AccountChange {
+                            client,
+                            field,
+                            old,
+                            new,
+                        });
+                    }
+                }
+            }
+            (old, _) => changes.push(AccountChange {
+                client,
+                field: "account",
+                old: presence(old.is_some()),
+                new: presence(old.is_none()),
+            }),
+        }
+    }
+    Ok(changes)
+}
+
+pub fn write_changes<W: io::Write>(changes: &[AccountChange], writer: W) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for change in changes {
+        writer.serialize(change)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn accounts(
+    bundle: &Bundle,
+    name: &'static str,
+) -> Result<BTreeMap<ClientId, Account<Decimal>>, CompareError> {
+    let snapshot = bundle
+        .entry(SNAPSHOT_ENTRY)
+        .ok_or(CompareError::MissingSnapshot(name))?;
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(snapshot)?;
+    Ok(engine
+        .accounts()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|account| (account.client_id, account))
+        .collect())
+}
+
+fn fields(account: &Account<Decimal>) -> [(&'static str, String); 9] {
+    [
+        ("available", account.available.normalize().to_string()),
+        ("held", account.held.normalize().to_string()),
+        ("reserve", account.reserve.normalize().to_string()),
+        ("total", account.calculate_total().normalize().to_string()),
+        ("locked", account.locked.to_string()),
+        ("quarantined", account.quarantined.to_string()),
+        ("closed", account.closed.to_string()),
+        ("dormant", account.dormant.to_string()),
+        ("dunning", account.dunning.as_str().to_string()),
+    ]
+}
+
+fn presence(present: bool) -> String {
+    if present { "present" } else { "missing" }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(snapshot: &str, input_sha256: &str) -> Bundle {
+        let mut bundle = Bundle::new(Vec::new());
+        bundle.add(SNAPSHOT_ENTRY, snapshot.as_bytes().to_vec());
+        bundle.manifest.inputs.push(crate::bundle::BundleEntry {
+            name: "input.csv".to_string(),
+            bytes: 1,
+            sha256: input_sha256.to_string(),
+        });
+        bundle
+    }
+
+    #[test]
+    fn test_compare_runs() {
+        let old = bundle(
+            "snapshot,1\n\
+             account,1,5.0,0.0,false,false,false\n\
+             account,2,1.0,0.0,false,false,false\n",
+            "aa",
+        );
+        let new = bundle(
+            "snapshot,1\n\
+             account,1,5,0,false,false,false\n\
+             account,2,0.5,0.5,true,false,false\n\
+             account,3,0,0,false,false,false\n",
+            "aa",
+        );
+        let input = FileDigest {
+            path: "same.csv".to_string(),
+            sha256: "aa".to_string(),
+            bytes: 1,
+        };
+        let change = |client: &str, field, old: &str, new: &str| AccountChange {
+            client: client.parse().unwrap(),
+            field,
+            old: old.to_string(),
+            new: new.to_string(),
+        };
+        // Client 1 is the same with other trailing zeros
+        assert_eq!(
+            compare_runs(&old, &new, &input).unwrap(),
+            [
+                change("2", "available", "1", "0.5"),
+                change("2", "held", "0", "0.5"),
+                change("2", "locked", "false", "true"),
+                change("3", "account", "missing", "present"),
+            ]
+        );
+        assert!(compare_runs(&old, &old, &input).unwrap().is_empty());
+
+        let other = FileDigest {
+            sha256: "bb".to_string(),
+            ..input.clone()
+        };
+        assert!(matches!(
+            compare_runs(&old, &new, &other),
+            Err(CompareError::InputMismatch("old", _))
+        ));
+        let mut unrecorded = new.clone();
+        unrecorded.manifest.inputs.clear();
+        assert!(matches!(
+            compare_runs(&old, &unrecorded, &input),
+            Err(CompareError::NoInputs("new"))
+        ));
+    }
+}
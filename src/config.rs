@@ -0,0 +1,525 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use rust_decimal::Decimal;
+use toml_edit::{DocumentMut, Item};
+
+use crate::engine::amount::Amount;
+use crate::engine::{
+    BusinessCalendar, Calendars, ChargebackDisputes, ClientGroup, Policies, PolicyHandle, Rule,
+    RuleAction, RuleError, RulePack, Timestamp, WEEKDAYS,
+};
+
+// How often `ConfigWatcher::spawn` checks the config file for changes.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Toml(#[from] toml_edit::TomlError),
+    #[error("Unknown config key: {0}")]
+    UnknownKey(String),
+    #[error("Invalid value of config key {0}")]
+    InvalidValue(String),
+    #[error("Invalid rule {0}: {1}")]
+    InvalidRule(String, RuleError),
+}
+
+// Policies set by a TOML config file, e.g.
+//
+// max_balance = "10000.00"
+// reject_zero_amounts = true
+// close_with_held_funds = false
+// chargeback_disputes = "resolve"
+//
+// [risk]
+// max_dispute_rate = 0.02
+// max_chargeback_rate = 0.01
+//
+// [[rule]]
+// name = "large_withdrawal"
+// reject_if = "type == \"withdrawal\" && amount > 5000"
+//
+// [[rule]]
+// name = "drains_account"
+// flag_if = "type == \"withdrawal\" && amount > available / 2"
+//
+// [[group]]
+// name = "eu_retail"
+// clients = "1-5000"
+// pack = "eu"
+// max_balance = "15000.00"
+//
+// Keys left out keep the policies the engine was started with, so removing a key from the file
+// reverts its policy. Rules and groups replace the ones the engine was started with as a whole.
+// A group's `pack` is one of `RULE_PACK_PRESETS`, overridden by the group's other keys.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub max_balance: Option<Decimal>,
+    pub reject_zero_amounts: Option<bool>,
+    pub close_with_held_funds: Option<bool>,
+    pub chargeback_disputes: Option<ChargebackDisputes>,
+    pub max_dispute_rate: Option<f64>,
+    pub max_chargeback_rate: Option<f64>,
+    pub rules: Option<Vec<Rule>>,
+    pub groups: Option<Vec<ClientGroup<Decimal>>>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    // The base policies overridden by the keys of the config.
+    pub fn apply<A: Amount>(&self, base: &Policies<A>) -> Policies<A> {
+        let mut policies = base.clone();
+        if let Some(max_balance) = self.max_balance {
+            // A limit above what the amount type can represent can't be exceeded anyway
+            policies.max_balance = A::from_decimal(max_balance);
+        }
+        if let Some(reject_zero_amounts) = self.reject_zero_amounts {
+            policies.reject_zero_amounts = reject_zero_amounts;
+        }
+        if let Some(close_with_held_funds) = self.close_with_held_funds {
+            policies.close_with_held_funds = close_with_held_funds;
+        }
+        if let Some(policy) = self.chargeback_disputes {
+            policies.chargeback_disputes = policy;
+        }
+        if let Some(rate) = self.max_dispute_rate {
+            policies.risk_thresholds.dispute_rate = rate;
+        }
+        if let Some(rate) = self.max_chargeback_rate {
+            policies.risk_thresholds.chargeback_rate = rate;
+        }
+        if let Some(rules) = &self.rules {
+            policies.rules = rules.clone();
+        }
+        if let Some(groups) = &self.groups {
+            policies.groups = groups
+                .iter()
+                .map(|group| ClientGroup {
+                    name: group.name.clone(),
+                    clients: group.clients.clone(),
+                    pack: RulePack {
+                        max_balance: group.pack.max_balance.and_then(A::from_decimal),
+                        reject_zero_amounts: group.pack.reject_zero_amounts,
+                        close_with_held_funds: group.pack.close_with_held_funds,
+                        chargeback_disputes: group.pack.chargeback_disputes,
+                        dispute_window: group.pack.dispute_window,
+                    },
+                })
+                .collect();
+        }
+        policies
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Config, ConfigError> {
+        let document: DocumentMut = s.parse()?;
+        let mut config = Config::default();
+        for (key, item) in document.iter() {
+            match key {
+                "max_balance" => config.max_balance = Some(decimal(key, item)?),
+                "reject_zero_amounts" => config.reject_zero_amounts = Some(boolean(key, item)?),
+                "close_with_held_funds" => config.close_with_held_funds = Some(boolean(key, item)?),
+                "chargeback_disputes" => {
+                    config.chargeback_disputes = Some(
+                        item.as_str()
+                            .and_then(|value| value.parse().ok())
+                            .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))?,
+                    )
+                }
+                "risk" => {
+                    let table = item
+                        .as_table()
+                        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))?;
+                    for (key, item) in table.iter() {
+                        let key = format!("risk.{}", key);
+                        match key.as_str() {
+                            "risk.max_dispute_rate" => {
+                                config.max_dispute_rate = Some(rate(&key, item)?)
+                            }
+                            "risk.max_chargeback_rate" => {
+                                config.max_chargeback_rate = Some(rate(&key, item)?)
+                            }
+                            _ => return Err(ConfigError::UnknownKey(key)),
+                        }
+                    }
+                }
+                "rule" => {
+                    let tables = item
+                        .as_array_of_tables()
+                        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))?;
+                    config.rules = Some(tables.iter().map(rule).collect::<Result<_, _>>()?);
+                }
+                "group" => {
+                    let tables = item
+                        .as_array_of_tables()
+                        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))?;
+                    config.groups = Some(tables.iter().map(group).collect::<Result<_, _>>()?);
+                }
+                _ => return Err(ConfigError::UnknownKey(key.to_string())),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// A `name` and one of `reject_if` or `flag_if` with the condition.
+fn rule(table: &toml_edit::Table) -> Result<Rule, ConfigError> {
+    let string = |key: &str| table.get(key).and_then(Item::as_str);
+    let name = string("name").ok_or_else(|| ConfigError::InvalidValue("rule.name".to_string()))?;
+    let (action, expression) = match (string("reject_if"), string("flag_if")) {
+        (Some(expression), None) => (RuleAction::Reject, expression),
+        (None, Some(expression)) => (RuleAction::Flag, expression),
+        _ => return Err(ConfigError::InvalidValue(format!("rule.{}", name))),
+    };
+    if let Some((key, _)) = table
+        .iter()
+        .find(|(key, _)| !["name", "reject_if", "flag_if"].contains(key))
+    {
+        return Err(ConfigError::UnknownKey(format!("rule.{}", key)));
+    }
+    Rule::new(name, action, expression).map_err(|e| ConfigError::InvalidRule(name.to_string(), e))
+}
+
+// A `name`, the `clients` as `ClientRanges` and the keys of the pack.
+fn group(table: &toml_edit::Table) -> Result<ClientGroup<Decimal>, ConfigError> {
+    let string = |key: &str| {
+        table
+            .get(key)
+            .map(|item| {
+                item.as_str()
+                    .ok_or_else(|| ConfigError::InvalidValue(format!("group.{}", key)))
+            })
+            .transpose()
+    };
+    let name =
+        string("name")?.ok_or_else(|| ConfigError::InvalidValue("group.name".to_string()))?;
+    let clients = string("clients")?
+        .and_then(|clients| clients.parse().ok())
+        .ok_or_else(|| ConfigError::InvalidValue(format!("group.{}.clients", name)))?;
+    let mut pack = match string("pack")? {
+        Some(preset) => RulePack::preset(preset)
+            .ok_or_else(|| ConfigError::InvalidValue(format!("group.{}.pack", name)))?,
+        None => RulePack::default(),
+    };
+    for (key, item) in table.iter() {
+        let key = format!("group.{}.{}", name, key);
+        match key.rsplit('.').next() {
+            Some("name" | "clients" | "pack") => {}
+            Some("max_balance") => pack.max_balance = Some(decimal(&key, item)?),
+            Some("reject_zero_amounts") => pack.reject_zero_amounts = Some(boolean(&key, item)?),
+            Some("close_with_held_funds") => {
+                pack.close_with_held_funds = Some(boolean(&key, item)?)
+            }
+            Some("chargeback_disputes") => {
+                pack.chargeback_disputes = Some(
+                    item.as_str()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| ConfigError::InvalidValue(key.clone()))?,
+                )
+            }
+            Some("dispute_window") => {
+                pack.dispute_window = Some(
+                    item.as_str()
+                        .and_then(|value| humantime::parse_duration(value).ok())
+                        .ok_or_else(|| ConfigError::InvalidValue(key.clone()))?,
+                )
+            }
+            _ => return Err(ConfigError::UnknownKey(key)),
+        }
+    }
+    Ok(ClientGroup {
+        name: name.to_string(),
+        clients,
+        pack,
+    })
+}
+
+fn boolean(key: &str, item: &Item) -> Result<bool, ConfigError> {
+    item.as_bool()
+        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))
+}
+
+fn rate(key: &str, item: &Item) -> Result<f64, ConfigError> {
+    item.as_float()
+        .or_else(|| item.as_integer().map(|rate| rate as f64))
+        .filter(|rate| *rate >= 0.0)
+        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))
+}
+
+// Business-day calendars of a TOML calendar file, e.g.
+//
+// weekend = ["saturday", "sunday"]
+// holidays = ["2024-12-25", "2025-01-01"]
+//
+// [group.eu_retail]
+// holidays = ["2024-12-25", "2024-12-26", "2025-01-01"]
+//
+// The top-level keys are the default calendar, a `group` table the calendar of the clients of the
+// config group with that name. Keys left out keep the default calendar's, Saturdays and Sundays
+// off and no holidays at the top level. Days are in UTC.
+pub fn load_calendars<P: AsRef<Path>>(path: P) -> Result<Calendars, ConfigError> {
+    parse_calendars(&fs::read_to_string(path)?)
+}
+
+pub fn parse_calendars(s: &str) -> Result<Calendars, ConfigError> {
+    let document: DocumentMut = s.parse()?;
+    let mut calendars = Calendars {
+        default: calendar(document.as_table(), "", &BusinessCalendar::default(), true)?,
+        ..Calendars::default()
+    };
+    if let Some(item) = document.get("group") {
+        let groups = item
+            .as_table()
+            .ok_or_else(|| ConfigError::InvalidValue("group".to_string()))?;
+        for (name, item) in groups.iter() {
+            let key = format!("group.{}", name);
+            let table = item
+                .as_table()
+                .ok_or_else(|| ConfigError::InvalidValue(key.clone()))?;
+            let group = calendar(table, &format!("{}.", key), &calendars.default, false)?;
+            calendars.groups.insert(name.to_string(), group);
+        }
+    }
+    Ok(calendars)
+}
+
+// The calendar of the table's `weekend` and `holidays` over the base calendar.
+fn calendar(
+    table: &toml_edit::Table,
+    prefix: &str,
+    base: &BusinessCalendar,
+    top_level: bool,
+) -> Result<BusinessCalendar, ConfigError> {
+    let mut calendar = base.clone();
+    for (key, item) in table.iter() {
+        let key = format!("{}{}", prefix, key);
+        match key.rsplit('.').next() {
+            Some("group") if top_level => {}
+            Some("weekend") => {
+                calendar.weekend = [false; 7];
+                for day in strings(&key, item)? {
+                    let index = WEEKDAYS
+                        .iter()
+                        .position(|weekday| weekday.starts_with(&day) && day.len() >= 3)
+                        .ok_or_else(|| ConfigError::InvalidValue(key.clone()))?;
+                    calendar.weekend[index] = true;
+                }
+                if calendar.weekend.iter().all(|&off| off) {
+                    return Err(ConfigError::InvalidValue(key));
+                }
+            }
+            Some("holidays") => {
+                calendar.holidays.clear();
+                for day in strings(&key, item)? {
+                    let day: Timestamp = day
+                        .parse()
+                        .map_err(|()| ConfigError::InvalidValue(key.clone()))?;
+                    calendar.add_holiday(day);
+                }
+            }
+            _ => return Err(ConfigError::UnknownKey(key)),
+        }
+    }
+    Ok(calendar)
+}
+
+// An array of strings, lowercased.
+fn strings(key: &str, item: &Item) -> Result<Vec<String>, ConfigError> {
+    item.as_array()
+        .and_then(|array| {
+            array
+                .iter()
+                .map(|value| value.as_str().map(str::to_lowercase))
+                .collect()
+        })
+        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))
+}
+
+// A string keeps the exact decimal, integers are accepted as well.
+fn decimal(key: &str, item: &Item) -> Result<Decimal, ConfigError> {
+    item.as_str()
+        .and_then(|value| value.parse().ok())
+        .or_else(|| item.as_integer().map(Decimal::from))
+        .filter(|value: &Decimal| !value.is_sign_negative())
+        .ok_or_else(|| ConfigError::InvalidValue(key.to_string()))
+}
+
+// Applies changes of a config file to the policies of a running engine.
+//
+// The file is polled for a new modification time rather than watched with OS notifications. An
+// invalid file is reported and ignored, the engine keeps its current policies until the file is
+// fixed.
+pub struct ConfigWatcher<A> {
+    path: PathBuf,
+    handle: PolicyHandle<A>,
+    // Policies the engine was started with, which the config overrides
+    base: Policies<A>,
+    modified: Option<SystemTime>,
+}
+
+impl<A: Amount> ConfigWatcher<A> {
+    pub fn new<P: AsRef<Path>>(path: P, handle: PolicyHandle<A>) -> ConfigWatcher<A> {
+        ConfigWatcher {
+            path: path.as_ref().to_path_buf(),
+            base: handle.get(),
+            handle,
+            modified: None,
+        }
+    }
+
+    // Apply the config file to the engine policies.
+    pub fn reload(&mut self) -> Result<(), ConfigError> {
+        self.modified = fs::metadata(&self.path)?.modified().ok();
+        let config = Config::load(&self.path)?;
+        self.handle.set(config.apply(&self.base));
+        Ok(())
+    }
+
+    // Reload the config file if it changed since it was last loaded. Returns whether it did.
+    pub fn poll(&mut self) -> Result<bool, ConfigError> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified.is_some() && modified == self.modified {
+            return Ok(false);
+        }
+        self.reload()?;
+        Ok(true)
+    }
+
+    // Poll the config file on a background thread for as long as the process runs.
+    pub fn spawn(mut self, interval: Duration) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match self.poll() {
+                Ok(true) => info!("Reloaded config {}", self.path.display()),
+                Ok(false) => {}
+                Err(e) => {
+                    // Don't report the same broken file again
+                    self.modified = fs::metadata(&self.path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok();
+                    warn!(
+                        "Keeping policies, invalid config {}: {}",
+                        self.path.display(),
+                        e
+                    );
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::RiskThresholds;
+
+    #[test]
+    fn test_parse_and_apply() {
+        let config: Config = "max_balance = \"100.50\"\n\
+                              reject_zero_amounts = true\n\
+                              chargeback_disputes = \"freeze\"\n\
+                              [risk]\n\
+                              max_chargeback_rate = 0.1\n\
+                              [[rule]]\n\
+                              name = \"large\"\n\
+                              reject_if = \"amount > 1000\"\n\
+                              [[group]]\n\
+                              name = \"eu\"\n\
+                              clients = \"1,5\"\n\
+                              pack = \"eu\"\n\
+                              dispute_window = \"30days\"\n"
+            .parse()
+            .unwrap();
+        let base = Policies {
+            close_with_held_funds: true,
+            ..Policies::default()
+        };
+
+        assert_eq!(
+            config.apply(&base),
+            Policies {
+                max_balance: Some(Decimal::new(10050, 2)),
+                reject_zero_amounts: true,
+                close_with_held_funds: true,
+                chargeback_disputes: ChargebackDisputes::Freeze,
+                risk_thresholds: RiskThresholds {
+                    chargeback_rate: 0.1,
+                    ..RiskThresholds::default()
+                },
+                rules: vec![Rule::new("large", RuleAction::Reject, "amount > 1000").unwrap()],
+                groups: vec![ClientGroup {
+                    name: "eu".to_string(),
+                    clients: "1,5".parse().unwrap(),
+                    pack: RulePack {
+                        dispute_window: Some(Duration::from_secs(30 * 24 * 3600)),
+                        ..RulePack::preset("eu").unwrap()
+                    },
+                }],
+            }
+        );
+        assert!(matches!(
+            "max_balanse = 1".parse::<Config>(),
+            Err(ConfigError::UnknownKey(key)) if key == "max_balanse"
+        ));
+        assert!(matches!(
+            "[risk]\nmax_dispute_rate = \"high\"".parse::<Config>(),
+            Err(ConfigError::InvalidValue(key)) if key == "risk.max_dispute_rate"
+        ));
+        assert!(matches!(
+            "[[rule]]\nname = \"typo\"\nflag_if = \"ammount > 1\"".parse::<Config>(),
+            Err(ConfigError::InvalidRule(name, RuleError::UnknownVariable(_))) if name == "typo"
+        ));
+        assert!(matches!(
+            "[[group]]\nname = \"x\"\nclients = \"1\"\npack = \"mars\"".parse::<Config>(),
+            Err(ConfigError::InvalidValue(key)) if key == "group.x.pack"
+        ));
+    }
+
+    #[test]
+    fn test_parse_calendars() {
+        let calendars = parse_calendars(
+            "weekend = [\"Fri\", \"saturday\"]\n\
+             holidays = [\"2024-12-25\"]\n\
+             [group.us]\n\
+             holidays = [\"2024-07-04\", \"2024-12-25\"]\n",
+        )
+        .unwrap();
+        let at = |s: &str| s.parse::<Timestamp>().unwrap();
+        let default = calendars.get(None);
+        // 2024-07-04 was a Thursday, 2024-07-07 a Sunday
+        assert!(!default.is_business_day(at("2024-07-05")));
+        assert!(default.is_business_day(at("2024-07-07")));
+        assert!(default.is_business_day(at("2024-07-04")));
+        let us = calendars.get(Some("us"));
+        assert!(!us.is_business_day(at("2024-07-04")));
+        assert!(!us.is_business_day(at("2024-07-05")));
+        assert_eq!(calendars.get(Some("eu")), default);
+
+        assert!(matches!(
+            parse_calendars("weekend = [\"caturday\"]"),
+            Err(ConfigError::InvalidValue(key)) if key == "weekend"
+        ));
+        assert!(matches!(
+            parse_calendars("[group.us]\nholidays = [\"someday\"]"),
+            Err(ConfigError::InvalidValue(key)) if key == "group.us.holidays"
+        ));
+        assert!(matches!(
+            parse_calendars("holiday = []"),
+            Err(ConfigError::UnknownKey(key)) if key == "holiday"
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::engine::Transaction;
+use crate::reorder::ReorderKey;
+
+// How records of the files of an input directory are merged into one stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeOrder {
+    // By `timestamp` column across all files, ties in file name order
+    Timestamp,
+    // File by file in name order
+    Filename,
+}
+
+impl FromStr for MergeOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<MergeOrder, ()> {
+        match s {
+            "timestamp" => Ok(MergeOrder::Timestamp),
+            "filename" => Ok(MergeOrder::Filename),
+            _ => Err(()),
+        }
+    }
+}
+
+// Files of an input directory in name order. Hidden files and subdirectories are skipped.
+pub fn input_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+struct Source<I, E> {
+    records: I,
+    head: Option<Result<Transaction, E>>,
+    // Key of the head, inherited from the record before it if it has no timestamp
+    key: u128,
+}
+
+// Records of several inputs merged into one deterministic stream.
+//
+// With `MergeOrder::Timestamp` every input is expected to be ordered by time, the next record is
+// the earliest head of all inputs and equal times go in input order. A record without a
+// timestamp keeps its place after the record before it in the same input, so inputs without
+// timestamps are merged input by input. Errors are passed through as soon as they are read.
+pub struct Merge<I, E> {
+    sources: Vec<Source<I, E>>,
+    order: MergeOrder,
+}
+
+impl<I, E> Merge<I, E>
+where
+    I: Iterator<Item = Result<Transaction, E>>,
+{
+    pub fn new(inputs: Vec<I>, order: MergeOrder) -> Merge<I, E> {
+        Merge {
+            sources: inputs
+                .into_iter()
+                .map(|records| Source {
+                    records,
+                    head: None,
+                    key: 0,
+                })
+                .collect(),
+            order,
+        }
+    }
+
+    fn fill(source: &mut Source<I, E>) {
+        if source.head.is_some() {
+            return;
+        }
+        source.head = source.records.next();
+        if let Some(Ok(transaction)) = &source.head {
+            if let Some(key) = ReorderKey::Timestamp.of(transaction) {
+                source.key = key;
+            }
+        }
+    }
+}
+
+impl<I, E> Iterator for Merge<I, E>
+where
+    I: Iterator<Item = Result<Transaction, E>>,
+{
+    type Item = Result<Transaction, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.order {
+            MergeOrder::Filename => self
+                .sources
+                .iter_mut()
+                .find_map(|source| source.records.next()),
+            MergeOrder::Timestamp => {
+                self.sources.iter_mut().for_each(Self::fill);
+                let next = self
+                    .sources
+                    .iter_mut()
+                    .filter(|source| source.head.is_some())
+                    // Errors first, then the earliest key, `min_by_key` keeps the first of equals
+                    .min_by_key(|source| {
+                        (source.head.as_ref().is_some_and(Result::is_ok), source.key)
+                    })?;
+                next.head.take()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ByteRecordParser, ParseError};
+
+    fn parse(input: &str) -> ByteRecordParser<&[u8]> {
+        ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap()
+    }
+
+    fn merge(inputs: &[&str], order: MergeOrder) -> Vec<String> {
+        Merge::new(inputs.iter().map(|input| parse(input)).collect(), order)
+            .map(|record: Result<Transaction, ParseError>| {
+                record.unwrap().transaction_id.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_by_timestamp() {
+        let first = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,1.0,2024-03-01T00:00:01Z\n\
+                     deposit,1,3,1.0,2024-03-01T00:00:03Z\n\
+                     withdrawal,1,4,1.0,\n";
+        let second = "type,client,tx,amount,timestamp\n\
+                      dispute,1,1,,2024-03-01T00:00:02Z\n\
+                      deposit,1,5,1.0,2024-03-01T00:00:03Z\n";
+
+        assert_eq!(
+            merge(&[first, second], MergeOrder::Timestamp),
+            ["1", "1", "3", "4", "5"]
+        );
+        assert_eq!(
+            merge(&[first, second], MergeOrder::Filename),
+            ["1", "3", "4", "1", "5"]
+        );
+    }
+
+    #[test]
+    fn test_input_files() {
+        let dir = std::env::temp_dir().join(format!("directory-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.csv", "a.csv", ".hidden.csv"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let files = input_files(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, [dir.join("a.csv"), dir.join("b.csv")]);
+    }
+}
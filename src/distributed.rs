@@ -0,0 +1,198 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::admin::constant_time_eq;
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{Engine, SnapshotError};
+use crate::parser::{ByteRecordParser, ParseError};
+use crate::partition::{self, PartitionError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum DistributedError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Partition(#[from] PartitionError),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error("Invalid snapshot from worker {0}: {1}")]
+    Worker(SocketAddr, SnapshotError),
+    #[error(transparent)]
+    Processing(#[from] anyhow::Error),
+    #[error("Job without the shared secret")]
+    Unauthorized,
+}
+
+// Connect, read and write timeout of job connections. Workers process the records as they arrive
+// and coordinators stream them to all workers at once, so a connection quiet for this long is of
+// a peer which went away.
+pub const IO_TIMEOUT: Duration = Duration::from_secs(60);
+// Longest first line of a job read as the secret
+const MAX_SECRET: u64 = 1024;
+
+// Run a job of a coordinator (see `coordinate`) on a fresh engine: process the records as they
+// arrive and answer with the snapshot of the engine. With a secret, jobs have to start with it on
+// a line of its own, others are refused before any record is processed.
+pub fn run_job<A, S>(
+    engine: &mut Engine<A, S>,
+    stream: TcpStream,
+    secret: Option<&str>,
+) -> Result<(), DistributedError>
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    if let Some(secret) = secret {
+        let mut line = Vec::new();
+        (&mut input).take(MAX_SECRET).read_until(b'\n', &mut line)?;
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        if !constant_time_eq(line, secret.as_bytes()) {
+            return Err(DistributedError::Unauthorized);
+        }
+    }
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    engine.process_transactions_blocking(ByteRecordParser::new(reader)?)?;
+
+    engine.write_snapshot(&stream)?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
+
+// Process the input on worker processes, e.g. on different machines, over plain TCP.
+//
+// Connects to every worker and streams it the input records of its client shard (see
+// `partition::shard_of`) in the input file format, then closes the sending side of the
+// connection and restores the snapshot the worker answers with into the engine. Shards don't
+// share clients, so the snapshots together are the complete state. The addresses have to be of
+// different workers, and the secret the one of the workers if they have one. Returns the number
+// of accounts.
+pub fn coordinate<R, A, S>(
+    reader: csv::Reader<R>,
+    workers: &[SocketAddr],
+    engine: &Engine<A, S>,
+    secret: Option<&str>,
+) -> Result<usize, DistributedError>
+where
+    R: io::Read,
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let streams = workers
+        .iter()
+        .map(|worker| {
+            let mut stream = TcpStream::connect_timeout(worker, IO_TIMEOUT)?;
+            stream.set_read_timeout(Some(IO_TIMEOUT))?;
+            stream.set_write_timeout(Some(IO_TIMEOUT))?;
+            if let Some(secret) = secret {
+                writeln!(stream, "{}", secret)?;
+            }
+            Ok(stream)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut writers = streams
+        .iter()
+        .map(|stream| {
+            Ok(csv::WriterBuilder::new()
+                .flexible(true)
+                .from_writer(stream.try_clone()?))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    partition::partition(reader, &mut writers)?;
+    drop(writers);
+
+    let mut accounts = 0;
+    for (stream, worker) in streams.iter().zip(workers) {
+        stream.shutdown(Shutdown::Write)?;
+        accounts += engine
+            .restore_snapshot(stream)
+            .map_err(|e| DistributedError::Worker(*worker, e))?;
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::engine::Transaction;
+
+    #[test]
+    fn test_matches_local_processing() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,2,2,3.0\n\
+                     deposit,3,3,1.0\n\
+                     dispute,1,1,\n\
+                     withdrawal,2,4,1.0\n\
+                     chargeback,1,1,\n\
+                     deposit,4,5,2.0\n";
+        let workers: Vec<_> = (0..2)
+            .map(|_| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let address = listener.local_addr().unwrap();
+                thread::spawn(move || {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut engine: Engine = Engine::new();
+                    run_job(&mut engine, stream, Some("secret")).unwrap();
+                });
+                address
+            })
+            .collect();
+
+        let distributed: Engine = Engine::new();
+        let accounts = coordinate(
+            csv::Reader::from_reader(input.as_bytes()),
+            &workers,
+            &distributed,
+            Some("secret"),
+        )
+        .unwrap();
+
+        let mut local: Engine = Engine::new();
+        local
+            .process_transactions_blocking(
+                csv::ReaderBuilder::new()
+                    .flexible(true)
+                    .from_reader(input.as_bytes())
+                    .into_deserialize::<Transaction>(),
+            )
+            .unwrap();
+        let sorted = |engine: &Engine| {
+            let mut accounts = engine.accounts().unwrap();
+            accounts.sort_by_key(|a| a.client_id);
+            accounts
+        };
+        assert_eq!(accounts, 4);
+        assert_eq!(sorted(&distributed), sorted(&local));
+    }
+
+    #[test]
+    fn test_refuses_jobs_without_the_secret() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let coordinator = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let _ = stream.write_all(b"guess\ntype,client,tx,amount\ndeposit,1,1,5.0\n");
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let mut engine: Engine = Engine::new();
+        assert!(matches!(
+            run_job(&mut engine, stream, Some("secret")),
+            Err(DistributedError::Unauthorized)
+        ));
+        assert!(engine.accounts().unwrap().is_empty());
+        coordinator.join().unwrap();
+    }
+}
@@ -1,6 +1,92 @@
 mod account;
 mod account_manager;
+mod admin;
+mod aging;
+pub mod alphanumeric_id;
+mod aml;
+pub mod amount;
+mod analytics;
+mod approvals;
+mod bloom;
+mod buckets;
+mod builder;
+mod calendar;
+mod clock;
+mod dormancy;
+mod dunning;
+#[allow(clippy::module_inception)]
 mod engine;
+mod error_code;
+mod expiry;
+mod fees;
+mod filter;
+mod finalize;
+mod hot;
+mod interceptor;
+mod journal;
+mod ledgers;
+mod locked_queue;
+mod memory;
+mod notes;
+mod packs;
+mod payments_engine;
+mod period;
+mod policies;
+mod query;
+mod record;
+mod reference;
+mod reserve;
+mod risk;
+mod rules;
+mod snapshot;
+pub mod store;
+pub mod stream;
+mod structuring;
+mod suspense;
+#[cfg(feature = "tokio")]
+pub mod tokio_runtime;
+mod trace;
 mod transaction;
 
-pub use self::engine::Engine;
+pub use self::account::{Account, AmountFormat, FormattedAccount};
+pub use self::account_manager::{AccountManager, AccountManagerError};
+pub use self::aging::HeldAging;
+pub use self::aml::{AmlEntry, AmlRegister, AmlThresholds};
+pub use self::analytics::{Analytics, AnalyticsReport, ClientVolume, DisputeOutcomes, TypeVolume};
+pub use self::approvals::SUBMITTER_COLUMN;
+pub use self::buckets::{Bucket, BucketWidth, TimeBuckets};
+pub use self::builder::EngineBuilder;
+pub use self::calendar::{BusinessCalendar, Calendars, WEEKDAYS};
+pub use self::clock::{Clock, SimulatedClock, Sweep, SystemClock};
+pub use self::dunning::{DunningPolicy, DunningStage};
+pub use self::engine::{CompactionStats, Engine, EngineError, TransactionError};
+pub use self::error_code::ErrorCode;
+pub use self::expiry::ExpiredDispute;
+pub use self::fees::{FeeTier, MaintenanceFees};
+pub use self::filter::{AccountFilter, ClientRanges, TransactionFilter};
+pub use self::hot::{HotAccount, HotAccounts};
+pub use self::interceptor::TransactionInterceptor;
+pub use self::journal::{
+    Book, Books, ClientLoss, ClientReserve, ExtendedReport, JournalEntry, LossAccount, Posting,
+    DEFAULT_LOSS_ACCOUNT,
+};
+pub use self::ledgers::{LedgerError, Ledgers, LEDGER_COLUMN, TO_LEDGER_COLUMN};
+pub use self::memory::MemoryStats;
+pub use self::notes::{Note, CASE_COLUMN, NOTE_COLUMN};
+pub use self::packs::{ClientGroup, RulePack, RULE_PACK_PRESETS};
+pub use self::payments_engine::{EngineStats, PaymentsEngine};
+pub use self::period::PeriodClose;
+pub use self::policies::{ChargebackDisputes, Policies, PolicyHandle};
+pub use self::query::AccountQuery;
+pub use self::record::EVENT_LOG_HEADERS;
+pub use self::reference::{diff_accounts, ReferenceEngine};
+pub use self::reserve::{Reserve, RollingReserve};
+pub use self::risk::{Activity, RiskEntry, RiskThresholds};
+pub use self::rules::{Rule, RuleAction, RuleError};
+pub use self::snapshot::{snapshot_version, SnapshotError, SNAPSHOT_VERSION};
+pub use self::structuring::{NearThreshold, StructuringEvidence, StructuringPolicy};
+pub use self::trace::TIMELINE_TARGET;
+pub use self::transaction::{
+    ClientId, Metadata, StoredTransaction, Timestamp, Transaction, TransactionId, TransactionPage,
+    TransactionType, TxStatus, SEQUENCE_COLUMN, SOURCE_COLUMN, TIMESTAMP_COLUMN,
+};
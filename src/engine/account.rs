@@ -1,49 +1,341 @@
+use std::str::FromStr;
+
 use rust_decimal::Decimal;
+use serde::de::{self, Deserialize, Deserializer};
 use serde::{ser::SerializeStruct, Serialize, Serializer};
 
-#[derive(Clone)]
-pub struct Account {
-    pub client_id: u16,
-    pub available: Decimal,
-    pub held: Decimal,
+use super::amount::{Amount, MinorUnits};
+use super::dunning::DunningStage;
+use super::transaction::ClientId;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account<A> {
+    pub client_id: ClientId,
+    pub available: A,
+    pub held: A,
+    // Funds held back from deposits by the rolling reserve, see `EngineBuilder::rolling_reserve`
+    pub reserve: A,
     pub locked: bool,
+    // Locked because a transaction would have left impossible balances, see
+    // `EngineBuilder::enforce_balance_invariants`
+    pub quarantined: bool,
+    // Closed by a `close_account` transaction, rejecting any further activity
+    pub closed: bool,
+    // Collection of negative available funds, see `EngineBuilder::dunning`
+    pub dunning: DunningStage,
+    // Without funds and transactions for a number of periods, see `EngineBuilder::dormancy`
+    pub dormant: bool,
 }
 
-impl Account {
-    pub fn new(id: u16) -> Account {
+impl<A: Amount> Account<A> {
+    pub fn new(id: ClientId) -> Account<A> {
         Account {
             client_id: id,
-            available: Decimal::new(0, 4),
-            held: Decimal::new(0, 4),
+            available: A::default(),
+            held: A::default(),
+            reserve: A::default(),
             locked: false,
+            quarantined: false,
+            closed: false,
+            dunning: DunningStage::Current,
+            dormant: false,
+        }
+    }
+
+    pub fn calculate_total(&self) -> A {
+        self.available + self.held + self.reserve
+    }
+
+    // Held funds don't exceed the total and no balance is negative.
+    pub fn satisfies_invariants(&self) -> bool {
+        !self.available.is_sign_negative()
+            && !self.held.is_sign_negative()
+            && !self.reserve.is_sign_negative()
+    }
+}
+
+// How amounts are written in the report.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AmountFormat {
+    // Trailing zeros trimmed, at least one decimal place: `1.5`, `2.0`
+    #[default]
+    Normalized,
+    // Always 4 decimal places: `1.5000`, `2.0000`
+    Fixed,
+    // Trailing zeros and the decimal point trimmed: `1.5`, `2`
+    Trimmed,
+    // Integer count of 1/10000 units: `15000`, `20000`
+    MinorUnits,
+}
+
+impl AmountFormat {
+    pub fn format<A: Amount>(self, value: A) -> String {
+        let value = value.to_decimal();
+        match self {
+            AmountFormat::Normalized => {
+                let mut value_str = value.normalize().to_string();
+                if !value_str.contains('.') {
+                    value_str.push_str(".0");
+                }
+                value_str
+            }
+            AmountFormat::Fixed => format!("{:.4}", value),
+            AmountFormat::Trimmed => value.normalize().to_string(),
+            AmountFormat::MinorUnits => {
+                let mut scaled = value;
+                scaled.rescale(MinorUnits::SCALE);
+                scaled.mantissa().to_string()
+            }
+        }
+    }
+}
+
+impl FromStr for AmountFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<AmountFormat, ()> {
+        match s {
+            "normalized" => Ok(AmountFormat::Normalized),
+            "fixed" => Ok(AmountFormat::Fixed),
+            "trimmed" => Ok(AmountFormat::Trimmed),
+            "minor-units" => Ok(AmountFormat::MinorUnits),
+            _ => Err(()),
         }
     }
+}
+
+// Account serialized with the given amount format.
+pub struct FormattedAccount<'a, A> {
+    account: &'a Account<A>,
+    format: AmountFormat,
+    closed_column: bool,
+    quarantined_column: bool,
+    dunning_column: bool,
+    dormant_column: bool,
+    ledger: Option<&'a str>,
+    parent: Option<&'a str>,
+}
 
-    pub fn calculate_total(&self) -> Decimal {
-        self.available + self.held
+impl<A: Amount> Account<A> {
+    pub fn formatted(&self, format: AmountFormat) -> FormattedAccount<'_, A> {
+        FormattedAccount {
+            account: self,
+            format,
+            closed_column: false,
+            quarantined_column: false,
+            dunning_column: false,
+            dormant_column: false,
+            ledger: None,
+            parent: None,
+        }
     }
 }
 
-impl Serialize for Account {
+impl<'a, A> FormattedAccount<'a, A> {
+    // Add a `closed` column after `locked`. It's not part of the default report format.
+    pub fn with_closed_column(mut self) -> Self {
+        self.closed_column = true;
+        self
+    }
+
+    // Add a `quarantined` column after `locked` and `closed`, telling accounts locked for breaking
+    // balance invariants apart from those locked by a chargeback.
+    pub fn with_quarantined_column(mut self) -> Self {
+        self.quarantined_column = true;
+        self
+    }
+
+    // Add a `dunning` column with the stage of collection of negative funds, last.
+    pub fn with_dunning_column(mut self) -> Self {
+        self.dunning_column = true;
+        self
+    }
+
+    // Add a `dormant` column, last, for reports including dormant accounts.
+    pub fn with_dormant_column(mut self) -> Self {
+        self.dormant_column = true;
+        self
+    }
+
+    // Add a leading `ledger` column, for reports of several ledgers.
+    pub fn with_ledger(mut self, ledger: &'a str) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    // Add a `parent` column before `client` with the client's parent entity, empty if it has none.
+    pub fn with_parent(mut self, parent: Option<&'a str>) -> Self {
+        self.parent = Some(parent.unwrap_or_default());
+        self
+    }
+}
+
+impl<A: Amount> Serialize for FormattedAccount<'_, A> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        fn format_decimal(value: Decimal) -> String {
-            let mut value_str = value.normalize().to_string();
-            if !value_str.contains('.') {
-                value_str.push_str(".0");
-            }
-            return value_str;
-        }
-
+        let account = self.account;
+        let columns = 5
+            + usize::from(self.closed_column)
+            + usize::from(self.quarantined_column)
+            + usize::from(self.dunning_column)
+            + usize::from(self.dormant_column)
+            + usize::from(self.ledger.is_some())
+            + usize::from(self.parent.is_some());
         let mut state: <S as Serializer>::SerializeStruct =
-            serializer.serialize_struct("Account", 5)?;
-        state.serialize_field("client", &self.client_id)?;
-        state.serialize_field("available", &format_decimal(self.available))?;
-        state.serialize_field("held", &format_decimal(self.held))?;
-        state.serialize_field("total", &format_decimal(self.calculate_total()))?;
-        state.serialize_field("locked", &self.locked)?;
+            serializer.serialize_struct("Account", columns)?;
+        if let Some(ledger) = self.ledger {
+            state.serialize_field("ledger", ledger)?;
+        }
+        if let Some(parent) = self.parent {
+            state.serialize_field("parent", parent)?;
+        }
+        state.serialize_field("client", &account.client_id)?;
+        state.serialize_field("available", &self.format.format(account.available))?;
+        state.serialize_field("held", &self.format.format(account.held))?;
+        state.serialize_field("total", &self.format.format(account.calculate_total()))?;
+        state.serialize_field("locked", &account.locked)?;
+        if self.closed_column {
+            state.serialize_field("closed", &account.closed)?;
+        }
+        if self.quarantined_column {
+            state.serialize_field("quarantined", &account.quarantined)?;
+        }
+        if self.dunning_column {
+            state.serialize_field("dunning", account.dunning.as_str())?;
+        }
+        if self.dormant_column {
+            state.serialize_field("dormant", &account.dormant)?;
+        }
         state.end()
     }
 }
+
+impl<A: Amount> Serialize for Account<A> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.formatted(AmountFormat::default())
+            .serialize(serializer)
+    }
+}
+
+// Layout of a report record, `total` is only used to validate the balances. The reserve isn't
+// part of the report, accounts with funds in it don't validate.
+#[derive(serde::Deserialize)]
+struct AccountRecord {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    quarantined: bool,
+}
+
+// Reads accounts written in any of the decimal formats, `AmountFormat::MinorUnits` is ambiguous
+// with whole amounts and isn't supported.
+impl<'de, A: Amount> Deserialize<'de> for Account<A> {
+    fn deserialize<D>(deserializer: D) -> Result<Account<A>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let record = AccountRecord::deserialize(deserializer)?;
+        if record.available.checked_add(record.held) != Some(record.total) {
+            return Err(de::Error::custom("total doesn't match available and held"));
+        }
+        let amount = |value: Decimal| {
+            A::from_decimal(value).ok_or_else(|| de::Error::custom("amount is not representable"))
+        };
+
+        Ok(Account {
+            client_id: record.client,
+            available: amount(record.available)?,
+            held: amount(record.held)?,
+            reserve: A::default(),
+            locked: record.locked,
+            quarantined: record.quarantined,
+            closed: record.closed,
+            dunning: DunningStage::Current,
+            dormant: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_formats() {
+        let formats = [
+            AmountFormat::Normalized,
+            AmountFormat::Fixed,
+            AmountFormat::Trimmed,
+            AmountFormat::MinorUnits,
+        ];
+        let format_all = |value: Decimal| formats.map(|format| format.format(value));
+
+        assert_eq!(
+            format_all(Decimal::new(15, 1)),
+            ["1.5", "1.5000", "1.5", "15000"]
+        );
+        assert_eq!(
+            format_all(Decimal::new(2000, 3)),
+            ["2.0", "2.0000", "2", "20000"]
+        );
+        assert_eq!(
+            format_all(Decimal::new(-1, 4)),
+            ["-0.0001", "-0.0001", "-0.0001", "-1"]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut account: Account<Decimal> = Account::new("7".parse().unwrap());
+        account.available = Decimal::new(-15, 1);
+        account.held = Decimal::new(20001, 4);
+        account.locked = true;
+        account.closed = true;
+        account.quarantined = true;
+
+        for format in [
+            AmountFormat::Normalized,
+            AmountFormat::Fixed,
+            AmountFormat::Trimmed,
+        ] {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer
+                .serialize(
+                    account
+                        .formatted(format)
+                        .with_closed_column()
+                        .with_quarantined_column(),
+                )
+                .unwrap();
+            let output = writer.into_inner().unwrap();
+
+            let accounts: Vec<Account<Decimal>> = csv::Reader::from_reader(output.as_slice())
+                .deserialize()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(accounts, [account.clone()]);
+        }
+    }
+
+    #[test]
+    fn test_total_mismatch() {
+        let input = "client,available,held,total,locked\n1,1.0,1.0,3.0,false\n";
+
+        let result = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Account<Decimal>>()
+            .next()
+            .unwrap();
+
+        assert!(result.is_err());
+    }
+}
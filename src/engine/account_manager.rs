@@ -1,15 +1,27 @@
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-
-use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use super::account::Account;
-use super::transaction::TransactionDetails;
+use super::amount::Amount;
+use super::dunning::DunningStage;
+use super::journal::{Book, Books, Posting};
+use super::notes::Note;
+use super::policies::ChargebackDisputes;
+use super::reserve::Reserve;
+use super::risk::Activity;
+use super::structuring::NearThreshold;
+use super::transaction::{
+    ClientId, Metadata, Timestamp, Transaction, TransactionDetails, TransactionId, TransactionType,
+    TxStatus,
+};
 
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum AccountManagerError {
     #[error("Account is locked")]
     AccountLocked,
+    #[error("Account is closed")]
+    AccountClosed,
+    #[error("Account has held funds")]
+    HeldFunds,
     #[error("Transaction already exists")]
     TransactionExist,
     #[error("Transaction does not exist")]
@@ -18,127 +30,504 @@ pub enum AccountManagerError {
     TransactionDisputed,
     #[error("Transaction not disputed")]
     TransactionNotDisputed,
+    #[error("Transaction already charged back")]
+    TransactionAlreadyChargedBack,
+    #[error("Transaction reversed")]
+    TransactionReversed,
+    #[error("Transaction can't move from {0:?} to {1:?}")]
+    IllegalTransition(TxStatus, TxStatus),
+    #[error("Reversal amount doesn't match the transaction")]
+    ReversalAmountMismatch,
+    #[error("Amount overflow")]
+    AmountOverflow,
     #[error("Insufficient funds")]
     InsufficientFunds,
 }
 
-pub struct AccountManager {
-    pub account: Account,
-    pub transactions: HashMap<u32, TransactionDetails>,
+// Balances change only through double-entry postings between the client's books, the account's
+// available and held funds are the balances of the client books.
+pub struct AccountManager<A> {
+    pub account: Account<A>,
+    pub transactions: HashMap<TransactionId, TransactionDetails<A>>,
+    pub activity: Activity,
+    pub books: Books<A>,
+    // Postings since the engine last took them for its journal
+    pub postings: Vec<Posting<A>>,
+    // Disputed transactions which didn't arrive yet, see `EngineBuilder::dispute_suspense`
+    pub suspense: HashSet<TransactionId>,
+    // Fingerprints of accepted records, see `EngineBuilder::idempotent`
+    pub delivered: HashSet<u64>,
+    // Time of the latest accepted transaction with a timestamp, see
+    // `EngineBuilder::finalize_idle_accounts`
+    pub last_activity: Option<Timestamp>,
+    // Parts of deposits in the rolling reserve until their release, see
+    // `EngineBuilder::rolling_reserve`
+    pub reserves: VecDeque<Reserve<A>>,
+    // Since when available funds are negative, see `EngineBuilder::dunning`
+    pub negative_since: Option<Timestamp>,
+    // Transactions rejected because the account was locked, replayed when it's unlocked, see
+    // `EngineBuilder::queue_locked`
+    pub queued: VecDeque<Transaction>,
+    // Transactions just below the structuring threshold within its window, and the ones the
+    // account was flagged for, see `EngineBuilder::structuring`
+    pub near_threshold: VecDeque<NearThreshold>,
+    pub structuring: Vec<NearThreshold>,
+    // Notes of investigators, see `Note`
+    pub notes: Vec<Note>,
+    // Closed periods in a row without transactions, see `EngineBuilder::dormancy`
+    pub idle_periods: u32,
+}
+
+// Status and dispute time of a transaction a transaction may change, None if it didn't exist.
+type SavedTransaction = (TransactionId, Option<(TxStatus, Option<Timestamp>)>);
+
+// State of an account before a transaction, to undo everything the transaction changed, see
+// `AccountManager::savepoint`.
+pub struct Savepoint<A> {
+    transaction_id: TransactionId,
+    account: Account<A>,
+    books: Books<A>,
+    activity: Activity,
+    transactions: Vec<SavedTransaction>,
+    suspended: bool,
+    reserves: usize,
+    notes: usize,
+    postings: usize,
 }
 
-impl AccountManager {
-    pub fn new(id: u16) -> AccountManager {
+impl<A: Amount> AccountManager<A> {
+    pub fn new(id: ClientId) -> AccountManager<A> {
         AccountManager {
             account: Account::new(id),
             transactions: HashMap::new(),
+            activity: Activity::default(),
+            books: Books::default(),
+            postings: Vec::new(),
+            suspense: HashSet::new(),
+            delivered: HashSet::new(),
+            last_activity: None,
+            reserves: VecDeque::new(),
+            negative_since: None,
+            queued: VecDeque::new(),
+            near_threshold: VecDeque::new(),
+            structuring: Vec::new(),
+            notes: Vec::new(),
+            idle_periods: 0,
         }
     }
 
     fn assure_account_active(&self) -> Result<(), AccountManagerError> {
-        self.account
-            .locked
-            .then(|| Err(AccountManagerError::AccountLocked))
-            .unwrap_or(Ok(()))
+        if self.account.closed {
+            return Err(AccountManagerError::AccountClosed);
+        }
+        if self.account.locked {
+            return Err(AccountManagerError::AccountLocked);
+        }
+        Ok(())
     }
 
     // Deposit funds into account.
     //
-    // * Increment available balance by the transaction amount
+    // * Post the transaction amount from settlement to available
     // * Record the transaction
     pub fn deposit(
         &mut self,
-        transaction_id: u32,
-        amount: Decimal,
+        transaction_id: TransactionId,
+        amount: A,
+    ) -> Result<(), AccountManagerError> {
+        self.deposit_with_metadata(transaction_id, amount, Metadata::new())
+    }
+
+    // Deposit funds into account, keeping metadata with the recorded transaction.
+    pub fn deposit_with_metadata(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: A,
+        metadata: Metadata,
     ) -> Result<(), AccountManagerError> {
         self.assure_account_active()?;
-        match self.transactions.entry(transaction_id) {
-            Entry::Occupied(_) => return Err(AccountManagerError::TransactionExist),
-            Entry::Vacant(entry) => {
-                self.account.available += amount;
-                entry.insert(TransactionDetails::new(amount));
-                Ok(())
-            }
+        if self.transactions.contains_key(&transaction_id) {
+            return Err(AccountManagerError::TransactionExist);
         }
+
+        self.post(
+            transaction_id,
+            Book::Settlement,
+            Book::ClientAvailable,
+            amount,
+        )?;
+        self.transactions.insert(
+            transaction_id,
+            TransactionDetails::with_metadata(amount, metadata),
+        );
+        Ok(())
     }
 
     // Withdraw funds from account.
     //
-    // * Decrement available balance by the transaction amount
-    // * Record the transaction
-    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), AccountManagerError> {
+    // * Post the transaction amount from available to settlement
+    pub fn withdraw(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: A,
+    ) -> Result<(), AccountManagerError> {
+        if self.account.closed {
+            return Err(AccountManagerError::AccountClosed);
+        }
+        if self.account.available < amount {
+            return Err(AccountManagerError::InsufficientFunds);
+        }
+
+        self.post(
+            transaction_id,
+            Book::ClientAvailable,
+            Book::Settlement,
+            amount,
+        )
+    }
+
+    // Reverse a deposit outside of a dispute.
+    //
+    // * Amount must match the reversed deposit
+    // * Mark the transaction as reversed
+    // * Post the transaction amount from available back to settlement
+    pub fn reverse(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: A,
+    ) -> Result<(), AccountManagerError> {
+        self.assure_account_active()?;
+
+        let reversed_transaction = self.transaction(transaction_id)?;
+        if reversed_transaction.amount != amount {
+            return Err(AccountManagerError::ReversalAmountMismatch);
+        }
         if self.account.available < amount {
             return Err(AccountManagerError::InsufficientFunds);
         }
 
-        self.account.available -= amount;
+        let status = reversed_transaction.status.transition(TxStatus::Reversed)?;
+        self.post(
+            transaction_id,
+            Book::ClientAvailable,
+            Book::Settlement,
+            amount,
+        )?;
+        self.set_status(transaction_id, status);
+
         Ok(())
     }
 
     // Dispute a transaction.
     //
     // * Mark the transaction as disputed
-    // * Move the transaction amount from available to held
-    pub fn dispute(&mut self, transaction_id: u32) -> Result<(), AccountManagerError> {
+    // * Post the transaction amount from available to held
+    pub fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), AccountManagerError> {
+        self.assure_not_final(transaction_id)?;
         self.assure_account_active()?;
 
-        let disputed_transaction = self
-            .transactions
-            .get_mut(&transaction_id)
-            .ok_or_else(|| AccountManagerError::TransactionNotExist)?;
-        if disputed_transaction.disputed {
-            return Err(AccountManagerError::TransactionDisputed);
-        }
-
-        disputed_transaction.disputed = true;
-        self.account.available -= disputed_transaction.amount;
-        self.account.held += disputed_transaction.amount;
+        let disputed_transaction = self.transaction(transaction_id)?;
+        let status = disputed_transaction.status.transition(TxStatus::Disputed)?;
+        let amount = disputed_transaction.amount;
+        self.post(
+            transaction_id,
+            Book::ClientAvailable,
+            Book::ClientHeld,
+            amount,
+        )?;
+        self.set_status(transaction_id, status);
 
         Ok(())
     }
 
     // Resolve a dispute.
     //
-    // * Mark the transaction as not disputed
-    // * Move the transaction amount from held to available
-    pub fn resolve(&mut self, transaction_id: u32) -> Result<(), AccountManagerError> {
+    // * Mark the transaction as resolved
+    // * Post the transaction amount from held back to available
+    pub fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), AccountManagerError> {
+        self.assure_not_final(transaction_id)?;
         self.assure_account_active()?;
+        self.settle(transaction_id, TxStatus::Resolved)
+    }
 
-        let disputed_transaction = self
+    // Chargeback a transaction.
+    //
+    // * Mark the transaction as charged back
+    // * Post the transaction amount from held to chargeback loss
+    // * Mark the account as locked
+    pub fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), AccountManagerError> {
+        self.assure_not_final(transaction_id)?;
+        self.assure_account_active()?;
+        self.settle(transaction_id, TxStatus::ChargedBack)
+    }
+
+    // Resolve or freeze the open disputes left after a chargeback locked the account. Returns
+    // the number of settled disputes.
+    pub fn settle_open_disputes(
+        &mut self,
+        policy: ChargebackDisputes,
+    ) -> Result<usize, AccountManagerError> {
+        if policy == ChargebackDisputes::Keep {
+            return Ok(0);
+        }
+        let mut disputed: Vec<TransactionId> = self
             .transactions
-            .get_mut(&transaction_id)
-            .ok_or_else(|| AccountManagerError::TransactionNotExist)?;
-        if !disputed_transaction.disputed {
-            return Err(AccountManagerError::TransactionNotDisputed);
+            .iter()
+            .filter(|(_, details)| details.status == TxStatus::Disputed)
+            .map(|(transaction_id, _)| *transaction_id)
+            .collect();
+        // Postings in a stable order
+        disputed.sort_unstable();
+        for transaction_id in &disputed {
+            match policy {
+                ChargebackDisputes::Keep => {}
+                ChargebackDisputes::Resolve => self.settle(*transaction_id, TxStatus::Resolved)?,
+                ChargebackDisputes::Freeze => {
+                    let status = self
+                        .transaction(*transaction_id)?
+                        .status
+                        .transition(TxStatus::Frozen)?;
+                    self.set_status(*transaction_id, status);
+                }
+            }
         }
+        Ok(disputed.len())
+    }
 
-        disputed_transaction.disputed = false;
-        self.account.available += disputed_transaction.amount;
-        self.account.held -= disputed_transaction.amount;
+    // Settle a dispute as resolved or charged back, also of a locked or closed account.
+    pub fn settle(
+        &mut self,
+        transaction_id: TransactionId,
+        outcome: TxStatus,
+    ) -> Result<(), AccountManagerError> {
+        let disputed_transaction = self.transaction(transaction_id)?;
+        let credit = match outcome {
+            TxStatus::Resolved => Book::ClientAvailable,
+            TxStatus::ChargedBack => Book::ChargebackLoss,
+            _ => {
+                return Err(AccountManagerError::IllegalTransition(
+                    disputed_transaction.status,
+                    outcome,
+                ))
+            }
+        };
+        let status = disputed_transaction.status.transition(outcome)?;
+        let amount = disputed_transaction.amount;
+        self.post(transaction_id, Book::ClientHeld, credit, amount)?;
+        self.set_status(transaction_id, status);
+        if status == TxStatus::ChargedBack {
+            self.account.locked = true;
+        }
 
         Ok(())
     }
 
-    // Chargeback a transaction.
+    // Close the account.
     //
-    // * Mark the transaction as not disputed
-    // * Decrement held balance by the transaction amount
-    // * Mark the account as locked
-    pub fn chargeback(&mut self, transaction_id: u32) -> Result<(), AccountManagerError> {
+    // * Held funds must be zero unless `allow_held_funds`
+    // * Mark the account as closed, rejecting any further transactions
+    pub fn close(&mut self, allow_held_funds: bool) -> Result<(), AccountManagerError> {
         self.assure_account_active()?;
+        if !allow_held_funds && self.account.held != A::default() {
+            return Err(AccountManagerError::HeldFunds);
+        }
 
-        let disputed_transaction = self
+        self.account.closed = true;
+        Ok(())
+    }
+
+    // Charged back and reversed transactions never move again. Referencing one is rejected as
+    // such before the account's state, which a chargeback leaves locked, so a second
+    // dispute of a charged back transaction reads as what it is rather than `AccountLocked`.
+    fn assure_not_final(&self, transaction_id: TransactionId) -> Result<(), AccountManagerError> {
+        match self
             .transactions
-            .get_mut(&transaction_id)
-            .ok_or_else(|| AccountManagerError::TransactionNotExist)?;
-        if !disputed_transaction.disputed {
-            return Err(AccountManagerError::TransactionNotDisputed);
+            .get(&transaction_id)
+            .map(|details| details.status)
+        {
+            Some(TxStatus::ChargedBack) => Err(AccountManagerError::TransactionAlreadyChargedBack),
+            Some(TxStatus::Reversed) => Err(AccountManagerError::TransactionReversed),
+            _ => Ok(()),
         }
+    }
 
-        disputed_transaction.disputed = false;
-        self.account.held -= disputed_transaction.amount;
-        self.account.locked = true;
+    fn transaction(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<&TransactionDetails<A>, AccountManagerError> {
+        self.transactions
+            .get(&transaction_id)
+            .ok_or(AccountManagerError::TransactionNotExist)
+    }
 
+    fn set_status(&mut self, transaction_id: TransactionId, status: TxStatus) {
+        if let Some(details) = self.transactions.get_mut(&transaction_id) {
+            details.status = status;
+        }
+    }
+
+    // Post a single movement between books, updating the account balances.
+    //
+    // * Fail without changing anything if a balance or the resulting total overflowed
+    pub(super) fn post(
+        &mut self,
+        transaction_id: TransactionId,
+        debit: Book,
+        credit: Book,
+        amount: A,
+    ) -> Result<(), AccountManagerError> {
+        let posting = Posting {
+            transaction_id,
+            debit,
+            credit,
+            amount,
+        };
+        let books = self
+            .books
+            .post(&[posting])
+            .ok_or(AccountManagerError::AmountOverflow)?;
+        let (available, held, reserve) = (
+            books.balance(Book::ClientAvailable),
+            books.balance(Book::ClientHeld),
+            books.balance(Book::ClientReserve),
+        );
+        if available
+            .checked_add(held)
+            .and_then(|total| total.checked_add(reserve))
+            .is_none()
+        {
+            return Err(AccountManagerError::AmountOverflow);
+        }
+
+        self.books = books;
+        self.account.available = available;
+        self.account.held = held;
+        self.account.reserve = reserve;
+        self.postings.push(posting);
         Ok(())
     }
+
+    // Savepoint to undo the transaction with, taken before applying it. Besides the transaction
+    // itself a chargeback may settle all other open disputes, which are kept as well.
+    pub fn savepoint(&self, transaction: &Transaction) -> Savepoint<A> {
+        let mut transaction_ids = vec![transaction.transaction_id];
+        if transaction.r#type == TransactionType::Chargeback {
+            transaction_ids.extend(
+                self.transactions
+                    .iter()
+                    .filter(|(_, details)| details.status == TxStatus::Disputed)
+                    .map(|(transaction_id, _)| *transaction_id),
+            );
+        }
+        Savepoint {
+            transaction_id: transaction.transaction_id,
+            account: self.account.clone(),
+            books: self.books,
+            activity: self.activity.clone(),
+            transactions: transaction_ids
+                .into_iter()
+                .map(|transaction_id| {
+                    let details = self.transactions.get(&transaction_id);
+                    (
+                        transaction_id,
+                        details.map(|details| (details.status, details.disputed_at)),
+                    )
+                })
+                .collect(),
+            suspended: self.suspense.contains(&transaction.transaction_id),
+            reserves: self.reserves.len(),
+            notes: self.notes.len(),
+            postings: self.postings.len(),
+        }
+    }
+
+    // Undo everything since the savepoint was taken. Transactions only ever add reserves, notes
+    // and postings, so dropping the ones added since is enough.
+    pub fn roll_back(&mut self, savepoint: Savepoint<A>) {
+        self.account = savepoint.account;
+        self.books = savepoint.books;
+        self.activity = savepoint.activity;
+        for (transaction_id, details) in savepoint.transactions {
+            match details {
+                Some((status, disputed_at)) => {
+                    if let Some(details) = self.transactions.get_mut(&transaction_id) {
+                        details.status = status;
+                        details.disputed_at = disputed_at;
+                    }
+                }
+                None => {
+                    self.transactions.remove(&transaction_id);
+                }
+            }
+        }
+        if savepoint.suspended {
+            self.suspense.insert(savepoint.transaction_id);
+        } else {
+            self.suspense.remove(&savepoint.transaction_id);
+        }
+        self.reserves.truncate(savepoint.reserves);
+        self.notes.truncate(savepoint.notes);
+        self.postings.truncate(savepoint.postings);
+    }
+
+    // Lock the account for review after it would have ended up with impossible balances.
+    pub fn quarantine(&mut self) {
+        self.account.locked = true;
+        self.account.quarantined = true;
+    }
+
+    // Lift a lock of a chargeback or a quarantine, e.g. after the client was reviewed.
+    pub fn unlock(&mut self) {
+        self.account.locked = false;
+        self.account.quarantined = false;
+        // Dunning starts over, it would lock the account again right away otherwise
+        self.account.dunning = DunningStage::Current;
+        self.negative_since = None;
+    }
+
+    // Drop all transaction details and their metadata, keeping the balances. Transactions queued
+    // on a locked account are dropped too and never replayed, and so are the notes on the account
+    // and its structuring evidence, which describe the client's transactions as well.
+    //
+    // Returns the number of dropped transactions.
+    pub fn purge(&mut self) -> usize {
+        let purged = self.transactions.len() + self.queued.len();
+        self.transactions = HashMap::new();
+        self.queued = VecDeque::new();
+        self.notes = Vec::new();
+        self.near_threshold = VecDeque::new();
+        self.structuring = Vec::new();
+        purged
+    }
+
+    // Drop details of all transactions except disputed ones, which still have to be resolved or
+    // charged back, and reset the activity counters.
+    //
+    // Returns the number of dropped transactions.
+    pub fn close_period(&mut self) -> usize {
+        let stored = self.transactions.len();
+        self.transactions
+            .retain(|_, details| details.status.is_open_dispute());
+        self.activity = Activity::default();
+        stored - self.transactions.len()
+    }
+
+    // Drop transaction details which can no longer be referenced.
+    //
+    // * Closed account rejects all transactions, so none of its transactions can be disputed
+    //   anymore, only its open disputes can still be force settled
+    // * Locked account is kept as is, an unlock accepts transactions again, which must not reuse
+    //   the ids of its transactions
+    //
+    // Returns the number of dropped transactions.
+    pub fn compact(&mut self) -> usize {
+        if !self.account.closed {
+            return 0;
+        }
+
+        let stored = self.transactions.len();
+        self.transactions
+            .retain(|_, details| details.status.is_open_dispute());
+        stored - self.transactions.len()
+    }
 }
@@ -0,0 +1,139 @@
+use log::info;
+
+use super::account_manager::AccountManagerError;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, TransactionId, TransactionType, TxStatus};
+
+// Operational corrections outside the transaction feed, each logged to the `audit` log target.
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Unlock a locked or quarantined account, e.g. after a chargeback was reviewed, and replay
+    // the transactions queued while it was locked. Returns whether the account was locked, None
+    // if the client has no account.
+    pub fn unlock(&self, client_id: ClientId) -> Option<bool> {
+        self.accounts.with_existing_account(client_id, |_| ())?;
+        let locked = self.accounts.with_account(client_id, |account_manager| {
+            let locked = account_manager.account.locked;
+            account_manager.unlock();
+            locked
+        });
+        info!(target: "audit", "Unlocked account of client {}", client_id);
+        self.replay_queued(client_id);
+        Some(locked)
+    }
+
+    // Settle a dispute as `TxStatus::Resolved` or `TxStatus::ChargedBack` even if the account is
+    // locked or closed, which would reject the resolve or chargeback transaction, e.g. for
+    // disputes stuck after a chargeback of another transaction. None if the client has no
+    // account.
+    pub fn force_settle(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        outcome: TxStatus,
+    ) -> Option<Result<(), AccountManagerError>> {
+        self.accounts.with_existing_account(client_id, |_| ())?;
+        let result = self.accounts.with_account(client_id, |account_manager| {
+            account_manager.postings.clear();
+            account_manager.settle(transaction_id, outcome)?;
+            if outcome == TxStatus::ChargedBack {
+                account_manager.activity.record(TransactionType::Chargeback);
+            }
+            self.record_postings(account_manager);
+            Ok(())
+        });
+        if result.is_ok() {
+            info!(
+                target: "audit",
+                "Force settled transaction {} of client {} as {}",
+                transaction_id,
+                client_id,
+                outcome.as_str()
+            );
+        }
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::Transaction;
+
+    #[test]
+    fn test_force_settle_and_unlock() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,3.0\n\
+                     dispute,1,1,\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n\
+                     resolve,1,1,\n";
+        let mut engine: Engine = Engine::new();
+        engine
+            .process_transactions_blocking(
+                csv::Reader::from_reader(input.as_bytes()).into_deserialize::<Transaction>(),
+            )
+            .unwrap();
+        let client_id: ClientId = 1.to_string().parse().unwrap();
+        let account = engine.account(client_id).unwrap();
+        assert_eq!((account.held, account.locked), (Decimal::from(5), true));
+
+        assert_eq!(
+            engine.force_settle(client_id, 2, TxStatus::Resolved),
+            Some(Err(AccountManagerError::TransactionAlreadyChargedBack))
+        );
+        assert_eq!(
+            engine.force_settle(client_id, 1, TxStatus::Resolved),
+            Some(Ok(()))
+        );
+        assert_eq!(engine.unlock(client_id), Some(true));
+        assert_eq!(engine.unlock(2.to_string().parse().unwrap()), None);
+
+        let account = engine.account(client_id).unwrap();
+        assert_eq!(account.available, Decimal::from(5));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_compact_locked_account() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,3.0\n\
+                     dispute,1,1,\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n";
+        let mut engine: Engine = Engine::new();
+        engine
+            .process_transactions_blocking(
+                csv::Reader::from_reader(input.as_bytes()).into_deserialize::<Transaction>(),
+            )
+            .unwrap();
+        engine.compact();
+
+        let client_id: ClientId = 1.to_string().parse().unwrap();
+        assert_eq!(
+            engine.force_settle(client_id, 1, TxStatus::Resolved),
+            Some(Ok(()))
+        );
+        assert_eq!(engine.unlock(client_id), Some(true));
+        // Ids of the transactions before the lock stay taken
+        let error = engine
+            .process(Transaction {
+                r#type: TransactionType::Deposit,
+                client_id,
+                transaction_id: 1,
+                amount: Some(Decimal::ONE),
+                metadata: Default::default(),
+            })
+            .unwrap_err();
+        assert_eq!(
+            error,
+            crate::engine::EngineError::AccountManagerError(AccountManagerError::TransactionExist)
+        );
+    }
+}
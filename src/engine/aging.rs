@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Timestamp};
+
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+// Held amounts of a client by how long they've been held, in whole days since the dispute.
+// `client` is empty for the total of all clients.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct HeldAging {
+    pub client: Option<ClientId>,
+    pub days_0_30: Decimal,
+    pub days_31_60: Decimal,
+    pub days_over_60: Decimal,
+    // Held by disputes without a timestamp, or restored from a snapshot before version 4
+    pub unknown: Decimal,
+    pub total: Decimal,
+}
+
+impl HeldAging {
+    fn add(&mut self, amount: Decimal, held_for: Option<Duration>) {
+        let bucket = match held_for.map(|held_for| held_for.as_secs() / DAY.as_secs()) {
+            Some(0..=30) => &mut self.days_0_30,
+            Some(31..=60) => &mut self.days_31_60,
+            Some(_) => &mut self.days_over_60,
+            None => &mut self.unknown,
+        };
+        *bucket += amount;
+        self.total += amount;
+    }
+
+    fn merge(&mut self, other: &HeldAging) {
+        self.days_0_30 += other.days_0_30;
+        self.days_31_60 += other.days_31_60;
+        self.days_over_60 += other.days_over_60;
+        self.unknown += other.unknown;
+        self.total += other.total;
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Amounts held by open disputes as of `now`, per client with held funds in client order,
+    // followed by the total of all clients. Disputes opened after `now` count as just opened.
+    pub fn held_aging(&self, now: Timestamp) -> Vec<HeldAging> {
+        let mut clients = BTreeMap::new();
+        self.accounts.for_each(|account_manager| {
+            let mut aging = HeldAging {
+                client: Some(account_manager.account.client_id),
+                ..HeldAging::default()
+            };
+            for details in account_manager.transactions.values() {
+                if details.status.is_open_dispute() {
+                    let held_for = details
+                        .disputed_at
+                        .map(|disputed_at| now.0.duration_since(disputed_at.0).unwrap_or_default());
+                    aging.add(details.amount.to_decimal(), held_for);
+                }
+            }
+            if !aging.total.is_zero() {
+                clients.insert(account_manager.account.client_id, aging);
+            }
+        });
+
+        let mut total = HeldAging::default();
+        for aging in clients.values() {
+            total.merge(aging);
+        }
+        clients.into_values().chain([total]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_held_aging() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,2024-01-01\n\
+                     deposit,1,2,3.0,2024-01-01\n\
+                     deposit,2,3,2.0,2024-01-01\n\
+                     deposit,2,4,1.0,2024-01-01\n\
+                     deposit,3,5,4.0,2024-01-01\n\
+                     dispute,1,1,,2024-01-01\n\
+                     dispute,1,2,,2024-02-10\n\
+                     dispute,2,3,,2024-03-01\n\
+                     dispute,2,4,,\n";
+        let mut engine: Engine = Engine::new();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        let client = |id: &str| -> Option<ClientId> { Some(id.parse().unwrap()) };
+        let aging = engine.held_aging("2024-03-15".parse().unwrap());
+        assert_eq!(
+            aging,
+            [
+                HeldAging {
+                    client: client("1"),
+                    days_31_60: Decimal::from(3),
+                    days_over_60: Decimal::from(5),
+                    total: Decimal::from(8),
+                    ..HeldAging::default()
+                },
+                HeldAging {
+                    client: client("2"),
+                    days_0_30: Decimal::from(2),
+                    unknown: Decimal::from(1),
+                    total: Decimal::from(3),
+                    ..HeldAging::default()
+                },
+                HeldAging {
+                    client: None,
+                    days_0_30: Decimal::from(2),
+                    days_31_60: Decimal::from(3),
+                    days_over_60: Decimal::from(5),
+                    unknown: Decimal::from(1),
+                    total: Decimal::from(11),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+use std::str::{self, FromStr};
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AlphanumericIdError {
+    #[error("Identifier is empty")]
+    Empty,
+    #[error("Identifier is longer than {} bytes", AlphanumericId::CAPACITY)]
+    TooLong,
+    #[error("Identifier contains invalid character")]
+    InvalidCharacter,
+}
+
+// Alphanumeric identifier (UUID, IBAN, ...) stored inline.
+//
+// Unlike `String` it's `Copy` and doesn't allocate, so it can replace numeric client ids without
+// changing how the engine passes them around. Ids are ordered by length first, which keeps plain
+// numbers in numeric order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AlphanumericId {
+    len: u8,
+    bytes: [u8; AlphanumericId::CAPACITY],
+}
+
+impl AlphanumericId {
+    pub const CAPACITY: usize = 39;
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII is ever stored
+        str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl FromStr for AlphanumericId {
+    type Err = AlphanumericIdError;
+
+    fn from_str(s: &str) -> Result<AlphanumericId, AlphanumericIdError> {
+        if s.is_empty() {
+            return Err(AlphanumericIdError::Empty);
+        }
+        if s.len() > Self::CAPACITY {
+            return Err(AlphanumericIdError::TooLong);
+        }
+        if !s
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(AlphanumericIdError::InvalidCharacter);
+        }
+
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(AlphanumericId {
+            len: s.len() as u8,
+            bytes,
+        })
+    }
+}
+
+impl fmt::Display for AlphanumericId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for AlphanumericId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Serialize for AlphanumericId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for AlphanumericId {
+    fn deserialize<D>(deserializer: D) -> Result<AlphanumericId, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AlphanumericIdVisitor;
+
+        impl Visitor<'_> for AlphanumericIdVisitor {
+            type Value = AlphanumericId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "alphanumeric identifier of at most {} bytes",
+                    AlphanumericId::CAPACITY
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<AlphanumericId, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(AlphanumericIdVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let uuid = "0f8fad5b-d9cb-469f-a165-70867728950e";
+
+        assert_eq!(AlphanumericId::from_str(uuid).unwrap().to_string(), uuid);
+        assert_eq!(
+            AlphanumericId::from_str(""),
+            Err(AlphanumericIdError::Empty)
+        );
+        assert_eq!(
+            AlphanumericId::from_str("GB82 WEST"),
+            Err(AlphanumericIdError::InvalidCharacter)
+        );
+        assert_eq!(
+            AlphanumericId::from_str(&"1".repeat(40)),
+            Err(AlphanumericIdError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_numeric_ids_keep_numeric_order() {
+        let mut ids: Vec<AlphanumericId> = ["17", "2", "100", "1"]
+            .iter()
+            .map(|id| id.parse().unwrap())
+            .collect();
+        ids.sort();
+
+        let ids: Vec<String> = ids.iter().map(ToString::to_string).collect();
+        assert_eq!(ids, ["1", "2", "17", "100"]);
+    }
+}
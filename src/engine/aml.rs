@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Timestamp, TransactionId, TransactionType};
+
+const DAY: u64 = 24 * 3600;
+
+// Amounts above which accepted deposits and withdrawals are registered, see
+// `EngineBuilder::aml_register`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AmlThresholds {
+    // A single transaction
+    pub single: Option<Decimal>,
+    // The sum of a client's transactions on a UTC day
+    pub daily: Option<Decimal>,
+}
+
+// Row of the large transaction register.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AmlEntry {
+    // `large_transaction` or `daily_aggregate`
+    pub reason: &'static str,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub r#type: &'static str,
+    pub amount: Decimal,
+    // RFC 3339 in UTC, empty without a timestamp
+    pub timestamp: String,
+    // UTC date of the timestamp, empty without a timestamp
+    pub day: String,
+    // Client's deposits and withdrawals on the day up to and including this one
+    pub daily_total: Option<Decimal>,
+}
+
+// Transactions of a client on the latest day it had any.
+#[derive(Debug)]
+struct ClientDay {
+    day: u64,
+    total: Decimal,
+    // Until the daily threshold is exceeded, registered all at once when it is
+    pending: Vec<AmlEntry>,
+    exceeded: bool,
+}
+
+// Register of transactions above the AML thresholds, collected as transactions are accepted.
+// Daily aggregates assume each client's transactions arrive in time order, a transaction of an
+// earlier day starts the day over.
+#[derive(Debug)]
+pub struct AmlRegister {
+    thresholds: AmlThresholds,
+    days: HashMap<ClientId, ClientDay>,
+    entries: Vec<AmlEntry>,
+}
+
+impl AmlRegister {
+    pub fn new(thresholds: AmlThresholds) -> AmlRegister {
+        AmlRegister {
+            thresholds,
+            days: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        r#type: TransactionType,
+        amount: Decimal,
+        timestamp: Option<Timestamp>,
+    ) {
+        // Negative deposits are reversals, see `EngineBuilder::negative_deposits_as_reversals`
+        if !matches!(r#type, TransactionType::Deposit | TransactionType::Withdraw)
+            || amount.is_sign_negative()
+        {
+            return;
+        }
+        let day = timestamp.map(|timestamp| {
+            timestamp
+                .0
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                / DAY
+        });
+        let daily = match (day, self.thresholds.daily) {
+            (Some(day), Some(_)) => {
+                let daily = self.days.entry(client).or_insert(ClientDay {
+                    day,
+                    total: Decimal::ZERO,
+                    pending: Vec::new(),
+                    exceeded: false,
+                });
+                if daily.day != day {
+                    *daily = ClientDay {
+                        day,
+                        total: Decimal::ZERO,
+                        pending: Vec::new(),
+                        exceeded: false,
+                    };
+                }
+                daily.total = daily.total.saturating_add(amount);
+                Some(daily)
+            }
+            _ => None,
+        };
+        let entry = AmlEntry {
+            reason: "large_transaction",
+            client,
+            tx,
+            r#type: r#type.name(),
+            amount,
+            timestamp: timestamp.map(|t| t.to_string()).unwrap_or_default(),
+            day: timestamp
+                .map(|t| t.to_string()[..10].to_string())
+                .unwrap_or_default(),
+            daily_total: daily.as_ref().map(|daily| daily.total),
+        };
+        if self.thresholds.single.is_some_and(|single| amount > single) {
+            self.entries.push(entry.clone());
+        }
+        let (Some(daily), Some(threshold)) = (daily, self.thresholds.daily) else {
+            return;
+        };
+        let entry = AmlEntry {
+            reason: "daily_aggregate",
+            ..entry
+        };
+        if daily.exceeded {
+            self.entries.push(entry);
+        } else if daily.total > threshold {
+            daily.exceeded = true;
+            self.entries.append(&mut daily.pending);
+            self.entries.push(entry);
+        } else {
+            daily.pending.push(entry);
+        }
+    }
+
+    // Entries in time order, entries without a timestamp first, then by client.
+    pub fn entries(&self) -> Vec<AmlEntry> {
+        let mut entries = self.entries.clone();
+        entries.sort_by(|a, b| (&a.timestamp, a.client).cmp(&(&b.timestamp, b.client)));
+        entries
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // The large transaction register so far, None unless enabled with
+    // `EngineBuilder::aml_register`.
+    pub fn aml_register(&self) -> Option<Vec<AmlEntry>> {
+        let register = self.aml_register.as_ref()?;
+        Some(register.lock().unwrap_or_else(|e| e.into_inner()).entries())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_aml_register() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,6000.0,2024-03-01T08:00:00Z\n\
+                     deposit,1,2,15000.0,2024-03-01T09:00:00Z\n\
+                     withdrawal,1,3,100.0,2024-03-01T10:00:00Z\n\
+                     deposit,1,4,100.0,2024-03-02T08:00:00Z\n\
+                     deposit,2,5,9000.0,2024-03-01T08:00:00Z\n\
+                     deposit,2,6,2000.0,2024-03-02T08:00:00Z\n\
+                     deposit,3,7,12000.0,\n";
+        let mut engine: Engine = Engine::builder()
+            .aml_register(AmlThresholds {
+                single: Some(Decimal::from(10000)),
+                daily: Some(Decimal::from(20000)),
+            })
+            .build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        let register = engine.aml_register().unwrap();
+        assert_eq!(
+            register
+                .iter()
+                .map(|entry| (entry.reason, entry.tx, entry.daily_total))
+                .collect::<Vec<_>>(),
+            [
+                ("large_transaction", 7, None),
+                ("daily_aggregate", 1, Some(Decimal::from(6000))),
+                ("large_transaction", 2, Some(Decimal::from(21000))),
+                ("daily_aggregate", 2, Some(Decimal::from(21000))),
+                ("daily_aggregate", 3, Some(Decimal::from(21100))),
+            ]
+        );
+        assert_eq!(register[1].day, "2024-03-01");
+        assert_eq!(register[1].timestamp, "2024-03-01T08:00:00Z");
+    }
+}
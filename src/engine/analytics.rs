@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, TransactionType};
+
+// Relative error of the amount percentiles
+const RELATIVE_ACCURACY: f64 = 0.01;
+const PERCENTILES: [(&str, f64); 5] = [
+    ("p50", 0.5),
+    ("p90", 0.9),
+    ("p95", 0.95),
+    ("p99", 0.99),
+    ("max", 1.0),
+];
+
+// Aggregates over accepted transactions, collected while processing so a report doesn't need a
+// second pass over the input. Memory grows with the number of clients, not transactions.
+#[derive(Debug, Default)]
+pub struct Analytics {
+    by_type: BTreeMap<&'static str, TypeVolume>,
+    client_volume: HashMap<ClientId, Decimal>,
+    amounts: AmountSketch,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct TypeVolume {
+    pub count: u64,
+    pub volume: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientVolume {
+    pub client: ClientId,
+    pub volume: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisputeOutcomes {
+    pub opened: u64,
+    pub resolved: u64,
+    pub charged_back: u64,
+    // Disputed and neither resolved nor charged back yet
+    pub pending: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnalyticsReport {
+    pub volume_by_type: BTreeMap<&'static str, TypeVolume>,
+    // Clients with the largest deposit and withdrawal volume
+    pub top_clients: Vec<ClientVolume>,
+    // Approximate percentiles of deposit and withdrawal amounts
+    pub amount_percentiles: BTreeMap<&'static str, f64>,
+    pub locked_accounts: usize,
+    pub disputes: DisputeOutcomes,
+}
+
+impl Analytics {
+    pub fn record(
+        &mut self,
+        r#type: TransactionType,
+        client_id: ClientId,
+        amount: Option<Decimal>,
+    ) {
+        let entry = self.by_type.entry(r#type.name()).or_default();
+        entry.count += 1;
+
+        let moves_funds = matches!(r#type, TransactionType::Deposit | TransactionType::Withdraw);
+        if let (true, Some(amount)) = (moves_funds, amount) {
+            // Reversals count towards volume like the deposits they reverse
+            let amount = amount.abs();
+            entry.volume += amount;
+            *self.client_volume.entry(client_id).or_default() += amount;
+            self.amounts.insert(amount.to_f64().unwrap_or_default());
+        }
+    }
+
+    pub fn report(&self, top_n: usize, locked_accounts: usize) -> AnalyticsReport {
+        let mut top_clients: Vec<ClientVolume> = self
+            .client_volume
+            .iter()
+            .map(|(client, volume)| ClientVolume {
+                client: *client,
+                volume: *volume,
+            })
+            .collect();
+        top_clients.sort_by(|a, b| b.volume.cmp(&a.volume).then(a.client.cmp(&b.client)));
+        top_clients.truncate(top_n);
+
+        let count =
+            |r#type: TransactionType| self.by_type.get(r#type.name()).map_or(0, |t| t.count);
+        let (opened, resolved, charged_back) = (
+            count(TransactionType::Dispute),
+            count(TransactionType::Resolve),
+            count(TransactionType::Chargeback),
+        );
+
+        AnalyticsReport {
+            volume_by_type: self.by_type.clone(),
+            top_clients,
+            amount_percentiles: PERCENTILES
+                .iter()
+                .filter_map(|&(name, q)| Some((name, self.amounts.quantile(q)?)))
+                .collect(),
+            locked_accounts,
+            disputes: DisputeOutcomes {
+                opened,
+                resolved,
+                charged_back,
+                pending: opened.saturating_sub(resolved + charged_back),
+            },
+        }
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Analytics over the transactions accepted so far, None unless enabled with
+    // `EngineBuilder::analytics`.
+    pub fn analytics_report(&self, top_n: usize) -> Option<AnalyticsReport> {
+        let analytics = self.analytics.as_ref()?;
+        let mut locked_accounts = 0;
+        self.accounts.for_each(|account_manager| {
+            if account_manager.account.locked {
+                locked_accounts += 1;
+            }
+        });
+        let analytics = analytics.lock().unwrap_or_else(|e| e.into_inner());
+        Some(analytics.report(top_n, locked_accounts))
+    }
+}
+
+// Quantile sketch with logarithmic buckets (as in DDSketch). Values are kept within
+// `RELATIVE_ACCURACY` in a number of buckets logarithmic in the range of values.
+#[derive(Debug)]
+struct AmountSketch {
+    gamma: f64,
+    buckets: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+}
+
+impl Default for AmountSketch {
+    fn default() -> Self {
+        AmountSketch {
+            gamma: (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY),
+            buckets: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+        }
+    }
+}
+
+impl AmountSketch {
+    fn insert(&mut self, value: f64) {
+        self.count += 1;
+        if value <= 0.0 {
+            self.zeros += 1;
+            return;
+        }
+        let index = value.log(self.gamma).ceil() as i32;
+        *self.buckets.entry(index).or_default() += 1;
+    }
+
+    fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        if rank <= self.zeros {
+            return Some(0.0);
+        }
+        let mut seen = self.zeros;
+        for (&index, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return Some(2.0 * self.gamma.powi(index) / (self.gamma + 1.0));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(analytics: &mut Analytics, r#type: TransactionType, client_id: &str, amount: i64) {
+        let amount = Some(Decimal::from(amount)).filter(|amount| !amount.is_zero());
+        analytics.record(r#type, client_id.parse().unwrap(), amount);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut analytics = Analytics::default();
+        for amount in 1..=100 {
+            record(&mut analytics, TransactionType::Deposit, "1", amount);
+        }
+        record(&mut analytics, TransactionType::Withdraw, "2", 1000);
+        record(&mut analytics, TransactionType::Dispute, "1", 0);
+        record(&mut analytics, TransactionType::Dispute, "1", 0);
+        record(&mut analytics, TransactionType::Chargeback, "1", 0);
+
+        let report = analytics.report(1, 1);
+
+        assert_eq!(
+            report.volume_by_type["deposit"],
+            TypeVolume {
+                count: 100,
+                volume: Decimal::from(5050),
+            }
+        );
+        assert_eq!(report.volume_by_type["withdrawal"].count, 1);
+        assert_eq!(
+            report.top_clients,
+            [ClientVolume {
+                client: "1".parse::<ClientId>().unwrap(),
+                volume: Decimal::from(5050),
+            }]
+        );
+        let p50 = report.amount_percentiles["p50"];
+        assert!((p50 - 51.0).abs() <= 51.0 * RELATIVE_ACCURACY, "{}", p50);
+        let max = report.amount_percentiles["max"];
+        assert!(
+            (max - 1000.0).abs() <= 1000.0 * RELATIVE_ACCURACY,
+            "{}",
+            max
+        );
+        assert_eq!(
+            report.disputes,
+            DisputeOutcomes {
+                opened: 2,
+                resolved: 0,
+                charged_back: 1,
+                pending: 1,
+            }
+        );
+    }
+}
+
+#[test]
+fn test_engine_report() {
+    let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,2,2,3.0\n\
+                     withdrawal,2,3,9.0\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n";
+    let engine: Engine = Engine::builder().analytics().build();
+    csv::Reader::from_reader(input.as_bytes())
+        .deserialize::<crate::engine::Transaction>()
+        .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+
+    let report = engine.analytics_report(10).unwrap();
+
+    // The rejected withdrawal isn't counted
+    assert!(!report.volume_by_type.contains_key("withdrawal"));
+    assert_eq!(
+        report.top_clients[0].client,
+        "2".parse::<ClientId>().unwrap()
+    );
+    assert_eq!(report.locked_accounts, 1);
+    assert_eq!(report.disputes.charged_back, 1);
+    assert!(Engine::<Decimal>::new().analytics_report(10).is_none());
+}
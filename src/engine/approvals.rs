@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use log::info;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::Transaction;
+
+// Metadata column of a held transaction naming who submitted it, set by whoever holds it, e.g.
+// `serve` from the approver token of the request. The submitter can't approve it themselves.
+pub const SUBMITTER_COLUMN: &str = "submitted_by";
+
+// Transactions held for approval instead of being applied, by the id they were held as, e.g. by
+// `serve --approval-threshold`. Kept in snapshots, so they survive a restart.
+#[derive(Debug, Default)]
+pub(super) struct Approvals {
+    last_id: u64,
+    pending: BTreeMap<u64, Transaction>,
+}
+
+impl Approvals {
+    // Replaces the transaction held under the same id, like accounts of the same client.
+    pub(super) fn restore(&mut self, id: u64, transaction: Transaction) {
+        self.last_id = self.last_id.max(id);
+        self.pending.insert(id, transaction);
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Hold the transaction until `take_held`, returns the id it's held as.
+    pub fn hold(&self, transaction: Transaction) -> u64 {
+        let mut approvals = self.approvals();
+        approvals.last_id += 1;
+        let id = approvals.last_id;
+        info!(
+            target: "audit",
+            "Transaction {} of client {} held for approval as {}",
+            transaction.transaction_id,
+            transaction.client_id,
+            id
+        );
+        approvals.pending.insert(id, transaction);
+        id
+    }
+
+    // Release a held transaction, to be processed if it was approved.
+    pub fn take_held(&self, id: u64) -> Option<Transaction> {
+        self.approvals().pending.remove(&id)
+    }
+
+    // Transactions held for approval by the id they are held as, in order.
+    pub fn held(&self) -> Vec<(u64, Transaction)> {
+        self.approvals()
+            .pending
+            .iter()
+            .map(|(id, transaction)| (*id, transaction.clone()))
+            .collect()
+    }
+
+    pub(super) fn approvals(&self) -> std::sync::MutexGuard<'_, Approvals> {
+        self.approvals.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::engine::{Engine, Transaction, TransactionType};
+
+    #[test]
+    fn test_held_transactions_in_snapshot() {
+        let transaction = |transaction_id| Transaction {
+            r#type: TransactionType::Deposit,
+            client_id: "1".parse().unwrap(),
+            transaction_id,
+            amount: Some(Decimal::from(5000)),
+            metadata: [("memo".to_string(), "invoice".to_string())].into(),
+        };
+        let engine: Engine = Engine::new();
+        assert_eq!(engine.hold(transaction(1)), 1);
+        assert_eq!(engine.hold(transaction(2)), 2);
+        assert_eq!(engine.take_held(1), Some(transaction(1)));
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.held(), [(2, transaction(2))]);
+        // No account until the transaction is approved
+        assert!(restored.accounts().unwrap().is_empty());
+        assert_eq!(restored.hold(transaction(3)), 3);
+
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.held().len(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use std::f64::consts::LN_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashSet;
+
+use super::transaction::TransactionId;
+
+// Exact set of used transaction ids, with a bloom filter in front.
+//
+// Fresh ids mostly miss the filter and never touch the set. A filter hit is confirmed against the
+// set, so a false positive never makes a fresh id look used.
+pub struct DuplicateFilter {
+    filter: BloomFilter,
+    ids: DashSet<TransactionId>,
+}
+
+impl DuplicateFilter {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> DuplicateFilter {
+        DuplicateFilter {
+            filter: BloomFilter::new(expected_items, false_positive_rate),
+            ids: DashSet::with_capacity(expected_items),
+        }
+    }
+
+    pub fn insert(&self, transaction_id: TransactionId) {
+        self.filter.insert(transaction_id);
+        self.ids.insert(transaction_id);
+    }
+
+    pub fn contains(&self, transaction_id: TransactionId) -> bool {
+        self.filter.contains(transaction_id) && self.ids.contains(&transaction_id)
+    }
+}
+
+// Concurrent bloom filter over transaction ids.
+//
+// Memory stays fixed regardless of how many ids are inserted: it's sized from the expected number
+// of ids and the accepted false positive rate. `contains` never misses an inserted id, but may
+// report an id which was never inserted with roughly the configured probability.
+pub struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    bit_count: u64,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> BloomFilter {
+        let expected_items = expected_items.max(1) as f64;
+        let false_positive_rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+
+        // Optimal size and number of hash functions for the given budget
+        let bit_count = (-expected_items * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let bit_count = bit_count.max(64);
+        let hashes = ((bit_count as f64 / expected_items) * LN_2)
+            .round()
+            .max(1.0) as u32;
+
+        BloomFilter {
+            bits: (0..bit_count.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            bit_count,
+            hashes,
+        }
+    }
+
+    pub fn insert(&self, transaction_id: TransactionId) {
+        for bit in self.bit_indexes(transaction_id) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    pub fn contains(&self, transaction_id: TransactionId) -> bool {
+        self.bit_indexes(transaction_id).all(|bit| {
+            self.bits[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+        })
+    }
+
+    // Kirsch-Mitzenmacher double hashing, k indexes derived from two halves of a single hash
+    // TransactionId is already u64 with the `tx-id-u64` feature
+    #[allow(clippy::unnecessary_cast)]
+    fn bit_indexes(&self, transaction_id: TransactionId) -> impl Iterator<Item = u64> + '_ {
+        let hash = splitmix64(transaction_id as u64);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count)
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let filter = BloomFilter::new(10_000, 0.01);
+        (0..10_000).for_each(|id| filter.insert(id * 7));
+
+        assert!((0..10_000).all(|id| filter.contains(id * 7)));
+    }
+
+    #[test]
+    fn test_false_positive_rate_within_budget() {
+        let filter = BloomFilter::new(10_000, 0.01);
+        (0..10_000).for_each(|id| filter.insert(id));
+
+        let false_positives = (10_000..110_000).filter(|&id| filter.contains(id)).count();
+
+        // 1% budget over 100k lookups, with some slack for variance
+        assert!(
+            false_positives < 1_500,
+            "{} false positives",
+            false_positives
+        );
+    }
+
+    #[test]
+    fn test_duplicate_filter_confirms_hits() {
+        // Far too small, almost every lookup hits the bloom filter
+        let filter = DuplicateFilter::new(1, 0.5);
+        (0..1_000).for_each(|id| filter.insert(id * 2));
+
+        assert!((0..1_000).all(|id| filter.contains(id * 2)));
+        assert!((0..1_000).all(|id| !filter.contains(id * 2 + 1)));
+    }
+}
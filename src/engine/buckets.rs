@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{Timestamp, TransactionType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketWidth {
+    Hour,
+    Day,
+}
+
+impl BucketWidth {
+    fn seconds(self) -> u64 {
+        match self {
+            BucketWidth::Hour => 60 * 60,
+            BucketWidth::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+impl FromStr for BucketWidth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<BucketWidth, ()> {
+        match s {
+            "hour" => Ok(BucketWidth::Hour),
+            "day" => Ok(BucketWidth::Day),
+            _ => Err(()),
+        }
+    }
+}
+
+// Aggregates of one period, amounts of accepted deposits and withdrawals.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    // Start of the period, RFC 3339 in UTC
+    pub start: String,
+    pub deposits: u64,
+    pub deposit_volume: Decimal,
+    pub withdrawals: u64,
+    pub withdrawal_volume: Decimal,
+    pub disputes: u64,
+    // Deposit minus withdrawal volume
+    pub net_flow: Decimal,
+}
+
+// Accepted transactions aggregated by the period of their timestamp. Transactions without a
+// (valid) timestamp aren't counted.
+#[derive(Debug)]
+pub struct TimeBuckets {
+    width: BucketWidth,
+    buckets: BTreeMap<Timestamp, Bucket>,
+}
+
+impl TimeBuckets {
+    pub fn new(width: BucketWidth) -> TimeBuckets {
+        TimeBuckets {
+            width,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        timestamp: Timestamp,
+        r#type: TransactionType,
+        amount: Option<Decimal>,
+    ) {
+        // Timestamps before the epoch can't be represented in the input anyway
+        let seconds = timestamp
+            .0
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let start = seconds - seconds % self.width.seconds();
+        // The start is never later than the timestamp, so always in range
+        let Some(start) = UNIX_EPOCH
+            .checked_add(Duration::from_secs(start))
+            .and_then(Timestamp::new)
+        else {
+            return;
+        };
+        let bucket = self.buckets.entry(start).or_default();
+        let amount = amount.unwrap_or_default().abs();
+        match r#type {
+            TransactionType::Deposit => {
+                bucket.deposits += 1;
+                bucket.deposit_volume += amount;
+                bucket.net_flow += amount;
+            }
+            TransactionType::Withdraw => {
+                bucket.withdrawals += 1;
+                bucket.withdrawal_volume += amount;
+                bucket.net_flow -= amount;
+            }
+            TransactionType::Dispute => bucket.disputes += 1,
+            _ => {}
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buckets.clear();
+    }
+
+    // Buckets in chronological order, periods without transactions are left out.
+    pub fn buckets(&self) -> Vec<Bucket> {
+        self.buckets
+            .iter()
+            .map(|(start, bucket)| Bucket {
+                start: start.to_string(),
+                ..bucket.clone()
+            })
+            .collect()
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Time buckets of the transactions accepted so far, None unless enabled with
+    // `EngineBuilder::time_buckets`.
+    pub fn time_buckets(&self) -> Option<Vec<Bucket>> {
+        let time_buckets = self.time_buckets.as_ref()?;
+        Some(
+            time_buckets
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .buckets(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ByteRecordParser;
+
+    #[test]
+    fn test_daily_buckets() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,2024-03-01T08:00:00Z\n\
+                     withdrawal,1,2,4.0,2024-03-01T23:59:59Z\n\
+                     dispute,1,1,,2024-03-03\n\
+                     deposit,1,3,1.0,\n\
+                     withdrawal,1,4,100.0,2024-03-03\n";
+        let engine: Engine = Engine::builder().time_buckets(BucketWidth::Day).build();
+        // Serde doesn't capture the timestamp metadata column
+        ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+
+        assert_eq!(
+            engine.time_buckets().unwrap(),
+            [
+                Bucket {
+                    start: "2024-03-01T00:00:00Z".to_string(),
+                    deposits: 1,
+                    deposit_volume: Decimal::from(10),
+                    withdrawals: 1,
+                    withdrawal_volume: Decimal::from(4),
+                    disputes: 0,
+                    net_flow: Decimal::from(6),
+                },
+                // The rejected withdrawal and the deposit without a timestamp aren't counted
+                Bucket {
+                    start: "2024-03-03T00:00:00Z".to_string(),
+                    disputes: 1,
+                    ..Bucket::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_last_bucket() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,253402300799\n\
+                     deposit,1,2,1.0,99999999999999999\n";
+        let engine: Engine = Engine::builder().time_buckets(BucketWidth::Day).build();
+        ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+
+        // The deposit after year 9999 has no valid timestamp and isn't counted
+        assert_eq!(
+            engine.time_buckets().unwrap(),
+            [Bucket {
+                start: "9999-12-31T00:00:00Z".to_string(),
+                deposits: 1,
+                deposit_volume: Decimal::from(10),
+                net_flow: Decimal::from(10),
+                ..Bucket::default()
+            }]
+        );
+    }
+}
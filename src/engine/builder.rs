@@ -0,0 +1,471 @@
+use std::collections::HashSet;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use log::warn;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use super::account::Account;
+use super::aml::{AmlRegister, AmlThresholds};
+use super::amount::{Amount, MinorUnits};
+use super::analytics::Analytics;
+use super::bloom::DuplicateFilter;
+use super::buckets::{BucketWidth, TimeBuckets};
+use super::calendar::Calendars;
+use super::clock::{Clock, SimulatedClock};
+use super::dunning::DunningPolicy;
+use super::engine::Engine;
+use super::fees::MaintenanceFees;
+use super::filter::ClientRanges;
+use super::finalize::Finalizer;
+use super::hot::HotAccounts;
+use super::interceptor::TransactionInterceptor;
+use super::journal::DEFAULT_LOSS_ACCOUNT;
+use super::packs::{ClientGroup, RulePack};
+use super::policies::{ChargebackDisputes, Policies};
+use super::reserve::RollingReserve;
+use super::risk::RiskThresholds;
+use super::rules::Rule;
+use super::store::{AccountStore, DashMapStore};
+use super::structuring::StructuringPolicy;
+use super::transaction::ClientId;
+
+pub struct EngineBuilder<A = Decimal, S = DashMapStore<A>> {
+    store: S,
+    duplicate_filter: Option<DuplicateFilter>,
+    compaction_interval: Option<u64>,
+    enforce_balance_invariants: bool,
+    negative_deposits_as_reversals: bool,
+    policies: Policies<A>,
+    blocklist: Option<HashSet<ClientId>>,
+    allowlist: Option<HashSet<ClientId>>,
+    analytics: bool,
+    hot_accounts: bool,
+    time_buckets: Option<BucketWidth>,
+    finalizer: Option<Finalizer<A>>,
+    clock: Option<Arc<dyn Clock>>,
+    rolling_reserve: Option<RollingReserve>,
+    calendars: Option<Calendars>,
+    maintenance_fees: Option<MaintenanceFees>,
+    dunning: Option<DunningPolicy>,
+    dormancy: Option<u32>,
+    trace_client: Option<ClientId>,
+    structuring: Option<StructuringPolicy>,
+    aml_register: Option<AmlThresholds>,
+    queue_locked: bool,
+    interceptors: Vec<Box<dyn TransactionInterceptor>>,
+    journal: Option<Box<dyn io::Write + Send>>,
+    recorder: Option<Box<dyn io::Write + Send>>,
+    loss_account: String,
+    dispute_suspense: bool,
+    idempotent: bool,
+    _amount: PhantomData<A>,
+}
+
+impl<A: Amount, S: AccountStore<A> + Default> Default for EngineBuilder<A, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Amount, S: AccountStore<A> + Default> EngineBuilder<A, S> {
+    pub fn new() -> EngineBuilder<A, S> {
+        Self::with_store(S::default())
+    }
+}
+
+// Sizing of the account map of the default store, replacing the store, so it has to come before
+// anything else touching it.
+impl<A: Amount> EngineBuilder<A, DashMapStore<A>> {
+    // Pre-size the map for the expected number of clients, avoiding rehashing while it grows, and
+    // optionally set the number of shards, see `DashMapStore::with_capacity_and_shards`.
+    pub fn account_map(
+        mut self,
+        expected_clients: usize,
+        shards: Option<usize>,
+    ) -> EngineBuilder<A, DashMapStore<A>> {
+        self.store = match shards {
+            Some(shards) => DashMapStore::with_capacity_and_shards(expected_clients, shards),
+            None => DashMapStore::with_capacity(expected_clients),
+        };
+        self
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> EngineBuilder<A, S> {
+    pub fn with_store(store: S) -> EngineBuilder<A, S> {
+        EngineBuilder {
+            store,
+            duplicate_filter: None,
+            compaction_interval: None,
+            enforce_balance_invariants: false,
+            negative_deposits_as_reversals: false,
+            policies: Policies::default(),
+            blocklist: None,
+            allowlist: None,
+            analytics: false,
+            hot_accounts: false,
+            time_buckets: None,
+            finalizer: None,
+            clock: None,
+            rolling_reserve: None,
+            calendars: None,
+            maintenance_fees: None,
+            dunning: None,
+            dormancy: None,
+            trace_client: None,
+            structuring: None,
+            aml_register: None,
+            queue_locked: false,
+            interceptors: Vec::new(),
+            journal: None,
+            recorder: None,
+            loss_account: DEFAULT_LOSS_ACCOUNT.to_string(),
+            dispute_suspense: false,
+            idempotent: false,
+            _amount: PhantomData,
+        }
+    }
+
+    // Detect transaction ids reused across clients.
+    //
+    // Ids are kept in a set behind a bloom filter sized for `expected_transactions`. Roughly
+    // `false_positive_rate` of fresh ids hit the filter and are looked up in the set, the rest
+    // skip it.
+    pub fn duplicate_filter(
+        mut self,
+        expected_transactions: usize,
+        false_positive_rate: f64,
+    ) -> EngineBuilder<A, S> {
+        self.duplicate_filter = Some(DuplicateFilter::new(
+            expected_transactions,
+            false_positive_rate,
+        ));
+        self
+    }
+
+    // Compact stored transactions every `transactions` processed transactions.
+    pub fn compaction_interval(mut self, transactions: u64) -> EngineBuilder<A, S> {
+        self.compaction_interval = Some(transactions.max(1));
+        self
+    }
+
+    // Reject transactions which would leave an account with negative available or held funds,
+    // e.g. a dispute after the deposit was withdrawn. The transaction is undone and the account
+    // quarantined instead.
+    pub fn enforce_balance_invariants(mut self) -> EngineBuilder<A, S> {
+        self.enforce_balance_invariants = true;
+        self
+    }
+
+    // Reject deposits and withdrawals of zero, which are accepted as no-ops by default.
+    pub fn reject_zero_amounts(mut self) -> EngineBuilder<A, S> {
+        self.policies.reject_zero_amounts = true;
+        self
+    }
+
+    // Treat a deposit with a negative amount as a reversal of the client's deposit with the same
+    // transaction id, instead of rejecting it.
+    pub fn negative_deposits_as_reversals(mut self) -> EngineBuilder<A, S> {
+        self.negative_deposits_as_reversals = true;
+        self
+    }
+
+    // Accept `close_account` transactions of accounts with held funds, which are rejected by
+    // default. The held funds stay with the closed account.
+    pub fn close_with_held_funds(mut self) -> EngineBuilder<A, S> {
+        self.policies.close_with_held_funds = true;
+        self
+    }
+
+    // Resolve or freeze the client's other open disputes when a chargeback locks the account,
+    // instead of leaving them open with their funds held, see `ChargebackDisputes`.
+    pub fn chargeback_disputes(mut self, policy: ChargebackDisputes) -> EngineBuilder<A, S> {
+        self.policies.chargeback_disputes = policy;
+        self
+    }
+
+    // Reject deposits which would bring an account's total above `max_balance`. A limit the amount
+    // type can't represent is lowered to its precision, or to its largest amount if it's beyond
+    // that, and logged.
+    pub fn max_balance(mut self, max_balance: Decimal) -> EngineBuilder<A, S> {
+        let limit = A::from_decimal(max_balance).unwrap_or_else(|| {
+            let limit = A::from_decimal(
+                max_balance.round_dp_with_strategy(MinorUnits::SCALE, RoundingStrategy::ToZero),
+            )
+            .unwrap_or(A::MAX);
+            warn!(
+                "Max balance {} isn't representable by the amount type, limiting to {}",
+                max_balance,
+                limit.to_decimal()
+            );
+            limit
+        });
+        self.policies.max_balance = Some(limit);
+        self
+    }
+
+    // Rates above which clients are flagged in the risk report, kept with the other `Policies` so
+    // that they can change while the engine runs.
+    pub fn risk_thresholds(mut self, risk_thresholds: RiskThresholds) -> EngineBuilder<A, S> {
+        self.policies.risk_thresholds = risk_thresholds;
+        self
+    }
+
+    // Validation and risk rules evaluated in order before every transaction, kept with the other
+    // `Policies` so that they can change while the engine runs, see `Rule`.
+    pub fn rules(mut self, rules: Vec<Rule>) -> EngineBuilder<A, S> {
+        self.policies.rules = rules;
+        self
+    }
+
+    // Apply the rule pack instead of the engine's policies to transactions of the clients, e.g.
+    // the accounts of one jurisdiction. Groups are matched in the order they were added.
+    pub fn client_group(
+        mut self,
+        name: &str,
+        clients: ClientRanges,
+        pack: RulePack<A>,
+    ) -> EngineBuilder<A, S> {
+        self.policies.groups.push(ClientGroup {
+            name: name.to_string(),
+            clients,
+            pack,
+        });
+        self
+    }
+
+    // Reject all transactions of the given clients, e.g. a sanctions or closure list. Their
+    // accounts are never created.
+    pub fn blocklist<I>(mut self, clients: I) -> EngineBuilder<A, S>
+    where
+        I: IntoIterator<Item = ClientId>,
+    {
+        self.blocklist = Some(clients.into_iter().collect());
+        self
+    }
+
+    // Reject all transactions of clients other than the given ones.
+    pub fn allowlist<I>(mut self, clients: I) -> EngineBuilder<A, S>
+    where
+        I: IntoIterator<Item = ClientId>,
+    {
+        self.allowlist = Some(clients.into_iter().collect());
+        self
+    }
+
+    // Collect analytics over accepted transactions while processing, see
+    // `Engine::analytics_report`. Updates are serialized on a single lock.
+    pub fn analytics(mut self) -> EngineBuilder<A, S> {
+        self.analytics = true;
+        self
+    }
+
+    // Count transactions and time spent per client while processing, see
+    // `Engine::hot_accounts`. Costs two clock reads per transaction and a single lock like
+    // `analytics`.
+    pub fn hot_accounts(mut self) -> EngineBuilder<A, S> {
+        self.hot_accounts = true;
+        self
+    }
+
+    // Aggregate accepted transactions with a timestamp by hour or day, see
+    // `Engine::time_buckets`.
+    pub fn time_buckets(mut self, width: BucketWidth) -> EngineBuilder<A, S> {
+        self.time_buckets = Some(width);
+        self
+    }
+
+    // Hand accounts without activity for `horizon` of input time and without open disputes to
+    // `sink` while processing a batch, dropping them from the store to bound its memory.
+    // Activity comes from the timestamp column, accounts of transactions without one are never
+    // finalized. Later transactions of a finalized account are rejected.
+    pub fn finalize_idle_accounts<F>(mut self, horizon: Duration, sink: F) -> EngineBuilder<A, S>
+    where
+        F: FnMut(Account<A>) + Send + 'static,
+    {
+        self.finalizer = Some(Finalizer::new(horizon, Box::new(sink)));
+        self
+    }
+
+    // Time of the time-dependent features, the `SimulatedClock` by default. A given clock following
+    // the input is advanced by every transaction with a timestamp, otherwise only when a
+    // time-dependent feature parses the timestamps anyway.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> EngineBuilder<A, S> {
+        self.clock = Some(clock);
+        self
+    }
+
+    // Move `rate` of every deposit with a timestamp from available into the account's reserve,
+    // released back after `period` of input time by the account's next transaction or
+    // `Engine::release_reserves`.
+    pub fn rolling_reserve(mut self, rolling_reserve: RollingReserve) -> EngineBuilder<A, S> {
+        self.rolling_reserve = Some(rolling_reserve);
+        self
+    }
+
+    // Count the whole days of the reserve period and of dispute windows as business days of the
+    // client's calendar, the one named after its group or the default one.
+    pub fn calendars(mut self, calendars: Calendars) -> EngineBuilder<A, S> {
+        self.calendars = Some(calendars);
+        self
+    }
+
+    // Charge maintenance fees to open accounts at every `Engine::close_period`, by the tier of
+    // their total.
+    pub fn maintenance_fees(mut self, fees: MaintenanceFees) -> EngineBuilder<A, S> {
+        self.maintenance_fees = Some(fees);
+        self
+    }
+
+    // Mark accounts dormant at an `Engine::close_period` once they were without funds and
+    // transactions for `periods` closed periods in a row, see `Account::dormant`.
+    pub fn dormancy(mut self, periods: u32) -> EngineBuilder<A, S> {
+        self.dormancy = Some(periods);
+        self
+    }
+
+    // Log every transaction of the client with its outcome and the account's balances and flags
+    // before and after it, at info level to the `TIMELINE_TARGET` log target.
+    pub fn trace_client(mut self, client_id: ClientId) -> EngineBuilder<A, S> {
+        self.trace_client = Some(client_id);
+        self
+    }
+
+    // Track accounts with negative available funds, e.g. after a dispute of spent funds: they
+    // are warned, then withdrawals are rejected after `restrict_after` and the account is locked
+    // after `lock_after` of input time, see `Account::dunning`. Escalated by the account's
+    // transactions and `Engine::escalate_dunning`.
+    pub fn dunning(mut self, policy: DunningPolicy) -> EngineBuilder<A, S> {
+        self.dunning = Some(policy);
+        self
+    }
+
+    // Flag clients splitting deposits or withdrawals into amounts just below a reporting
+    // threshold within a window of input time, listing them in the risk report with
+    // `Engine::structuring_evidence`.
+    pub fn structuring(mut self, policy: StructuringPolicy) -> EngineBuilder<A, S> {
+        self.structuring = Some(policy);
+        self
+    }
+
+    // Register accepted deposits and withdrawals above the single transaction threshold, and all
+    // transactions of a client's day once their sum exceeds the daily threshold, see
+    // `Engine::aml_register`.
+    pub fn aml_register(mut self, thresholds: AmlThresholds) -> EngineBuilder<A, S> {
+        self.aml_register = Some(thresholds);
+        self
+    }
+
+    // Queue transactions rejected only because the account is locked instead of dropping them,
+    // replaying them in order when the account is unlocked with `Engine::unlock`. See
+    // `Engine::queued`.
+    pub fn queue_locked(mut self) -> EngineBuilder<A, S> {
+        self.queue_locked = true;
+        self
+    }
+
+    // Run the interceptor's hooks around every transaction, e.g. for custom validation. Several
+    // interceptors run in the order they were added, see `TransactionInterceptor`.
+    pub fn interceptor<I: TransactionInterceptor + 'static>(
+        mut self,
+        interceptor: I,
+    ) -> EngineBuilder<A, S> {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    // Write the postings of accepted transactions as CSV to `writer`, see `engine::journal`.
+    pub fn journal<W: io::Write + Send + 'static>(mut self, writer: W) -> EngineBuilder<A, S> {
+        self.journal = Some(Box::new(writer));
+        self
+    }
+
+    // Write every transaction reaching the engine as CSV to `writer`, in the order they are
+    // applied, after any filtering and reordering of the input. Processing the log with the same
+    // options, from the same starting state, reproduces the state of the engine, see
+    // `replay::EventLogParser`.
+    pub fn record<W: io::Write + Send + 'static>(mut self, writer: W) -> EngineBuilder<A, S> {
+        self.recorder = Some(Box::new(writer));
+        self
+    }
+
+    // Name of the account chargeback losses land in, as shown by `Engine::extended_report`.
+    pub fn loss_account(mut self, name: impl Into<String>) -> EngineBuilder<A, S> {
+        self.loss_account = name.into();
+        self
+    }
+
+    // Park disputes of unknown transactions in suspense instead of rejecting them, applying them
+    // when the deposit arrives later, e.g. with out-of-order feeds. See `Engine::suspense`.
+    pub fn dispute_suspense(mut self) -> EngineBuilder<A, S> {
+        self.dispute_suspense = true;
+        self
+    }
+
+    // Skip records identical to an already accepted one (same type, client, id and amount)
+    // instead of rejecting them, for at-least-once feeds which redeliver records. An identical
+    // record is always taken for a redelivery, e.g. disputing a transaction again after it was
+    // resolved is skipped too.
+    pub fn idempotent(mut self) -> EngineBuilder<A, S> {
+        self.idempotent = true;
+        self
+    }
+
+    pub fn build(self) -> Engine<A, S> {
+        Engine {
+            accounts: Arc::new(self.store),
+            duplicate_filter: self.duplicate_filter,
+            compaction_interval: self.compaction_interval,
+            enforce_balance_invariants: self.enforce_balance_invariants,
+            negative_deposits_as_reversals: self.negative_deposits_as_reversals,
+            policies: Arc::new(RwLock::new(self.policies)),
+            blocklist: self.blocklist,
+            allowlist: self.allowlist,
+            analytics: self.analytics.then(|| Mutex::new(Analytics::default())),
+            hot_accounts: self
+                .hot_accounts
+                .then(|| Mutex::new(HotAccounts::default())),
+            time_buckets: self
+                .time_buckets
+                .map(|width| Mutex::new(TimeBuckets::new(width))),
+            finalizer: self.finalizer,
+            clock_from_input: self
+                .clock
+                .as_ref()
+                .is_some_and(|clock| clock.follows_input()),
+            clock: self
+                .clock
+                .unwrap_or_else(|| Arc::new(SimulatedClock::default())),
+            rolling_reserve: self.rolling_reserve,
+            calendars: self.calendars,
+            maintenance_fees: self.maintenance_fees,
+            dunning: self.dunning,
+            dormancy: self.dormancy,
+            trace_client: self.trace_client,
+            structuring: self.structuring,
+            aml_register: self
+                .aml_register
+                .map(|thresholds| Mutex::new(AmlRegister::new(thresholds))),
+            queue_locked: self.queue_locked,
+            approvals: Mutex::default(),
+            interceptors: self.interceptors,
+            journal: self
+                .journal
+                .map(|writer| Mutex::new(csv::Writer::from_writer(writer))),
+            recorder: self
+                .recorder
+                .map(|writer| Mutex::new(csv::Writer::from_writer(writer))),
+            loss_account: self.loss_account,
+            dispute_suspense: self.dispute_suspense,
+            idempotent: self.idempotent,
+            processed: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            rejections: Default::default(),
+            _amount: PhantomData,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::transaction::Timestamp;
+
+const DAY: u64 = 24 * 3600;
+
+// Names of the days of the week, Monday first, as in calendar files.
+pub const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+// Days funds move on, in UTC. Periods in whole days count business days only, see `add`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusinessCalendar {
+    // Days of the week without business by `WEEKDAYS` index
+    pub weekend: [bool; 7],
+    // Days since the Unix epoch
+    pub holidays: BTreeSet<i64>,
+}
+
+impl Default for BusinessCalendar {
+    // Saturdays and Sundays off, no holidays.
+    fn default() -> Self {
+        BusinessCalendar {
+            weekend: [false, false, false, false, false, true, true],
+            holidays: BTreeSet::new(),
+        }
+    }
+}
+
+impl BusinessCalendar {
+    // Mark the day of the timestamp as a holiday.
+    pub fn add_holiday(&mut self, day: Timestamp) {
+        self.holidays.insert(day_number(day));
+    }
+
+    pub fn is_business_day(&self, at: Timestamp) -> bool {
+        let day = day_number(at);
+        // The epoch was a Thursday
+        !self.weekend[(day + 3).rem_euclid(7) as usize] && !self.holidays.contains(&day)
+    }
+
+    // Time `period` after `from` with its whole days counted as business days: each day moves to
+    // the next business day at the same time of day, the rest of the period is added as is. A
+    // deposit on Friday with a 2 day period is due on Tuesday. None if the calendar has no
+    // business days or the time is after `Timestamp::MAX_SECONDS`.
+    pub fn add(&self, from: Timestamp, period: Duration) -> Option<Timestamp> {
+        if self.weekend.iter().all(|&off| off) {
+            return None;
+        }
+        let mut at = from.0;
+        for _ in 0..period.as_secs() / DAY {
+            at = at.checked_add(Duration::from_secs(DAY))?;
+            while !self.is_business_day(Timestamp(at)) {
+                at = at.checked_add(Duration::from_secs(DAY))?;
+            }
+        }
+        let rest = period.checked_sub(Duration::from_secs(period.as_secs() / DAY * DAY))?;
+        Timestamp::new(at.checked_add(rest)?)
+    }
+}
+
+// Calendars of the engine, by the name of the client group of `Policies::groups` they apply to,
+// e.g. a program or the books of one currency, and the default one for other clients. See
+// `EngineBuilder::calendars`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calendars {
+    pub default: BusinessCalendar,
+    pub groups: HashMap<String, BusinessCalendar>,
+}
+
+impl Calendars {
+    pub fn get(&self, group: Option<&str>) -> &BusinessCalendar {
+        group
+            .and_then(|group| self.groups.get(group))
+            .unwrap_or(&self.default)
+    }
+}
+
+// Days since the Unix epoch of the timestamp, in UTC.
+fn day_number(at: Timestamp) -> i64 {
+    match at.0.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() / DAY) as i64,
+        Err(e) => -(e.duration().as_secs().div_ceil(DAY) as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> Timestamp {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_business_days() {
+        let mut calendar = BusinessCalendar::default();
+        calendar.add_holiday(at("2024-12-25"));
+        // 2024-12-20 was a Friday
+        assert!(calendar.is_business_day(at("2024-12-20T23:00:00Z")));
+        assert!(!calendar.is_business_day(at("2024-12-21")));
+        assert!(!calendar.is_business_day(at("2024-12-25T12:00:00Z")));
+        // Sunday, 1969-12-28
+        assert!(!calendar.is_business_day(Timestamp(UNIX_EPOCH - Duration::from_secs(4 * DAY))));
+
+        let day = Duration::from_secs(DAY);
+        assert_eq!(
+            calendar.add(at("2024-12-20T10:00:00Z"), 2 * day),
+            Some(at("2024-12-24T10:00:00Z"))
+        );
+        // Over the weekend and the holiday, with part of a day
+        assert_eq!(
+            calendar.add(
+                at("2024-12-20T10:00:00Z"),
+                3 * day + Duration::from_secs(3600)
+            ),
+            Some(at("2024-12-26T11:00:00Z"))
+        );
+        assert_eq!(
+            calendar.add(at("2024-12-21"), Duration::from_secs(3600)),
+            Some(at("2024-12-21T01:00:00Z"))
+        );
+        calendar.weekend = [true; 7];
+        assert_eq!(calendar.add(at("2024-12-21"), day), None);
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::expiry::ExpiredDispute;
+use super::store::AccountStore;
+use super::transaction::{Timestamp, TxStatus};
+
+// Time of the time-dependent features of the engine: idle accounts, reserves, dunning, dispute
+// expiry and held funds aging. A batch backtest runs on a `SimulatedClock` following the input,
+// a live server on the `SystemClock`, through the same `Engine::sweep`.
+pub trait Clock: Send + Sync {
+    // None while a simulated clock hasn't seen a timestamp yet
+    fn now(&self) -> Option<Timestamp>;
+
+    // Whether `advance` moves the clock, so the engine parses the timestamps of transactions for
+    // it
+    fn follows_input(&self) -> bool {
+        false
+    }
+
+    // Take the timestamp of a transaction, in input order
+    fn advance(&self, _timestamp: Timestamp) {}
+}
+
+// Wall clock of the host.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Option<Timestamp> {
+        Some(Timestamp(SystemTime::now()))
+    }
+}
+
+// Clock at the latest timestamp of the input so far, never going back for records out of order.
+// The default clock of the engine.
+#[derive(Debug, Default)]
+pub struct SimulatedClock {
+    latest: Mutex<Option<Timestamp>>,
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Option<Timestamp> {
+        *self.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn follows_input(&self) -> bool {
+        true
+    }
+
+    fn advance(&self, timestamp: Timestamp) {
+        let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        *latest = (*latest).max(Some(timestamp));
+    }
+}
+
+// Outcome of `Engine::sweep`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sweep {
+    pub now: Timestamp,
+    // Reserves released, see `EngineBuilder::rolling_reserve`
+    pub released: usize,
+    pub expired: Vec<ExpiredDispute>,
+    // Accounts in dunning, see `EngineBuilder::dunning`
+    pub dunning: usize,
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Time of the engine's clock.
+    pub fn now(&self) -> Option<Timestamp> {
+        self.clock.now()
+    }
+
+    // Bring time-dependent state up to the engine's clock: release due reserves, escalate
+    // dunning and, with `expiry`, expire disputes open for longer than the window, see
+    // `expire_disputes`. Called at the end of an input and periodically while serving. None
+    // while the clock has no time.
+    pub fn sweep(&self, expiry: Option<(Duration, TxStatus)>) -> Option<Sweep> {
+        let now = self.now()?;
+        Some(Sweep {
+            now,
+            released: self.release_reserves(now),
+            expired: expiry
+                .map(|(window, outcome)| self.expire_disputes(now, window, outcome))
+                .unwrap_or_default(),
+            dunning: self.escalate_dunning(now),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::EngineBuilder;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_simulated_clock() {
+        let engine: Engine = EngineBuilder::new()
+            .clock(Arc::new(SimulatedClock::default()))
+            .build();
+        assert_eq!(engine.now(), None);
+        assert!(engine.sweep(None).is_none());
+
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,2024-01-01\n\
+                     dispute,1,1,,2024-01-02\n\
+                     deposit,1,2,1.0,2024-01-01T12:00:00Z\n\
+                     deposit,2,3,1.0,2024-01-09\n";
+        for transaction in SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap() {
+            let _ = engine.process(transaction.unwrap());
+        }
+        // Out of order records don't move the clock back
+        assert_eq!(engine.now(), "2024-01-09".parse().ok());
+
+        let sweep = engine
+            .sweep(Some((Duration::from_secs(86400 * 7), TxStatus::Resolved)))
+            .unwrap();
+        assert_eq!(sweep.now, "2024-01-09".parse().unwrap());
+        assert_eq!(sweep.expired.len(), 1);
+        assert_eq!(sweep.expired[0].tx, 1);
+        let account = engine.account("1".parse().unwrap()).unwrap();
+        assert_eq!(account.available, Decimal::from(6));
+    }
+}
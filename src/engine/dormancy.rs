@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::io;
+
+use log::info;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::snapshot::{write_account_manager, SnapshotError, SNAPSHOT_VERSION};
+use super::store::AccountStore;
+
+// Accounts without funds and without transactions for a number of closed periods are dormant, see
+// `EngineBuilder::dormancy`. They stay in the engine but are left out of reports by default, see
+// `AccountFilter::include_dormant`, and their history can be moved to cold storage with
+// `Engine::archive_dormant`. The next accepted transaction of the client wakes the account up.
+impl<A: Amount> AccountManager<A> {
+    // Count the period being closed, before its activity is reset. Returns whether the account
+    // became dormant with it.
+    pub(super) fn close_dormancy_period(&mut self, dormancy: Option<u32>) -> bool {
+        if self.activity.transactions == 0 {
+            self.idle_periods = self.idle_periods.saturating_add(1);
+        } else {
+            self.idle_periods = 0;
+        }
+        let Some(periods) = dormancy else {
+            return false;
+        };
+        if self.account.dormant || self.idle_periods < periods || !self.is_empty() {
+            return false;
+        }
+        self.account.dormant = true;
+        info!(
+            target: "audit",
+            "Account of client {} dormant after {} idle periods",
+            self.account.client_id,
+            self.idle_periods
+        );
+        true
+    }
+
+    // No funds and nothing pending which could still move funds.
+    fn is_empty(&self) -> bool {
+        let zero = A::default();
+        let account = &self.account;
+        account.available == zero
+            && account.held == zero
+            && account.reserve == zero
+            && self.transactions.is_empty()
+            && self.suspense.is_empty()
+            && self.queued.is_empty()
+    }
+
+    pub(super) fn wake(&mut self) {
+        if self.account.dormant {
+            self.account.dormant = false;
+            info!(target: "audit", "Account of client {} woke up", self.account.client_id);
+        }
+        self.idle_periods = 0;
+    }
+
+    fn has_history(&self) -> bool {
+        !self.notes.is_empty() || !self.near_threshold.is_empty() || !self.structuring.is_empty()
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Move the history of dormant accounts to cold storage: their complete state is written to
+    // `writer` as a snapshot, then their notes and structuring windows and flags are dropped. The
+    // accounts stay with their balances, flags and fingerprints of delivered records, which
+    // recognize redelivered records in idempotent mode. Returns the number of archived accounts.
+    pub fn archive_dormant<W: io::Write>(&self, writer: W) -> Result<usize, SnapshotError> {
+        let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+        writer.write_record(["snapshot", &SNAPSHOT_VERSION.to_string()])?;
+
+        let mut archived = 0;
+        let mut result = Ok(());
+        self.accounts.try_for_each_mut(|account_manager| {
+            if result.is_err() || !account_manager.account.dormant || !account_manager.has_history()
+            {
+                return;
+            }
+            result = write_account_manager(&mut writer, account_manager);
+            account_manager.notes = Vec::new();
+            account_manager.near_threshold = VecDeque::new();
+            account_manager.structuring = Vec::new();
+            archived += 1;
+        })?;
+        result?;
+        writer.flush().map_err(csv::Error::from)?;
+        info!(target: "audit", "Archived the history of {} dormant accounts", archived);
+        Ok(archived)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{AccountFilter, Transaction};
+    use crate::parser::ByteRecordParser;
+
+    #[test]
+    fn test_dormancy() {
+        let engine: Engine = Engine::builder().dormancy(2).idempotent().build();
+        let process = |input: &str| {
+            ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+                .unwrap()
+                .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+        };
+        process(
+            "type,client,tx,amount,note\n\
+             deposit,1,1,5.0,\n\
+             withdrawal,1,2,5.0,\n\
+             note,1,2,,moved away\n\
+             deposit,2,3,5.0,\n",
+        );
+        let client = "1".parse().unwrap();
+        let closes: Vec<_> = (0..3)
+            .map(|_| engine.close_period(io::sink()).unwrap().dormant)
+            .collect();
+        // Active in the first period, idle in the next two
+        assert_eq!(closes, [0, 0, 1]);
+        let account = engine.account(client).unwrap();
+        assert!(account.dormant);
+        assert!(!AccountFilter::default().matches(&account));
+        assert!(AccountFilter {
+            include_dormant: true,
+            ..AccountFilter::default()
+        }
+        .matches(&account));
+        // With funds, never dormant
+        assert!(!engine.account("2".parse().unwrap()).unwrap().dormant);
+
+        let mut archive = Vec::new();
+        assert_eq!(engine.archive_dormant(&mut archive).unwrap(), 1);
+        assert_eq!(engine.notes(client), Some(Vec::new()));
+        let cold: Engine = Engine::new();
+        cold.restore_snapshot(archive.as_slice()).unwrap();
+        assert_eq!(cold.notes(client).unwrap()[0].text, "moved away");
+        // Redelivered records are still skipped
+        process("type,client,tx,amount\ndeposit,1,1,5.0\n");
+        assert_eq!(
+            engine.account(client).unwrap().available,
+            rust_decimal::Decimal::ZERO
+        );
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert!(restored.account(client).unwrap().dormant);
+
+        let deposit: Transaction =
+            csv::Reader::from_reader("type,client,tx,amount\ndeposit,1,4,1.0\n".as_bytes())
+                .deserialize()
+                .next()
+                .unwrap()
+                .unwrap();
+        engine.process(deposit).unwrap();
+        assert!(!engine.account(client).unwrap().dormant);
+    }
+}
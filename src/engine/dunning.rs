@@ -0,0 +1,195 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::info;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::Timestamp;
+
+// How far collection of an account with negative available funds has escalated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DunningStage {
+    #[default]
+    Current,
+    // Negative, nothing enforced yet
+    Warned,
+    // Withdrawals are rejected
+    Restricted,
+    // The account is locked
+    Locked,
+}
+
+impl DunningStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DunningStage::Current => "current",
+            DunningStage::Warned => "warned",
+            DunningStage::Restricted => "restricted",
+            DunningStage::Locked => "locked",
+        }
+    }
+}
+
+impl FromStr for DunningStage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<DunningStage, ()> {
+        match s {
+            "current" => Ok(DunningStage::Current),
+            "warned" => Ok(DunningStage::Warned),
+            "restricted" => Ok(DunningStage::Restricted),
+            "locked" => Ok(DunningStage::Locked),
+            _ => Err(()),
+        }
+    }
+}
+
+// How long available funds may stay negative before withdrawals are restricted and before the
+// account is locked, in input time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DunningPolicy {
+    pub restrict_after: Duration,
+    pub lock_after: Duration,
+}
+
+impl DunningPolicy {
+    fn stage(&self, negative_for: Duration) -> DunningStage {
+        if negative_for >= self.lock_after {
+            DunningStage::Locked
+        } else if negative_for >= self.restrict_after {
+            DunningStage::Restricted
+        } else {
+            DunningStage::Warned
+        }
+    }
+}
+
+// `<restrict_after>,<lock_after>` as humantime durations, e.g. `30days,60days`.
+impl FromStr for DunningPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<DunningPolicy, ()> {
+        let (restrict_after, lock_after) = s.split_once(',').ok_or(())?;
+        let policy = DunningPolicy {
+            restrict_after: humantime::parse_duration(restrict_after.trim()).map_err(drop)?,
+            lock_after: humantime::parse_duration(lock_after.trim()).map_err(drop)?,
+        };
+        if policy.lock_after < policy.restrict_after {
+            return Err(());
+        }
+        Ok(policy)
+    }
+}
+
+impl<A: Amount> AccountManager<A> {
+    // Escalate the dunning stage of an account with negative available funds by how long they
+    // have been negative at `now`, or clear it once they aren't. Without a time the account is
+    // only warned. Stages never go back while the funds stay negative.
+    pub fn update_dunning(&mut self, policy: &DunningPolicy, now: Option<Timestamp>) {
+        if !self.account.available.is_sign_negative() {
+            self.negative_since = None;
+            self.account.dunning = DunningStage::Current;
+            return;
+        }
+        let stage = match now {
+            Some(now) => {
+                let since = *self.negative_since.get_or_insert(now);
+                policy.stage(now.0.duration_since(since.0).unwrap_or_default())
+            }
+            None => DunningStage::Warned,
+        };
+        if stage <= self.account.dunning {
+            return;
+        }
+        self.account.dunning = stage;
+        if stage == DunningStage::Locked {
+            self.account.locked = true;
+        }
+        info!(
+            target: "audit",
+            "Dunning of client {} escalated to {}",
+            self.account.client_id,
+            stage.as_str()
+        );
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Escalate the dunning of all accounts with negative available funds at `now`, e.g. at the
+    // end of the input, as it's otherwise only escalated by the account's transactions. Returns
+    // the number of accounts in dunning.
+    pub fn escalate_dunning(&self, now: Timestamp) -> usize {
+        let Some(policy) = &self.dunning else {
+            return 0;
+        };
+        let mut dunning = 0;
+        self.accounts.for_each_mut(|account_manager| {
+            if account_manager.account.available.is_sign_negative() {
+                account_manager.update_dunning(policy, Some(now));
+                dunning += 1;
+            }
+        });
+        dunning
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::{ClientId, EngineError, ErrorCode, Transaction, TransactionType};
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_dunning_escalation() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,2024-01-01\n\
+                     withdrawal,1,2,8.0,2024-01-02\n\
+                     dispute,1,1,,2024-01-03\n\
+                     deposit,2,3,10.0,2024-01-01\n\
+                     dispute,2,3,,2024-01-03\n\
+                     deposit,2,4,1.0,2024-02-10\n";
+        let mut engine: Engine = Engine::builder()
+            .dunning("30days,60days".parse().unwrap())
+            .build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        let client = |id: &str| -> ClientId { id.parse().unwrap() };
+        let dunning = |id: &str| engine.account(client(id)).unwrap().dunning;
+        assert_eq!(dunning("1"), DunningStage::Warned);
+        // Client 2 didn't go negative, the dispute took held funds only
+        assert_eq!(dunning("2"), DunningStage::Current);
+
+        let withdrawal = Transaction {
+            r#type: TransactionType::Withdraw,
+            client_id: client("1"),
+            transaction_id: 5,
+            amount: Some(Decimal::ONE),
+            metadata: [("timestamp".to_string(), "2024-02-15".to_string())].into(),
+        };
+        let error = engine.process(withdrawal).unwrap_err();
+        assert_eq!(error, EngineError::WithdrawalsRestricted);
+        assert_eq!(error.code(), ErrorCode::WithdrawalsRestricted);
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.account(client("1")), engine.account(client("1")));
+
+        assert_eq!(engine.escalate_dunning("2024-03-05".parse().unwrap()), 1);
+        let account = engine.account(client("1")).unwrap();
+        assert_eq!(
+            (account.dunning, account.locked),
+            (DunningStage::Locked, true)
+        );
+    }
+}
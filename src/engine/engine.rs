@@ -1,12 +1,44 @@
 use anyhow::Result;
-use dashmap::DashMap;
-use std::{error::Error, sync::Arc};
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use std::future::poll_fn;
+use std::io;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use std::{error::Error, marker::PhantomData, sync::Arc};
 
 use super::account::Account;
 use super::account_manager::{AccountManager, AccountManagerError};
-use super::transaction::{Transaction, TransactionType, TransactionValidationError};
+use super::aml::AmlRegister;
+use super::amount::Amount;
+use super::analytics::Analytics;
+use super::approvals::Approvals;
+use super::bloom::DuplicateFilter;
+use super::buckets::TimeBuckets;
+use super::builder::EngineBuilder;
+use super::calendar::Calendars;
+use super::clock::Clock;
+use super::dunning::{DunningPolicy, DunningStage};
+use super::error_code::ErrorCode;
+use super::fees::MaintenanceFees;
+use super::finalize::Finalizer;
+use super::hot::HotAccounts;
+use super::interceptor::TransactionInterceptor;
+use super::notes::Note;
+use super::payments_engine::EngineStats;
+use super::policies::Policies;
+use super::reserve::RollingReserve;
+use super::store::{AccountStore, DashMapStore};
+use super::stream::Stream;
+use super::structuring::{NearThreshold, StructuringPolicy};
+use super::transaction::{
+    ClientId, Metadata, StoredTransaction, Transaction, TransactionId, TransactionPage,
+    TransactionType, TransactionValidationError, TxStatus,
+};
 
-use log::warn;
+use log::{debug, info, warn};
 
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum EngineError {
@@ -14,91 +46,727 @@ pub enum EngineError {
     TransactionValidationError(#[from] TransactionValidationError),
     #[error("AccountManager error: {0}")]
     AccountManagerError(#[from] AccountManagerError),
+    #[error("Transaction id used already by another client")]
+    ProbableDuplicate,
+    #[error("Transaction would violate balance invariants, account quarantined")]
+    BalanceInvariantViolation,
+    #[error("Client is blocked")]
+    ClientBlocked,
+    #[error("Disputed transaction not found yet, dispute parked in suspense")]
+    DisputeSuspended,
+    #[error("Account finalized already")]
+    AccountFinalized,
+    #[error("Withdrawals restricted while the balance is negative")]
+    WithdrawalsRestricted,
+    #[error("Account locked, transaction queued until it's unlocked")]
+    TransactionQueued,
+    #[error("Rejected by interceptor: {0}")]
+    Intercepted(String),
+    #[error("Rejected by rule {0}")]
+    RuleRejected(String),
+    #[error("Account store unavailable: {0}")]
+    StoreUnavailable(String),
 }
 
-pub struct Engine {
-    accounts: Arc<DashMap<u16, AccountManager>>,
+// A transaction the engine rejected with where it was in the input, for callers repairing or
+// retrying it.
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("Error processing transaction {} (record {position}): {error}", transaction.transaction_id)]
+pub struct TransactionError {
+    // Number of the record in the input, counted from 1 including records which couldn't be
+    // parsed, so the data line of a CSV input without multi-line fields
+    pub position: u64,
+    pub transaction: Transaction,
+    #[source]
+    pub error: EngineError,
+}
+
+pub struct Engine<A = Decimal, S = DashMapStore<A>> {
+    pub(super) accounts: Arc<S>,
+    pub(super) duplicate_filter: Option<DuplicateFilter>,
+    pub(super) compaction_interval: Option<u64>,
+    pub(super) enforce_balance_invariants: bool,
+    pub(super) negative_deposits_as_reversals: bool,
+    pub(super) policies: Arc<RwLock<Policies<A>>>,
+    pub(super) blocklist: Option<HashSet<ClientId>>,
+    pub(super) allowlist: Option<HashSet<ClientId>>,
+    pub(super) analytics: Option<Mutex<Analytics>>,
+    pub(super) hot_accounts: Option<Mutex<HotAccounts>>,
+    pub(super) time_buckets: Option<Mutex<TimeBuckets>>,
+    pub(super) finalizer: Option<Finalizer<A>>,
+    pub(super) clock: Arc<dyn Clock>,
+    // Timestamps are parsed for the clock even without a time-dependent feature, see
+    // `EngineBuilder::clock`
+    pub(super) clock_from_input: bool,
+    pub(super) rolling_reserve: Option<RollingReserve>,
+    pub(super) calendars: Option<Calendars>,
+    pub(super) maintenance_fees: Option<MaintenanceFees>,
+    pub(super) dunning: Option<DunningPolicy>,
+    pub(super) dormancy: Option<u32>,
+    pub(super) trace_client: Option<ClientId>,
+    pub(super) structuring: Option<StructuringPolicy>,
+    pub(super) aml_register: Option<Mutex<AmlRegister>>,
+    pub(super) queue_locked: bool,
+    pub(super) approvals: Mutex<Approvals>,
+    pub(super) interceptors: Vec<Box<dyn TransactionInterceptor>>,
+    pub(super) journal: Option<Mutex<csv::Writer<Box<dyn io::Write + Send>>>>,
+    pub(super) recorder: Option<Mutex<csv::Writer<Box<dyn io::Write + Send>>>>,
+    pub(super) loss_account: String,
+    pub(super) dispute_suspense: bool,
+    pub(super) idempotent: bool,
+    pub(super) processed: AtomicU64,
+    pub(super) rejected: AtomicU64,
+    // Rejections by `ErrorCode::index`
+    pub(super) rejections: [AtomicU64; ErrorCode::ALL.len()],
+    pub(super) _amount: PhantomData<A>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CompactionStats {
+    pub accounts: usize,
+    pub dropped_transactions: usize,
+}
+
+impl<A: Amount, S: AccountStore<A> + Default> Default for Engine<A, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Amount, S: AccountStore<A> + Default> Engine<A, S> {
+    pub fn new() -> Engine<A, S> {
+        EngineBuilder::new().build()
+    }
+
+    pub fn builder() -> EngineBuilder<A, S> {
+        EngineBuilder::new()
+    }
 }
 
-impl Engine {
-    pub fn new() -> Engine {
-        Engine {
-            accounts: Arc::new(DashMap::new()),
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    pub fn with_store(store: S) -> Engine<A, S> {
+        EngineBuilder::with_store(store).build()
+    }
+
+    pub fn accounts(&self) -> Result<Vec<Account<A>>> {
+        let mut accounts = Vec::new();
+        self.accounts
+            .try_for_each(|account_manager| accounts.push(account_manager.account.clone()))?;
+        Ok(accounts)
+    }
+
+    pub(super) fn account_snapshot(&self) -> Vec<Account<A>> {
+        let mut accounts = Vec::new();
+        self.accounts
+            .for_each(|account_manager| accounts.push(account_manager.account.clone()));
+        accounts
+    }
+
+    pub fn stats(&self) -> EngineStats {
+        let mut accounts = 0;
+        self.accounts.for_each(|_| accounts += 1);
+        EngineStats {
+            processed: self.processed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            rejections: ErrorCode::ALL
+                .iter()
+                .zip(&self.rejections)
+                .map(|(code, count)| (*code, count.load(Ordering::Relaxed)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            accounts,
         }
     }
 
-    pub fn accounts(&self) -> Result<Vec<Account>> {
-        Ok(self
+    // Status of a stored transaction of the client, None if the transaction isn't stored.
+    pub fn transaction_status(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Option<TxStatus> {
+        self.accounts
+            .with_existing_account(client_id, |account_manager| {
+                account_manager
+                    .transactions
+                    .get(&transaction_id)
+                    .map(|details| details.status)
+            })
+            .flatten()
+    }
+
+    // Metadata passed through with a stored transaction of the client, None if the transaction
+    // isn't stored.
+    pub fn transaction_metadata(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Option<Metadata> {
+        self.accounts
+            .with_existing_account(client_id, |account_manager| {
+                account_manager
+                    .transactions
+                    .get(&transaction_id)
+                    .map(|details| details.metadata.clone())
+            })
+            .flatten()
+    }
+
+    // Account of a single client, None if the client has no account.
+    pub fn account(&self, client_id: ClientId) -> Option<Account<A>> {
+        self.accounts
+            .with_existing_account(client_id, |account_manager| account_manager.account.clone())
+    }
+
+    // Up to `limit` stored transactions of the client with ids above `cursor`, in id order. None
+    // if the client has no account.
+    pub fn transactions(
+        &self,
+        client_id: ClientId,
+        limit: usize,
+        cursor: Option<TransactionId>,
+    ) -> Option<TransactionPage<A>> {
+        self.accounts
+            .with_existing_account(client_id, |account_manager| {
+                let mut transactions: Vec<_> = account_manager
+                    .transactions
+                    .iter()
+                    .filter(|(transaction_id, _)| cursor.is_none_or(|c| **transaction_id > c))
+                    .collect();
+                transactions.sort_unstable_by_key(|(transaction_id, _)| **transaction_id);
+
+                let more = transactions.len() > limit;
+                transactions.truncate(limit);
+                let next_cursor = transactions
+                    .last()
+                    .filter(|_| more)
+                    .map(|(transaction_id, _)| **transaction_id);
+                TransactionPage {
+                    transactions: transactions
+                        .into_iter()
+                        .map(|(transaction_id, details)| StoredTransaction {
+                            transaction_id: *transaction_id,
+                            amount: details.amount,
+                            status: details.status,
+                            metadata: details.metadata.clone(),
+                            disputed_at: details.disputed_at,
+                        })
+                        .collect(),
+                    next_cursor,
+                }
+            })
+    }
+
+    // Remove all stored transactions of the client and their metadata, e.g. to honor an erasure
+    // request, including the ones queued on its locked account, notes and structuring evidence. The account keeps its balances,
+    // later disputes of purged transactions fail as if they never existed. Returns the number of
+    // purged transactions, None if the client has no account.
+    pub fn purge_client(&self, client_id: ClientId) -> Option<usize> {
+        self.accounts.with_existing_account(client_id, |_| ())?;
+        let purged = self
             .accounts
-            .iter()
-            .map(|acc| acc.value().account.clone())
-            .collect())
+            .with_account(client_id, |account_manager| account_manager.purge());
+        info!(target: "audit", "Purged {} transactions of client {}", purged, client_id);
+        Some(purged)
     }
 
+    // Drop stored transaction details which can no longer be referenced by any transaction,
+    // shrinking memory of long-running engines.
+    pub fn compact(&self) -> CompactionStats {
+        let mut stats = CompactionStats::default();
+        self.accounts.for_each_mut(|account_manager| {
+            let dropped = account_manager.compact();
+            if dropped > 0 {
+                stats.accounts += 1;
+                stats.dropped_transactions += dropped;
+            }
+        });
+        debug!("Compaction: {:?}", stats);
+        stats
+    }
+
+    // Async adapters over the synchronous core. They never await anything, so they run on any
+    // executor; see `engine::tokio_runtime` for offloading the work from an async runtime.
     pub async fn process_transactions<I, E>(&mut self, transacations_iter: I) -> Result<()>
     where
         I: Iterator<Item = std::result::Result<Transaction, E>>,
         E: Error + Sync + Send + 'static,
     {
-        for transaction in transacations_iter {
-            if let Ok(transaction) = transaction {
-                let transaction_id = transaction.transaction_id;
-                let accounts = Arc::clone(&self.accounts);
-                if let Err(e) = Self::process_transaction(accounts, transaction).await {
-                    // Log error and continue processing
-                    warn!("Error processing transaction {}: {}", transaction_id, e);
-                }
+        self.process_transactions_blocking(transacations_iter)
+    }
+
+    // Process all transactions of an async source in order, e.g. records arriving over the
+    // network, logging and skipping the ones which fail. Only waits for the source, processing
+    // itself runs without yielding.
+    pub async fn process_stream<St, E>(&mut self, stream: St) -> Result<()>
+    where
+        St: Stream<Item = std::result::Result<Transaction, E>>,
+        E: Error + Sync + Send + 'static,
+    {
+        let mut stream = pin!(stream);
+        let mut processed = 0;
+        while let Some(record) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+            let Ok(transaction) = record else {
+                continue;
+            };
+            processed += 1;
+            let transaction_id = transaction.transaction_id;
+            if let Err(e) = self.process_counted(processed, transaction) {
+                warn!("Error processing transaction {}: {}", transaction_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn process_transaction(&self, transaction: Transaction) -> Result<(), EngineError> {
+        self.process(transaction)
+    }
+
+    // Process all transactions in order, logging and skipping the ones which fail.
+    pub fn process_transactions_blocking<I, E>(&mut self, transacations_iter: I) -> Result<()>
+    where
+        I: Iterator<Item = std::result::Result<Transaction, E>>,
+        E: Error + Sync + Send + 'static,
+    {
+        self.process_transactions_with(transacations_iter, |e| warn!("{}", e))
+    }
+
+    // Process all transactions in order, handing the ones which fail to `on_error` and going on.
+    // Records which can't be parsed are skipped.
+    pub fn process_transactions_with<I, E, F>(
+        &mut self,
+        transacations_iter: I,
+        mut on_error: F,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = std::result::Result<Transaction, E>>,
+        E: Error + Sync + Send + 'static,
+        F: FnMut(TransactionError),
+    {
+        let transactions = (1..)
+            .zip(transacations_iter)
+            .filter_map(|(position, record)| Some((position, record.ok()?)));
+        for (processed, (position, transaction)) in (1..).zip(transactions) {
+            if let Err(error) = self.process_counted(processed, transaction.clone()) {
+                on_error(TransactionError {
+                    position,
+                    transaction,
+                    error,
+                });
             }
         }
 
         Ok(())
     }
 
-    pub async fn process_transaction(
-        accounts: Arc<DashMap<u16, AccountManager>>,
+    // Process the `processed`th transaction of an input, compacting every
+    // `compaction_interval` transactions and finalizing idle accounts when due.
+    fn process_counted(
+        &mut self,
+        processed: u64,
+        transaction: Transaction,
+    ) -> Result<(), EngineError> {
+        let result = self.process(transaction);
+        if matches!(self.compaction_interval, Some(n) if processed.is_multiple_of(n)) {
+            self.compact();
+        }
+        self.finalize_idle();
+        result
+    }
+
+    pub fn process(&self, transaction: Transaction) -> Result<(), EngineError> {
+        let start = self.hot_accounts.as_ref().map(|_| Instant::now());
+        let client_id = transaction.client_id;
+        // Kept only for the traced client, with its account before and after
+        let traced = (self.trace_client == Some(client_id)).then(|| transaction.clone());
+        let mut balances = None;
+        let result = if self.is_blocked(&transaction.client_id) {
+            self.record_event(&transaction);
+            Err(EngineError::ClientBlocked)
+        } else if self
+            .finalizer
+            .as_ref()
+            .is_some_and(|finalizer| finalizer.is_finalized(&client_id))
+        {
+            self.record_event(&transaction);
+            Err(EngineError::AccountFinalized)
+        } else {
+            // Get existing or create new account manager and process the transaction
+            // An account which can't be read back is left as it is, like any rejected transaction
+            self.accounts
+                .try_with_account(transaction.client_id, |account_manager| {
+                    // Recorded under the account's lock, in the order of application
+                    self.record_event(&transaction);
+                    let before = traced.as_ref().map(|_| account_manager.account.clone());
+                    let result = if self.interceptors.is_empty() {
+                        self.process_locked(account_manager, transaction)
+                    } else {
+                        self.process_intercepted(account_manager, transaction)
+                    };
+                    balances = before.map(|before| (before, account_manager.account.clone()));
+                    result
+                })
+                .unwrap_or_else(|e| Err(EngineError::StoreUnavailable(e.to_string())))
+        };
+        if let Some(transaction) = &traced {
+            self.trace(transaction, balances, &result);
+        }
+
+        if let (Some(hot_accounts), Some(start)) = (&self.hot_accounts, start) {
+            hot_accounts
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(client_id, start.elapsed());
+        }
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = &result {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            self.rejections[e.code().index()].fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    // Apply a transaction to its account, under the account's lock.
+    pub(super) fn process_locked(
+        &self,
+        account_manager: &mut AccountManager<A>,
         transaction: Transaction,
     ) -> Result<(), EngineError> {
-        // Get existing or create new account manager
-        let mut account_manager = accounts
-            .entry(transaction.client_id)
-            .or_insert(AccountManager::new(transaction.client_id));
+        let (r#type, transaction_id, amount) = (
+            transaction.r#type,
+            transaction.transaction_id,
+            transaction.amount,
+        );
+        let fingerprint = self.idempotent.then(|| transaction.fingerprint());
+        if fingerprint.is_some_and(|f| account_manager.delivered.contains(&f)) {
+            debug!(
+                "Skipping redelivered transaction {}",
+                transaction.transaction_id
+            );
+            return Ok(());
+        }
+        // Parsed only when needed
+        let timestamp = (self.time_buckets.is_some()
+            || self.finalizer.is_some()
+            || self.rolling_reserve.is_some()
+            || self.dunning.is_some()
+            || self.structuring.is_some()
+            || self.aml_register.is_some()
+            || self.clock_from_input)
+            .then(|| transaction.timestamp())
+            .flatten();
+        if let Some(timestamp) = timestamp {
+            self.clock.advance(timestamp);
+        }
+        account_manager.postings.clear();
+        if let (Some(_), Some(timestamp)) = (self.rolling_reserve, timestamp) {
+            self.release_due_reserves(account_manager, timestamp);
+        }
+        if let Some(dunning) = &self.dunning {
+            account_manager.update_dunning(dunning, timestamp);
+            if account_manager.account.dunning == DunningStage::Restricted
+                && r#type == TransactionType::Withdraw
+            {
+                return Err(EngineError::WithdrawalsRestricted);
+            }
+        }
+        self.check_rules(&account_manager.account, &transaction)?;
+        let creates_transaction = self.creates_transaction(&transaction);
+        // Kept only when it may be rejected for the lock
+        let queued =
+            (self.queue_locked && account_manager.account.locked).then(|| transaction.clone());
+        let result = if self.enforce_balance_invariants {
+            self.apply_enforcing_invariants(account_manager, transaction)
+        } else {
+            self.apply_transaction(account_manager, transaction)
+        };
+        if let (
+            Err(EngineError::AccountManagerError(AccountManagerError::AccountLocked)),
+            Some(transaction),
+        ) = (&result, queued)
+        {
+            account_manager.queued.push_back(transaction);
+            return Err(EngineError::TransactionQueued);
+        }
+        if result.is_ok() {
+            if let Some(dunning) = &self.dunning {
+                account_manager.update_dunning(dunning, timestamp);
+            }
+            if let Some(fingerprint) = fingerprint {
+                account_manager.delivered.insert(fingerprint);
+            }
+            // Only once the transaction stands, also with the invariants enforced
+            if let (Some(filter), true) = (&self.duplicate_filter, creates_transaction) {
+                filter.insert(transaction_id);
+            }
+            account_manager.activity.record(r#type);
+            if r#type != TransactionType::Note {
+                account_manager.wake();
+            }
+            self.record_postings(account_manager);
+            if let Some(analytics) = &self.analytics {
+                analytics.lock().unwrap_or_else(|e| e.into_inner()).record(
+                    r#type,
+                    account_manager.account.client_id,
+                    amount,
+                );
+            }
+            if let (Some(time_buckets), Some(timestamp)) = (&self.time_buckets, timestamp) {
+                time_buckets
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record(timestamp, r#type, amount);
+            }
+            if let (
+                Some(policy),
+                Some(at),
+                Some(amount),
+                TransactionType::Deposit | TransactionType::Withdraw,
+            ) = (&self.structuring, timestamp, amount, r#type)
+            {
+                account_manager.record_near_threshold(
+                    policy,
+                    NearThreshold {
+                        transaction_id,
+                        r#type,
+                        amount,
+                        at,
+                    },
+                );
+            }
+            if let (Some(register), Some(amount)) = (&self.aml_register, amount) {
+                register.lock().unwrap_or_else(|e| e.into_inner()).record(
+                    account_manager.account.client_id,
+                    transaction_id,
+                    r#type,
+                    amount,
+                    timestamp,
+                );
+            }
+            if let (Some(_), Some(timestamp)) = (&self.finalizer, timestamp) {
+                account_manager.last_activity = account_manager.last_activity.max(Some(timestamp));
+            }
+        }
+        result
+    }
 
-        // Process the transaction
+    fn is_blocked(&self, client_id: &ClientId) -> bool {
+        self.blocklist
+            .as_ref()
+            .is_some_and(|blocklist| blocklist.contains(client_id))
+            || self
+                .allowlist
+                .as_ref()
+                .is_some_and(|allowlist| !allowlist.contains(client_id))
+    }
+
+    fn apply_transaction(
+        &self,
+        account_manager: &mut AccountManager<A>,
+        transaction: Transaction,
+    ) -> Result<(), EngineError> {
+        let all_policies = self.policies.read().unwrap_or_else(|e| e.into_inner());
+        let policies = all_policies.for_client(&account_manager.account.client_id);
         let transaction_id = transaction.transaction_id;
-        return match transaction.r#type {
-            TransactionType::Deposit => {
-                let amount = transaction.get_amount_or_error()?;
+        let reversal = self.is_reversal(&transaction);
+        if self.creates_transaction(&transaction) {
+            self.check_duplicate(account_manager, transaction_id)?;
+        }
+
+        match transaction.r#type {
+            TransactionType::Deposit if reversal => {
+                let amount = transaction
+                    .amount
+                    .and_then(|amount| A::from_decimal(-amount))
+                    .ok_or(TransactionValidationError::UnrepresentableAmount)?;
                 account_manager
-                    .deposit(transaction_id, amount)
+                    .reverse(transaction_id, amount)
                     .map_err(EngineError::from)
             }
+            TransactionType::Deposit => {
+                let amount = Self::amount(&policies, &transaction)?;
+                Self::check_max_balance(&policies, account_manager, amount)?;
+                let group = all_policies.group(&account_manager.account.client_id);
+                let reserve =
+                    self.reserve_for(&transaction, amount, group.map(|g| g.name.as_str()));
+                let savepoint = reserve.map(|_| account_manager.savepoint(&transaction));
+                account_manager
+                    .deposit_with_metadata(transaction_id, amount, transaction.metadata)
+                    .map_err(EngineError::from)?;
+                if let (Some((reserved, release_at)), Some(savepoint)) = (reserve, savepoint) {
+                    // The deposit doesn't stand without its reserve
+                    if let Err(e) =
+                        account_manager.hold_reserve(transaction_id, reserved, release_at)
+                    {
+                        account_manager.roll_back(savepoint);
+                        return Err(e.into());
+                    }
+                }
+                self.apply_suspended_dispute(account_manager, transaction_id);
+                Ok(())
+            }
             TransactionType::Withdraw => {
-                let amount = transaction.get_amount_or_error()?;
-                account_manager.withdraw(amount).map_err(EngineError::from)
+                let amount = Self::amount(&policies, &transaction)?;
+                account_manager
+                    .withdraw(transaction_id, amount)
+                    .map_err(EngineError::from)
             }
-            TransactionType::Dispute => account_manager
-                .dispute(transaction_id)
-                .map_err(EngineError::from),
+            TransactionType::Dispute => match account_manager.dispute(transaction_id) {
+                Ok(()) => {
+                    if let Some(details) = account_manager.transactions.get_mut(&transaction_id) {
+                        details.disputed_at = transaction.timestamp();
+                    }
+                    Ok(())
+                }
+                Err(AccountManagerError::TransactionNotExist) if self.dispute_suspense => {
+                    account_manager.suspense.insert(transaction_id);
+                    Err(EngineError::DisputeSuspended)
+                }
+                result => result.map_err(EngineError::from),
+            },
             TransactionType::Resolve => account_manager
                 .resolve(transaction_id)
                 .map_err(EngineError::from),
-            TransactionType::Chargeback => account_manager
-                .chargeback(transaction_id)
+            TransactionType::Chargeback => {
+                account_manager.chargeback(transaction_id)?;
+                // The chargeback stands even if settling the other disputes fails
+                let client_id = account_manager.account.client_id;
+                match account_manager.settle_open_disputes(policies.chargeback_disputes) {
+                    Ok(0) => {}
+                    Ok(settled) => info!(
+                        target: "audit",
+                        "Chargeback of transaction {} of client {} settled {} other disputes as {:?}",
+                        transaction_id,
+                        client_id,
+                        settled,
+                        policies.chargeback_disputes
+                    ),
+                    Err(e) => warn!(
+                        "Error settling other disputes of client {}: {}",
+                        client_id, e
+                    ),
+                }
+                Ok(())
+            }
+            TransactionType::CloseAccount => account_manager
+                .close(policies.close_with_held_funds)
                 .map_err(EngineError::from),
+            TransactionType::Note => {
+                let note = Note::from_transaction(&transaction)
+                    .ok_or(TransactionValidationError::NoteMissing)?;
+                account_manager.add_note(note);
+                Ok(())
+            }
+        }
+    }
+
+    fn is_reversal(&self, transaction: &Transaction) -> bool {
+        self.negative_deposits_as_reversals
+            && transaction.r#type == TransactionType::Deposit
+            && transaction
+                .amount
+                .is_some_and(|amount| amount.is_sign_negative())
+    }
+
+    // Whether the transaction is recorded under its id, which is then checked for duplicates.
+    fn creates_transaction(&self, transaction: &Transaction) -> bool {
+        !self.is_reversal(transaction)
+            && matches!(
+                transaction.r#type,
+                TransactionType::Deposit | TransactionType::Withdraw
+            )
+    }
+
+    fn amount(
+        policies: &Policies<A>,
+        transaction: &Transaction,
+    ) -> Result<A, TransactionValidationError> {
+        let amount: A = transaction.get_amount_or_error()?;
+        if policies.reject_zero_amounts && amount == A::default() {
+            return Err(TransactionValidationError::AmountZero);
+        }
+        Ok(amount)
+    }
+
+    fn check_max_balance(
+        policies: &Policies<A>,
+        account_manager: &AccountManager<A>,
+        amount: A,
+    ) -> Result<(), AccountManagerError> {
+        let Some(max_balance) = policies.max_balance else {
+            return Ok(());
         };
+        match account_manager
+            .account
+            .calculate_total()
+            .checked_add(amount)
+        {
+            Some(total) if total <= max_balance => Ok(()),
+            _ => Err(AccountManagerError::AmountOverflow),
+        }
+    }
+
+    // Apply the transaction, undoing it and quarantining the account if it left impossible
+    // balances behind.
+    fn apply_enforcing_invariants(
+        &self,
+        account_manager: &mut AccountManager<A>,
+        transaction: Transaction,
+    ) -> Result<(), EngineError> {
+        let savepoint = account_manager.savepoint(&transaction);
+        self.apply_transaction(account_manager, transaction)?;
+        if account_manager.account.satisfies_invariants() {
+            return Ok(());
+        }
+
+        account_manager.roll_back(savepoint);
+        account_manager.quarantine();
+        Err(EngineError::BalanceInvariantViolation)
+    }
+
+    // Reject transaction ids which were already used by any client, as `TransactionExist` within
+    // the client and `ProbableDuplicate` across clients.
+    fn check_duplicate(
+        &self,
+        account_manager: &AccountManager<A>,
+        transaction_id: TransactionId,
+    ) -> Result<(), EngineError> {
+        match &self.duplicate_filter {
+            Some(filter) if filter.contains(transaction_id) => {
+                if account_manager.transactions.contains_key(&transaction_id) {
+                    return Err(AccountManagerError::TransactionExist.into());
+                }
+                Err(EngineError::ProbableDuplicate)
+            }
+            _ => Ok(()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::engine::amount::MinorUnits;
+    use crate::engine::journal::Book;
+    use crate::engine::store::AccountStore;
+    use crate::engine::store::{BTreeMapStore, ShardedStore, SpillingStore};
+    use crate::engine::stream;
+    use crate::engine::RollingReserve;
+    use crate::engine::{
+        ChargebackDisputes, CompactionStats, EngineBuilder, ErrorCode, Metadata, Transaction,
+        TxStatus,
+    };
+    use crate::parser::{ByteRecordParser, SerdeParser};
     use crate::Engine;
+    use rust_decimal::Decimal;
     use tokio::test;
 
     // Helper macro to read transaction records from a string, process them and compare them to the expected output.
     macro_rules! assert_account_balance {
-        ($input:expr => $expected:expr) => {{
+        (<$engine:ty> $input:expr => $expected:expr) => {
+            assert_account_balance!(engine: <$engine>::new(), $input => $expected)
+        };
+        (engine: $engine:expr, $input:expr => $expected:expr) => {{
             // Initialize the engine and prepare input and expected output strings
-            let mut engine = Engine::new();
+            let mut engine = $engine;
             let input = $input
                 .split_whitespace()
                 .map(|s| format!("{}\n", s))
@@ -141,6 +809,9 @@ mod tests {
             }
             assert_eq!(output, expected_output);
         }};
+        ($input:expr => $expected:expr) => {
+            assert_account_balance!(<Engine<Decimal>> $input => $expected)
+        };
     }
 
     #[test]
@@ -352,6 +1023,101 @@ mod tests {
         )
     }
 
+    #[test]
+    async fn test_double_chargeback() {
+        let process = |engine: &Engine, input: &str| -> Vec<_> {
+            csv::Reader::from_reader(input.as_bytes())
+                .deserialize::<Transaction>()
+                .map(|transaction| engine.process(transaction.unwrap()).err())
+                .map(|error| error.map(|e| e.code()))
+                .collect()
+        };
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,2.0\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n";
+        let engine: Engine = Engine::new();
+        let charged_back = Some(ErrorCode::TransactionChargedBack);
+        assert_eq!(process(&engine, input)[4..], [charged_back; 2]);
+
+        // Not even once the account is unlocked
+        let client_id = "1".parse().unwrap();
+        engine.unlock(client_id);
+        let input = "type,client,tx,amount\n\
+                     dispute,1,2,\n\
+                     resolve,1,2,\n\
+                     chargeback,1,2,\n";
+        assert_eq!(process(&engine, input), [charged_back; 3]);
+        let account = engine.account(client_id).unwrap();
+        assert_eq!(
+            (account.available, account.held, account.calculate_total()),
+            (Decimal::ONE, Decimal::ZERO, Decimal::ONE)
+        );
+        assert_eq!(
+            engine.transaction_status(client_id, 2),
+            Some(TxStatus::ChargedBack)
+        );
+
+        let engine: Engine = Engine::builder().negative_deposits_as_reversals().build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,1,-1.0\n\
+                     dispute,1,1,\n";
+        assert_eq!(
+            process(&engine, input),
+            [None, None, Some(ErrorCode::TransactionReversed)]
+        );
+    }
+
+    #[test]
+    async fn test_chargeback_disputes() {
+        let input = "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                deposit,1,2,2.0
+                dispute,1,1,
+                dispute,1,2,
+                chargeback,1,2,
+            ";
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder()
+                .chargeback_disputes(ChargebackDisputes::Resolve)
+                .build(),
+            input
+            =>
+            "
+                client,available,held,total,locked
+                1,1.0,0.0,1.0,true
+            "
+        );
+
+        let mut engine: Engine = Engine::builder()
+            .chargeback_disputes(ChargebackDisputes::Freeze)
+            .build();
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(reader.into_deserialize())
+            .await
+            .unwrap();
+        let client_id = "1".parse().unwrap();
+        let account = engine.account(client_id).unwrap();
+        assert_eq!((account.held, account.locked), (Decimal::ONE, true));
+        assert_eq!(
+            engine.transaction_status(client_id, 1),
+            Some(TxStatus::Frozen)
+        );
+        assert_eq!(
+            engine.force_settle(client_id, 1, TxStatus::Resolved),
+            Some(Ok(()))
+        );
+        assert_eq!(engine.account(client_id).unwrap().available, Decimal::ONE);
+    }
+
     #[test]
     async fn test_chargeback_without_dispute() {
         assert_account_balance!(
@@ -368,4 +1134,770 @@ mod tests {
             "
         )
     }
+
+    #[test]
+    async fn test_minor_units_amounts() {
+        assert_account_balance!(
+            <Engine<MinorUnits>>
+            "
+                type,client,tx,amount
+                deposit,1,1,1.1
+                deposit,1,2,2.2
+                withdrawal,1,3,0.0001
+                dispute,1,2,
+                deposit,2,4,1.00001
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,1.0999,2.2,3.2999,false
+                2,0.0,0.0,0.0,false
+            "
+        )
+    }
+
+    #[test]
+    async fn test_float_amounts() {
+        assert_account_balance!(
+            <Engine<f64>>
+            "
+                type,client,tx,amount
+                deposit,1,1,1.1
+                deposit,1,2,2.2
+                deposit,1,3,3.3
+                dispute,1,3,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,3.3,3.3,6.6,false
+            "
+        )
+    }
+
+    #[test]
+    async fn test_sharded_store() {
+        assert_account_balance!(
+            <Engine<Decimal, ShardedStore<Decimal>>>
+            "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                deposit,2,2,2.0
+                deposit,17,3,3.0
+                dispute,1,1,
+                chargeback,1,1,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,0.0,0.0,0.0,true
+                2,2.0,0.0,2.0,false
+                17,3.0,0.0,3.0,false
+            "
+        )
+    }
+
+    #[test]
+    async fn test_btree_map_store() {
+        assert_account_balance!(
+            <Engine<Decimal, BTreeMapStore<Decimal>>>
+            "
+                type,client,tx,amount
+                deposit,3,1,1.0
+                deposit,2,2,2.0
+                withdrawal,3,3,0.5
+                dispute,2,2,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                2,0.0,2.0,2.0,false
+                3,0.5,0.0,0.5,false
+            "
+        )
+    }
+
+    #[test]
+    async fn test_duplicate_filter() {
+        assert_account_balance!(
+            engine: EngineBuilder::<Decimal>::new().duplicate_filter(100, 0.0001).build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                deposit,2,1,2.0
+                withdrawal,1,2,0.5
+                withdrawal,1,2,0.5
+                deposit,2,3,3.0
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,0.5,0.0,0.5,false
+                2,3.0,0.0,3.0,false
+            "
+        )
+    }
+
+    #[test]
+    async fn test_duplicate_filter_false_positives() {
+        // Far too small, almost every fresh id hits the bloom filter
+        let deposits = (1..=200)
+            .map(|tx| format!("deposit,{},{},1.0 ", tx % 3 + 1, tx))
+            .collect::<String>();
+        assert_account_balance!(
+            engine: EngineBuilder::<Decimal>::new().duplicate_filter(1, 0.5).build(),
+            format!("type,client,tx,amount {}", deposits)
+            =>
+            "
+                client,available,held,total,locked
+                1,66.0,0.0,66.0,false
+                2,67.0,0.0,67.0,false
+                3,67.0,0.0,67.0,false
+            "
+        )
+    }
+
+    #[test]
+    async fn test_idempotent() {
+        assert_account_balance!(
+            engine: EngineBuilder::<Decimal>::new().idempotent().build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                withdrawal,1,2,0.5
+                withdrawal,1,2,0.50
+                deposit,1,1,1.0
+                dispute,1,1,
+                dispute,1,1,
+                deposit,2,3,3.0
+                withdrawal,2,4,1.0
+                withdrawal,2,4,2.0
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,-0.5,1.0,0.5,false
+                2,0.0,0.0,0.0,false
+            "
+        );
+    }
+
+    #[test]
+    async fn test_compaction() {
+        let mut engine: Engine = Engine::builder().close_with_held_funds().build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,2.0\n\
+                     deposit,2,3,3.0\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n\
+                     deposit,2,4,4.0\n\
+                     dispute,2,4,\n\
+                     close_account,2,5,\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(reader.into_deserialize())
+            .await
+            .unwrap();
+
+        // The locked account can be unlocked, the open dispute of the closed one force settled
+        assert_eq!(
+            engine.compact(),
+            CompactionStats {
+                accounts: 1,
+                dropped_transactions: 1,
+            }
+        );
+        assert_eq!(engine.compact(), CompactionStats::default());
+        let client_id: crate::engine::ClientId = "2".parse().unwrap();
+        assert_eq!(
+            engine.force_settle(client_id, 4, TxStatus::Resolved),
+            Some(Ok(()))
+        );
+    }
+
+    #[test]
+    async fn test_spilling_store() {
+        let directory =
+            std::env::temp_dir().join(format!("spilling-store-test-{}", std::process::id()));
+        assert_account_balance!(
+            engine: EngineBuilder::with_store(SpillingStore::<Decimal>::new(&directory, 1).unwrap()).build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                deposit,2,2,2.0
+                dispute,1,1,
+                deposit,3,3,3.0
+                withdrawal,2,4,0.5
+                chargeback,1,1,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,0.0,0.0,0.0,true
+                2,1.5,0.0,1.5,false
+                3,3.0,0.0,3.0,false
+            "
+        );
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    async fn test_transaction_metadata() {
+        let mut engine: Engine = Engine::new();
+        let input = "type,client,tx,amount,memo,reference\n\
+                     deposit,1,1,1.0,rent,INV-1\n\
+                     deposit,1,2,2.0,,\n\
+                     withdrawal,1,3,0.5,atm,\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(SerdeParser::new(reader).unwrap())
+            .await
+            .unwrap();
+
+        let metadata = |client_id: &str, transaction_id| {
+            engine.transaction_metadata(client_id.parse().unwrap(), transaction_id)
+        };
+        assert_eq!(
+            metadata("1", 1),
+            Some(Metadata::from([
+                ("memo".to_string(), "rent".to_string()),
+                ("reference".to_string(), "INV-1".to_string()),
+            ]))
+        );
+        assert_eq!(metadata("1", 2), Some(Metadata::new()));
+        // Withdrawals aren't stored
+        assert_eq!(metadata("1", 3), None);
+        assert_eq!(metadata("2", 1), None);
+        assert_eq!(engine.accounts().unwrap().len(), 1);
+    }
+
+    #[test]
+    async fn test_transaction_status() {
+        let mut engine: Engine = Engine::new();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,2.0\n\
+                     deposit,1,3,3.0\n\
+                     dispute,1,2,\n\
+                     dispute,1,3,\n\
+                     resolve,1,3,\n\
+                     deposit,2,4,1.0\n\
+                     dispute,2,4,\n\
+                     chargeback,2,4,\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(reader.into_deserialize())
+            .await
+            .unwrap();
+
+        let status = |client_id: &str, transaction_id| {
+            engine.transaction_status(client_id.parse().unwrap(), transaction_id)
+        };
+        assert_eq!(status("1", 1), Some(TxStatus::Settled));
+        assert_eq!(status("1", 2), Some(TxStatus::Disputed));
+        assert_eq!(status("1", 3), Some(TxStatus::Resolved));
+        assert_eq!(status("2", 4), Some(TxStatus::ChargedBack));
+        assert_eq!(status("1", 4), None);
+    }
+
+    #[test]
+    async fn test_close_account() {
+        let input = "
+                type,client,tx,amount
+                deposit,1,1,5.0
+                close_account,1,2,
+                deposit,1,3,1.0
+                withdrawal,1,4,1.0
+                dispute,1,1,
+                deposit,2,5,3.0
+                dispute,2,5,
+                close_account,2,6,
+            ";
+
+        assert_account_balance!(
+            input
+            =>
+            "
+                client,available,held,total,locked
+                1,5.0,0.0,5.0,false
+                2,0.0,3.0,3.0,false
+            "
+        );
+
+        let mut engine: Engine = Engine::builder().close_with_held_funds().build();
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(reader.into_deserialize())
+            .await
+            .unwrap();
+        let mut accounts = engine.accounts().unwrap();
+        accounts.sort_by_key(|a| a.client_id);
+        assert!(accounts.iter().all(|a| a.closed && !a.locked));
+        assert_eq!(engine.stats().rejected, 3);
+    }
+
+    #[test]
+    async fn test_transaction_errors() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,x,1.0\n\
+                     withdrawal,1,2,9.0\n\
+                     deposit,1,1,1.0\n";
+        let mut engine: Engine = Engine::new();
+        let mut errors = Vec::new();
+        engine
+            .process_transactions_with(
+                csv::Reader::from_reader(input.as_bytes()).into_deserialize::<Transaction>(),
+                |e| errors.push(e),
+            )
+            .unwrap();
+
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.position, e.transaction.transaction_id, e.error.code()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (3, 2, ErrorCode::InsufficientFunds),
+                (4, 1, ErrorCode::TransactionExists)
+            ]
+        );
+    }
+
+    #[test]
+    async fn test_process_stream() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     withdrawal,1,2,2.0\n\
+                     dispute,1,1,\n";
+        let mut engine: Engine = Engine::builder().compaction_interval(2).build();
+        let records = csv::Reader::from_reader(input.as_bytes()).into_deserialize::<Transaction>();
+        engine.process_stream(stream::iter(records)).await.unwrap();
+
+        let account = engine.account("1".parse().unwrap()).unwrap();
+        assert_eq!(account.available, Decimal::from(-2));
+        assert_eq!(account.held, Decimal::from(5));
+    }
+
+    #[test]
+    async fn test_purge_client() {
+        let mut engine: Engine = Engine::new();
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,1.0,rent\n\
+                     deposit,1,2,2.0,salary\n\
+                     deposit,2,3,3.0,\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(SerdeParser::new(reader).unwrap())
+            .await
+            .unwrap();
+
+        let client_id = "1".parse().unwrap();
+        assert_eq!(engine.purge_client(client_id), Some(2));
+        assert_eq!(engine.purge_client("3".parse().unwrap()), None);
+        assert_eq!(engine.transaction_metadata(client_id, 1), None);
+        assert_eq!(
+            engine.account(client_id).map(|account| account.available),
+            Some(Decimal::from(3))
+        );
+        assert!(engine.transaction_status("2".parse().unwrap(), 3).is_some());
+
+        // Transactions queued on a locked account aren't replayed after the purge
+        let mut engine: Engine = Engine::builder().queue_locked().build();
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,1.0,rent\n\
+                     dispute,1,1,,\n\
+                     chargeback,1,1,,\n\
+                     deposit,1,2,2.0,salary\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let _ = engine
+            .process_transactions(SerdeParser::new(reader).unwrap())
+            .await;
+        assert_eq!(engine.purge_client(client_id), Some(2));
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        assert!(!String::from_utf8(snapshot).unwrap().contains("salary"));
+        engine.unlock(client_id);
+        assert_eq!(
+            engine.account(client_id).map(|account| account.available),
+            Some(Decimal::ZERO)
+        );
+
+        // Notes and structuring evidence go as well
+        let mut engine: Engine = Engine::builder()
+            .structuring("10000,1000,2,1day".parse().unwrap())
+            .build();
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,9500.0,2024-01-01T08:00:00Z\n\
+                     deposit,1,2,9500.0,2024-01-01T09:00:00Z\n";
+        engine
+            .process_transactions(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(engine.add_note(
+            client_id,
+            crate::engine::Note {
+                tx: Some(1),
+                case: None,
+                text: "paid in cash".to_string(),
+                at: None,
+            }
+        ));
+        assert_eq!(engine.structuring_evidence().len(), 2);
+        assert_eq!(engine.purge_client(client_id), Some(2));
+        assert!(engine.structuring_evidence().is_empty());
+        assert_eq!(engine.notes(client_id), Some(Vec::new()));
+    }
+
+    #[test]
+    async fn test_client_blocklist() {
+        let client_id = |id: &str| id.parse().unwrap();
+        let input = "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                deposit,2,2,2.0
+                deposit,3,3,3.0
+            ";
+
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder().blocklist([client_id("2")]).build(),
+            input
+            =>
+            "
+                client,available,held,total,locked
+                1,1.0,0.0,1.0,false
+                3,3.0,0.0,3.0,false
+            "
+        );
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder()
+                .allowlist([client_id("1"), client_id("2")])
+                .blocklist([client_id("2")])
+                .build(),
+            input
+            =>
+            "
+                client,available,held,total,locked
+                1,1.0,0.0,1.0,false
+            "
+        );
+    }
+
+    #[test]
+    async fn test_transaction_pages() {
+        let mut engine: Engine = Engine::new();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,3,3.0\n\
+                     deposit,1,1,1.0\n\
+                     deposit,2,2,2.0\n\
+                     deposit,1,5,5.0\n\
+                     dispute,1,5,\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(reader.into_deserialize())
+            .await
+            .unwrap();
+
+        let client_id = "1".parse().unwrap();
+        let page = engine.transactions(client_id, 2, None).unwrap();
+        let ids: Vec<_> = page.transactions.iter().map(|t| t.transaction_id).collect();
+        assert_eq!(ids, [1, 3]);
+        assert_eq!(page.next_cursor, Some(3));
+
+        let page = engine.transactions(client_id, 2, page.next_cursor).unwrap();
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].transaction_id, 5);
+        assert_eq!(page.transactions[0].status, TxStatus::Disputed);
+        assert_eq!(page.next_cursor, None);
+
+        assert_eq!(
+            engine.account(client_id).map(|account| account.held),
+            Some(Decimal::from(5))
+        );
+        assert_eq!(engine.account("3".parse().unwrap()), None);
+        assert_eq!(engine.transactions("3".parse().unwrap(), 2, None), None);
+    }
+
+    #[test]
+    async fn test_stored_transaction_times() {
+        let mut engine: Engine = Engine::new();
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,1.0,2024-03-01\n\
+                     deposit,1,2,2.0,\n\
+                     dispute,1,1,,2024-03-02T10:00:00Z\n";
+        engine
+            .process_transactions(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let page = engine.transactions("1".parse().unwrap(), 10, None).unwrap();
+        let times: Vec<_> = page
+            .transactions
+            .iter()
+            .map(|t| {
+                (
+                    t.timestamp().map(|t| t.to_string()),
+                    t.disputed_at.map(|t| t.to_string()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            times,
+            [
+                (
+                    Some("2024-03-01T00:00:00Z".to_string()),
+                    Some("2024-03-02T10:00:00Z".to_string())
+                ),
+                (None, None),
+            ]
+        );
+    }
+
+    #[test]
+    async fn test_enforce_balance_invariants() {
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder().enforce_balance_invariants().build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,2.0
+                withdrawal,1,2,1.5
+                dispute,1,1,
+                deposit,1,3,1.0
+                deposit,2,4,1.0
+                dispute,2,4,
+                resolve,2,4,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,0.5,0.0,0.5,true
+                2,1.0,0.0,1.0,false
+            "
+        );
+
+        let mut engine: Engine = Engine::builder().enforce_balance_invariants().build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,2.0\n\
+                     withdrawal,1,2,1.5\n\
+                     dispute,1,1,\n";
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine
+            .process_transactions(reader.into_deserialize())
+            .await
+            .unwrap();
+
+        // Dispute was undone
+        let client_id = "1".parse().unwrap();
+        assert_eq!(
+            engine.transaction_status(client_id, 1),
+            Some(TxStatus::Settled)
+        );
+        assert!(engine.accounts().unwrap()[0].quarantined);
+    }
+
+    #[test]
+    async fn test_enforce_balance_invariants_undoes_side_effects() {
+        let engine: Engine = Engine::builder()
+            .enforce_balance_invariants()
+            .dispute_suspense()
+            .rolling_reserve(RollingReserve {
+                rate: Decimal::new(1, 1),
+                period: std::time::Duration::from_secs(24 * 3600),
+            })
+            .build();
+        // The suspended dispute holds the whole deposit, of which a part is already reserved
+        let input = "type,client,tx,amount,timestamp\n\
+                     dispute,1,1,,\n\
+                     deposit,1,1,10.0,2024-01-01\n";
+        let results: Vec<_> = ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .map(|transaction| engine.process(transaction.unwrap()))
+            .collect();
+        assert_eq!(
+            results[1].as_ref().map_err(|e| e.code()),
+            Err(ErrorCode::BalanceInvariantViolation)
+        );
+
+        let client_id = "1".parse().unwrap();
+        let account = engine.account(client_id).unwrap();
+        assert_eq!(
+            (account.available, account.reserve, account.quarantined),
+            (Decimal::ZERO, Decimal::ZERO, true)
+        );
+        assert_eq!(engine.transaction_status(client_id, 1), None);
+        assert_eq!(engine.suspense(), [(client_id, 1)]);
+        assert_eq!(engine.release_reserves("2024-02-01".parse().unwrap()), 0);
+        assert_eq!(engine.risk_report(Default::default())[0].disputes, 0);
+    }
+
+    #[test]
+    async fn test_deposit_undone_if_reserve_fails() {
+        let engine: Engine = Engine::builder()
+            .rolling_reserve(RollingReserve {
+                rate: Decimal::new(1, 1),
+                period: std::time::Duration::from_secs(24 * 3600),
+            })
+            .build();
+        // A full reserve book, which the reserve of the deposit overflows
+        let client_id = "1".parse().unwrap();
+        engine.accounts.with_account(client_id, |account_manager| {
+            account_manager.books.set(Book::ClientReserve, Decimal::MAX);
+            account_manager
+                .books
+                .set(Book::ClientAvailable, -Decimal::MAX);
+            account_manager.account.reserve = Decimal::MAX;
+            account_manager.account.available = -Decimal::MAX;
+        });
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,2024-01-01\n";
+        let transaction = ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            engine.process(transaction).map_err(|e| e.code()),
+            Err(ErrorCode::AmountOverflow)
+        );
+        let account = engine.account(client_id).unwrap();
+        assert_eq!(
+            (account.available, account.reserve),
+            (-Decimal::MAX, Decimal::MAX)
+        );
+        assert_eq!(engine.transaction_status(client_id, 1), None);
+        // The deposit's posting from settlement was undone too
+        let trial_balance = engine.trial_balance().unwrap();
+        assert!(trial_balance.contains(&(Book::Settlement, Decimal::ZERO)));
+    }
+
+    #[test]
+    async fn test_reject_zero_amounts() {
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder().reject_zero_amounts().build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,0.0
+                deposit,1,2,1.0
+                withdrawal,1,3,0
+                dispute,1,1,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,1.0,0.0,1.0,false
+            "
+        );
+    }
+
+    #[test]
+    async fn test_negative_deposits_as_reversals() {
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder().negative_deposits_as_reversals().build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,1.0
+                deposit,1,2,2.0
+                deposit,1,1,-1.0
+                deposit,1,2,-1.0
+                deposit,1,3,-1.0
+                dispute,1,1,
+                deposit,2,4,1.0
+                deposit,2,4,-1.0
+                deposit,2,4,-1.0
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,2.0,0.0,2.0,false
+                2,0.0,0.0,0.0,false
+            "
+        );
+    }
+
+    #[test]
+    async fn test_amount_overflow() {
+        assert_account_balance!(
+            <Engine<MinorUnits>>
+            "
+                type,client,tx,amount
+                deposit,1,1,900000000000000
+                deposit,1,2,900000000000000
+                deposit,1,3,1
+                dispute,1,1,
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,1.0,900000000000000.0,900000000000001.0,false
+            "
+        );
+    }
+
+    #[test]
+    async fn test_unrepresentable_max_balance() {
+        // Beyond the amount type, the limit is its largest amount rather than none
+        let engine = Engine::<MinorUnits>::builder()
+            .max_balance(Decimal::from_i128_with_scale(10i128.pow(20), 0))
+            .build();
+        assert_eq!(
+            engine.policies().max_balance,
+            Some(MinorUnits::new(i64::MAX))
+        );
+        // Finer than its precision, the limit is rounded down
+        let engine = Engine::<MinorUnits>::builder()
+            .max_balance(Decimal::new(1000009, 5))
+            .build();
+        assert_eq!(engine.policies().max_balance, Some(MinorUnits::new(100000)));
+    }
+
+    #[test]
+    async fn test_max_balance() {
+        assert_account_balance!(
+            engine: Engine::<Decimal>::builder().max_balance(Decimal::from(10)).build(),
+            "
+                type,client,tx,amount
+                deposit,1,1,6.0
+                deposit,1,2,5.0
+                deposit,1,3,4.0
+                deposit,2,4,11.0
+            "
+            =>
+            "
+                client,available,held,total,locked
+                1,10.0,0.0,10.0,false
+                2,0.0,0.0,0.0,false
+            "
+        );
+    }
 }
@@ -0,0 +1,235 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::account_manager::AccountManagerError;
+use super::engine::EngineError;
+use super::transaction::TransactionValidationError;
+
+// Stable code of every reason the engine rejects a transaction, for outputs read by other
+// programs. Numbers and names never change meaning, new reasons get new codes: 1xx for invalid
+// transactions, 2xx for transactions the account state doesn't allow, 3xx for engine rules and
+// 4xx for failures of the engine itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    AmountMissing,
+    AmountNegative,
+    AmountZero,
+    UnrepresentableAmount,
+    NoteMissing,
+    AccountLocked,
+    AccountClosed,
+    HeldFunds,
+    TransactionExists,
+    TransactionNotFound,
+    TransactionDisputed,
+    TransactionNotDisputed,
+    TransactionChargedBack,
+    TransactionReversed,
+    IllegalTransition,
+    ReversalAmountMismatch,
+    AmountOverflow,
+    InsufficientFunds,
+    ProbableDuplicate,
+    BalanceInvariantViolation,
+    ClientBlocked,
+    DisputeSuspended,
+    AccountFinalized,
+    WithdrawalsRestricted,
+    TransactionQueued,
+    Intercepted,
+    RuleRejected,
+    StoreUnavailable,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 28] = [
+        ErrorCode::AmountMissing,
+        ErrorCode::AmountNegative,
+        ErrorCode::AmountZero,
+        ErrorCode::UnrepresentableAmount,
+        ErrorCode::NoteMissing,
+        ErrorCode::AccountLocked,
+        ErrorCode::AccountClosed,
+        ErrorCode::HeldFunds,
+        ErrorCode::TransactionExists,
+        ErrorCode::TransactionNotFound,
+        ErrorCode::TransactionDisputed,
+        ErrorCode::TransactionNotDisputed,
+        ErrorCode::TransactionChargedBack,
+        ErrorCode::TransactionReversed,
+        ErrorCode::IllegalTransition,
+        ErrorCode::ReversalAmountMismatch,
+        ErrorCode::AmountOverflow,
+        ErrorCode::InsufficientFunds,
+        ErrorCode::ProbableDuplicate,
+        ErrorCode::BalanceInvariantViolation,
+        ErrorCode::ClientBlocked,
+        ErrorCode::DisputeSuspended,
+        ErrorCode::AccountFinalized,
+        ErrorCode::WithdrawalsRestricted,
+        ErrorCode::TransactionQueued,
+        ErrorCode::Intercepted,
+        ErrorCode::RuleRejected,
+        ErrorCode::StoreUnavailable,
+    ];
+
+    pub fn code(self) -> u16 {
+        match self {
+            ErrorCode::AmountMissing => 101,
+            ErrorCode::AmountNegative => 102,
+            ErrorCode::AmountZero => 103,
+            ErrorCode::UnrepresentableAmount => 104,
+            ErrorCode::NoteMissing => 105,
+            ErrorCode::AccountLocked => 201,
+            ErrorCode::AccountClosed => 202,
+            ErrorCode::HeldFunds => 203,
+            ErrorCode::TransactionExists => 204,
+            ErrorCode::TransactionNotFound => 205,
+            ErrorCode::TransactionDisputed => 206,
+            ErrorCode::TransactionNotDisputed => 207,
+            ErrorCode::TransactionChargedBack => 208,
+            ErrorCode::TransactionReversed => 209,
+            ErrorCode::IllegalTransition => 210,
+            ErrorCode::ReversalAmountMismatch => 211,
+            ErrorCode::AmountOverflow => 212,
+            ErrorCode::InsufficientFunds => 213,
+            ErrorCode::ProbableDuplicate => 301,
+            ErrorCode::BalanceInvariantViolation => 302,
+            ErrorCode::ClientBlocked => 303,
+            ErrorCode::DisputeSuspended => 304,
+            ErrorCode::AccountFinalized => 305,
+            ErrorCode::WithdrawalsRestricted => 306,
+            ErrorCode::TransactionQueued => 307,
+            ErrorCode::Intercepted => 308,
+            ErrorCode::RuleRejected => 309,
+            ErrorCode::StoreUnavailable => 401,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::AmountMissing => "amount_missing",
+            ErrorCode::AmountNegative => "amount_negative",
+            ErrorCode::AmountZero => "amount_zero",
+            ErrorCode::UnrepresentableAmount => "unrepresentable_amount",
+            ErrorCode::NoteMissing => "note_missing",
+            ErrorCode::AccountLocked => "account_locked",
+            ErrorCode::AccountClosed => "account_closed",
+            ErrorCode::HeldFunds => "held_funds",
+            ErrorCode::TransactionExists => "transaction_exists",
+            ErrorCode::TransactionNotFound => "transaction_not_found",
+            ErrorCode::TransactionDisputed => "transaction_disputed",
+            ErrorCode::TransactionNotDisputed => "transaction_not_disputed",
+            ErrorCode::TransactionChargedBack => "transaction_charged_back",
+            ErrorCode::TransactionReversed => "transaction_reversed",
+            ErrorCode::IllegalTransition => "illegal_transition",
+            ErrorCode::ReversalAmountMismatch => "reversal_amount_mismatch",
+            ErrorCode::AmountOverflow => "amount_overflow",
+            ErrorCode::InsufficientFunds => "insufficient_funds",
+            ErrorCode::ProbableDuplicate => "probable_duplicate",
+            ErrorCode::BalanceInvariantViolation => "balance_invariant_violation",
+            ErrorCode::ClientBlocked => "client_blocked",
+            ErrorCode::DisputeSuspended => "dispute_suspended",
+            ErrorCode::AccountFinalized => "account_finalized",
+            ErrorCode::WithdrawalsRestricted => "withdrawals_restricted",
+            ErrorCode::TransactionQueued => "transaction_queued",
+            ErrorCode::Intercepted => "intercepted",
+            ErrorCode::RuleRejected => "rule_rejected",
+            ErrorCode::StoreUnavailable => "store_unavailable",
+        }
+    }
+
+    // Position in `ALL`, for per-code counters.
+    pub(super) fn index(self) -> usize {
+        ErrorCode::ALL
+            .iter()
+            .position(|code| *code == self)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{} {}", self.code(), self.as_str())
+    }
+}
+
+impl TransactionValidationError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            TransactionValidationError::AmountMissing => ErrorCode::AmountMissing,
+            TransactionValidationError::AmountNegative => ErrorCode::AmountNegative,
+            TransactionValidationError::AmountZero => ErrorCode::AmountZero,
+            TransactionValidationError::UnrepresentableAmount => ErrorCode::UnrepresentableAmount,
+            TransactionValidationError::NoteMissing => ErrorCode::NoteMissing,
+        }
+    }
+}
+
+impl AccountManagerError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AccountManagerError::AccountLocked => ErrorCode::AccountLocked,
+            AccountManagerError::AccountClosed => ErrorCode::AccountClosed,
+            AccountManagerError::HeldFunds => ErrorCode::HeldFunds,
+            AccountManagerError::TransactionExist => ErrorCode::TransactionExists,
+            AccountManagerError::TransactionNotExist => ErrorCode::TransactionNotFound,
+            AccountManagerError::TransactionDisputed => ErrorCode::TransactionDisputed,
+            AccountManagerError::TransactionNotDisputed => ErrorCode::TransactionNotDisputed,
+            AccountManagerError::TransactionAlreadyChargedBack => ErrorCode::TransactionChargedBack,
+            AccountManagerError::TransactionReversed => ErrorCode::TransactionReversed,
+            AccountManagerError::IllegalTransition(..) => ErrorCode::IllegalTransition,
+            AccountManagerError::ReversalAmountMismatch => ErrorCode::ReversalAmountMismatch,
+            AccountManagerError::AmountOverflow => ErrorCode::AmountOverflow,
+            AccountManagerError::InsufficientFunds => ErrorCode::InsufficientFunds,
+        }
+    }
+}
+
+impl EngineError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EngineError::TransactionValidationError(e) => e.code(),
+            EngineError::AccountManagerError(e) => e.code(),
+            EngineError::ProbableDuplicate => ErrorCode::ProbableDuplicate,
+            EngineError::BalanceInvariantViolation => ErrorCode::BalanceInvariantViolation,
+            EngineError::ClientBlocked => ErrorCode::ClientBlocked,
+            EngineError::DisputeSuspended => ErrorCode::DisputeSuspended,
+            EngineError::AccountFinalized => ErrorCode::AccountFinalized,
+            EngineError::WithdrawalsRestricted => ErrorCode::WithdrawalsRestricted,
+            EngineError::TransactionQueued => ErrorCode::TransactionQueued,
+            EngineError::Intercepted(_) => ErrorCode::Intercepted,
+            EngineError::RuleRejected(_) => ErrorCode::RuleRejected,
+            EngineError::StoreUnavailable(_) => ErrorCode::StoreUnavailable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique() {
+        let codes: HashSet<u16> = ErrorCode::ALL.iter().map(|code| code.code()).collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+
+        for (index, code) in ErrorCode::ALL.iter().enumerate() {
+            assert_eq!(code.index(), index);
+            assert_eq!(
+                serde_json::to_string(code).unwrap(),
+                format!("\"{}\"", code.as_str())
+            );
+        }
+        assert_eq!(
+            EngineError::from(AccountManagerError::InsufficientFunds)
+                .code()
+                .to_string(),
+            "E213 insufficient_funds"
+        );
+    }
+}
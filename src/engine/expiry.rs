@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::policies::Policies;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Timestamp, TransactionId, TxStatus};
+
+// Dispute settled by `Engine::expire_disputes` because it was open for too long.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpiredDispute {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Decimal,
+    // RFC 3339 in UTC
+    pub disputed_at: String,
+    // `resolved` or `chargedback`
+    pub outcome: &'static str,
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Settle disputes opened more than `window` before `now` as `outcome`, like `force_settle`,
+    // as card networks don't let disputes stay open forever. Disputes without a timestamp, or
+    // restored from a snapshot before version 4, never expire. Disputes of clients in a group whose rule pack has a
+    // dispute window expire after that window instead. With calendars the window's days are
+    // business days of the client's calendar. Returns the settled disputes by client and
+    // transaction id.
+    pub fn expire_disputes(
+        &self,
+        now: Timestamp,
+        window: Duration,
+        outcome: TxStatus,
+    ) -> Vec<ExpiredDispute> {
+        // Copied, accounts aren't visited under the policies' lock
+        let policies = Policies {
+            groups: self.policies().groups,
+            ..Policies::default()
+        };
+        let mut stale = Vec::new();
+        self.accounts.for_each(|account_manager| {
+            let client_id = account_manager.account.client_id;
+            let group = policies.group(&client_id);
+            let window = group
+                .and_then(|group| group.pack.dispute_window)
+                .unwrap_or(window);
+            let calendar = self
+                .calendars
+                .as_ref()
+                .map(|calendars| calendars.get(group.map(|group| group.name.as_str())));
+            let expired = |disputed_at: &Timestamp| match calendar {
+                Some(calendar) => calendar
+                    .add(*disputed_at, window)
+                    .is_some_and(|due| due <= now),
+                None => now
+                    .0
+                    .checked_sub(window)
+                    .is_some_and(|cutoff| disputed_at.0 <= cutoff),
+            };
+            for (transaction_id, details) in &account_manager.transactions {
+                if details.status != TxStatus::Disputed {
+                    continue;
+                }
+                if let Some(disputed_at) = details.disputed_at.filter(expired) {
+                    stale.push(ExpiredDispute {
+                        client: account_manager.account.client_id,
+                        tx: *transaction_id,
+                        amount: details.amount.to_decimal(),
+                        disputed_at: disputed_at.to_string(),
+                        outcome: outcome.as_str(),
+                    });
+                }
+            }
+        });
+        stale.sort_by_key(|dispute| (dispute.client, dispute.tx));
+
+        // Collected first, settling takes the account's lock again
+        stale.retain(|dispute| {
+            matches!(
+                self.force_settle(dispute.client, dispute.tx, outcome),
+                Some(Ok(()))
+            )
+        });
+        stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_expire_disputes() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,2024-01-01\n\
+                     deposit,1,2,3.0,2024-01-01\n\
+                     deposit,2,3,2.0,2024-01-01\n\
+                     dispute,1,1,,2024-01-02\n\
+                     dispute,1,2,,2024-03-01\n\
+                     dispute,2,3,,\n";
+        let mut engine: Engine = Engine::new();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        let client = |id: &str| -> ClientId { id.parse().unwrap() };
+        let now = "2024-03-15".parse().unwrap();
+        let expired = engine.expire_disputes(
+            now,
+            Duration::from_secs(30 * 24 * 3600),
+            TxStatus::ChargedBack,
+        );
+        // Transaction 2 was disputed recently, 3 without a timestamp
+        assert_eq!(
+            expired,
+            [ExpiredDispute {
+                client: client("1"),
+                tx: 1,
+                amount: Decimal::from(5),
+                disputed_at: "2024-01-02T00:00:00Z".to_string(),
+                outcome: "chargedback",
+            }]
+        );
+        let account = engine.account(client("1")).unwrap();
+        assert_eq!((account.held, account.locked), (Decimal::from(3), true));
+    }
+}
@@ -0,0 +1,146 @@
+use std::str::FromStr;
+
+use log::info;
+use rust_decimal::Decimal;
+
+use super::account_manager::{AccountManager, AccountManagerError};
+use super::amount::Amount;
+use super::engine::Engine;
+use super::journal::Book;
+use super::store::AccountStore;
+use super::transaction::TransactionId;
+
+// Fee charged when an account's total is below `below`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeTier {
+    pub below: Decimal,
+    pub fee: Decimal,
+}
+
+// Maintenance fees charged at every period close, by the tier of the account's total. An account
+// pays the fee of the lowest tier its total is below, accounts above all tiers pay nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceFees {
+    tiers: Vec<FeeTier>,
+}
+
+impl MaintenanceFees {
+    pub fn new(mut tiers: Vec<FeeTier>) -> MaintenanceFees {
+        tiers.sort_by_key(|tier| tier.below);
+        MaintenanceFees { tiers }
+    }
+
+    // A single fee for accounts below a minimum balance.
+    pub fn minimum_balance(minimum_balance: Decimal, fee: Decimal) -> MaintenanceFees {
+        MaintenanceFees::new(vec![FeeTier {
+            below: minimum_balance,
+            fee,
+        }])
+    }
+
+    pub fn fee(&self, total: Decimal) -> Option<Decimal> {
+        self.tiers
+            .iter()
+            .find(|tier| total < tier.below)
+            .map(|tier| tier.fee)
+            .filter(|fee| *fee > Decimal::ZERO)
+    }
+}
+
+// Tiers as `<below>:<fee>` separated by commas, e.g. `100:5,1000:2`.
+impl FromStr for MaintenanceFees {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<MaintenanceFees, ()> {
+        let tiers = s
+            .split(',')
+            .map(|tier| {
+                let (below, fee) = tier.split_once(':').ok_or(())?;
+                Ok(FeeTier {
+                    below: below.trim().parse().map_err(drop)?,
+                    fee: fee.trim().parse().map_err(drop)?,
+                })
+            })
+            .collect::<Result<Vec<_>, ()>>()?;
+        if tiers.iter().any(|tier| tier.fee.is_sign_negative()) {
+            return Err(());
+        }
+        Ok(MaintenanceFees::new(tiers))
+    }
+}
+
+impl<A: Amount> AccountManager<A> {
+    // Charge a fee from available funds to fee income, also if it leaves available funds
+    // negative.
+    pub fn charge_fee(
+        &mut self,
+        transaction_id: TransactionId,
+        fee: A,
+    ) -> Result<(), AccountManagerError> {
+        if self.account.closed {
+            return Err(AccountManagerError::AccountClosed);
+        }
+        self.post(transaction_id, Book::ClientAvailable, Book::FeeIncome, fee)
+    }
+
+    // Id for a transaction generated by the engine, the highest one the client doesn't have.
+    fn generated_transaction_id(&self) -> TransactionId {
+        (0..=TransactionId::MAX)
+            .rev()
+            .find(|transaction_id| !self.transactions.contains_key(transaction_id))
+            .unwrap_or_default()
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Charge the maintenance fees of `EngineBuilder::maintenance_fees` to every open account,
+    // each as a generated withdrawal into fee income in the journal. Returns the number of
+    // charged accounts and the fees charged.
+    pub(super) fn charge_maintenance_fees(&self) -> (usize, Decimal) {
+        let Some(fees) = &self.maintenance_fees else {
+            return (0, Decimal::ZERO);
+        };
+        let (mut charged, mut income) = (0, Decimal::ZERO);
+        self.accounts.for_each_mut(|account_manager| {
+            let total = account_manager.account.calculate_total().to_decimal();
+            let Some(fee) = fees.fee(total).and_then(A::from_decimal) else {
+                return;
+            };
+            let transaction_id = account_manager.generated_transaction_id();
+            account_manager.postings.clear();
+            if account_manager.charge_fee(transaction_id, fee).is_ok() {
+                self.record_postings(account_manager);
+                info!(
+                    target: "audit",
+                    "Charged maintenance fee {} to client {} as transaction {}",
+                    fee.to_decimal(),
+                    account_manager.account.client_id,
+                    transaction_id
+                );
+                charged += 1;
+                income += fee.to_decimal();
+            }
+            account_manager.postings.clear();
+        });
+        (charged, income)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_tiers() {
+        let fees: MaintenanceFees = "1000:2, 100:5".parse().unwrap();
+        assert_eq!(fees.fee(Decimal::from(50)), Some(Decimal::from(5)));
+        assert_eq!(fees.fee(Decimal::from(100)), Some(Decimal::from(2)));
+        assert_eq!(fees.fee(Decimal::from(1000)), None);
+        assert_eq!(
+            MaintenanceFees::minimum_balance(Decimal::from(10), Decimal::ONE),
+            "10:1".parse().unwrap()
+        );
+        assert!("10".parse::<MaintenanceFees>().is_err());
+        assert!("10:-1".parse::<MaintenanceFees>().is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use super::account::Account;
+use super::amount::Amount;
+use super::query::AccountQuery;
+use super::transaction::{ClientId, Timestamp, Transaction, TransactionType};
+
+// Clients given as a list of ids and inclusive ranges, e.g. `1,5,100-200`. Ranges follow the
+// order of client ids, which is lexicographic for alphanumeric ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRanges(Vec<RangeInclusive<ClientId>>);
+
+impl ClientRanges {
+    pub fn contains(&self, client_id: &ClientId) -> bool {
+        self.0.iter().any(|range| range.contains(client_id))
+    }
+}
+
+impl FromStr for ClientRanges {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ClientRanges, ()> {
+        s.split(',')
+            .map(str::trim)
+            .map(|item| {
+                // Alphanumeric ids may contain a dash themselves, an id takes precedence
+                if let Ok(client_id) = item.parse::<ClientId>() {
+                    return Ok(client_id..=client_id);
+                }
+                let (start, end) = item.split_once('-').ok_or(())?;
+                Ok(start.trim().parse().map_err(drop)?..=end.trim().parse().map_err(drop)?)
+            })
+            .collect::<Result<_, ()>>()
+            .map(ClientRanges)
+    }
+}
+
+// Selection of accounts to include in a report. The default filter matches every account but
+// dormant ones.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccountFilter {
+    pub clients: Option<ClientRanges>,
+    pub only_locked: bool,
+    // Minimum total balance
+    pub min_balance: Option<Decimal>,
+    // Include dormant accounts, see `EngineBuilder::dormancy`
+    pub include_dormant: bool,
+    // Accounts the query doesn't evaluate to true on, e.g. `locked + 1`, don't match
+    pub query: Option<AccountQuery>,
+}
+
+impl AccountFilter {
+    pub fn matches<A: Amount>(&self, account: &Account<A>) -> bool {
+        self.clients
+            .as_ref()
+            .is_none_or(|clients| clients.contains(&account.client_id))
+            && (!self.only_locked || account.locked)
+            && (self.include_dormant || !account.dormant)
+            && self
+                .min_balance
+                .is_none_or(|min_balance| account.calculate_total().to_decimal() >= min_balance)
+            && self
+                .query
+                .as_ref()
+                .is_none_or(|query| query.matches(account) == Ok(true))
+    }
+}
+
+// Selection of input transactions to process, applied before the engine. The default filter
+// matches every transaction.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TransactionFilter {
+    pub types: Option<Vec<TransactionType>>,
+    // Inclusive start and exclusive end of the time range. Transactions without a timestamp don't
+    // match a time range.
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+}
+
+impl TransactionFilter {
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        if !self
+            .types
+            .as_ref()
+            .is_none_or(|types| types.contains(&transaction.r#type))
+        {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        transaction.timestamp().is_some_and(|timestamp| {
+            self.from.is_none_or(|from| timestamp >= from)
+                && self.to.is_none_or(|to| timestamp < to)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Metadata, TIMESTAMP_COLUMN};
+
+    fn account(client_id: &str, total: i64, locked: bool) -> Account<Decimal> {
+        Account {
+            available: Decimal::from(total),
+            locked,
+            ..Account::new(client_id.parse().unwrap())
+        }
+    }
+
+    // Ranges of alphanumeric ids compare lexicographically
+    #[cfg(not(feature = "client-id-string"))]
+    #[test]
+    fn test_client_ranges() {
+        let clients: ClientRanges = "1, 5,100-200".parse().unwrap();
+
+        let matching: Vec<u16> = (0..300)
+            .filter(|id| clients.contains(&id.to_string().parse().unwrap()))
+            .collect();
+        assert_eq!(matching.len(), 103);
+        assert_eq!(matching[..3], [1, 5, 100]);
+        assert_eq!(matching.last(), Some(&200));
+        assert!("1,,2".parse::<ClientRanges>().is_err());
+    }
+
+    #[test]
+    fn test_transaction_filter() {
+        let transaction = |r#type, timestamp: Option<&str>| Transaction {
+            r#type,
+            client_id: "1".parse().unwrap(),
+            transaction_id: 1,
+            amount: Some(Decimal::ONE),
+            metadata: timestamp
+                .map(|t| Metadata::from([(TIMESTAMP_COLUMN.to_string(), t.to_string())]))
+                .unwrap_or_default(),
+        };
+        let filter = TransactionFilter {
+            types: Some(vec![TransactionType::Deposit]),
+            from: "2024-03-01".parse().ok(),
+            to: "2024-04-01".parse().ok(),
+        };
+
+        assert!(filter.matches(&transaction(TransactionType::Deposit, Some("2024-03-01"))));
+        assert!(filter.matches(&transaction(
+            TransactionType::Deposit,
+            Some("2024-03-31T23:59:59Z")
+        )));
+        assert!(!filter.matches(&transaction(TransactionType::Deposit, Some("2024-04-01"))));
+        assert!(!filter.matches(&transaction(TransactionType::Withdraw, Some("2024-03-02"))));
+        assert!(!filter.matches(&transaction(TransactionType::Deposit, None)));
+        assert!(TransactionFilter::default().matches(&transaction(TransactionType::Withdraw, None)));
+    }
+
+    #[test]
+    fn test_account_filter() {
+        let accounts = [
+            account("1", 10, false),
+            account("2", 50, true),
+            account("3", 100, true),
+        ];
+        let filter = AccountFilter {
+            clients: Some("2,3".parse().unwrap()),
+            only_locked: true,
+            min_balance: Some(Decimal::from(60)),
+            include_dormant: false,
+            query: None,
+        };
+
+        let matching: Vec<_> = accounts.iter().filter(|a| filter.matches(a)).collect();
+        assert_eq!(matching, [&accounts[2]]);
+        let filter = AccountFilter {
+            query: Some("total < 60 AND locked OR client = 1".parse().unwrap()),
+            ..AccountFilter::default()
+        };
+        let matching: Vec<_> = accounts.iter().filter(|a| filter.matches(a)).collect();
+        assert_eq!(matching, [&accounts[0], &accounts[1]]);
+        assert!(accounts.iter().all(|a| AccountFilter::default().matches(a)));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::debug;
+
+use super::account::Account;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Timestamp};
+
+// Receives the accounts finalized before the end of the input.
+type FinalizedSink<A> = Box<dyn FnMut(Account<A>) + Send>;
+
+// Accounts handed out and dropped once idle, see `EngineBuilder::finalize_idle_accounts`.
+pub(super) struct Finalizer<A> {
+    horizon: Duration,
+    state: Mutex<FinalizerState<A>>,
+}
+
+struct FinalizerState<A> {
+    sink: FinalizedSink<A>,
+    last_sweep: Option<Timestamp>,
+    finalized: HashSet<ClientId>,
+}
+
+impl<A> Finalizer<A> {
+    pub(super) fn new(horizon: Duration, sink: FinalizedSink<A>) -> Finalizer<A> {
+        Finalizer {
+            horizon,
+            state: Mutex::new(FinalizerState {
+                sink,
+                last_sweep: None,
+                finalized: HashSet::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FinalizerState<A>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(super) fn is_finalized(&self, client_id: &ClientId) -> bool {
+        self.state().finalized.contains(client_id)
+    }
+
+    // Accounts last active before the returned time are idle, once a horizon passed on the
+    // engine's clock since the previous sweep.
+    fn due(&self, now: Option<Timestamp>) -> Option<Timestamp> {
+        let mut state = self.state();
+        let now = now?;
+        let last_sweep = *state.last_sweep.get_or_insert(now);
+        if now.0 < last_sweep.0 + self.horizon {
+            return None;
+        }
+        state.last_sweep = Some(now);
+        Some(Timestamp(now.0.checked_sub(self.horizon)?))
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Hand out and drop the accounts idle for longer than the horizon, which have no open
+    // disputes, if a sweep is due. Only called between transactions of a batch, so no
+    // transaction of a dropped account is in flight.
+    pub(super) fn finalize_idle(&mut self) {
+        let Some(finalizer) = &self.finalizer else {
+            return;
+        };
+        let Some(cutoff) = finalizer.due(self.clock.now()) else {
+            return;
+        };
+
+        let mut idle = Vec::new();
+        self.accounts.for_each(|account_manager| {
+            let open_disputes = !account_manager.suspense.is_empty()
+                || !account_manager.queued.is_empty()
+                || account_manager
+                    .transactions
+                    .values()
+                    .any(|details| details.status.is_open_dispute());
+            if account_manager
+                .last_activity
+                .is_some_and(|last_activity| last_activity < cutoff)
+                && !open_disputes
+            {
+                idle.push(account_manager.account.client_id);
+            }
+        });
+
+        let mut state = finalizer.state();
+        for client_id in &idle {
+            if let Some(account_manager) = self.accounts.remove(*client_id) {
+                state.finalized.insert(*client_id);
+                (state.sink)(account_manager.account);
+            }
+        }
+        debug!("Finalized {} idle accounts", idle.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::{EngineError, ErrorCode, Transaction};
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_finalizes_idle_accounts() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,2024-01-01\n\
+                     deposit,2,2,3.0,2024-01-01\n\
+                     dispute,2,2,,2024-01-02\n\
+                     deposit,3,3,1.0,2024-01-20\n\
+                     deposit,3,4,1.0,2024-02-16\n";
+        let finalized = Arc::new(Mutex::new(Vec::new()));
+        let mut engine: Engine = Engine::builder()
+            .finalize_idle_accounts(Duration::from_secs(30 * 24 * 3600), {
+                let finalized = Arc::clone(&finalized);
+                move |account| finalized.lock().unwrap().push(account)
+            })
+            .build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        // Client 2 has an open dispute, client 3 was active within the horizon
+        let client = |id: &str| -> ClientId { id.parse().unwrap() };
+        let finalized = finalized.lock().unwrap();
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(
+            (finalized[0].client_id, finalized[0].available),
+            (client("1"), Decimal::from(5))
+        );
+        let mut remaining: Vec<_> = engine
+            .accounts()
+            .unwrap()
+            .iter()
+            .map(|account| account.client_id)
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, [client("2"), client("3")]);
+
+        let error = engine
+            .process(Transaction {
+                r#type: crate::engine::TransactionType::Deposit,
+                client_id: client("1"),
+                transaction_id: 5,
+                amount: Some(Decimal::ONE),
+                metadata: Default::default(),
+            })
+            .unwrap_err();
+        assert_eq!(error, EngineError::AccountFinalized);
+        assert_eq!(error.code(), ErrorCode::AccountFinalized);
+    }
+}
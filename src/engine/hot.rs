@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::ClientId;
+
+// Transactions and processing time per client, to find the few clients dominating a run.
+// Rejected transactions count as well, they take time too.
+#[derive(Debug, Default)]
+pub struct HotAccounts {
+    clients: HashMap<ClientId, ClientLoad>,
+    transactions: u64,
+    busy: Duration,
+}
+
+#[derive(Debug, Default)]
+struct ClientLoad {
+    transactions: u64,
+    busy: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HotAccount {
+    pub client: ClientId,
+    pub transactions: u64,
+    // Fraction of all transactions
+    #[serde(serialize_with = "serialize_share")]
+    pub transaction_share: f64,
+    // Time spent processing the client's transactions in microseconds
+    pub busy_us: u64,
+    // Fraction of the time spent processing all transactions
+    #[serde(serialize_with = "serialize_share")]
+    pub busy_share: f64,
+}
+
+fn serialize_share<S: serde::Serializer>(share: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:.4}", share))
+}
+
+impl HotAccounts {
+    pub fn record(&mut self, client_id: ClientId, elapsed: Duration) {
+        let load = self.clients.entry(client_id).or_default();
+        load.transactions += 1;
+        load.busy += elapsed;
+        self.transactions += 1;
+        self.busy += elapsed;
+    }
+
+    // The `top_n` clients the most time was spent on, busiest first.
+    pub fn report(&self, top_n: usize) -> Vec<HotAccount> {
+        let mut clients: Vec<_> = self.clients.iter().collect();
+        clients.sort_by(|(a_id, a), (b_id, b)| b.busy.cmp(&a.busy).then(a_id.cmp(b_id)));
+        clients
+            .into_iter()
+            .take(top_n)
+            .map(|(client_id, load)| HotAccount {
+                client: *client_id,
+                transactions: load.transactions,
+                transaction_share: share(load.transactions as f64, self.transactions as f64),
+                busy_us: load.busy.as_micros() as u64,
+                busy_share: share(load.busy.as_nanos() as f64, self.busy.as_nanos() as f64),
+            })
+            .collect()
+    }
+}
+
+fn share(part: f64, total: f64) -> f64 {
+    if total == 0.0 {
+        0.0
+    } else {
+        part / total
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Clients the most processing time was spent on, None unless enabled with
+    // `EngineBuilder::hot_accounts`.
+    pub fn hot_accounts(&self, top_n: usize) -> Option<Vec<HotAccount>> {
+        let hot_accounts = self.hot_accounts.as_ref()?;
+        Some(
+            hot_accounts
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .report(top_n),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busiest_clients_first() {
+        let client = |id: u16| -> ClientId { id.to_string().parse().unwrap() };
+        let mut hot_accounts = HotAccounts::default();
+        hot_accounts.record(client(1), Duration::from_micros(10));
+        hot_accounts.record(client(2), Duration::from_micros(60));
+        hot_accounts.record(client(1), Duration::from_micros(30));
+        hot_accounts.record(client(3), Duration::from_micros(0));
+
+        let report = hot_accounts.report(2);
+        assert_eq!(
+            report,
+            [
+                HotAccount {
+                    client: client(2),
+                    transactions: 1,
+                    transaction_share: 0.25,
+                    busy_us: 60,
+                    busy_share: 0.6,
+                },
+                HotAccount {
+                    client: client(1),
+                    transactions: 2,
+                    transaction_share: 0.5,
+                    busy_us: 40,
+                    busy_share: 0.4,
+                },
+            ]
+        );
+    }
+}
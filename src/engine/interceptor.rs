@@ -0,0 +1,117 @@
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::{Engine, EngineError};
+use super::store::AccountStore;
+use super::transaction::Transaction;
+
+// Hooks of an embedding application around every transaction, e.g. sanctions screening, without
+// changing the engine. Registered with `EngineBuilder::interceptor`.
+//
+// Both hooks run under the account's lock, in input order for each client, so they must not
+// process transactions of the engine themselves.
+pub trait TransactionInterceptor: Send + Sync {
+    // Before the transaction is applied. It may be changed, e.g. annotated with metadata which is
+    // stored with deposits, or rejected with a reason, failing with `EngineError::Intercepted`
+    // without reaching later interceptors.
+    fn before_apply(&self, _transaction: &mut Transaction) -> Result<(), String> {
+        Ok(())
+    }
+
+    // After the transaction was applied or rejected, with the transaction as it was applied.
+    fn after_apply(&self, _transaction: &Transaction, _result: &Result<(), EngineError>) {}
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    pub(super) fn process_intercepted(
+        &self,
+        account_manager: &mut AccountManager<A>,
+        mut transaction: Transaction,
+    ) -> Result<(), EngineError> {
+        let rejection = self
+            .interceptors
+            .iter()
+            .find_map(|interceptor| interceptor.before_apply(&mut transaction).err());
+        let result = match rejection {
+            Some(reason) => Err(EngineError::Intercepted(reason)),
+            None => self.process_locked(account_manager, transaction.clone()),
+        };
+        for interceptor in &self.interceptors {
+            interceptor.after_apply(&transaction, &result);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::{ClientId, ErrorCode};
+    use crate::parser::SerdeParser;
+
+    // Rejects deposits above a limit and tags the ones it screened
+    struct Screening {
+        limit: Decimal,
+        outcomes: Arc<Mutex<Vec<Option<ErrorCode>>>>,
+    }
+
+    impl TransactionInterceptor for Screening {
+        fn before_apply(&self, transaction: &mut Transaction) -> Result<(), String> {
+            if transaction.amount.is_some_and(|amount| amount > self.limit) {
+                return Err(format!("amount above {}", self.limit));
+            }
+            transaction
+                .metadata
+                .insert("screened".to_string(), "true".to_string());
+            Ok(())
+        }
+
+        fn after_apply(&self, _transaction: &Transaction, result: &Result<(), EngineError>) {
+            let code = result.as_ref().err().map(EngineError::code);
+            self.outcomes.lock().unwrap().push(code);
+        }
+    }
+
+    #[test]
+    fn test_interceptor() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,500.0\n\
+                     withdrawal,1,3,9.0\n";
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let mut engine: Engine = Engine::builder()
+            .interceptor(Screening {
+                limit: Decimal::from(100),
+                outcomes: outcomes.clone(),
+            })
+            .build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            *outcomes.lock().unwrap(),
+            [
+                None,
+                Some(ErrorCode::Intercepted),
+                Some(ErrorCode::InsufficientFunds)
+            ]
+        );
+        let client_id: ClientId = "1".parse().unwrap();
+        assert_eq!(
+            engine.account(client_id).unwrap().available,
+            Decimal::from(5)
+        );
+        assert_eq!(
+            engine
+                .transaction_metadata(client_id, 1)
+                .and_then(|metadata| metadata.get("screened").cloned()),
+            Some("true".to_string())
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
+
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, TransactionId};
+
+// Internal accounts of a client's books. Funds only ever move between them, so the balances of
+// all books of a client always sum to zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Book {
+    // Counterpart of funds entering and leaving the engine, deposits and withdrawals
+    Settlement,
+    ClientAvailable,
+    ClientHeld,
+    ChargebackLoss,
+    FeeIncome,
+    // Part of deposits held back by the rolling reserve, see `EngineBuilder::rolling_reserve`
+    ClientReserve,
+}
+
+impl Book {
+    pub const ALL: [Book; 6] = [
+        Book::Settlement,
+        Book::ClientAvailable,
+        Book::ClientHeld,
+        Book::ChargebackLoss,
+        Book::FeeIncome,
+        Book::ClientReserve,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Book::Settlement => "settlement",
+            Book::ClientAvailable => "client_available",
+            Book::ClientHeld => "client_held",
+            Book::ChargebackLoss => "chargeback_loss",
+            Book::FeeIncome => "fee_income",
+            Book::ClientReserve => "client_reserve",
+        }
+    }
+}
+
+impl FromStr for Book {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Book, ()> {
+        Book::ALL
+            .into_iter()
+            .find(|book| book.as_str() == s)
+            .ok_or(())
+    }
+}
+
+// Movement of `amount` out of the `debit` book into the `credit` book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posting<A> {
+    pub transaction_id: TransactionId,
+    pub debit: Book,
+    pub credit: Book,
+    pub amount: A,
+}
+
+// Posting of a client, as recorded in the engine journal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalEntry {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub debit: Book,
+    pub credit: Book,
+    pub amount: Decimal,
+}
+
+impl JournalEntry {
+    pub fn new<A: Amount>(client: ClientId, posting: &Posting<A>) -> JournalEntry {
+        JournalEntry {
+            client,
+            tx: posting.transaction_id,
+            debit: posting.debit,
+            credit: posting.credit,
+            amount: posting.amount.to_decimal(),
+        }
+    }
+}
+
+// Name of the account chargeback losses land in, unless configured with
+// `EngineBuilder::loss_account`.
+pub const DEFAULT_LOSS_ACCOUNT: &str = "chargeback_loss";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LossAccount {
+    pub name: String,
+    pub balance: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientLoss {
+    pub client: ClientId,
+    pub chargeback_loss: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientReserve {
+    pub client: ClientId,
+    pub reserve: Decimal,
+}
+
+// Where funds ended up beyond the client balances of the report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExtendedReport {
+    // Funds pulled from held by chargebacks
+    pub loss_account: LossAccount,
+    // Trial balance, the loss account under its configured name
+    pub books: BTreeMap<String, Decimal>,
+    // Clients with chargeback losses, by client id
+    pub losses: Vec<ClientLoss>,
+    // Clients with funds in the rolling reserve, by client id
+    pub reserves: Vec<ClientReserve>,
+}
+
+// Balances of a client's books, the result of all postings so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Books<A> {
+    balances: [A; Book::ALL.len()],
+}
+
+impl<A: Amount> Books<A> {
+    // Books holding the given client balances, funded from settlement, e.g. when restoring an
+    // account without its books.
+    pub fn opening(available: A, held: A) -> Option<Books<A>> {
+        let mut books = Books::default();
+        books.set(Book::ClientAvailable, available);
+        books.set(Book::ClientHeld, held);
+        books.set(
+            Book::Settlement,
+            A::default().checked_sub(available.checked_add(held)?)?,
+        );
+        Some(books)
+    }
+
+    pub fn balance(&self, book: Book) -> A {
+        self.balances[book as usize]
+    }
+
+    pub(super) fn set(&mut self, book: Book, balance: A) {
+        self.balances[book as usize] = balance;
+    }
+
+    // Books after the postings, None if a balance would overflow.
+    pub fn post(&self, postings: &[Posting<A>]) -> Option<Books<A>> {
+        let mut books = *self;
+        for posting in postings {
+            let (debit, credit) = (posting.debit as usize, posting.credit as usize);
+            books.balances[debit] = books.balances[debit].checked_sub(posting.amount)?;
+            books.balances[credit] = books.balances[credit].checked_add(posting.amount)?;
+        }
+        Some(books)
+    }
+
+    // Whether the balances sum to zero, None if the sum overflows.
+    pub fn is_balanced(&self) -> Option<bool> {
+        let sum = self
+            .balances
+            .iter()
+            .try_fold(A::default(), |sum, balance| sum.checked_add(*balance))?;
+        Some(sum == A::default())
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Books of all clients added up. Every balance in the report is derivable from the journal,
+    // and the trial balance sums to zero. None if a total overflows the amount type, which a
+    // single account's balances can fit while the sum over all clients doesn't.
+    pub fn trial_balance(&self) -> Option<Vec<(Book, A)>> {
+        let mut totals = Some(Book::ALL.map(|book| (book, A::default())));
+        self.accounts.for_each(|account_manager| {
+            let Some(books) = &mut totals else {
+                return;
+            };
+            for (book, total) in books.iter_mut() {
+                match total.checked_add(account_manager.books.balance(*book)) {
+                    Some(sum) => *total = sum,
+                    None => {
+                        totals = None;
+                        return;
+                    }
+                }
+            }
+        });
+        totals.map(|totals| totals.to_vec())
+    }
+
+    // None if the trial balance overflows, see `trial_balance`.
+    pub fn extended_report(&self) -> Option<ExtendedReport> {
+        let name = |book: Book| match book {
+            Book::ChargebackLoss => self.loss_account.clone(),
+            book => book.as_str().to_string(),
+        };
+        let trial_balance = self.trial_balance()?;
+        let (mut losses, mut reserves) = (Vec::new(), Vec::new());
+        self.accounts.for_each(|account_manager| {
+            let loss = account_manager.books.balance(Book::ChargebackLoss);
+            if loss != A::default() {
+                losses.push(ClientLoss {
+                    client: account_manager.account.client_id,
+                    chargeback_loss: loss.to_decimal(),
+                });
+            }
+            let reserve = account_manager.account.reserve;
+            if reserve != A::default() {
+                reserves.push(ClientReserve {
+                    client: account_manager.account.client_id,
+                    reserve: reserve.to_decimal(),
+                });
+            }
+        });
+        losses.sort_by_key(|loss| loss.client);
+        reserves.sort_by_key(|reserve| reserve.client);
+
+        Some(ExtendedReport {
+            loss_account: LossAccount {
+                name: self.loss_account.clone(),
+                balance: trial_balance
+                    .iter()
+                    .find(|(book, _)| *book == Book::ChargebackLoss)
+                    .map_or(Decimal::ZERO, |(_, balance)| balance.to_decimal()),
+            },
+            books: trial_balance
+                .into_iter()
+                .map(|(book, balance)| (name(book), balance.to_decimal()))
+                .collect(),
+            losses,
+            reserves,
+        })
+    }
+
+    pub(super) fn record_postings(&self, account_manager: &AccountManager<A>) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let mut journal = journal.lock().unwrap_or_else(|e| e.into_inner());
+        for posting in &account_manager.postings {
+            let entry = JournalEntry::new(account_manager.account.client_id, posting);
+            if let Err(e) = journal.serialize(entry) {
+                warn!("Error writing journal: {}", e);
+            }
+        }
+    }
+
+    pub fn flush_journal(&self) -> io::Result<()> {
+        match &self.journal {
+            Some(journal) => journal.lock().unwrap_or_else(|e| e.into_inner()).flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::amount::MinorUnits;
+
+    #[test]
+    fn test_postings_balance() {
+        let posting = |debit, credit, amount| Posting {
+            transaction_id: 1,
+            debit,
+            credit,
+            amount: Decimal::from(amount),
+        };
+        let books = Books::opening(Decimal::from(5), Decimal::ZERO)
+            .unwrap()
+            .post(&[
+                posting(Book::Settlement, Book::ClientAvailable, 10),
+                posting(Book::ClientAvailable, Book::ClientHeld, 3),
+                posting(Book::ClientHeld, Book::ChargebackLoss, 3),
+            ])
+            .unwrap();
+
+        assert_eq!(books.balance(Book::ClientAvailable), Decimal::from(12));
+        assert_eq!(books.balance(Book::ClientHeld), Decimal::ZERO);
+        assert_eq!(books.balance(Book::ChargebackLoss), Decimal::from(3));
+        assert_eq!(books.balance(Book::Settlement), Decimal::from(-15));
+        assert_eq!(books.is_balanced(), Some(true));
+        assert_eq!("chargeback_loss".parse(), Ok(Book::ChargebackLoss));
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_engine_journal() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     withdrawal,1,2,1.5\n\
+                     withdrawal,1,3,10.0\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n";
+        let buffer = SharedBuffer::default();
+        let engine: Engine = Engine::builder().journal(buffer.clone()).build();
+        csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<crate::engine::Transaction>()
+            .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+        engine.flush_journal().unwrap();
+
+        let journal = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            journal,
+            "client,tx,debit,credit,amount\n\
+             1,1,settlement,client_available,5\n\
+             1,2,client_available,settlement,1.5\n\
+             1,1,client_available,client_held,5\n\
+             1,1,client_held,chargeback_loss,5\n"
+        );
+        let report = engine.extended_report().unwrap();
+        assert_eq!(report.loss_account.name, DEFAULT_LOSS_ACCOUNT);
+        assert_eq!(report.loss_account.balance, Decimal::from(5));
+        assert_eq!(
+            report.losses,
+            [ClientLoss {
+                client: "1".parse().unwrap(),
+                chargeback_loss: Decimal::from(5),
+            }]
+        );
+        let trial_balance = engine.trial_balance().unwrap();
+        assert_eq!(
+            trial_balance,
+            [
+                (Book::Settlement, Decimal::new(-35, 1)),
+                (Book::ClientAvailable, Decimal::new(-15, 1)),
+                (Book::ClientHeld, Decimal::ZERO),
+                (Book::ChargebackLoss, Decimal::from(5)),
+                (Book::FeeIncome, Decimal::ZERO),
+                (Book::ClientReserve, Decimal::ZERO),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trial_balance_overflow() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,900000000000000\n\
+                     deposit,2,2,900000000000000\n";
+        let engine: Engine<MinorUnits> = Engine::new();
+        for transaction in csv::Reader::from_reader(input.as_bytes()).deserialize() {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        // Each client's books fit, their sum doesn't
+        assert_eq!(engine.trial_balance(), None);
+        assert!(engine.extended_report().is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use rust_decimal::Decimal;
+
+use super::amount::Amount;
+use super::engine::{Engine, EngineError};
+use super::store::{AccountStore, DashMapStore};
+use super::transaction::{Transaction, TransactionType};
+
+// Metadata column naming the ledger of a transaction, the default ledger if missing.
+pub const LEDGER_COLUMN: &str = "ledger";
+// Metadata column of a withdrawal naming the ledger the funds are transferred to.
+pub const TO_LEDGER_COLUMN: &str = "to_ledger";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum LedgerError {
+    #[error("Unknown ledger: {0}")]
+    UnknownLedger(String),
+    #[error(transparent)]
+    EngineError(#[from] EngineError),
+}
+
+// Independent named ledgers (books), each an engine with its own accounts, e.g. "live", "test"
+// and "fees".
+//
+// Transactions are routed by their `ledger` column. A withdrawal with a `to_ledger` column is a
+// transfer of the client's funds between ledgers: the balancing deposit with the same transaction
+// id is booked in the target ledger, and the transfer is rejected as a whole if either side is.
+pub struct Ledgers<A = Decimal, S = DashMapStore<A>> {
+    // In configuration order, the first one is the default
+    ledgers: Vec<(String, Engine<A, S>)>,
+}
+
+impl<A: Amount, S: AccountStore<A>> Ledgers<A, S> {
+    // None without any ledger.
+    pub fn new<I>(ledgers: I) -> Option<Ledgers<A, S>>
+    where
+        I: IntoIterator<Item = (String, Engine<A, S>)>,
+    {
+        let ledgers: Vec<_> = ledgers.into_iter().collect();
+        (!ledgers.is_empty()).then_some(Ledgers { ledgers })
+    }
+
+    pub fn ledger(&self, name: &str) -> Option<&Engine<A, S>> {
+        self.ledgers
+            .iter()
+            .find(|(ledger, _)| ledger == name)
+            .map(|(_, engine)| engine)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Engine<A, S>)> {
+        self.ledgers
+            .iter()
+            .map(|(name, engine)| (name.as_str(), engine))
+    }
+
+    fn route(&self, name: Option<&String>) -> Result<&Engine<A, S>, LedgerError> {
+        match name {
+            None => Ok(&self.ledgers[0].1),
+            Some(name) => self
+                .ledger(name)
+                .ok_or_else(|| LedgerError::UnknownLedger(name.clone())),
+        }
+    }
+
+    pub fn process(&self, transaction: Transaction) -> Result<(), LedgerError> {
+        let engine = self.route(transaction.metadata.get(LEDGER_COLUMN))?;
+        let target = match transaction.metadata.get(TO_LEDGER_COLUMN) {
+            Some(name) if transaction.r#type == TransactionType::Withdraw => {
+                Some(self.route(Some(name))?)
+            }
+            _ => None,
+        };
+        let Some(target) = target else {
+            return Ok(engine.process(transaction)?);
+        };
+
+        // The deposit can be undone as a reversal, unlike the withdrawal
+        let (client_id, transaction_id) = (transaction.client_id, transaction.transaction_id);
+        let amount = transaction
+            .get_amount_or_error::<A>()
+            .map_err(EngineError::from)?;
+        target.process(Transaction {
+            r#type: TransactionType::Deposit,
+            ..transaction.clone()
+        })?;
+        if let Err(e) = engine.process(transaction) {
+            target
+                .accounts
+                .with_account(client_id, |account_manager| {
+                    account_manager.reverse(transaction_id, amount)
+                })
+                .map_err(EngineError::from)?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{AccountManagerError, ClientId};
+    use crate::parser::ByteRecordParser;
+
+    fn ledgers() -> Ledgers {
+        Ledgers::new(["live", "fees"].map(|name| (name.to_string(), Engine::new()))).unwrap()
+    }
+
+    fn available(ledgers: &Ledgers, ledger: &str) -> Option<Decimal> {
+        let client_id: ClientId = "1".parse().unwrap();
+        ledgers
+            .ledger(ledger)?
+            .account(client_id)
+            .map(|account| account.available)
+    }
+
+    #[test]
+    fn test_routing_and_transfers() {
+        let input = "type,client,tx,amount,ledger,to_ledger\n\
+                     deposit,1,1,10.0,,\n\
+                     deposit,1,2,1.0,fees,\n\
+                     withdrawal,1,3,4.0,live,fees\n\
+                     withdrawal,1,4,7.0,live,fees\n\
+                     deposit,1,5,1.0,test,\n";
+        let ledgers = ledgers();
+        let results: Vec<_> = ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .map(|transaction| ledgers.process(transaction.unwrap()))
+            .collect();
+
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(EngineError::from(AccountManagerError::InsufficientFunds).into()),
+                Err(LedgerError::UnknownLedger("test".to_string())),
+            ]
+        );
+        assert_eq!(available(&ledgers, "live"), Some(Decimal::from(6)));
+        // The rejected transfer's deposit is undone
+        assert_eq!(available(&ledgers, "fees"), Some(Decimal::from(5)));
+    }
+}
@@ -0,0 +1,103 @@
+use std::mem;
+
+use log::{info, warn};
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Transaction};
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Transactions still queued on locked accounts, in client order and in the order they
+    // arrived for each client.
+    pub fn queued(&self) -> Vec<Transaction> {
+        let mut queued = Vec::new();
+        self.accounts.for_each(|account_manager| {
+            queued.extend(account_manager.queued.iter().cloned());
+        });
+        queued.sort_by_key(|transaction| transaction.client_id);
+        queued
+    }
+
+    // Replay the transactions queued while the account was locked, in order, after it was
+    // unlocked. A replayed transaction locking the account again queues the rest. Returns the
+    // number of replayed transactions.
+    pub(super) fn replay_queued(&self, client_id: ClientId) -> usize {
+        let queued = self.accounts.with_account(client_id, |account_manager| {
+            mem::take(&mut account_manager.queued)
+        });
+        let replayed = queued.len();
+        for transaction in queued {
+            let transaction_id = transaction.transaction_id;
+            if let Err(e) = self.process(transaction) {
+                warn!(
+                    "Error replaying queued transaction {} of client {}: {}",
+                    transaction_id, client_id, e
+                );
+            }
+        }
+        if replayed > 0 {
+            info!(
+                target: "audit",
+                "Replayed {} queued transactions of client {}",
+                replayed,
+                client_id
+            );
+        }
+        replayed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::engine::{ClientId, Engine, EngineError, ErrorCode, Transaction};
+
+    #[test]
+    fn test_locked_queue_replay() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,3.0\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n\
+                     deposit,1,3,4.0\n\
+                     dispute,1,3,\n";
+        let engine: Engine = Engine::builder().queue_locked().build();
+        let results: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|transaction| engine.process(transaction.unwrap()))
+            .collect();
+        assert_eq!(results[4], Err(EngineError::TransactionQueued));
+        assert_eq!(
+            results[5].as_ref().unwrap_err().code(),
+            ErrorCode::TransactionQueued
+        );
+
+        let client_id: ClientId = "1".parse().unwrap();
+        let queued = engine.queued();
+        assert_eq!(
+            queued.iter().map(|t| t.transaction_id).collect::<Vec<_>>(),
+            [3, 3]
+        );
+        assert_eq!(
+            engine.account(client_id).unwrap().available,
+            Decimal::from(5)
+        );
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::builder().queue_locked().build();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.queued(), queued);
+
+        // The deposit lands before its dispute
+        assert_eq!(restored.unlock(client_id), Some(true));
+        assert!(restored.queued().is_empty());
+        let account = restored.account(client_id).unwrap();
+        assert_eq!(
+            (account.available, account.held, account.locked),
+            (Decimal::from(5), Decimal::from(4), false)
+        );
+    }
+}
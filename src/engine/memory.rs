@@ -0,0 +1,121 @@
+use std::mem::size_of;
+
+use serde::Serialize;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::journal::Posting;
+use super::notes::Note;
+use super::reserve::Reserve;
+use super::store::AccountStore;
+use super::structuring::NearThreshold;
+use super::transaction::{ClientId, Transaction, TransactionDetails, TransactionId};
+
+// Rough per-entry overhead of a BTreeMap entry of the metadata, beyond the key and value bytes
+const METADATA_ENTRY_OVERHEAD: usize = 2 * size_of::<String>() + 16;
+
+// Memory held by the accounts of an engine. Accounts a store keeps outside of memory, like
+// spilled ones, aren't counted.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct MemoryStats {
+    pub accounts: usize,
+    // Stored transactions which can still be disputed or are in a dispute
+    pub transactions: usize,
+    pub metadata_entries: usize,
+    // Estimate from the sizes and capacities of the account structures, allocator overhead and
+    // the engine's other state, e.g. analytics, aren't included
+    pub estimated_bytes: usize,
+    // Accounts per shard of the store
+    pub shards: Vec<usize>,
+}
+
+impl<A: Amount> AccountManager<A> {
+    // Bytes held by the account manager, from the capacity of its collections. Hash tables are
+    // counted with a control byte per slot.
+    pub fn estimated_bytes(&self) -> usize {
+        let metadata: usize = self
+            .transactions
+            .values()
+            .flat_map(|details| &details.metadata)
+            .map(|(key, value)| key.capacity() + value.capacity() + METADATA_ENTRY_OVERHEAD)
+            .sum();
+        size_of::<(ClientId, AccountManager<A>)>()
+            + self.transactions.capacity()
+                * (size_of::<(TransactionId, TransactionDetails<A>)>() + 1)
+            + metadata
+            + self.suspense.capacity() * (size_of::<TransactionId>() + 1)
+            + self.delivered.capacity() * (size_of::<u64>() + 1)
+            + self.postings.capacity() * size_of::<Posting<A>>()
+            + self.reserves.capacity() * size_of::<Reserve<A>>()
+            + self.queued.capacity() * size_of::<Transaction>()
+            + (self.near_threshold.capacity() + self.structuring.capacity())
+                * size_of::<NearThreshold>()
+            + self.notes.capacity() * size_of::<Note>()
+            + self
+                .notes
+                .iter()
+                .map(|note| note.text.capacity() + note.case.as_ref().map_or(0, String::capacity))
+                .sum::<usize>()
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Walks all accounts in memory, taking their locks one by one like `stats`.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        self.accounts.for_each_resident(|account_manager| {
+            stats.accounts += 1;
+            stats.transactions += account_manager.transactions.len();
+            stats.metadata_entries += account_manager
+                .transactions
+                .values()
+                .map(|details| details.metadata.len())
+                .sum::<usize>();
+            stats.estimated_bytes += account_manager.estimated_bytes();
+        });
+        stats.shards = self.accounts.shard_sizes();
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::store::ShardedStore;
+    use crate::engine::EngineBuilder;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_memory_stats() {
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,5.0,rent\n\
+                     deposit,1,2,3.0,\n\
+                     deposit,2,3,1.0,\n";
+        let mut engine: Engine<Decimal, _> =
+            EngineBuilder::with_store(ShardedStore::new(4)).build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        let stats = engine.memory_stats();
+        assert_eq!(
+            (stats.accounts, stats.transactions, stats.metadata_entries),
+            (2, 3, 1)
+        );
+        assert_eq!(stats.shards.len(), 4);
+        assert_eq!(stats.shards.iter().sum::<usize>(), 2);
+        assert!(stats.estimated_bytes > 2 * size_of::<AccountManager<Decimal>>());
+    }
+
+    #[test]
+    fn test_account_map_shards() {
+        let engine: Engine = EngineBuilder::new().account_map(1000, Some(5)).build();
+        // Rounded up to a power of two
+        assert_eq!(engine.memory_stats().shards, [0; 8]);
+    }
+}
@@ -0,0 +1,154 @@
+use log::info;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Timestamp, Transaction, TransactionId};
+
+// Metadata columns of a `note` input row with the text of the note and the optional id of the
+// case it belongs to.
+pub const NOTE_COLUMN: &str = "note";
+pub const CASE_COLUMN: &str = "case";
+
+// Free-text note left on an account by an investigator, from a `note` input row or the admin
+// API. Notes don't change balances.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    // Transaction the note is about, the `tx` column of a `note` row
+    pub tx: Option<TransactionId>,
+    pub case: Option<String>,
+    pub text: String,
+    // From the timestamp column of a `note` row, or when it was added through the admin API
+    pub at: Option<Timestamp>,
+}
+
+impl Note {
+    // Note of a `note` input row, None without a text.
+    pub fn from_transaction(transaction: &Transaction) -> Option<Note> {
+        let text = transaction.metadata.get(NOTE_COLUMN)?;
+        if text.trim().is_empty() {
+            return None;
+        }
+        Some(Note {
+            tx: Some(transaction.transaction_id),
+            case: transaction
+                .metadata
+                .get(CASE_COLUMN)
+                .filter(|case| !case.is_empty())
+                .cloned(),
+            text: text.clone(),
+            at: transaction.timestamp(),
+        })
+    }
+}
+
+impl<A: Amount> AccountManager<A> {
+    pub fn add_note(&mut self, note: Note) {
+        info!(
+            target: "audit",
+            "Note on account of client {}{}: {}",
+            self.account.client_id,
+            note.case
+                .as_ref()
+                .map(|case| format!(" in case {}", case))
+                .unwrap_or_default(),
+            note.text
+        );
+        self.notes.push(note);
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Add a note to an existing account, even a locked or closed one. Returns false if the client
+    // has no account.
+    pub fn add_note(&self, client_id: ClientId, note: Note) -> bool {
+        if self
+            .accounts
+            .with_existing_account(client_id, |_| ())
+            .is_none()
+        {
+            return false;
+        }
+        self.accounts
+            .with_account(client_id, |account_manager| account_manager.add_note(note));
+        true
+    }
+
+    // Notes of the client in the order they were added, None if the client has no account.
+    pub fn notes(&self, client_id: ClientId) -> Option<Vec<Note>> {
+        self.accounts
+            .with_existing_account(client_id, |account_manager| account_manager.notes.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ErrorCode;
+    use crate::parser::ByteRecordParser;
+
+    #[test]
+    fn test_notes() {
+        let input = "type,client,tx,amount,timestamp,note,case\n\
+                     deposit,1,1,5.0,2024-03-01,,\n\
+                     dispute,1,1,,2024-03-02,,\n\
+                     chargeback,1,1,,2024-03-03,,\n\
+                     note,1,1,,2024-03-04,\"called the bank, card stolen\",C-42\n\
+                     note,1,1,,2024-03-05,,C-42\n";
+        let engine: Engine = Engine::new();
+        let results: Vec<_> = ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .map(|transaction| engine.process(transaction.unwrap()))
+            .collect();
+        assert_eq!(
+            results[3..]
+                .iter()
+                .map(|result| result.as_ref().err().map(|e| e.code()))
+                .collect::<Vec<_>>(),
+            [None, Some(ErrorCode::NoteMissing)]
+        );
+
+        let client: ClientId = "1".parse().unwrap();
+        assert!(engine.add_note(
+            client,
+            Note {
+                tx: None,
+                case: None,
+                text: "closed the case".to_string(),
+                at: None,
+            }
+        ));
+        assert!(!engine.add_note(
+            "2".parse().unwrap(),
+            Note {
+                tx: None,
+                case: None,
+                text: "no account".to_string(),
+                at: None,
+            }
+        ));
+        let notes = engine.notes(client).unwrap();
+        assert_eq!(
+            notes
+                .iter()
+                .map(|note| (note.tx, note.case.as_deref(), note.text.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    Some(1u16.into()),
+                    Some("C-42"),
+                    "called the bank, card stolen"
+                ),
+                (None, None, "closed the case")
+            ]
+        );
+        assert_eq!(notes[0].at, "2024-03-04".parse().ok());
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.notes(client), Some(notes));
+    }
+}
@@ -0,0 +1,164 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use super::amount::Amount;
+use super::filter::ClientRanges;
+use super::policies::{ChargebackDisputes, Policies};
+use super::transaction::ClientId;
+
+const DAY: u64 = 24 * 3600;
+
+// Names of the built-in packs, see `RulePack::preset`.
+pub const RULE_PACK_PRESETS: [&str; 3] = ["eu", "us", "strict"];
+
+// Policies of a group of clients, e.g. the books of one jurisdiction, replacing the engine's
+// policies for them. Policies left out keep the engine's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulePack<A> {
+    pub max_balance: Option<A>,
+    pub reject_zero_amounts: Option<bool>,
+    pub close_with_held_funds: Option<bool>,
+    pub chargeback_disputes: Option<ChargebackDisputes>,
+    // Age after which `Engine::expire_disputes` settles the group's disputes
+    pub dispute_window: Option<Duration>,
+}
+
+impl<A> RulePack<A> {
+    // Built-in packs as starting points to override, not legal advice:
+    // - `eu`: disputes stay open for 13 months, the window for unauthorised payments of PSD2,
+    //   and a chargeback freezes the account's other disputes
+    // - `us`: disputes stay open for 120 days, a common card network chargeback window, and a
+    //   chargeback resolves the account's other disputes
+    // - `strict`: zero amounts are rejected, accounts with held funds can't be closed, disputes
+    //   stay open for 60 days and a chargeback freezes the account's other disputes
+    pub fn preset(name: &str) -> Option<RulePack<A>> {
+        let pack = match name {
+            "eu" => RulePack {
+                chargeback_disputes: Some(ChargebackDisputes::Freeze),
+                dispute_window: Some(Duration::from_secs(396 * DAY)),
+                ..RulePack::default()
+            },
+            "us" => RulePack {
+                chargeback_disputes: Some(ChargebackDisputes::Resolve),
+                dispute_window: Some(Duration::from_secs(120 * DAY)),
+                ..RulePack::default()
+            },
+            "strict" => RulePack {
+                reject_zero_amounts: Some(true),
+                close_with_held_funds: Some(false),
+                chargeback_disputes: Some(ChargebackDisputes::Freeze),
+                dispute_window: Some(Duration::from_secs(60 * DAY)),
+                ..RulePack::default()
+            },
+            _ => return None,
+        };
+        Some(pack)
+    }
+}
+
+impl<A> Default for RulePack<A> {
+    fn default() -> Self {
+        RulePack {
+            max_balance: None,
+            reject_zero_amounts: None,
+            close_with_held_funds: None,
+            chargeback_disputes: None,
+            dispute_window: None,
+        }
+    }
+}
+
+// Clients the rule pack applies to, see `EngineBuilder::client_group`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientGroup<A> {
+    pub name: String,
+    pub clients: ClientRanges,
+    pub pack: RulePack<A>,
+}
+
+impl<A: Amount> Policies<A> {
+    // The first group containing the client.
+    pub fn group(&self, client_id: &ClientId) -> Option<&ClientGroup<A>> {
+        self.groups
+            .iter()
+            .find(|group| group.clients.contains(client_id))
+    }
+
+    // The policies applying to transactions of the client, with the pack of its group. Rules and
+    // groups don't depend on the client and are left out of a group's policies.
+    pub fn for_client(&self, client_id: &ClientId) -> Cow<'_, Policies<A>> {
+        let Some(ClientGroup { pack, .. }) = self.group(client_id) else {
+            return Cow::Borrowed(self);
+        };
+        Cow::Owned(Policies {
+            max_balance: pack.max_balance.or(self.max_balance),
+            reject_zero_amounts: pack.reject_zero_amounts.unwrap_or(self.reject_zero_amounts),
+            close_with_held_funds: pack
+                .close_with_held_funds
+                .unwrap_or(self.close_with_held_funds),
+            chargeback_disputes: pack.chargeback_disputes.unwrap_or(self.chargeback_disputes),
+            risk_thresholds: self.risk_thresholds,
+            rules: Vec::new(),
+            groups: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::{Engine, ErrorCode, Transaction};
+
+    #[test]
+    fn test_client_groups() {
+        let engine: Engine = Engine::builder()
+            .max_balance(Decimal::from(100))
+            .client_group(
+                "eu",
+                "1,5".parse().unwrap(),
+                RulePack {
+                    max_balance: Some(Decimal::from(10)),
+                    ..RulePack::preset("strict").unwrap()
+                },
+            )
+            .build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,20.0\n\
+                     deposit,1,2,0.0\n\
+                     deposit,20,3,20.0\n\
+                     deposit,20,4,0.0\n";
+        let results: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|transaction| engine.process(transaction.unwrap()))
+            .collect();
+
+        let codes: Vec<_> = results
+            .iter()
+            .map(|result| result.as_ref().err().map(|e| e.code()))
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                Some(ErrorCode::AmountOverflow),
+                Some(ErrorCode::AmountZero),
+                None,
+                None
+            ]
+        );
+
+        let policies = engine.policies();
+        let client = |id: &str| -> ClientId { id.parse().unwrap() };
+        assert_eq!(
+            policies
+                .group(&client("5"))
+                .map(|group| group.name.as_str()),
+            Some("eu")
+        );
+        assert_eq!(
+            policies.for_client(&client("20")).max_balance,
+            Some(Decimal::from(100))
+        );
+    }
+}
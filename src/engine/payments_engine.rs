@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::account::Account;
+use super::amount::Amount;
+use super::engine::{Engine, EngineError};
+use super::error_code::ErrorCode;
+use super::store::AccountStore;
+use super::transaction::Transaction;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct EngineStats {
+    pub processed: u64,
+    pub rejected: u64,
+    // Rejected transactions by reason, only reasons which occurred
+    pub rejections: BTreeMap<ErrorCode, u64>,
+    pub accounts: usize,
+}
+
+// Interface of a payments engine, for embedding applications to mock and for alternative
+// implementations.
+//
+// Transactions of one client must be processed in input order, transactions of different
+// clients may be processed concurrently.
+pub trait PaymentsEngine<A: Amount = Decimal>: Send + Sync {
+    fn process(&self, transaction: Transaction) -> Result<(), EngineError>;
+
+    // Snapshot of all accounts, order is implementation specific.
+    fn accounts(&self) -> Vec<Account<A>>;
+
+    fn stats(&self) -> EngineStats;
+}
+
+impl<A: Amount, S: AccountStore<A>> PaymentsEngine<A> for Engine<A, S> {
+    fn process(&self, transaction: Transaction) -> Result<(), EngineError> {
+        Engine::process(self, transaction)
+    }
+
+    fn accounts(&self) -> Vec<Account<A>> {
+        self.account_snapshot()
+    }
+
+    fn stats(&self) -> EngineStats {
+        Engine::stats(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{TransactionId, TransactionType};
+
+    fn deposit(client_id: &str, transaction_id: TransactionId, amount: i64) -> Transaction {
+        Transaction {
+            r#type: TransactionType::Deposit,
+            client_id: client_id.parse().unwrap(),
+            transaction_id,
+            amount: Some(Decimal::from(amount)),
+            metadata: Default::default(),
+        }
+    }
+
+    // Embedding code only depends on the trait
+    fn settle(engine: &dyn PaymentsEngine, transactions: Vec<Transaction>) -> EngineStats {
+        for transaction in transactions {
+            let _ = engine.process(transaction);
+        }
+        engine.stats()
+    }
+
+    #[test]
+    fn test_engine_implements_trait() {
+        let engine: Engine = Engine::new();
+
+        let stats = settle(
+            &engine,
+            vec![deposit("1", 1, 5), deposit("2", 2, 3), deposit("1", 1, 1)],
+        );
+
+        assert_eq!(
+            stats,
+            EngineStats {
+                processed: 3,
+                rejected: 1,
+                rejections: BTreeMap::from([(ErrorCode::TransactionExists, 1)]),
+                accounts: 2,
+            }
+        );
+        assert_eq!(PaymentsEngine::accounts(&engine).len(), 2);
+    }
+}
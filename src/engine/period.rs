@@ -0,0 +1,167 @@
+use std::io;
+use std::sync::atomic::Ordering;
+
+use log::info;
+use rust_decimal::Decimal;
+
+use super::amount::Amount;
+use super::analytics::Analytics;
+use super::engine::Engine;
+use super::hot::HotAccounts;
+use super::snapshot::SnapshotError;
+use super::store::AccountStore;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PeriodClose {
+    pub accounts: usize,
+    // Transactions in the archive only, they can't be disputed anymore
+    pub archived_transactions: usize,
+    // Disputed transactions carried over into the next period
+    pub carried_over_transactions: usize,
+    // Accounts charged a maintenance fee, see `EngineBuilder::maintenance_fees`
+    pub maintenance_fees: usize,
+    pub fee_income: Decimal,
+    // Accounts which became dormant, see `EngineBuilder::dormancy`
+    pub dormant: usize,
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Close the current accounting period.
+    //
+    // Maintenance fees of the period are charged first, if configured. The engine state is
+    // archived as a snapshot, then the period's journal is frozen: stored
+    // transactions are dropped so later disputes of them fail, except for open disputes which
+    // carry over. Closing balances stay as the opening balances of the next period, while the
+    // processing counters, per-client activity, analytics, hot accounts and time buckets start from
+    // zero.
+    pub fn close_period<W: io::Write>(&self, archive: W) -> Result<PeriodClose, SnapshotError> {
+        let (maintenance_fees, fee_income) = self.charge_maintenance_fees();
+        self.write_snapshot(archive)?;
+
+        let mut close = PeriodClose {
+            maintenance_fees,
+            fee_income,
+            ..PeriodClose::default()
+        };
+        self.accounts.try_for_each_mut(|account_manager| {
+            close.accounts += 1;
+            close.dormant += usize::from(account_manager.close_dormancy_period(self.dormancy));
+            close.archived_transactions += account_manager.close_period();
+            close.carried_over_transactions += account_manager.transactions.len();
+        })?;
+        self.processed.store(0, Ordering::Relaxed);
+        self.rejected.store(0, Ordering::Relaxed);
+        self.rejections
+            .iter()
+            .for_each(|count| count.store(0, Ordering::Relaxed));
+        if let Some(analytics) = &self.analytics {
+            *analytics.lock().unwrap_or_else(|e| e.into_inner()) = Analytics::default();
+        }
+        if let Some(hot_accounts) = &self.hot_accounts {
+            *hot_accounts.lock().unwrap_or_else(|e| e.into_inner()) = HotAccounts::default();
+        }
+        if let Some(time_buckets) = &self.time_buckets {
+            time_buckets
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .reset();
+        }
+
+        info!(target: "audit", "Closed period: {:?}", close);
+        Ok(close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Transaction, TxStatus};
+
+    #[test]
+    fn test_close_period() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,2.0\n\
+                     dispute,1,2,\n\
+                     deposit,2,3,3.0\n\
+                     withdrawal,2,4,5.0\n";
+        let engine: Engine = Engine::builder().analytics().build();
+        csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+        let accounts = engine.account_snapshot();
+
+        let mut archive = Vec::new();
+        let close = engine.close_period(&mut archive).unwrap();
+
+        assert_eq!(
+            close,
+            PeriodClose {
+                accounts: 2,
+                archived_transactions: 2,
+                carried_over_transactions: 1,
+                ..PeriodClose::default()
+            }
+        );
+        let archived: Engine = Engine::new();
+        archived.restore_snapshot(archive.as_slice()).unwrap();
+        assert_eq!(
+            archived.transaction_status("1".parse().unwrap(), 1),
+            Some(TxStatus::Settled)
+        );
+
+        assert_eq!(engine.account_snapshot(), accounts);
+        assert_eq!(engine.stats().processed, 0);
+        assert_eq!(engine.risk_report(Default::default())[0].transactions, 0);
+        assert_eq!(engine.analytics_report(1).unwrap().top_clients, []);
+        // The open dispute carries over, settled deposits are frozen
+        let client_id = "1".parse().unwrap();
+        assert!(engine.process(transaction("resolve", client_id, 2)).is_ok());
+        assert!(engine
+            .process(transaction("dispute", client_id, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_maintenance_fees() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,50.0\n\
+                     deposit,2,2,500.0\n\
+                     deposit,3,3,5000.0\n";
+        let engine: Engine = Engine::builder()
+            .maintenance_fees("100:5,1000:2".parse().unwrap())
+            .build();
+        csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+
+        let close = engine.close_period(io::sink()).unwrap();
+        assert_eq!(
+            (close.maintenance_fees, close.fee_income),
+            (2, Decimal::from(7))
+        );
+        let available = |id: &str| engine.account(id.parse().unwrap()).unwrap().available;
+        assert_eq!(available("1"), Decimal::from(45));
+        assert_eq!(available("2"), Decimal::from(498));
+        assert_eq!(available("3"), Decimal::from(5000));
+        let fee_income = engine
+            .trial_balance()
+            .unwrap()
+            .into_iter()
+            .find(|(book, _)| *book == crate::engine::Book::FeeIncome);
+        assert_eq!(
+            fee_income,
+            Some((crate::engine::Book::FeeIncome, Decimal::from(7)))
+        );
+    }
+
+    fn transaction(r#type: &str, client_id: crate::engine::ClientId, tx: u16) -> Transaction {
+        Transaction {
+            r#type: r#type.parse().unwrap(),
+            client_id,
+            transaction_id: tx.into(),
+            amount: None,
+            metadata: Default::default(),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use log::info;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::packs::ClientGroup;
+use super::risk::RiskThresholds;
+use super::rules::Rule;
+use super::store::AccountStore;
+
+// Limits and rules of an engine which can be changed while it processes transactions, see
+// `PolicyHandle`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policies<A> {
+    pub max_balance: Option<A>,
+    pub reject_zero_amounts: bool,
+    pub close_with_held_funds: bool,
+    pub chargeback_disputes: ChargebackDisputes,
+    pub risk_thresholds: RiskThresholds,
+    // Evaluated in order before every transaction, see `Rule`
+    pub rules: Vec<Rule>,
+    // Rule packs replacing the policies above for groups of clients, the first containing a
+    // client applies
+    pub groups: Vec<ClientGroup<A>>,
+}
+
+// What a chargeback does to the other open disputes of the account it locks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ChargebackDisputes {
+    // Left open, though the locked account rejects their resolve or chargeback
+    #[default]
+    Keep,
+    // Resolved, their funds return to available
+    Resolve,
+    // Held as `TxStatus::Frozen` until settled with `Engine::force_settle`, and exempt from
+    // dispute expiry
+    Freeze,
+}
+
+impl FromStr for ChargebackDisputes {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ChargebackDisputes, ()> {
+        match s {
+            "keep" => Ok(ChargebackDisputes::Keep),
+            "resolve" => Ok(ChargebackDisputes::Resolve),
+            "freeze" => Ok(ChargebackDisputes::Freeze),
+            _ => Err(()),
+        }
+    }
+}
+
+impl<A> Default for Policies<A> {
+    fn default() -> Self {
+        Policies {
+            max_balance: None,
+            reject_zero_amounts: false,
+            close_with_held_funds: false,
+            chargeback_disputes: ChargebackDisputes::Keep,
+            risk_thresholds: RiskThresholds::default(),
+            rules: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+}
+
+// Shared access to the policies of an engine, e.g. for a thread reloading them from a config
+// file. A change applies from the next transaction on, the transactions being processed finish
+// under the policies they started with.
+#[derive(Clone)]
+pub struct PolicyHandle<A> {
+    pub(super) policies: Arc<RwLock<Policies<A>>>,
+}
+
+impl<A: Amount> PolicyHandle<A> {
+    pub fn get(&self) -> Policies<A> {
+        self.policies
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    // Replace the policies, writing an entry for every changed policy to the `audit` log target.
+    pub fn set(&self, policies: Policies<A>) {
+        let mut current = self.policies.write().unwrap_or_else(|e| e.into_inner());
+        audit("max_balance", &current.max_balance, &policies.max_balance);
+        audit(
+            "reject_zero_amounts",
+            &current.reject_zero_amounts,
+            &policies.reject_zero_amounts,
+        );
+        audit(
+            "close_with_held_funds",
+            &current.close_with_held_funds,
+            &policies.close_with_held_funds,
+        );
+        audit(
+            "chargeback_disputes",
+            &current.chargeback_disputes,
+            &policies.chargeback_disputes,
+        );
+        audit(
+            "risk.max_dispute_rate",
+            &current.risk_thresholds.dispute_rate,
+            &policies.risk_thresholds.dispute_rate,
+        );
+        audit(
+            "risk.max_chargeback_rate",
+            &current.risk_thresholds.chargeback_rate,
+            &policies.risk_thresholds.chargeback_rate,
+        );
+        if current.rules != policies.rules {
+            let rules: Vec<String> = policies
+                .rules
+                .iter()
+                .map(|rule| {
+                    format!(
+                        "{} {} if {}",
+                        rule.name,
+                        rule.action.as_str(),
+                        rule.expression()
+                    )
+                })
+                .collect();
+            info!(target: "audit", "Policy rules changed to [{}]", rules.join("; "));
+        }
+        if current.groups != policies.groups {
+            let groups: Vec<String> = policies
+                .groups
+                .iter()
+                .map(|group| format!("{} {:?}", group.name, group.pack))
+                .collect();
+            info!(target: "audit", "Policy groups changed to [{}]", groups.join("; "));
+        }
+        *current = policies;
+    }
+}
+
+fn audit<T: Debug + PartialEq>(name: &str, from: &T, to: &T) {
+    if from != to {
+        info!(target: "audit", "Policy {} changed from {:?} to {:?}", name, from, to);
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    pub fn policies(&self) -> Policies<A> {
+        self.policy_handle().get()
+    }
+
+    pub fn policy_handle(&self) -> PolicyHandle<A> {
+        PolicyHandle {
+            policies: Arc::clone(&self.policies),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::Transaction;
+
+    fn process(engine: &mut Engine, input: &str) {
+        let input = format!("type,client,tx,amount\n{}", input);
+        engine
+            .process_transactions_blocking(
+                csv::Reader::from_reader(input.as_bytes()).into_deserialize::<Transaction>(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_policy_change_applies_to_next_transactions() {
+        let mut engine: Engine = Engine::builder().max_balance(Decimal::from(10)).build();
+        process(&mut engine, "deposit,1,1,8.0\ndeposit,1,2,8.0\n");
+
+        let handle = engine.policy_handle();
+        handle.set(Policies {
+            max_balance: Some(Decimal::from(20)),
+            ..handle.get()
+        });
+        process(&mut engine, "deposit,1,3,8.0\n");
+
+        assert_eq!(engine.policies().max_balance, Some(Decimal::from(20)));
+        assert_eq!(
+            engine
+                .account(1.to_string().parse().unwrap())
+                .unwrap()
+                .available,
+            Decimal::from(16)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+use super::account::Account;
+use super::amount::Amount;
+use super::rules::{identifier, parse_condition, Expr, RuleError, Value};
+
+// Variables of account queries.
+const VARIABLES: [&str; 10] = [
+    "client",
+    "available",
+    "held",
+    "reserve",
+    "total",
+    "locked",
+    "quarantined",
+    "closed",
+    "dormant",
+    "dunning",
+];
+
+// Condition selecting accounts, in the language of rule conditions over the account's balances
+// and flags, e.g. `locked = true AND total > 1000`, see `Rule`. Set as `AccountFilter::query`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountQuery {
+    expression: String,
+    condition: Expr,
+}
+
+impl AccountQuery {
+    pub fn new(expression: &str) -> Result<AccountQuery, RuleError> {
+        Ok(AccountQuery {
+            expression: expression.to_string(),
+            condition: parse_condition(expression, &VARIABLES, false)?,
+        })
+    }
+
+    // The condition as it was written.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    pub fn matches<A: Amount>(&self, account: &Account<A>) -> Result<bool, String> {
+        let variable = |name: &str| -> Value {
+            match name {
+                "client" => identifier(account.client_id.to_string()),
+                "available" => Value::Number(account.available.to_decimal()),
+                "held" => Value::Number(account.held.to_decimal()),
+                "reserve" => Value::Number(account.reserve.to_decimal()),
+                "total" => Value::Number(account.calculate_total().to_decimal()),
+                "locked" => Value::Bool(account.locked),
+                "quarantined" => Value::Bool(account.quarantined),
+                "closed" => Value::Bool(account.closed),
+                "dormant" => Value::Bool(account.dormant),
+                "dunning" => Value::Str(account.dunning.as_str().to_string()),
+                _ => Value::Null,
+            }
+        };
+        match self.condition.eval(&variable)? {
+            Value::Bool(matches) => Ok(matches),
+            value => Err(format!("query is {}, not true or false", value)),
+        }
+    }
+}
+
+impl FromStr for AccountQuery {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<AccountQuery, RuleError> {
+        AccountQuery::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn test_account_query() {
+        let mut account: Account<Decimal> = Account::new("7".parse().unwrap());
+        account.available = Decimal::from(1500);
+        account.locked = true;
+        let matches = |query: &str| AccountQuery::new(query).unwrap().matches(&account);
+
+        assert_eq!(matches("locked = true AND total > 1000"), Ok(true));
+        assert_eq!(matches("client == 7 and not closed"), Ok(true));
+        assert_eq!(matches("held > 0 OR dunning != \"current\""), Ok(false));
+        assert!(matches("total").is_err());
+        // Transaction variables aren't there
+        assert_eq!(
+            AccountQuery::new("amount > 1"),
+            Err(RuleError::UnknownVariable("amount".to_string()))
+        );
+        assert!(AccountQuery::new("meta.country = \"XX\"").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+
+use log::warn;
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Transaction, TransactionId};
+
+// Columns of the event log, see `EngineBuilder::record`.
+pub const EVENT_LOG_HEADERS: [&str; 5] = ["type", "client", "tx", "amount", "metadata"];
+
+// Transaction as it reached the engine, metadata as a JSON object.
+#[derive(Serialize)]
+struct Event {
+    r#type: &'static str,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<String>,
+    metadata: String,
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Append the transaction to the event log. Called for every transaction in the order the
+    // engine applies them, which is the same as the input order for the transactions of a
+    // client.
+    pub(super) fn record_event(&self, transaction: &Transaction) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let event = Event {
+            r#type: transaction.r#type.name(),
+            client: transaction.client_id,
+            tx: transaction.transaction_id,
+            amount: transaction.amount.map(|amount| amount.to_string()),
+            metadata: if transaction.metadata.is_empty() {
+                String::new()
+            } else {
+                serde_json::to_string(&transaction.metadata).unwrap_or_default()
+            },
+        };
+        let mut recorder = recorder.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = recorder.serialize(event) {
+            warn!("Error writing event log: {}", e);
+        }
+    }
+
+    pub fn flush_record(&self) -> io::Result<()> {
+        match &self.recorder {
+            Some(recorder) => recorder.lock().unwrap_or_else(|e| e.into_inner()).flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use rust_decimal::Decimal;
+
+use super::account::Account;
+use super::account_manager::AccountManagerError;
+use super::amount::Amount;
+use super::engine::EngineError;
+use super::notes::NOTE_COLUMN;
+use super::payments_engine::{EngineStats, PaymentsEngine};
+use super::transaction::{
+    ClientId, Transaction, TransactionId, TransactionType, TransactionValidationError, TxStatus,
+};
+
+struct ReferenceAccount {
+    account: Account<Decimal>,
+    deposits: HashMap<TransactionId, (Decimal, TxStatus)>,
+}
+
+#[derive(Default)]
+struct State {
+    accounts: BTreeMap<ClientId, ReferenceAccount>,
+    stats: EngineStats,
+}
+
+// Deliberately simple sequential engine, the oracle for differential testing of `Engine`.
+//
+// Implements the default transaction rules from scratch on top of a single lock and plain
+// collections, without stores, filters or engine options. Any difference from `Engine` on the
+// same input is a bug in one of them.
+#[derive(Default)]
+pub struct ReferenceEngine {
+    state: Mutex<State>,
+}
+
+impl ReferenceEngine {
+    pub fn new() -> ReferenceEngine {
+        ReferenceEngine::default()
+    }
+}
+
+impl PaymentsEngine for ReferenceEngine {
+    fn process(&self, transaction: Transaction) -> Result<(), EngineError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let client_id = transaction.client_id;
+        let entry = state
+            .accounts
+            .entry(client_id)
+            .or_insert_with(|| ReferenceAccount {
+                account: Account::new(client_id),
+                deposits: HashMap::new(),
+            });
+        let result = apply(entry, &transaction);
+
+        state.stats.processed += 1;
+        if let Err(e) = &result {
+            state.stats.rejected += 1;
+            *state.stats.rejections.entry(e.code()).or_default() += 1;
+        }
+        result
+    }
+
+    fn accounts(&self) -> Vec<Account<Decimal>> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .accounts
+            .values()
+            .map(|entry| entry.account.clone())
+            .collect()
+    }
+
+    fn stats(&self) -> EngineStats {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        EngineStats {
+            accounts: state.accounts.len(),
+            ..state.stats.clone()
+        }
+    }
+}
+
+fn apply(entry: &mut ReferenceAccount, transaction: &Transaction) -> Result<(), EngineError> {
+    let account = &mut entry.account;
+    let transaction_id = transaction.transaction_id;
+    let locked = if account.closed {
+        Err(AccountManagerError::AccountClosed)
+    } else if account.locked {
+        Err(AccountManagerError::AccountLocked)
+    } else {
+        Ok(())
+    };
+
+    match transaction.r#type {
+        TransactionType::Deposit => {
+            let amount: Decimal = transaction.get_amount_or_error()?;
+            locked?;
+            if entry.deposits.contains_key(&transaction_id) {
+                return Err(AccountManagerError::TransactionExist.into());
+            }
+            set_balances(
+                account,
+                account.available.checked_add(amount),
+                Some(account.held),
+            )?;
+            entry
+                .deposits
+                .insert(transaction_id, (amount, TxStatus::Settled));
+        }
+        TransactionType::Withdraw => {
+            let amount: Decimal = transaction.get_amount_or_error()?;
+            if account.closed {
+                return Err(AccountManagerError::AccountClosed.into());
+            }
+            if account.available < amount {
+                return Err(AccountManagerError::InsufficientFunds.into());
+            }
+            set_balances(
+                account,
+                account.available.checked_sub(amount),
+                Some(account.held),
+            )?;
+        }
+        TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+            // Charged back deposits are final, whatever the state of the account
+            if let Some((_, TxStatus::ChargedBack)) = entry.deposits.get(&transaction_id) {
+                return Err(AccountManagerError::TransactionAlreadyChargedBack.into());
+            }
+            locked?;
+            let (amount, status) = entry
+                .deposits
+                .get_mut(&transaction_id)
+                .ok_or(AccountManagerError::TransactionNotExist)?;
+            let (next, available, held) = match transaction.r#type {
+                TransactionType::Dispute => (
+                    TxStatus::Disputed,
+                    account.available.checked_sub(*amount),
+                    account.held.checked_add(*amount),
+                ),
+                TransactionType::Resolve => (
+                    TxStatus::Resolved,
+                    account.available.checked_add(*amount),
+                    account.held.checked_sub(*amount),
+                ),
+                _ => (
+                    TxStatus::ChargedBack,
+                    Some(account.available),
+                    account.held.checked_sub(*amount),
+                ),
+            };
+            let next = status.transition(next)?;
+            set_balances(account, available, held)?;
+            *status = next;
+            if next == TxStatus::ChargedBack {
+                account.locked = true;
+            }
+        }
+        TransactionType::CloseAccount => {
+            locked?;
+            if !account.held.is_zero() {
+                return Err(AccountManagerError::HeldFunds.into());
+            }
+            account.closed = true;
+        }
+        // Notes don't touch the account
+        TransactionType::Note => {
+            if transaction
+                .metadata
+                .get(NOTE_COLUMN)
+                .is_none_or(|text| text.trim().is_empty())
+            {
+                return Err(TransactionValidationError::NoteMissing.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_balances(
+    account: &mut Account<Decimal>,
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+) -> Result<(), AccountManagerError> {
+    match (available, held) {
+        (Some(available), Some(held)) if available.checked_add(held).is_some() => {
+            account.available = available;
+            account.held = held;
+            Ok(())
+        }
+        _ => Err(AccountManagerError::AmountOverflow),
+    }
+}
+
+// Clients whose accounts differ between two account snapshots, e.g. of `Engine` and
+// `ReferenceEngine`. Amounts are compared as `Decimal`.
+pub fn diff_accounts<A: Amount, B: Amount>(
+    accounts: &[Account<A>],
+    other: &[Account<B>],
+) -> Vec<ClientId> {
+    let accounts: BTreeMap<_, _> = accounts
+        .iter()
+        .map(|a| (a.client_id, balances(a)))
+        .collect();
+    let other: BTreeMap<_, _> = other.iter().map(|a| (a.client_id, balances(a))).collect();
+
+    let mut clients: Vec<ClientId> = accounts
+        .iter()
+        .filter(|(client_id, balances)| other.get(client_id) != Some(balances))
+        .map(|(client_id, _)| *client_id)
+        .chain(other.keys().filter(|c| !accounts.contains_key(c)).copied())
+        .collect();
+    clients.sort();
+    clients
+}
+
+fn balances<A: Amount>(account: &Account<A>) -> (Decimal, Decimal, bool, bool) {
+    (
+        account.available.to_decimal(),
+        account.held.to_decimal(),
+        account.locked,
+        account.closed,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::engine::store::ShardedStore;
+    use crate::engine::Engine;
+
+    // Pseudo-random but reproducible mix of all transaction types over a few clients
+    fn generate(count: u64, seed: u64) -> Vec<Transaction> {
+        let mut state = seed;
+        let mut next = |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        (0..count)
+            .map(|transaction_id| {
+                let (r#type, transaction_id) = match next(10) {
+                    0..=3 => (TransactionType::Deposit, transaction_id),
+                    4..=5 => (TransactionType::Withdraw, transaction_id),
+                    6..=7 => (TransactionType::Dispute, next(transaction_id + 1)),
+                    8 => (TransactionType::Resolve, next(transaction_id + 1)),
+                    _ => (TransactionType::Chargeback, next(transaction_id + 1)),
+                };
+                Transaction {
+                    r#type,
+                    client_id: next(8).to_string().parse().unwrap(),
+                    // Identifier widths depend on enabled features
+                    transaction_id: transaction_id.to_string().parse().unwrap(),
+                    amount: Some(Decimal::new(next(100_000) as i64, 2)),
+                    metadata: Default::default(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_concurrent_engine() {
+        let transactions = generate(20_000, 0x5eed);
+        let reference = ReferenceEngine::new();
+        transactions
+            .iter()
+            .for_each(|transaction| drop(reference.process(transaction.clone())));
+
+        // Each worker owns a disjoint set of clients, keeping their transactions in order
+        let engine: Arc<Engine<Decimal, ShardedStore<Decimal>>> = Arc::new(Engine::new());
+        let transactions = Arc::new(transactions);
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let engine = Arc::clone(&engine);
+                let transactions = Arc::clone(&transactions);
+                thread::spawn(move || {
+                    transactions
+                        .iter()
+                        .filter(|t| t.client_id.to_string().parse::<usize>().unwrap() % 4 == worker)
+                        .for_each(|transaction| drop(engine.process(transaction.clone())));
+                })
+            })
+            .collect();
+        workers.into_iter().for_each(|w| w.join().unwrap());
+
+        assert_eq!(
+            diff_accounts(&PaymentsEngine::accounts(&*engine), &reference.accounts()),
+            [] as [ClientId; 0]
+        );
+        assert_eq!(PaymentsEngine::stats(&*engine), reference.stats());
+    }
+}
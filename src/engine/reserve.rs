@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+use log::warn;
+use rust_decimal::Decimal;
+
+use super::account_manager::{AccountManager, AccountManagerError};
+use super::amount::{Amount, MinorUnits};
+use super::calendar::BusinessCalendar;
+use super::engine::Engine;
+use super::journal::Book;
+use super::store::AccountStore;
+use super::transaction::{Timestamp, Transaction, TransactionId};
+
+// Share of every deposit moved from available into the reserve, and how long it stays there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollingReserve {
+    // Fraction of the deposit, e.g. 0.1 for 10%
+    pub rate: Decimal,
+    pub period: Duration,
+}
+
+// Part of a deposit in the reserve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reserve<A> {
+    pub transaction_id: TransactionId,
+    pub amount: A,
+    pub release_at: Timestamp,
+}
+
+impl RollingReserve {
+    // Part of the deposit to reserve and when to release it, None for deposits without a
+    // timestamp, which would never be released, or too small to reserve anything. With a
+    // calendar the period's days are business days.
+    fn reserve<A: Amount>(
+        &self,
+        transaction: &Transaction,
+        amount: A,
+        calendar: Option<&BusinessCalendar>,
+    ) -> Option<(A, Timestamp)> {
+        let deposited_at = transaction.timestamp()?;
+        let release_at = match calendar {
+            Some(calendar) => calendar.add(deposited_at, self.period)?,
+            None => Timestamp::new(deposited_at.0.checked_add(self.period)?)?,
+        };
+        let reserved = (amount.to_decimal() * self.rate).round_dp(MinorUnits::SCALE);
+        if reserved <= Decimal::ZERO {
+            return None;
+        }
+        Some((A::from_decimal(reserved)?, release_at))
+    }
+}
+
+impl<A: Amount> AccountManager<A> {
+    // Move part of a deposit from available into the reserve until `release_at`.
+    pub fn hold_reserve(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: A,
+        release_at: Timestamp,
+    ) -> Result<(), AccountManagerError> {
+        self.post(
+            transaction_id,
+            Book::ClientAvailable,
+            Book::ClientReserve,
+            amount,
+        )?;
+        self.reserves.push_back(Reserve {
+            transaction_id,
+            amount,
+            release_at,
+        });
+        Ok(())
+    }
+
+    // Move reserves due by `now` back to available. Returns the number of released reserves.
+    pub fn release_reserves(&mut self, now: Timestamp) -> Result<usize, AccountManagerError> {
+        let mut released = 0;
+        while let Some(index) = self
+            .reserves
+            .iter()
+            .position(|reserve| reserve.release_at <= now)
+        {
+            let reserve = self.reserves[index];
+            self.post(
+                reserve.transaction_id,
+                Book::ClientReserve,
+                Book::ClientAvailable,
+                reserve.amount,
+            )?;
+            self.reserves.remove(index);
+            released += 1;
+        }
+        Ok(released)
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Part of a deposit to put in the reserve, see `EngineBuilder::rolling_reserve`, for a client
+    // of the group.
+    pub(super) fn reserve_for(
+        &self,
+        transaction: &Transaction,
+        amount: A,
+        group: Option<&str>,
+    ) -> Option<(A, Timestamp)> {
+        let calendar = self
+            .calendars
+            .as_ref()
+            .map(|calendars| calendars.get(group));
+        self.rolling_reserve?.reserve(transaction, amount, calendar)
+    }
+
+    // Release the account's reserves due by the time of its transaction being processed,
+    // journaled on their own as the transaction may be rejected.
+    pub(super) fn release_due_reserves(
+        &self,
+        account_manager: &mut AccountManager<A>,
+        now: Timestamp,
+    ) {
+        if let Err(e) = account_manager.release_reserves(now) {
+            warn!(
+                "Error releasing reserves of client {}: {}",
+                account_manager.account.client_id, e
+            );
+        }
+        self.record_postings(account_manager);
+        account_manager.postings.clear();
+    }
+
+    // Release the reserves of all accounts due by `now`, e.g. at the end of the input, as
+    // reserves are otherwise released by the account's next transaction. Returns the number of
+    // released reserves.
+    pub fn release_reserves(&self, now: Timestamp) -> usize {
+        let mut released = 0;
+        self.accounts.for_each_mut(|account_manager| {
+            if account_manager
+                .reserves
+                .iter()
+                .any(|reserve| reserve.release_at <= now)
+            {
+                released += account_manager.reserves.len();
+                self.release_due_reserves(account_manager, now);
+                released -= account_manager.reserves.len();
+            }
+        });
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ClientId;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_rolling_reserve() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,100.0,2024-01-01\n\
+                     deposit,1,2,10.0,\n\
+                     withdrawal,1,3,105.0,2024-01-15\n\
+                     deposit,1,4,50.0,2024-02-01\n\
+                     withdrawal,1,5,95.0,2024-02-01\n";
+        let mut engine: Engine = Engine::builder()
+            .rolling_reserve(RollingReserve {
+                rate: Decimal::new(1, 1),
+                period: Duration::from_secs(30 * 24 * 3600),
+            })
+            .build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        // 10 of the first deposit released by the deposit a month later, 5 of that deposit left.
+        // The withdrawal of 105 before the release was rejected.
+        let client: ClientId = "1".parse().unwrap();
+        let account = engine.account(client).unwrap();
+        assert_eq!(
+            (
+                account.available,
+                account.reserve,
+                account.calculate_total()
+            ),
+            (Decimal::from(60), Decimal::from(5), Decimal::from(65))
+        );
+        assert_eq!(
+            engine.extended_report().unwrap().reserves[0].reserve,
+            Decimal::from(5)
+        );
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.account(client), engine.account(client));
+
+        assert_eq!(restored.release_reserves("2024-03-02".parse().unwrap()), 1);
+        let account = restored.account(client).unwrap();
+        assert_eq!(
+            (account.available, account.reserve),
+            (Decimal::from(65), Decimal::ZERO)
+        );
+    }
+}
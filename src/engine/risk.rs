@@ -0,0 +1,166 @@
+use serde::Serialize;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, TransactionType};
+
+// Accepted transactions of an account by type, maintained as transactions are processed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Activity {
+    pub transactions: u64,
+    pub deposits: u64,
+    pub disputes: u64,
+    pub chargebacks: u64,
+}
+
+impl Activity {
+    pub fn record(&mut self, r#type: TransactionType) {
+        // Notes aren't transactions of the client
+        if r#type == TransactionType::Note {
+            return;
+        }
+        self.transactions += 1;
+        match r#type {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            _ => {}
+        }
+    }
+
+    // Disputes per transaction
+    pub fn dispute_rate(&self) -> f64 {
+        ratio(self.disputes, self.transactions)
+    }
+
+    // Chargebacks per deposit
+    pub fn chargeback_rate(&self) -> f64 {
+        ratio(self.chargebacks, self.deposits)
+    }
+}
+
+fn ratio(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+// Rates above which a client is flagged in the risk report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskThresholds {
+    pub dispute_rate: f64,
+    pub chargeback_rate: f64,
+}
+
+impl Default for RiskThresholds {
+    fn default() -> Self {
+        RiskThresholds {
+            dispute_rate: 0.01,
+            chargeback_rate: 0.005,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RiskEntry {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub transactions: u64,
+    pub deposits: u64,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    #[serde(serialize_with = "serialize_rate")]
+    pub dispute_rate: f64,
+    #[serde(serialize_with = "serialize_rate")]
+    pub chargeback_rate: f64,
+    // Transactions the client was flagged for structuring with, only with
+    // `EngineBuilder::structuring`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structuring: Option<usize>,
+    pub flagged: bool,
+}
+
+fn serialize_rate<S: serde::Serializer>(rate: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:.4}", rate))
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Activity and rates of every client, flagged clients first, then by client id.
+    pub fn risk_report(&self, thresholds: RiskThresholds) -> Vec<RiskEntry> {
+        let mut entries = Vec::new();
+        self.accounts.for_each(|account_manager| {
+            let activity = &account_manager.activity;
+            let (dispute_rate, chargeback_rate) =
+                (activity.dispute_rate(), activity.chargeback_rate());
+            let structuring = self
+                .structuring
+                .is_some()
+                .then_some(account_manager.structuring.len());
+            entries.push(RiskEntry {
+                client_id: account_manager.account.client_id,
+                transactions: activity.transactions,
+                deposits: activity.deposits,
+                disputes: activity.disputes,
+                chargebacks: activity.chargebacks,
+                dispute_rate,
+                chargeback_rate,
+                structuring,
+                flagged: dispute_rate > thresholds.dispute_rate
+                    || chargeback_rate > thresholds.chargeback_rate
+                    || structuring.is_some_and(|count| count > 0),
+            });
+        });
+        entries.sort_by_key(|entry| (!entry.flagged, entry.client_id));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Transaction;
+
+    #[test]
+    fn test_risk_report() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,1.0\n\
+                     withdrawal,1,3,1.0\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n\
+                     deposit,2,4,1.0\n\
+                     withdrawal,2,5,5.0\n\
+                     dispute,2,9,\n";
+        let engine: Engine = Engine::new();
+        csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .for_each(|transaction| drop(engine.process(transaction.unwrap())));
+
+        let report = engine.risk_report(RiskThresholds {
+            dispute_rate: 0.1,
+            chargeback_rate: 0.5,
+        });
+
+        assert_eq!(report.len(), 2);
+        assert_eq!(
+            report[0],
+            RiskEntry {
+                client_id: "1".parse().unwrap(),
+                transactions: 5,
+                deposits: 2,
+                disputes: 1,
+                chargebacks: 1,
+                dispute_rate: 0.2,
+                chargeback_rate: 0.5,
+                structuring: None,
+                flagged: true,
+            }
+        );
+        // Rejected transactions don't count
+        assert_eq!(report[1].transactions, 1);
+        assert!(!report[1].flagged);
+    }
+}
@@ -0,0 +1,612 @@
+use std::fmt;
+use std::str::FromStr;
+
+use log::{info, warn};
+use rust_decimal::Decimal;
+
+use super::account::Account;
+use super::amount::Amount;
+use super::engine::{Engine, EngineError};
+use super::store::AccountStore;
+use super::transaction::Transaction;
+
+// Variables of rule conditions besides `meta.<column>`, the metadata columns of the transaction.
+const VARIABLES: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "closed",
+];
+
+// What a rule does to a transaction matching its condition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleAction {
+    // Reject with `EngineError::RuleRejected`
+    Reject,
+    // Apply, logging the match to the `audit` log target
+    Flag,
+}
+
+impl RuleAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuleAction::Reject => "reject",
+            RuleAction::Flag => "flag",
+        }
+    }
+}
+
+impl FromStr for RuleAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<RuleAction, ()> {
+        match s {
+            "reject" => Ok(RuleAction::Reject),
+            "flag" => Ok(RuleAction::Flag),
+            _ => Err(()),
+        }
+    }
+}
+
+// Validation or risk rule evaluated before every transaction, on the transaction and the
+// account's balances before it, e.g. `type == "withdrawal" && amount > available / 2`. Set by
+// `EngineBuilder::rules` or the config file, so they change without a rebuild.
+//
+// Conditions are expressions over numbers, strings, `true`, `false` and `null` with `||`, `&&`,
+// `!`, comparisons, `+`, `-`, `*`, `/` and parentheses. Missing values, e.g. the amount of a
+// dispute or a metadata column the input doesn't have, are `null`; arithmetic on `null` is `null`
+// and ordering comparisons with it are false. A condition which fails to evaluate, e.g. adding
+// a string, doesn't match and is logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub action: RuleAction,
+    expression: String,
+    condition: Expr,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RuleError {
+    #[error("Unexpected {0} in rule condition")]
+    Unexpected(String),
+    #[error("Unknown variable {0} in rule condition")]
+    UnknownVariable(String),
+    #[error("Rule condition is too long or nested too deeply")]
+    TooComplex,
+}
+
+impl Rule {
+    pub fn new(name: &str, action: RuleAction, expression: &str) -> Result<Rule, RuleError> {
+        Ok(Rule {
+            name: name.to_string(),
+            action,
+            expression: expression.to_string(),
+            condition: parse_condition(expression, &VARIABLES, true)?,
+        })
+    }
+
+    // The condition as it was written.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    // Whether the transaction matches the condition, given the account's balances before it.
+    pub fn matches<A: Amount>(
+        &self,
+        transaction: &Transaction,
+        account: &Account<A>,
+    ) -> Result<bool, String> {
+        let variable = |name: &str| -> Value {
+            if let Some(column) = name.strip_prefix("meta.") {
+                return transaction
+                    .metadata
+                    .get(column)
+                    .map_or(Value::Null, |value| Value::Str(value.clone()));
+            }
+            match name {
+                "type" => Value::Str(transaction.r#type.name().to_string()),
+                "client" => identifier(transaction.client_id.to_string()),
+                "tx" => identifier(transaction.transaction_id.to_string()),
+                "amount" => transaction.amount.map_or(Value::Null, Value::Number),
+                "available" => Value::Number(account.available.to_decimal()),
+                "held" => Value::Number(account.held.to_decimal()),
+                "total" => Value::Number(account.calculate_total().to_decimal()),
+                "locked" => Value::Bool(account.locked),
+                "closed" => Value::Bool(account.closed),
+                _ => Value::Null,
+            }
+        };
+        match self.condition.eval(&variable)? {
+            Value::Bool(matches) => Ok(matches),
+            value => Err(format!("condition is {}, not true or false", value)),
+        }
+    }
+}
+
+// Numeric ids compare as numbers, alphanumeric ones as strings.
+pub(super) fn identifier(id: String) -> Value {
+    id.parse().map_or(Value::Str(id), Value::Number)
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Evaluate the rules of the policies on a transaction about to be applied to the account.
+    pub(super) fn check_rules(
+        &self,
+        account: &Account<A>,
+        transaction: &Transaction,
+    ) -> Result<(), EngineError> {
+        let policies = self.policies.read().unwrap_or_else(|e| e.into_inner());
+        for rule in &policies.rules {
+            match rule.matches(transaction, account) {
+                Ok(false) => {}
+                Ok(true) if rule.action == RuleAction::Reject => {
+                    return Err(EngineError::RuleRejected(rule.name.clone()));
+                }
+                Ok(true) => info!(
+                    target: "audit",
+                    "Rule {} flagged transaction {} of client {}",
+                    rule.name,
+                    transaction.transaction_id,
+                    transaction.client_id
+                ),
+                Err(e) => warn!(
+                    "Error evaluating rule {} on transaction {}: {}",
+                    rule.name, transaction.transaction_id, e
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Null,
+    Bool(bool),
+    Number(Decimal),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    Literal(Value),
+    Variable(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub(super) fn eval(&self, variable: &dyn Fn(&str) -> Value) -> Result<Value, String> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(name) => Ok(variable(name)),
+            Expr::Not(expr) => match expr.eval(variable)? {
+                Value::Bool(value) => Ok(Value::Bool(!value)),
+                value => Err(format!("! of {}", value)),
+            },
+            Expr::Neg(expr) => match expr.eval(variable)? {
+                Value::Number(value) => Ok(Value::Number(-value)),
+                Value::Null => Ok(Value::Null),
+                value => Err(format!("- of {}", value)),
+            },
+            Expr::Binary(op @ (Op::Or | Op::And), left, right) => {
+                let Value::Bool(left) = left.eval(variable)? else {
+                    return Err(format!("{:?} of a value which isn't true or false", op));
+                };
+                // Short-circuits
+                if left == (*op == Op::Or) {
+                    return Ok(Value::Bool(left));
+                }
+                match right.eval(variable)? {
+                    Value::Bool(right) => Ok(Value::Bool(right)),
+                    value => Err(format!("{:?} of {}", op, value)),
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                binary(*op, left.eval(variable)?, right.eval(variable)?)
+            }
+        }
+    }
+}
+
+fn binary(op: Op, left: Value, right: Value) -> Result<Value, String> {
+    match (op, &left, &right) {
+        (Op::Eq, _, _) => Ok(Value::Bool(left == right)),
+        (Op::Ne, _, _) => Ok(Value::Bool(left != right)),
+        (Op::Lt | Op::Le | Op::Gt | Op::Ge, Value::Null, _)
+        | (Op::Lt | Op::Le | Op::Gt | Op::Ge, _, Value::Null) => Ok(Value::Bool(false)),
+        (Op::Lt | Op::Le | Op::Gt | Op::Ge, Value::Number(l), Value::Number(r)) => {
+            Ok(Value::Bool(compare(op, l.cmp(r))))
+        }
+        (Op::Lt | Op::Le | Op::Gt | Op::Ge, Value::Str(l), Value::Str(r)) => {
+            Ok(Value::Bool(compare(op, l.cmp(r))))
+        }
+        (Op::Add | Op::Sub | Op::Mul | Op::Div, Value::Null, Value::Null | Value::Number(_))
+        | (Op::Add | Op::Sub | Op::Mul | Op::Div, Value::Number(_), Value::Null) => Ok(Value::Null),
+        (Op::Add | Op::Sub | Op::Mul | Op::Div, Value::Number(l), Value::Number(r)) => {
+            let value = match op {
+                Op::Add => l.checked_add(*r),
+                Op::Sub => l.checked_sub(*r),
+                Op::Mul => l.checked_mul(*r),
+                _ => l.checked_div(*r),
+            };
+            value
+                .map(Value::Number)
+                .ok_or_else(|| format!("{} {:?} {} overflows or divides by zero", l, op, r))
+        }
+        _ => Err(format!("{} {:?} {}", left, op, right)),
+    }
+}
+
+fn compare(op: Op, ordering: std::cmp::Ordering) -> bool {
+    match op {
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Decimal),
+    Str(String),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Str(value) => write!(f, "{:?}", value),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+// Longer symbols first, `<=` isn't `<` followed by `=`. A single `=` is `==`.
+const SYMBOLS: [&str; 16] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "=", "!", "+", "-", "*", "/", "(", ")",
+];
+
+// Keywords spelling symbols, in any case, e.g. `locked = true AND total > 1000`
+const KEYWORDS: [(&str, &str); 3] = [("or", "||"), ("and", "&&"), ("not", "!")];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, RuleError> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+        {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else if c == '"' {
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| RuleError::Unexpected("unterminated string".to_string()))?;
+            tokens.push(Token::Str(rest[1..end + 1].to_string()));
+            end + 2
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| RuleError::Unexpected(rest[..end].to_string()))?;
+            tokens.push(Token::Number(number));
+            end
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '.')
+                .unwrap_or(rest.len());
+            let ident = &rest[..end];
+            match KEYWORDS
+                .iter()
+                .find(|(keyword, _)| ident.eq_ignore_ascii_case(keyword))
+            {
+                Some((_, symbol)) => tokens.push(Token::Symbol(symbol)),
+                None => tokens.push(Token::Ident(ident.to_string())),
+            }
+            end
+        } else {
+            return Err(RuleError::Unexpected(c.to_string()));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// Limits on a condition, which can come from a query parameter. Parsing, evaluating and dropping
+// an expression all recurse, so its nesting is bounded to keep them off the end of the stack.
+const MAX_TOKENS: usize = 1024;
+const MAX_DEPTH: usize = 64;
+
+// Parse a condition over the variables, and `meta.<column>` if `metadata` is set.
+pub(super) fn parse_condition(
+    expression: &str,
+    variables: &'static [&'static str],
+    metadata: bool,
+) -> Result<Expr, RuleError> {
+    let tokens = tokenize(expression)?;
+    if tokens.len() > MAX_TOKENS {
+        return Err(RuleError::TooComplex);
+    }
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        depth: 0,
+        variables,
+        metadata,
+    };
+    let condition = parser.or()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(RuleError::Unexpected(token.to_string()));
+    }
+    Ok(condition)
+}
+
+// Recursive descent over the tokens, from the lowest precedence: `||`, `&&`, `!`, comparisons,
+// `+` and `-`, `*` and `/`, unary `-`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    // Nesting of `!`, unary `-` and parentheses around the current token.
+    depth: usize,
+    variables: &'static [&'static str],
+    metadata: bool,
+}
+
+impl Parser {
+    fn next_symbol(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) if symbols.contains(symbol) => {
+                self.position += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    // Parse one level deeper, failing past `MAX_DEPTH`.
+    fn nested(
+        &mut self,
+        parse: fn(&mut Parser) -> Result<Expr, RuleError>,
+    ) -> Result<Expr, RuleError> {
+        if self.depth == MAX_DEPTH {
+            return Err(RuleError::TooComplex);
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn or(&mut self) -> Result<Expr, RuleError> {
+        let mut expr = self.and()?;
+        while self.next_symbol(&["||"]).is_some() {
+            expr = Expr::Binary(Op::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, RuleError> {
+        let mut expr = self.not()?;
+        while self.next_symbol(&["&&"]).is_some() {
+            expr = Expr::Binary(Op::And, Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, RuleError> {
+        if self.next_symbol(&["!"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.nested(Parser::not)?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, RuleError> {
+        let expr = self.sum()?;
+        let op = match self.next_symbol(&["==", "=", "!=", "<", "<=", ">", ">="]) {
+            Some("==" | "=") => Op::Eq,
+            Some("!=") => Op::Ne,
+            Some("<") => Op::Lt,
+            Some("<=") => Op::Le,
+            Some(">") => Op::Gt,
+            Some(">=") => Op::Ge,
+            _ => return Ok(expr),
+        };
+        Ok(Expr::Binary(op, Box::new(expr), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, RuleError> {
+        let mut expr = self.product()?;
+        while let Some(symbol) = self.next_symbol(&["+", "-"]) {
+            let op = if symbol == "+" { Op::Add } else { Op::Sub };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, RuleError> {
+        let mut expr = self.unary()?;
+        while let Some(symbol) = self.next_symbol(&["*", "/"]) {
+            let op = if symbol == "*" { Op::Mul } else { Op::Div };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, RuleError> {
+        if self.next_symbol(&["-"]).is_some() {
+            return Ok(Expr::Neg(Box::new(self.nested(Parser::unary)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, RuleError> {
+        if self.next_symbol(&["("]).is_some() {
+            let expr = self.nested(Parser::or)?;
+            return match self.next_symbol(&[")"]) {
+                Some(_) => Ok(expr),
+                None => Err(self.unexpected()),
+            };
+        }
+        let expr = match self.tokens.get(self.position) {
+            Some(Token::Number(value)) => Expr::Literal(Value::Number(*value)),
+            Some(Token::Str(value)) => Expr::Literal(Value::Str(value.clone())),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                name if self.variables.contains(&name)
+                    || (self.metadata && name.starts_with("meta.")) =>
+                {
+                    Expr::Variable(name.to_string())
+                }
+                name => return Err(RuleError::UnknownVariable(name.to_string())),
+            },
+            _ => return Err(self.unexpected()),
+        };
+        self.position += 1;
+        Ok(expr)
+    }
+
+    fn unexpected(&self) -> RuleError {
+        RuleError::Unexpected(
+            self.tokens
+                .get(self.position)
+                .map_or("end".to_string(), Token::to_string),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::TransactionType;
+
+    #[test]
+    fn test_rule_conditions() {
+        let mut account: Account<Decimal> = Account::new("1".parse().unwrap());
+        account.available = Decimal::from(100);
+        let transaction = Transaction {
+            r#type: TransactionType::Withdraw,
+            client_id: "1".parse().unwrap(),
+            transaction_id: 7,
+            amount: Some(Decimal::from(60)),
+            metadata: [("country".to_string(), "XX".to_string())].into(),
+        };
+        let matches = |condition: &str| {
+            Rule::new("test", RuleAction::Reject, condition)
+                .unwrap()
+                .matches(&transaction, &account)
+        };
+
+        assert_eq!(
+            matches("type == \"withdrawal\" && amount > available / 2"),
+            Ok(true)
+        );
+        assert_eq!(matches("amount - 10 * 2 >= 40 && !locked"), Ok(true));
+        assert_eq!(
+            matches("meta.country == \"XX\" || meta.missing > 1"),
+            Ok(true)
+        );
+        assert_eq!(matches("meta.missing > 1 || held != 0"), Ok(false));
+        assert_eq!(matches("-(amount) < 0 && tx == 7 && client == 1"), Ok(true));
+        assert_eq!(matches("amount = 60 AND not locked Or closed"), Ok(true));
+        assert!(matches("type + 1 > 0").is_err());
+        assert!(matches("amount").is_err());
+
+        assert_eq!(
+            Rule::new("typo", RuleAction::Reject, "ammount > 1"),
+            Err(RuleError::UnknownVariable("ammount".to_string()))
+        );
+        assert_eq!(
+            Rule::new("unbalanced", RuleAction::Reject, "(amount > 1"),
+            Err(RuleError::Unexpected("end".to_string()))
+        );
+        assert!(Rule::new("trailing", RuleAction::Flag, "amount > 1 1").is_err());
+        assert_eq!(
+            Rule::new("deep", RuleAction::Flag, &"!".repeat(60_000)),
+            Err(RuleError::TooComplex)
+        );
+        let nested = format!("{}true{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(Rule::new("nested", RuleAction::Flag, &nested).is_ok());
+        let nested = format!("({})", nested);
+        assert_eq!(
+            Rule::new("nested", RuleAction::Flag, &nested),
+            Err(RuleError::TooComplex)
+        );
+    }
+
+    #[test]
+    fn test_reject_rule() {
+        let engine: Engine = Engine::builder()
+            .rules(vec![
+                Rule::new("flag_all", RuleAction::Flag, "true").unwrap(),
+                Rule::new(
+                    "drains_account",
+                    RuleAction::Reject,
+                    "type == \"withdrawal\" && amount > available / 2",
+                )
+                .unwrap(),
+            ])
+            .build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     withdrawal,1,2,6.0\n\
+                     withdrawal,1,3,4.0\n";
+        let results: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|transaction| engine.process(transaction.unwrap()))
+            .collect();
+
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(EngineError::RuleRejected("drains_account".to_string())),
+                Ok(())
+            ]
+        );
+        assert_eq!(
+            engine.account("1".parse().unwrap()).unwrap().available,
+            Decimal::from(6)
+        );
+    }
+}
@@ -0,0 +1,616 @@
+use std::io;
+use std::str::FromStr;
+
+use log::info;
+use rust_decimal::Decimal;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::dunning::DunningStage;
+use super::engine::Engine;
+use super::journal::{Book, Books};
+use super::notes::Note;
+use super::reserve::Reserve;
+use super::risk::Activity;
+use super::store::AccountStore;
+use super::structuring::NearThreshold;
+use super::transaction::{ClientId, Metadata, Transaction, TransactionDetails};
+
+// Version of the snapshot layout, bumped when a record changes. Records of earlier versions are
+// read as they were written, see `read_record`, so state of earlier releases stays loadable and
+// `migrate` can rewrite it in the current layout.
+// * 1: `books` records without the client reserve before the rolling reserve, or missing
+// * 2: `books` records with all four balances
+// * 3: `account` records with the idle periods and dormant flag
+// * 4: `tx` records with the time of the dispute
+// * 5: `account` records with the time of the last activity
+// * 6: `approval` records
+pub const SNAPSHOT_VERSION: u32 = 6;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("Malformed snapshot record on line {0}")]
+    Malformed(u64),
+    #[error("Unsupported snapshot version: {0}")]
+    UnsupportedVersion(String),
+    #[error("Client {0} is in both engines")]
+    ClientConflict(ClientId),
+    #[error("Failed to load spilled account {0}: {1}")]
+    Spilled(ClientId, Box<SnapshotError>),
+}
+
+// Complete engine state, the accounts with their stored transactions. Written as CSV with one
+// record per line:
+// * `snapshot,<version>`
+// * `account,<client>,<available>,<held>,<locked>,<quarantined>,<closed>,<idle_periods>,<dormant>,
+//   <last_activity>` for each account, `last_activity` empty if unknown
+// * `books,<settlement>,<chargeback_loss>,<fee_income>,<client_reserve>` balances of the
+//   preceding account's books other than its available and held funds
+// * `activity,<transactions>,<deposits>,<disputes>,<chargebacks>` of the preceding account
+// * `tx,<id>,<amount>,<status>,<disputed_at>[,<key>,<value>...]` for each stored transaction of
+//   the preceding account, when it was last disputed (empty if unknown) and its metadata
+// * `reserve,<id>,<amount>,<release_at>` for each part of a deposit of the preceding account in
+//   the rolling reserve
+// * `dunning,<stage>,<negative_since>` of the preceding account if its available funds are
+//   negative or it was escalated, `negative_since` empty if unknown
+// * `queued,<type>,<id>,<amount>[,<key>,<value>...]` for each transaction queued on the
+//   preceding locked account, in order, and its metadata
+// * `near_threshold,<type>,<id>,<amount>,<at>` for each transaction of the preceding account in
+//   the structuring window, and `structuring,...` alike for each one it was flagged for
+// * `note,<tx>,<at>,<case>,<text>` for each note on the preceding account, in order, `tx`, `at`
+//   and `case` empty if unset
+// * `suspense,<id>` for each dispute of the preceding account waiting for its transaction
+// * `delivered,<fingerprint>` for each record accepted for the preceding account in idempotent
+//   mode
+// * `approval,<id>,<client>,<type>,<tx>,<amount>[,<key>,<value>...]` for each transaction held for
+//   approval, see `Engine::hold`, after all accounts
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    pub fn write_snapshot<W: io::Write>(&self, writer: W) -> Result<(), SnapshotError> {
+        let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+        writer.write_record(["snapshot", &SNAPSHOT_VERSION.to_string()])?;
+
+        let mut result = Ok(());
+        self.accounts.try_for_each(|account_manager| {
+            if result.is_ok() {
+                result = write_account_manager(&mut writer, account_manager);
+            }
+        })?;
+        result?;
+        for (id, transaction) in self.held() {
+            writer.write_record(
+                [
+                    "approval".to_string(),
+                    id.to_string(),
+                    transaction.client_id.to_string(),
+                ]
+                .into_iter()
+                .chain(transaction_fields(&transaction)),
+            )?;
+        }
+        writer.flush().map_err(csv::Error::from)?;
+        Ok(())
+    }
+
+    // Load accounts from a snapshot, replacing accounts of the same clients. Returns the number
+    // of loaded accounts.
+    pub fn restore_snapshot<R: io::Read>(&self, reader: R) -> Result<usize, SnapshotError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader);
+        let mut records = reader.records();
+        let version = read_version(records.next().transpose()?)?;
+
+        let mut restored = 0;
+        let mut current = None;
+        for (line, record) in (2..).zip(records) {
+            let record = record?;
+            if version >= 6 && record.get(0) == Some("approval") {
+                let (id, transaction) =
+                    read_approval(&record).map_err(|_| SnapshotError::Malformed(line))?;
+                self.approvals().restore(id, transaction);
+                continue;
+            }
+            let completed = read_record(&record, version, &mut current)
+                .map_err(|_| SnapshotError::Malformed(line))?;
+            if let Some(account_manager) = completed {
+                self.restore(account_manager);
+                restored += 1;
+            }
+        }
+        if let Some(account_manager) = current {
+            self.restore(account_manager);
+            restored += 1;
+        }
+        info!("Restored {} accounts from snapshot", restored);
+        Ok(restored)
+    }
+
+    // Copy all accounts of another engine, e.g. of another client shard, into this one. The
+    // engines must not share clients. Returns the number of merged accounts.
+    pub fn merge<T: AccountStore<A>>(&self, other: &Engine<A, T>) -> Result<usize, SnapshotError> {
+        let mut conflict = None;
+        other.accounts.try_for_each(|account_manager| {
+            let client_id = account_manager.account.client_id;
+            if conflict.is_none() && self.account(client_id).is_some() {
+                conflict = Some(client_id);
+            }
+        })?;
+        if let Some(client_id) = conflict {
+            return Err(SnapshotError::ClientConflict(client_id));
+        }
+
+        let mut snapshot = Vec::new();
+        other.write_snapshot(&mut snapshot)?;
+        self.restore_snapshot(snapshot.as_slice())
+    }
+
+    fn restore(&self, account_manager: AccountManager<A>) {
+        self.accounts
+            .with_account(account_manager.account.client_id, |existing| {
+                *existing = account_manager
+            });
+    }
+}
+
+// Version of the snapshot in the reader, from its header.
+pub fn snapshot_version<R: io::Read>(reader: R) -> Result<u32, SnapshotError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    read_version(reader.records().next().transpose()?)
+}
+
+fn read_version(header: Option<csv::StringRecord>) -> Result<u32, SnapshotError> {
+    match header {
+        Some(header) if header.get(0) == Some("snapshot") => {
+            let version = header.get(1).unwrap_or_default();
+            match version.parse() {
+                Ok(version @ 1..=SNAPSHOT_VERSION) => Ok(version),
+                _ => Err(SnapshotError::UnsupportedVersion(version.to_string())),
+            }
+        }
+        _ => Err(SnapshotError::Malformed(1)),
+    }
+}
+
+// `<type>,<tx>,<amount>[,<key>,<value>...]` of a queued or held transaction.
+fn transaction_fields(transaction: &Transaction) -> impl Iterator<Item = String> + '_ {
+    [
+        transaction.r#type.name().to_string(),
+        transaction.transaction_id.to_string(),
+        transaction
+            .amount
+            .map(|amount| amount.to_string())
+            .unwrap_or_default(),
+    ]
+    .into_iter()
+    .chain(
+        transaction
+            .metadata
+            .iter()
+            .flat_map(|(key, value)| [key.clone(), value.clone()]),
+    )
+}
+
+// Transaction of the client from the fields of `transaction_fields` starting at `start`.
+fn read_transaction(
+    client_id: ClientId,
+    record: &csv::StringRecord,
+    start: usize,
+) -> Result<Transaction, ()> {
+    let mut fields = record.iter().skip(start);
+    let (Some(r#type), Some(transaction_id), Some(value)) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(());
+    };
+    let mut transaction = Transaction {
+        r#type: r#type.parse()?,
+        client_id,
+        transaction_id: transaction_id.parse().map_err(drop)?,
+        amount: None,
+        metadata: Metadata::new(),
+    };
+    if !value.is_empty() {
+        transaction.amount = Some(Decimal::from_str(value).map_err(drop)?);
+    }
+    while let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+        transaction
+            .metadata
+            .insert(key.to_string(), value.to_string());
+    }
+    Ok(transaction)
+}
+
+fn read_approval(record: &csv::StringRecord) -> Result<(u64, Transaction), ()> {
+    let id = record.get(1).ok_or(())?.parse().map_err(drop)?;
+    let client_id = record.get(2).ok_or(())?.parse().map_err(drop)?;
+    Ok((id, read_transaction(client_id, record, 3)?))
+}
+
+// Records of an account and its stored transactions, shared with the spilled account files.
+pub(super) fn write_account_manager<A: Amount, W: io::Write>(
+    writer: &mut csv::Writer<W>,
+    account_manager: &AccountManager<A>,
+) -> Result<(), SnapshotError> {
+    let account = &account_manager.account;
+    writer.write_record([
+        "account",
+        &account.client_id.to_string(),
+        &account.available.to_decimal().to_string(),
+        &account.held.to_decimal().to_string(),
+        &account.locked.to_string(),
+        &account.quarantined.to_string(),
+        &account.closed.to_string(),
+        &account_manager.idle_periods.to_string(),
+        &account.dormant.to_string(),
+        &account_manager
+            .last_activity
+            .map(|at| at.to_string())
+            .unwrap_or_default(),
+    ])?;
+    let books = &account_manager.books;
+    writer.write_record(
+        std::iter::once("books".to_string()).chain(
+            [
+                Book::Settlement,
+                Book::ChargebackLoss,
+                Book::FeeIncome,
+                Book::ClientReserve,
+            ]
+            .map(|book| books.balance(book).to_decimal().to_string()),
+        ),
+    )?;
+    let activity = &account_manager.activity;
+    writer.write_record([
+        "activity".to_string(),
+        activity.transactions.to_string(),
+        activity.deposits.to_string(),
+        activity.disputes.to_string(),
+        activity.chargebacks.to_string(),
+    ])?;
+    for (transaction_id, details) in &account_manager.transactions {
+        let fields = [
+            "tx".to_string(),
+            transaction_id.to_string(),
+            details.amount.to_decimal().to_string(),
+            details.status.as_str().to_string(),
+            details
+                .disputed_at
+                .map(|at| at.to_string())
+                .unwrap_or_default(),
+        ];
+        let metadata = details
+            .metadata
+            .iter()
+            .flat_map(|(key, value)| [key.clone(), value.clone()]);
+        writer.write_record(fields.into_iter().chain(metadata))?;
+    }
+    for reserve in &account_manager.reserves {
+        writer.write_record([
+            "reserve".to_string(),
+            reserve.transaction_id.to_string(),
+            reserve.amount.to_decimal().to_string(),
+            reserve.release_at.to_string(),
+        ])?;
+    }
+    if account_manager.account.dunning != DunningStage::Current
+        || account_manager.negative_since.is_some()
+    {
+        writer.write_record([
+            "dunning".to_string(),
+            account_manager.account.dunning.as_str().to_string(),
+            account_manager
+                .negative_since
+                .map(|since| since.to_string())
+                .unwrap_or_default(),
+        ])?;
+    }
+    for transaction in &account_manager.queued {
+        writer.write_record(
+            std::iter::once("queued".to_string()).chain(transaction_fields(transaction)),
+        )?;
+    }
+    for (kind, transactions) in [
+        (
+            "near_threshold",
+            account_manager.near_threshold.iter().collect::<Vec<_>>(),
+        ),
+        ("structuring", account_manager.structuring.iter().collect()),
+    ] {
+        for transaction in transactions {
+            writer.write_record([
+                kind.to_string(),
+                transaction.r#type.name().to_string(),
+                transaction.transaction_id.to_string(),
+                transaction.amount.to_string(),
+                transaction.at.to_string(),
+            ])?;
+        }
+    }
+    for note in &account_manager.notes {
+        writer.write_record([
+            "note".to_string(),
+            note.tx.map(|tx| tx.to_string()).unwrap_or_default(),
+            note.at.map(|at| at.to_string()).unwrap_or_default(),
+            note.case.clone().unwrap_or_default(),
+            note.text.clone(),
+        ])?;
+    }
+    for transaction_id in &account_manager.suspense {
+        writer.write_record(["suspense".to_string(), transaction_id.to_string()])?;
+    }
+    for fingerprint in &account_manager.delivered {
+        writer.write_record(["delivered".to_string(), fingerprint.to_string()])?;
+    }
+    Ok(())
+}
+
+// Apply one record to the account being read. An account record starts a new account and returns
+// the previous one, which is complete.
+pub(super) fn read_record<A: Amount>(
+    record: &csv::StringRecord,
+    version: u32,
+    current: &mut Option<AccountManager<A>>,
+) -> Result<Option<AccountManager<A>>, ()> {
+    let amount = |value: &str| {
+        Decimal::from_str(value)
+            .ok()
+            .and_then(A::from_decimal)
+            .ok_or(())
+    };
+    let flag = |value: Option<&str>| value.ok_or(())?.parse::<bool>().map_err(drop);
+
+    match (record.get(0), record.get(1), record.get(2), record.get(3)) {
+        (Some("account"), Some(client_id), Some(available), Some(held)) => {
+            let mut account_manager =
+                AccountManager::new(client_id.parse::<ClientId>().map_err(drop)?);
+            let account = &mut account_manager.account;
+            account.available = amount(available)?;
+            account.held = amount(held)?;
+            account.locked = flag(record.get(4))?;
+            account.quarantined = flag(record.get(5))?;
+            account.closed = flag(record.get(6))?;
+            // Version 2 and earlier snapshots don't count idle periods
+            if version >= 3 {
+                account.dormant = flag(record.get(8))?;
+            }
+            // Version 1 snapshots without books are funded from settlement, later versions
+            // have the `books` record
+            account_manager.books = Books::opening(account.available, account.held).ok_or(())?;
+            if version >= 3 {
+                account_manager.idle_periods = record.get(7).ok_or(())?.parse().map_err(drop)?;
+            }
+            // Version 4 and earlier snapshots lose when accounts were last active
+            if version >= 5 {
+                let last_activity = record.get(9).ok_or(())?;
+                if !last_activity.is_empty() {
+                    account_manager.last_activity = Some(last_activity.parse()?);
+                }
+            }
+            Ok(current.replace(account_manager))
+        }
+        (Some("books"), Some(settlement), Some(chargeback_loss), Some(fee_income)) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            let books = &mut account_manager.books;
+            books.set(Book::Settlement, amount(settlement)?);
+            books.set(Book::ChargebackLoss, amount(chargeback_loss)?);
+            books.set(Book::FeeIncome, amount(fee_income)?);
+            match record.get(4) {
+                Some(client_reserve) => {
+                    books.set(Book::ClientReserve, amount(client_reserve)?);
+                    account_manager.account.reserve = amount(client_reserve)?;
+                }
+                // Version 1 snapshots before the rolling reserve don't have it
+                None if version == 1 => {}
+                None => return Err(()),
+            }
+            Ok(None)
+        }
+        (Some("activity"), Some(transactions), Some(deposits), Some(disputes)) => {
+            let count = |value: &str| value.parse::<u64>().map_err(drop);
+            current.as_mut().ok_or(())?.activity = Activity {
+                transactions: count(transactions)?,
+                deposits: count(deposits)?,
+                disputes: count(disputes)?,
+                chargebacks: count(record.get(4).ok_or(())?)?,
+            };
+            Ok(None)
+        }
+        (Some("tx"), Some(transaction_id), Some(value), Some(status)) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            let mut details = TransactionDetails::new(amount(value)?);
+            details.status = status.parse()?;
+            // Version 3 and earlier snapshots lose when transactions were disputed
+            let mut metadata = record.iter().skip(4);
+            if version >= 4 {
+                let disputed_at = metadata.next().ok_or(())?;
+                if !disputed_at.is_empty() {
+                    details.disputed_at = Some(disputed_at.parse()?);
+                }
+            }
+            while let (Some(key), Some(value)) = (metadata.next(), metadata.next()) {
+                details.metadata.insert(key.to_string(), value.to_string());
+            }
+            account_manager
+                .transactions
+                .insert(transaction_id.parse().map_err(drop)?, details);
+            Ok(None)
+        }
+        (Some("reserve"), Some(transaction_id), Some(value), Some(release_at)) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            account_manager.reserves.push_back(Reserve {
+                transaction_id: transaction_id.parse().map_err(drop)?,
+                amount: amount(value)?,
+                release_at: release_at.parse()?,
+            });
+            Ok(None)
+        }
+        (Some("dunning"), Some(stage), Some(negative_since), None) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            account_manager.account.dunning = stage.parse()?;
+            if !negative_since.is_empty() {
+                account_manager.negative_since = Some(negative_since.parse()?);
+            }
+            Ok(None)
+        }
+        (Some("queued"), ..) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            let transaction = read_transaction(account_manager.account.client_id, record, 1)?;
+            account_manager.queued.push_back(transaction);
+            Ok(None)
+        }
+        (
+            Some(kind @ ("near_threshold" | "structuring")),
+            Some(r#type),
+            Some(transaction_id),
+            Some(value),
+        ) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            let transaction = NearThreshold {
+                transaction_id: transaction_id.parse().map_err(drop)?,
+                r#type: r#type.parse()?,
+                amount: Decimal::from_str(value).map_err(drop)?,
+                at: record.get(4).ok_or(())?.parse()?,
+            };
+            if kind == "near_threshold" {
+                account_manager.near_threshold.push_back(transaction);
+            } else {
+                account_manager.structuring.push(transaction);
+            }
+            Ok(None)
+        }
+        (Some("note"), Some(transaction_id), Some(at), Some(case)) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            account_manager.notes.push(Note {
+                tx: (!transaction_id.is_empty())
+                    .then(|| transaction_id.parse())
+                    .transpose()
+                    .map_err(drop)?,
+                at: (!at.is_empty()).then(|| at.parse()).transpose()?,
+                case: (!case.is_empty()).then(|| case.to_string()),
+                text: record.get(4).ok_or(())?.to_string(),
+            });
+            Ok(None)
+        }
+        (Some("suspense"), Some(transaction_id), None, None) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            account_manager
+                .suspense
+                .insert(transaction_id.parse().map_err(drop)?);
+            Ok(None)
+        }
+        (Some("delivered"), Some(fingerprint), None, None) => {
+            let account_manager = current.as_mut().ok_or(())?;
+            account_manager
+                .delivered
+                .insert(fingerprint.parse().map_err(drop)?);
+            Ok(None)
+        }
+        _ => Err(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Metadata, TxStatus};
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_round_trip() {
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,1.5,\"rent, march\"\n\
+                     deposit,1,2,2.0,\n\
+                     dispute,1,2,,\n\
+                     dispute,1,9,,\n\
+                     deposit,2,3,3.0,\n\
+                     dispute,2,3,,\n\
+                     chargeback,2,3,,\n";
+        let reader = csv::Reader::from_reader(input.as_bytes());
+        let mut engine: Engine = Engine::builder().dispute_suspense().build();
+        engine
+            .process_transactions_blocking(SerdeParser::new(reader).unwrap())
+            .unwrap();
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::new();
+        assert_eq!(restored.restore_snapshot(snapshot.as_slice()).unwrap(), 2);
+
+        let sorted = |engine: &Engine| {
+            let mut accounts = engine.accounts().unwrap();
+            accounts.sort_by_key(|a| a.client_id);
+            accounts
+        };
+        assert_eq!(sorted(&restored), sorted(&engine));
+        assert_eq!(
+            restored.risk_report(Default::default()),
+            engine.risk_report(Default::default())
+        );
+        assert_eq!(restored.trial_balance(), engine.trial_balance());
+        assert_eq!(restored.suspense(), engine.suspense());
+        let client_id = "1".parse().unwrap();
+        assert_eq!(
+            restored.transaction_metadata(client_id, 1),
+            Some(Metadata::from([(
+                "memo".to_string(),
+                "rent, march".to_string()
+            )]))
+        );
+        assert_eq!(
+            restored.transaction_status(client_id, 2),
+            Some(TxStatus::Disputed)
+        );
+    }
+
+    #[test]
+    fn test_round_trip_last_timestamp() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,9999-12-31T23:59:59Z\n\
+                     deposit,1,2,10.0,253402300800\n\
+                     dispute,1,1,,253402300799\n";
+        let reader = csv::Reader::from_reader(input.as_bytes());
+        let mut engine: Engine = Engine::new();
+        engine
+            .process_transactions_blocking(SerdeParser::new(reader).unwrap())
+            .unwrap();
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        assert!(String::from_utf8_lossy(&snapshot).contains("9999-12-31T23:59:59Z"));
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+
+        let client_id = "1".parse().unwrap();
+        assert_eq!(restored.account(client_id), engine.account(client_id));
+        assert_eq!(
+            restored.transaction_status(client_id, 1),
+            Some(TxStatus::Disputed)
+        );
+    }
+
+    #[test]
+    fn test_invalid_snapshots() {
+        let engine: Engine = Engine::new();
+        let restore = |input: &str| engine.restore_snapshot(input.as_bytes());
+
+        assert!(matches!(
+            restore(&format!("snapshot,{}\n", SNAPSHOT_VERSION + 1)),
+            Err(SnapshotError::UnsupportedVersion(version))
+                if version == (SNAPSHOT_VERSION + 1).to_string()
+        ));
+        assert!(matches!(
+            restore("account,1,1.0,0.0,false,false,false\n"),
+            Err(SnapshotError::Malformed(1))
+        ));
+        assert!(matches!(
+            restore("snapshot,1\ntx,1,1.0,settled\n"),
+            Err(SnapshotError::Malformed(2))
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+use dashmap::DashMap;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::snapshot::SnapshotError;
+use super::transaction::ClientId;
+
+mod retry;
+mod spill;
+
+pub use self::retry::{is_retryable, RetryPolicy};
+pub use self::spill::SpillingStore;
+
+// Storage of per-client account managers used by the engine.
+//
+// Implementations decide how concurrent access to different accounts is synchronized. Access to
+// a single account is always exclusive, which is what keeps transactions of one client ordered.
+//
+// Stores keeping accounts outside of memory can fail to read them back. They return the error
+// from the `try_` methods, which the engine uses wherever it can fail, and panic in the others.
+pub trait AccountStore<A: Amount>: Send + Sync {
+    // Run `f` with exclusive access to the client's account manager, creating it if necessary.
+    fn with_account<R, F>(&self, client_id: ClientId, f: F) -> R
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R;
+
+    // Run `f` with the client's account manager if it exists, without creating it.
+    fn with_existing_account<R, F>(&self, client_id: ClientId, f: F) -> Option<R>
+    where
+        F: FnOnce(&AccountManager<A>) -> R;
+
+    // Visit all account managers. Order is implementation specific.
+    fn for_each<F>(&self, f: F)
+    where
+        F: FnMut(&AccountManager<A>);
+
+    // Visit all account managers with exclusive access.
+    fn for_each_mut<F>(&self, f: F)
+    where
+        F: FnMut(&mut AccountManager<A>);
+
+    // Remove the client's account manager, returning it if it existed.
+    fn remove(&self, client_id: ClientId) -> Option<AccountManager<A>>;
+
+    // As `with_account`, failing if the account can't be read back.
+    fn try_with_account<R, F>(&self, client_id: ClientId, f: F) -> Result<R, SnapshotError>
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R,
+    {
+        Ok(self.with_account(client_id, f))
+    }
+
+    // As `for_each`, failing if an account can't be read back. Accounts visited before stay
+    // visited.
+    fn try_for_each<F>(&self, f: F) -> Result<(), SnapshotError>
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        self.for_each(f);
+        Ok(())
+    }
+
+    // As `for_each_mut`, failing if an account can't be read back. Changes to accounts visited
+    // before are kept.
+    fn try_for_each_mut<F>(&self, f: F) -> Result<(), SnapshotError>
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        self.for_each_mut(f);
+        Ok(())
+    }
+
+    // Visit the account managers held in memory, all of them unless the store keeps some
+    // elsewhere.
+    fn for_each_resident<F>(&self, f: F)
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        self.for_each(f)
+    }
+
+    // Number of accounts in memory per shard, a single shard for unsharded stores.
+    fn shard_sizes(&self) -> Vec<usize> {
+        let mut accounts = 0;
+        self.for_each_resident(|_| accounts += 1);
+        vec![accounts]
+    }
+}
+
+// Concurrent hashmap with fine grained internal locking, the default store.
+pub struct DashMapStore<A> {
+    accounts: DashMap<ClientId, AccountManager<A>>,
+}
+
+impl<A: Amount> Default for DashMapStore<A> {
+    fn default() -> Self {
+        DashMapStore {
+            accounts: DashMap::new(),
+        }
+    }
+}
+
+impl<A: Amount> DashMapStore<A> {
+    // Map pre-sized for `capacity` accounts, so it doesn't rehash while growing to that size.
+    pub fn with_capacity(capacity: usize) -> DashMapStore<A> {
+        DashMapStore {
+            accounts: DashMap::with_capacity(capacity),
+        }
+    }
+
+    // Map pre-sized for `capacity` accounts split over `shards` locks, rounded up to a power of
+    // two as the map requires. More shards mean less contention between workers.
+    pub fn with_capacity_and_shards(capacity: usize, shards: usize) -> DashMapStore<A> {
+        DashMapStore {
+            accounts: DashMap::with_capacity_and_shard_amount(
+                capacity,
+                shards.max(2).next_power_of_two(),
+            ),
+        }
+    }
+}
+
+impl<A: Amount> AccountStore<A> for DashMapStore<A> {
+    fn with_account<R, F>(&self, client_id: ClientId, f: F) -> R
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R,
+    {
+        let mut account_manager = self
+            .accounts
+            .entry(client_id)
+            .or_insert_with(|| AccountManager::new(client_id));
+        f(&mut account_manager)
+    }
+
+    fn with_existing_account<R, F>(&self, client_id: ClientId, f: F) -> Option<R>
+    where
+        F: FnOnce(&AccountManager<A>) -> R,
+    {
+        self.accounts.get(&client_id).map(|entry| f(entry.value()))
+    }
+
+    fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        self.accounts.iter().for_each(|entry| f(entry.value()));
+    }
+
+    fn for_each_mut<F>(&self, mut f: F)
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        self.accounts
+            .iter_mut()
+            .for_each(|mut entry| f(entry.value_mut()));
+    }
+
+    fn shard_sizes(&self) -> Vec<usize> {
+        self.accounts
+            .shards()
+            .iter()
+            .map(|shard| shard.read().len())
+            .collect()
+    }
+
+    fn remove(&self, client_id: ClientId) -> Option<AccountManager<A>> {
+        self.accounts
+            .remove(&client_id)
+            .map(|(_, account_manager)| account_manager)
+    }
+}
+
+// Fixed number of mutex protected hashmaps, a client is always assigned to the same shard.
+pub struct ShardedStore<A> {
+    shards: Vec<Mutex<HashMap<ClientId, AccountManager<A>>>>,
+}
+
+impl<A: Amount> ShardedStore<A> {
+    pub fn new(shards: usize) -> ShardedStore<A> {
+        Self::with_capacity(shards, 0)
+    }
+
+    // Few shards per core keep the chance of two workers contending on a shard low.
+    pub fn default_shards() -> usize {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        cores * 4
+    }
+
+    // Shards pre-sized to hold `capacity` accounts together.
+    pub fn with_capacity(shards: usize, capacity: usize) -> ShardedStore<A> {
+        let shards = shards.max(1);
+        ShardedStore {
+            shards: (0..shards)
+                .map(|_| Mutex::new(HashMap::with_capacity(capacity.div_ceil(shards))))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, client_id: ClientId) -> MutexGuard<'_, HashMap<ClientId, AccountManager<A>>> {
+        let mut hasher = DefaultHasher::new();
+        client_id.hash(&mut hasher);
+        let shard = &self.shards[hasher.finish() as usize % self.shards.len()];
+        shard.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<A: Amount> Default for ShardedStore<A> {
+    fn default() -> Self {
+        Self::new(Self::default_shards())
+    }
+}
+
+impl<A: Amount> AccountStore<A> for ShardedStore<A> {
+    fn with_account<R, F>(&self, client_id: ClientId, f: F) -> R
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R,
+    {
+        let mut accounts = self.shard(client_id);
+        f(accounts
+            .entry(client_id)
+            .or_insert_with(|| AccountManager::new(client_id)))
+    }
+
+    fn with_existing_account<R, F>(&self, client_id: ClientId, f: F) -> Option<R>
+    where
+        F: FnOnce(&AccountManager<A>) -> R,
+    {
+        self.shard(client_id).get(&client_id).map(f)
+    }
+
+    fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        for shard in &self.shards {
+            let accounts = shard.lock().unwrap_or_else(|e| e.into_inner());
+            accounts.values().for_each(&mut f);
+        }
+    }
+
+    fn for_each_mut<F>(&self, mut f: F)
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        for shard in &self.shards {
+            let mut accounts = shard.lock().unwrap_or_else(|e| e.into_inner());
+            accounts.values_mut().for_each(&mut f);
+        }
+    }
+
+    fn shard_sizes(&self) -> Vec<usize> {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|e| e.into_inner()).len())
+            .collect()
+    }
+
+    fn remove(&self, client_id: ClientId) -> Option<AccountManager<A>> {
+        self.shard(client_id).remove(&client_id)
+    }
+}
+
+// Single mutex protected BTreeMap, visits accounts ordered by client id.
+pub struct BTreeMapStore<A> {
+    accounts: Mutex<BTreeMap<ClientId, AccountManager<A>>>,
+}
+
+impl<A: Amount> Default for BTreeMapStore<A> {
+    fn default() -> Self {
+        BTreeMapStore {
+            accounts: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<A: Amount> AccountStore<A> for BTreeMapStore<A> {
+    fn with_account<R, F>(&self, client_id: ClientId, f: F) -> R
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R,
+    {
+        let mut accounts = self.accounts.lock().unwrap_or_else(|e| e.into_inner());
+        f(accounts
+            .entry(client_id)
+            .or_insert_with(|| AccountManager::new(client_id)))
+    }
+
+    fn with_existing_account<R, F>(&self, client_id: ClientId, f: F) -> Option<R>
+    where
+        F: FnOnce(&AccountManager<A>) -> R,
+    {
+        let accounts = self.accounts.lock().unwrap_or_else(|e| e.into_inner());
+        accounts.get(&client_id).map(f)
+    }
+
+    fn for_each<F>(&self, f: F)
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        let accounts = self.accounts.lock().unwrap_or_else(|e| e.into_inner());
+        accounts.values().for_each(f);
+    }
+
+    fn for_each_mut<F>(&self, f: F)
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        let mut accounts = self.accounts.lock().unwrap_or_else(|e| e.into_inner());
+        accounts.values_mut().for_each(f);
+    }
+
+    fn remove(&self, client_id: ClientId) -> Option<AccountManager<A>> {
+        let mut accounts = self.accounts.lock().unwrap_or_else(|e| e.into_inner());
+        accounts.remove(&client_id)
+    }
+}
@@ -0,0 +1,162 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::engine::snapshot::SnapshotError;
+
+// How a store retries operations on its backing storage which failed transiently, e.g. a busy
+// disk or a network filesystem briefly gone. The backoff doubles after every failed attempt up to
+// `max_backoff`. Fatal errors, like a corrupt or missing record, are returned right away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    // Attempts including the first one, 1 to never retry
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    // Run `operation` until it succeeds, fails fatally or runs out of attempts, returning its
+    // last error.
+    pub fn run<T, F>(&self, what: &str, mut operation: F) -> Result<T, SnapshotError>
+    where
+        F: FnMut() -> Result<T, SnapshotError>,
+    {
+        for attempt in 1u32.. {
+            match operation() {
+                Err(e) => {
+                    let Some(backoff) = self.backoff(attempt, &e) else {
+                        return Err(e);
+                    };
+                    warn!(
+                        "Attempt {} to {} failed, retrying in {:?}: {}",
+                        attempt, what, backoff, e
+                    );
+                    thread::sleep(backoff);
+                }
+                result => return result,
+            }
+        }
+        unreachable!("attempts are unbounded")
+    }
+
+    // How long to wait after the failed `attempt` (counted from 1) before the next one, None if
+    // the error is fatal or it was the last attempt. For callers which can't sleep in `run`, e.g.
+    // holding a lock they release while waiting.
+    pub fn backoff(&self, attempt: u32, error: &SnapshotError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_retryable(error) {
+            return None;
+        }
+        let doublings = 2u32.saturating_pow(attempt - 1);
+        Some(
+            self.initial_backoff
+                .saturating_mul(doublings)
+                .min(self.max_backoff),
+        )
+    }
+}
+
+// Whether an error may go away by trying again. Only I/O errors of kinds which describe a
+// temporary condition are, anything else means the stored data or its location is wrong.
+pub fn is_retryable(error: &SnapshotError) -> bool {
+    let SnapshotError::Csv(error) = error else {
+        return false;
+    };
+    let csv::ErrorKind::Io(error) = error.kind() else {
+        return false;
+    };
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error(kind: io::ErrorKind) -> SnapshotError {
+        SnapshotError::Csv(csv::Error::from(io::Error::from(kind)))
+    }
+
+    #[test]
+    fn test_retries_transient_errors_only() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::ZERO,
+            ..RetryPolicy::default()
+        };
+
+        let mut attempts = 0;
+        let result = policy.run("read", || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(io_error(io::ErrorKind::TimedOut)),
+                _ => Ok(attempts),
+            }
+        });
+        assert!(matches!(result, Ok(3)));
+
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run("read", || {
+            attempts += 1;
+            Err(io_error(io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run("read", || {
+            attempts += 1;
+            Err(io_error(io::ErrorKind::Interrupted))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 5);
+        assert!(!is_retryable(&SnapshotError::Malformed(1)));
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        let timed_out = io_error(io::ErrorKind::TimedOut);
+        let backoffs: Vec<_> = (1..=10)
+            .map(|attempt| policy.backoff(attempt, &timed_out))
+            .collect();
+        assert_eq!(backoffs[0], Some(Duration::from_millis(100)));
+        assert_eq!(backoffs[1], Some(Duration::from_millis(200)));
+        assert_eq!(backoffs[2], Some(Duration::from_millis(400)));
+        assert_eq!(backoffs[8], Some(Duration::from_millis(500)));
+        assert_eq!(backoffs[9], None);
+        assert_eq!(policy.backoff(1, &SnapshotError::Malformed(1)), None);
+    }
+}
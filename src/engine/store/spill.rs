@@ -0,0 +1,522 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+use log::{error, warn};
+
+use super::{AccountStore, RetryPolicy};
+use crate::engine::account_manager::AccountManager;
+use crate::engine::amount::Amount;
+use crate::engine::snapshot::{self, SnapshotError};
+use crate::engine::transaction::ClientId;
+
+struct Resident<A> {
+    account_manager: AccountManager<A>,
+    last_used: u64,
+}
+
+struct State<A> {
+    resident: HashMap<ClientId, Resident<A>>,
+    // Least recently used account first
+    recency: BTreeMap<u64, ClientId>,
+    spilled: HashSet<ClientId>,
+    tick: u64,
+}
+
+// Locked state and the account read from its file, if it was spilled
+type Loaded<'a, A> = (MutexGuard<'a, State<A>>, Option<AccountManager<A>>);
+
+// Store keeping a bounded number of account managers in memory.
+//
+// When more than `max_resident` accounts are in memory the least recently used ones are spilled
+// to per-client files in `directory` and transparently loaded back on the next reference. All
+// accesses go through a single lock, trading throughput for bounded memory.
+//
+// Reads of the files are retried following the store's `RetryPolicy`, with the lock released
+// while backing off. Accounts which can't be read back fail the `try_` methods of
+// `AccountStore`; losing one would silently corrupt balances, so the others panic instead.
+// Accounts which can't be written stay in memory and are written on a later eviction.
+pub struct SpillingStore<A> {
+    state: Mutex<State<A>>,
+    directory: PathBuf,
+    max_resident: usize,
+    retry: RetryPolicy,
+}
+
+impl<A: Amount> SpillingStore<A> {
+    pub fn new<P: AsRef<Path>>(directory: P, max_resident: usize) -> io::Result<SpillingStore<A>> {
+        fs::create_dir_all(&directory)?;
+        Ok(SpillingStore {
+            state: Mutex::new(State {
+                resident: HashMap::new(),
+                recency: BTreeMap::new(),
+                spilled: HashSet::new(),
+                tick: 0,
+            }),
+            directory: directory.as_ref().to_path_buf(),
+            max_resident: max_resident.max(1),
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> SpillingStore<A> {
+        self.retry = retry;
+        self
+    }
+
+    fn path(&self, client_id: ClientId) -> PathBuf {
+        self.directory.join(format!("{}.csv", client_id))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<A>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Lock the state and read the client's account if it's spilled. The account stays spilled,
+    // callers taking it into memory remove its file.
+    fn load(&self, client_id: ClientId) -> Result<Loaded<'_, A>, SnapshotError> {
+        let mut state = self.lock();
+        for attempt in 1u32.. {
+            if !state.spilled.contains(&client_id) {
+                return Ok((state, None));
+            }
+            match read_account_manager(&self.path(client_id)) {
+                Ok(account_manager) => return Ok((state, Some(account_manager))),
+                Err(e) => {
+                    let Some(backoff) = self.retry.backoff(attempt, &e) else {
+                        return Err(SnapshotError::Spilled(client_id, Box::new(e)));
+                    };
+                    warn!(
+                        "Attempt {} to load spilled account {} failed, retrying in {:?}: {}",
+                        attempt, client_id, backoff, e
+                    );
+                    drop(state);
+                    thread::sleep(backoff);
+                    state = self.lock();
+                }
+            }
+        }
+        unreachable!("attempts are unbounded")
+    }
+
+    // Visit the accounts spilled when called, after the resident ones were. Accounts which fail
+    // to load transiently are retried after the others, with the lock released while backing off;
+    // meanwhile they may be taken into memory, where they're visited then, or removed.
+    fn for_each_spilled<'a, F>(
+        &'a self,
+        mut state: MutexGuard<'a, State<A>>,
+        write_back: bool,
+        mut f: F,
+    ) -> Result<(), SnapshotError>
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        let mut pending: Vec<ClientId> = state.spilled.iter().copied().collect();
+        for attempt in 1u32.. {
+            let mut failed = None;
+            let mut retry = Vec::new();
+            for client_id in pending {
+                if let Some(resident) = state.resident.get_mut(&client_id) {
+                    f(&mut resident.account_manager);
+                    continue;
+                }
+                if !state.spilled.contains(&client_id) {
+                    continue;
+                }
+                let path = self.path(client_id);
+                let mut account_manager = match read_account_manager(&path) {
+                    Ok(account_manager) => account_manager,
+                    Err(e) => match self.retry.backoff(attempt, &e) {
+                        Some(backoff) => {
+                            failed = Some((backoff, e));
+                            retry.push(client_id);
+                            continue;
+                        }
+                        None => return Err(SnapshotError::Spilled(client_id, Box::new(e))),
+                    },
+                };
+                f(&mut account_manager);
+                if write_back {
+                    if let Err(e) = write_account_manager(&path, &account_manager) {
+                        // Keep the changes in memory, the account is spilled again on an eviction
+                        error!("Failed to write back spilled account {}: {}", client_id, e);
+                        let _ = fs::remove_file(&path);
+                        state.spilled.remove(&client_id);
+                        state.tick += 1;
+                        let tick = state.tick;
+                        state.recency.insert(tick, client_id);
+                        state.resident.insert(
+                            client_id,
+                            Resident {
+                                account_manager,
+                                last_used: tick,
+                            },
+                        );
+                    }
+                }
+            }
+            let Some((backoff, e)) = failed else {
+                return Ok(());
+            };
+            warn!(
+                "Attempt {} to load {} spilled accounts failed, retrying in {:?}: {}",
+                attempt,
+                retry.len(),
+                backoff,
+                e
+            );
+            drop(state);
+            thread::sleep(backoff);
+            state = self.lock();
+            pending = retry;
+        }
+        unreachable!("attempts are unbounded")
+    }
+
+    // Spill the least recently used accounts. Writes aren't retried here, with the lock held; an
+    // account which fails to be written stays in memory for the next eviction.
+    fn evict(&self, state: &mut State<A>) {
+        while state.resident.len() > self.max_resident {
+            let Some((&last_used, &client_id)) = state.recency.iter().next() else {
+                break;
+            };
+            let resident = &state.resident[&client_id];
+            if let Err(e) = write_account_manager(&self.path(client_id), &resident.account_manager)
+            {
+                error!("Failed to spill account {}: {}", client_id, e);
+                break;
+            }
+
+            state.recency.remove(&last_used);
+            state.resident.remove(&client_id);
+            state.spilled.insert(client_id);
+        }
+    }
+}
+
+impl<A: Amount> AccountStore<A> for SpillingStore<A> {
+    fn with_account<R, F>(&self, client_id: ClientId, f: F) -> R
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R,
+    {
+        self.try_with_account(client_id, f)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_with_account<R, F>(&self, client_id: ClientId, f: F) -> Result<R, SnapshotError>
+    where
+        F: FnOnce(&mut AccountManager<A>) -> R,
+    {
+        let (mut guard, loaded) = self.load(client_id)?;
+        let state = &mut *guard;
+
+        state.tick += 1;
+        let tick = state.tick;
+        match state.resident.get_mut(&client_id) {
+            Some(resident) => {
+                state.recency.remove(&resident.last_used);
+                resident.last_used = tick;
+            }
+            None => {
+                let account_manager = match loaded {
+                    Some(account_manager) => {
+                        state.spilled.remove(&client_id);
+                        let _ = fs::remove_file(self.path(client_id));
+                        account_manager
+                    }
+                    None => AccountManager::new(client_id),
+                };
+                state.resident.insert(
+                    client_id,
+                    Resident {
+                        account_manager,
+                        last_used: tick,
+                    },
+                );
+            }
+        }
+        state.recency.insert(tick, client_id);
+
+        let result = f(&mut state
+            .resident
+            .get_mut(&client_id)
+            .expect("account is resident")
+            .account_manager);
+        self.evict(state);
+        Ok(result)
+    }
+
+    fn with_existing_account<R, F>(&self, client_id: ClientId, f: F) -> Option<R>
+    where
+        F: FnOnce(&AccountManager<A>) -> R,
+    {
+        let (state, loaded) = self.load(client_id).unwrap_or_else(|e| panic!("{}", e));
+        match state.resident.get(&client_id) {
+            Some(resident) => Some(f(&resident.account_manager)),
+            // Read in place, a lookup shouldn't evict another account
+            None => loaded.map(|account_manager| f(&account_manager)),
+        }
+    }
+
+    fn for_each<F>(&self, f: F)
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        self.try_for_each(f).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_for_each<F>(&self, mut f: F) -> Result<(), SnapshotError>
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        let state = self.lock();
+        for resident in state.resident.values() {
+            f(&resident.account_manager);
+        }
+        self.for_each_spilled(state, false, |account_manager| f(account_manager))
+    }
+
+    fn for_each_mut<F>(&self, f: F)
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        self.try_for_each_mut(f).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_for_each_mut<F>(&self, mut f: F) -> Result<(), SnapshotError>
+    where
+        F: FnMut(&mut AccountManager<A>),
+    {
+        let mut state = self.lock();
+        for resident in state.resident.values_mut() {
+            f(&mut resident.account_manager);
+        }
+        self.for_each_spilled(state, true, f)
+    }
+
+    fn for_each_resident<F>(&self, mut f: F)
+    where
+        F: FnMut(&AccountManager<A>),
+    {
+        let state = self.lock();
+        for resident in state.resident.values() {
+            f(&resident.account_manager);
+        }
+    }
+
+    fn remove(&self, client_id: ClientId) -> Option<AccountManager<A>> {
+        let (mut state, loaded) = self.load(client_id).unwrap_or_else(|e| panic!("{}", e));
+        if let Some(resident) = state.resident.remove(&client_id) {
+            state.recency.remove(&resident.last_used);
+            return Some(resident.account_manager);
+        }
+        let account_manager = loaded?;
+        state.spilled.remove(&client_id);
+        let _ = fs::remove_file(self.path(client_id));
+        Some(account_manager)
+    }
+}
+
+impl<A> Drop for SpillingStore<A> {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|e| e.into_inner());
+        for &client_id in &state.spilled {
+            let _ = fs::remove_file(self.directory.join(format!("{}.csv", client_id)));
+        }
+        // Only succeeds if nothing else was stored in the directory
+        let _ = fs::remove_dir(&self.directory);
+    }
+}
+
+// Spilled accounts use the snapshot records of a single account.
+fn write_account_manager<A: Amount>(
+    path: &Path,
+    account_manager: &AccountManager<A>,
+) -> Result<(), SnapshotError> {
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_path(path)?;
+    snapshot::write_account_manager(&mut writer, account_manager)?;
+    writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+fn read_account_manager<A: Amount>(path: &Path) -> Result<AccountManager<A>, SnapshotError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)?;
+    let mut account_manager = None;
+    for (line, record) in (1..).zip(reader.records()) {
+        match snapshot::read_record(&record?, snapshot::SNAPSHOT_VERSION, &mut account_manager) {
+            Ok(None) => {}
+            // A second account or a malformed record
+            _ => return Err(SnapshotError::Malformed(line)),
+        }
+    }
+    account_manager.ok_or(SnapshotError::Malformed(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::engine::transaction::{Metadata, TxStatus};
+    use crate::engine::{EngineBuilder, EngineError, ErrorCode};
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_spills_and_reloads_accounts() {
+        let directory = std::env::temp_dir().join(format!("spill-test-{}", std::process::id()));
+        let store: SpillingStore<Decimal> = SpillingStore::new(&directory, 2).unwrap();
+
+        let client_id = |id: u16| id.to_string().parse().unwrap();
+        for (id, transaction_id) in (0..5).zip(0..) {
+            store
+                .with_account(client_id(id), |account_manager| {
+                    account_manager.deposit(transaction_id, Decimal::from(id))
+                })
+                .unwrap();
+        }
+        assert_eq!(store.state.lock().unwrap().resident.len(), 2);
+        assert!(directory.join("0.csv").exists());
+
+        // Spilled account comes back with its balance and stored transactions
+        let memo = || Metadata::from([("memo".to_string(), "rent, march".to_string())]);
+        store.with_account(client_id(1), |account_manager| {
+            account_manager.transactions.get_mut(&1).unwrap().metadata = memo()
+        });
+        // Touch other accounts until the updated one is spilled again
+        (3..5).for_each(|id| store.with_account(client_id(id), |_| ()));
+        assert!(directory.join("1.csv").exists());
+        assert_eq!(
+            store.with_existing_account(client_id(1), |account_manager| {
+                account_manager.transactions[&1].metadata.clone()
+            }),
+            Some(memo())
+        );
+        assert!(store.with_existing_account(client_id(9), |_| ()).is_none());
+
+        store
+            .with_account(client_id(0), |account_manager| account_manager.dispute(0))
+            .unwrap();
+        assert!(!directory.join("0.csv").exists());
+
+        let mut balances = Vec::new();
+        store.for_each(|account_manager| {
+            balances.push((
+                account_manager.account.client_id,
+                account_manager.account.calculate_total(),
+            ))
+        });
+        balances.sort();
+        assert_eq!(
+            balances,
+            (0..5)
+                .map(|id| (client_id(id), Decimal::from(id)))
+                .collect::<Vec<_>>()
+        );
+
+        drop(store);
+        let _ = fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_spilled_disputes_expire() {
+        let directory =
+            std::env::temp_dir().join(format!("spill-expiry-test-{}", std::process::id()));
+        let store: SpillingStore<Decimal> = SpillingStore::new(&directory, 1).unwrap();
+        let engine = EngineBuilder::with_store(store).build();
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,2024-01-01\n\
+                     dispute,1,1,,2024-01-02\n\
+                     deposit,2,2,1.0,2024-03-01\n";
+        for transaction in SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap() {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        assert!(directory.join("1.csv").exists());
+
+        let expired = engine.expire_disputes(
+            "2024-03-01".parse().unwrap(),
+            Duration::from_secs(30 * 24 * 3600),
+            TxStatus::Resolved,
+        );
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].disputed_at, "2024-01-02T00:00:00Z");
+
+        drop(engine);
+        let _ = fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_spilled_accounts_finalize() {
+        let directory =
+            std::env::temp_dir().join(format!("spill-finalize-test-{}", std::process::id()));
+        let store: SpillingStore<Decimal> = SpillingStore::new(&directory, 1).unwrap();
+        let finalized = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = EngineBuilder::with_store(store)
+            .finalize_idle_accounts(Duration::from_secs(30 * 24 * 3600), {
+                let finalized = Arc::clone(&finalized);
+                move |account| finalized.lock().unwrap().push(account.client_id)
+            })
+            .build();
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,2024-01-01\n\
+                     deposit,2,2,1.0,2024-01-20\n\
+                     deposit,2,3,1.0,2024-02-16\n";
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        // Client 1 was idle while spilled
+        let client_id: ClientId = "1".parse().unwrap();
+        assert_eq!(*finalized.lock().unwrap(), [client_id]);
+
+        drop(engine);
+        let _ = fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_unreadable_account_fails_transactions() {
+        let directory =
+            std::env::temp_dir().join(format!("spill-unreadable-test-{}", std::process::id()));
+        let store: SpillingStore<Decimal> = SpillingStore::new(&directory, 1).unwrap();
+        let engine = EngineBuilder::with_store(store).build();
+        let process = |input: &str| {
+            SerdeParser::new(csv::Reader::from_reader(input.as_bytes()))
+                .unwrap()
+                .map(|transaction| engine.process(transaction.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        process("type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n");
+        let spilled = directory.join("1.csv");
+        let content = fs::read(&spilled).unwrap();
+        fs::write(&spilled, "account,1,garbage\n").unwrap();
+
+        let results = process("type,client,tx,amount\nwithdrawal,1,3,1.0\n");
+        assert!(matches!(results[0], Err(EngineError::StoreUnavailable(_))));
+        assert_eq!(
+            results[0].as_ref().err().map(|e| e.code()),
+            Some(ErrorCode::StoreUnavailable)
+        );
+        assert!(engine.accounts().is_err());
+        assert!(engine.write_snapshot(Vec::new()).is_err());
+
+        // Still spilled, and applied once it's readable again
+        fs::write(&spilled, content).unwrap();
+        let results = process("type,client,tx,amount\nwithdrawal,1,3,1.0\n");
+        assert!(results[0].is_ok());
+        let account = engine.account("1".parse().unwrap()).unwrap();
+        assert_eq!(account.available, Decimal::from(4));
+
+        drop(engine);
+        let _ = fs::remove_dir_all(directory);
+    }
+}
@@ -0,0 +1,56 @@
+// Asynchronous sequence of values for `Engine::process_stream`.
+//
+// The same shape as `futures_core::Stream`, which isn't a dependency, so sources implement it
+// with a one line `poll_next` forwarding to their own. Channels of the `tokio` feature implement
+// it already.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub trait Stream {
+    type Item;
+
+    // Next value, Ready(None) once the stream ended. Like `Future::poll`, returning Pending
+    // arranges for the task to be woken when a value may be ready.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+}
+
+impl<S: Stream + Unpin + ?Sized> Stream for &mut S {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+}
+
+// Stream of the values of an iterator, always ready.
+pub struct Iter<I>(I);
+
+pub fn iter<I: IntoIterator>(values: I) -> Iter<I::IntoIter> {
+    Iter(values.into_iter())
+}
+
+impl<I: Iterator + Unpin> Stream for Iter<I> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.0.next())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Stream for tokio::sync::mpsc::Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Stream for tokio::sync::mpsc::UnboundedReceiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
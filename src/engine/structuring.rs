@@ -0,0 +1,186 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::info;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, Timestamp, TransactionId, TransactionType};
+
+// When deposits and withdrawals just below a reporting threshold add up to structuring: `count`
+// of them with amounts from `threshold - margin` up to, but excluding, `threshold` within
+// `window` of input time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StructuringPolicy {
+    pub threshold: Decimal,
+    pub margin: Decimal,
+    pub count: usize,
+    pub window: Duration,
+}
+
+impl StructuringPolicy {
+    fn is_near_threshold(&self, amount: Decimal) -> bool {
+        amount < self.threshold && amount >= self.threshold - self.margin
+    }
+}
+
+// `<threshold>,<margin>,<count>,<window>` with a humantime window, e.g. `10000,1000,3,1day`.
+impl FromStr for StructuringPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<StructuringPolicy, ()> {
+        let fields: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [threshold, margin, count, window] = fields[..] else {
+            return Err(());
+        };
+        let policy = StructuringPolicy {
+            threshold: threshold.parse().map_err(drop)?,
+            margin: margin.parse().map_err(drop)?,
+            count: count.parse().map_err(drop)?,
+            window: humantime::parse_duration(window).map_err(drop)?,
+        };
+        if policy.margin.is_sign_negative() || policy.margin > policy.threshold || policy.count < 2
+        {
+            return Err(());
+        }
+        Ok(policy)
+    }
+}
+
+// Deposit or withdrawal just below the structuring threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearThreshold {
+    pub transaction_id: TransactionId,
+    pub r#type: TransactionType,
+    pub amount: Decimal,
+    pub at: Timestamp,
+}
+
+// Transaction of a client flagged for structuring, as evidence in the risk report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StructuringEvidence {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub r#type: &'static str,
+    pub amount: Decimal,
+    // RFC 3339 in UTC
+    pub timestamp: String,
+}
+
+impl<A: Amount> AccountManager<A> {
+    // Keep an accepted transaction just below the threshold and flag the account once enough of
+    // them fall into the window, adding them to the evidence. Returns whether it was flagged.
+    pub fn record_near_threshold(
+        &mut self,
+        policy: &StructuringPolicy,
+        transaction: NearThreshold,
+    ) -> bool {
+        if !policy.is_near_threshold(transaction.amount) {
+            return false;
+        }
+        let cutoff = transaction.at.0.checked_sub(policy.window);
+        self.near_threshold
+            .retain(|earlier| cutoff.is_none_or(|cutoff| earlier.at.0 > cutoff));
+        self.near_threshold.push_back(transaction);
+        if self.near_threshold.len() < policy.count {
+            return false;
+        }
+        for transaction in &self.near_threshold {
+            if !self.structuring.contains(transaction) {
+                self.structuring.push(transaction.clone());
+            }
+        }
+        info!(
+            target: "audit",
+            "Client {} flagged for structuring, {} transactions below {} within {}",
+            self.account.client_id,
+            self.near_threshold.len(),
+            policy.threshold,
+            humantime::format_duration(policy.window)
+        );
+        true
+    }
+}
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Transactions of the clients flagged for structuring, by client and in input order.
+    pub fn structuring_evidence(&self) -> Vec<StructuringEvidence> {
+        let mut evidence = Vec::new();
+        self.accounts.for_each(|account_manager| {
+            let client = account_manager.account.client_id;
+            evidence.extend(account_manager.structuring.iter().map(|transaction| {
+                StructuringEvidence {
+                    client,
+                    tx: transaction.transaction_id,
+                    r#type: transaction.r#type.name(),
+                    amount: transaction.amount,
+                    timestamp: transaction.at.to_string(),
+                }
+            }));
+        });
+        evidence.sort_by_key(|evidence| evidence.client);
+        evidence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::RiskThresholds;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_structuring() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,9500.0,2024-01-01T08:00:00Z\n\
+                     deposit,1,2,12000.0,2024-01-01T09:00:00Z\n\
+                     withdrawal,1,3,9900.0,2024-01-01T10:00:00Z\n\
+                     deposit,1,4,9800.0,2024-01-01T11:00:00Z\n\
+                     deposit,2,5,9500.0,2024-01-01T08:00:00Z\n\
+                     deposit,2,6,9500.0,2024-01-03T08:00:00Z\n\
+                     deposit,2,7,9500.0,2024-01-05T08:00:00Z\n";
+        let mut engine: Engine = Engine::builder()
+            .structuring("10000,1000,3,1day".parse().unwrap())
+            .build();
+        engine
+            .process_transactions_blocking(
+                SerdeParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+
+        let client: ClientId = "1".parse().unwrap();
+        let evidence = engine.structuring_evidence();
+        assert_eq!(
+            evidence
+                .iter()
+                .map(|e| (e.client, e.tx))
+                .collect::<Vec<_>>(),
+            [(client, 1), (client, 3), (client, 4)]
+        );
+        assert_eq!(evidence[1].r#type, "withdrawal");
+
+        let report = engine.risk_report(RiskThresholds::default());
+        assert_eq!(
+            report
+                .iter()
+                .map(|entry| (entry.client_id, entry.structuring, entry.flagged))
+                .collect::<Vec<_>>(),
+            [
+                (client, Some(3), true),
+                ("2".parse().unwrap(), Some(0), false)
+            ]
+        );
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored: Engine = Engine::builder()
+            .structuring("10000,1000,3,1day".parse().unwrap())
+            .build();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.structuring_evidence(), evidence);
+    }
+}
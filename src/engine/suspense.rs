@@ -0,0 +1,83 @@
+use log::warn;
+
+use super::account_manager::AccountManager;
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::{ClientId, TransactionId, TransactionType};
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Disputes still waiting in suspense for their transaction, by client and transaction id.
+    pub fn suspense(&self) -> Vec<(ClientId, TransactionId)> {
+        let mut suspense = Vec::new();
+        self.accounts.for_each(|account_manager| {
+            let client_id = account_manager.account.client_id;
+            suspense.extend(
+                account_manager
+                    .suspense
+                    .iter()
+                    .map(|transaction_id| (client_id, *transaction_id)),
+            );
+        });
+        suspense.sort();
+        suspense
+    }
+
+    // Apply a dispute parked for a just deposited transaction. The deposit stands even if the
+    // dispute fails.
+    pub(super) fn apply_suspended_dispute(
+        &self,
+        account_manager: &mut AccountManager<A>,
+        transaction_id: TransactionId,
+    ) {
+        if !account_manager.suspense.remove(&transaction_id) {
+            return;
+        }
+        match account_manager.dispute(transaction_id) {
+            Ok(()) => account_manager.activity.record(TransactionType::Dispute),
+            Err(e) => warn!(
+                "Error applying suspended dispute of transaction {}: {}",
+                transaction_id, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::engine::{Engine, EngineError, Transaction, TxStatus};
+
+    #[test]
+    fn test_suspended_disputes() {
+        let input = "type,client,tx,amount\n\
+                     dispute,1,1,\n\
+                     dispute,1,2,\n\
+                     deposit,1,1,3.0\n";
+        let engine: Engine = Engine::builder().dispute_suspense().build();
+        let results: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|transaction| engine.process(transaction.unwrap()))
+            .collect();
+
+        assert_eq!(
+            results,
+            [
+                Err(EngineError::DisputeSuspended),
+                Err(EngineError::DisputeSuspended),
+                Ok(()),
+            ]
+        );
+        let client_id = "1".parse().unwrap();
+        assert_eq!(
+            engine.transaction_status(client_id, 1),
+            Some(TxStatus::Disputed)
+        );
+        assert_eq!(
+            engine.account(client_id).map(|account| account.held),
+            Some(Decimal::from(3))
+        );
+        assert_eq!(engine.suspense(), [(client_id, 2)]);
+    }
+}
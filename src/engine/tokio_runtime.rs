@@ -0,0 +1,65 @@
+use std::error::Error;
+
+use anyhow::Result;
+
+use super::amount::Amount;
+use super::engine::Engine;
+use super::store::AccountStore;
+use super::transaction::Transaction;
+
+// Process transactions on tokio's blocking thread pool.
+//
+// Processing never waits on I/O, but a large input keeps a thread busy for a long time, which
+// would starve other tasks of an async worker thread. The engine is handed back once done.
+pub async fn process_transactions<A, S, I, E>(
+    mut engine: Engine<A, S>,
+    transactions_iter: I,
+) -> Result<Engine<A, S>>
+where
+    A: Amount,
+    S: AccountStore<A> + 'static,
+    I: Iterator<Item = std::result::Result<Transaction, E>> + Send + 'static,
+    E: Error + Sync + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        engine.process_transactions_blocking(transactions_iter)?;
+        Ok(engine)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::SerdeParser;
+
+    #[tokio::test]
+    async fn test_process_on_blocking_pool() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+        let reader = csv::Reader::from_reader(input.as_bytes());
+
+        let engine: Engine = Engine::new();
+        let engine = process_transactions(engine, SerdeParser::new(reader).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(engine.stats().processed, 2);
+    }
+
+    #[tokio::test]
+    async fn test_process_channel() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\n";
+        tokio::spawn(async move {
+            let reader = csv::Reader::from_reader(input.as_bytes());
+            for record in SerdeParser::new(reader).unwrap() {
+                sender.send(record).await.unwrap();
+            }
+        });
+
+        let mut engine: Engine = Engine::new();
+        engine.process_stream(receiver).await.unwrap();
+
+        assert_eq!((engine.stats().processed, engine.stats().rejected), (2, 1));
+    }
+}
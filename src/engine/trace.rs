@@ -0,0 +1,134 @@
+use std::fmt::Write;
+
+use log::info;
+
+use super::account::Account;
+use super::amount::Amount;
+use super::engine::{Engine, EngineError};
+use super::store::AccountStore;
+use super::transaction::Transaction;
+
+// Log target of the timeline of the traced client, see `EngineBuilder::trace_client`.
+pub const TIMELINE_TARGET: &str = "timeline";
+
+impl<A: Amount, S: AccountStore<A>> Engine<A, S> {
+    // Log a transaction of the traced client with its outcome and the account before and after
+    // it. Transactions rejected before reaching the account, e.g. of blocked clients, come
+    // without `balances`.
+    pub(super) fn trace(
+        &self,
+        transaction: &Transaction,
+        balances: Option<(Account<A>, Account<A>)>,
+        result: &Result<(), EngineError>,
+    ) {
+        info!(
+            target: TIMELINE_TARGET,
+            "{}",
+            timeline_entry(transaction, balances.as_ref(), result)
+        );
+    }
+}
+
+// `client 1: deposit 3 of 5.0 applied, available 0 -> 5.0, ...`, listing only the flags which
+// changed.
+fn timeline_entry<A: Amount>(
+    transaction: &Transaction,
+    balances: Option<&(Account<A>, Account<A>)>,
+    result: &Result<(), EngineError>,
+) -> String {
+    let mut entry = format!(
+        "client {}: {} {}",
+        transaction.client_id,
+        transaction.r#type.name(),
+        transaction.transaction_id
+    );
+    if let Some(amount) = transaction.amount {
+        let _ = write!(entry, " of {}", amount);
+    }
+    match result {
+        Ok(()) => entry.push_str(" applied"),
+        Err(e) => {
+            let _ = write!(entry, " rejected ({})", e);
+        }
+    }
+    let Some((before, after)) = balances else {
+        return entry;
+    };
+    let amounts = [
+        ("available", before.available, after.available),
+        ("held", before.held, after.held),
+        ("reserve", before.reserve, after.reserve),
+        ("total", before.calculate_total(), after.calculate_total()),
+    ];
+    for (name, before, after) in amounts {
+        let _ = write!(
+            entry,
+            ", {} {} -> {}",
+            name,
+            before.to_decimal(),
+            after.to_decimal()
+        );
+    }
+    let flags = [
+        ("locked", before.locked, after.locked),
+        ("quarantined", before.quarantined, after.quarantined),
+        ("closed", before.closed, after.closed),
+        ("dormant", before.dormant, after.dormant),
+    ];
+    for (name, before, after) in flags {
+        if before != after {
+            let _ = write!(entry, ", {} {} -> {}", name, before, after);
+        }
+    }
+    if before.dunning != after.dunning {
+        let _ = write!(
+            entry,
+            ", dunning {:?} -> {:?}",
+            before.dunning, after.dunning
+        );
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::account_manager::AccountManagerError;
+
+    #[test]
+    fn test_timeline_entry() {
+        let transaction: Transaction =
+            csv::Reader::from_reader("type,client,tx,amount\nwithdrawal,1,2,3.0\n".as_bytes())
+                .deserialize()
+                .next()
+                .unwrap()
+                .unwrap();
+        let before: Account<Decimal> = Account {
+            available: "5.0".parse().unwrap(),
+            ..Account::new(transaction.client_id)
+        };
+        let after = Account {
+            available: "2.0".parse().unwrap(),
+            locked: true,
+            ..before.clone()
+        };
+        assert_eq!(
+            timeline_entry(&transaction, Some(&(before.clone(), after)), &Ok(())),
+            "client 1: withdrawal 2 of 3 applied, available 5.0 -> 2.0, held 0 -> 0, \
+             reserve 0 -> 0, total 5.0 -> 2.0, locked false -> true"
+        );
+        assert_eq!(
+            timeline_entry::<Decimal>(&transaction, None, &Err(EngineError::ClientBlocked)),
+            "client 1: withdrawal 2 of 3 rejected (Client is blocked)"
+        );
+        let rejected = timeline_entry(
+            &transaction,
+            Some(&(before.clone(), before)),
+            &Err(AccountManagerError::InsufficientFunds.into()),
+        );
+        assert!(rejected
+            .ends_with("available 5.0 -> 5.0, held 0 -> 0, reserve 0 -> 0, total 5.0 -> 5.0"));
+    }
+}
@@ -1,53 +1,278 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-pub struct TransactionDetails {
-    pub amount: Decimal,
-    pub disputed: bool,
+use super::account_manager::AccountManagerError;
+use super::amount::Amount;
+
+// Identifier types, the defaults follow the input specification. Wider ids can be enabled with
+// the `client-id-u32`, `client-id-u64` and `tx-id-u64` features, alphanumeric client ids with
+// `client-id-string`.
+#[cfg(not(any(
+    feature = "client-id-u32",
+    feature = "client-id-u64",
+    feature = "client-id-string"
+)))]
+pub type ClientId = u16;
+#[cfg(all(
+    feature = "client-id-u32",
+    not(any(feature = "client-id-u64", feature = "client-id-string"))
+))]
+pub type ClientId = u32;
+#[cfg(all(feature = "client-id-u64", not(feature = "client-id-string")))]
+pub type ClientId = u64;
+#[cfg(feature = "client-id-string")]
+pub type ClientId = super::alphanumeric_id::AlphanumericId;
+
+#[cfg(not(feature = "tx-id-u64"))]
+pub type TransactionId = u32;
+#[cfg(feature = "tx-id-u64")]
+pub type TransactionId = u64;
+
+// Extra input columns passed through with a transaction, e.g. a memo or an external reference.
+pub type Metadata = BTreeMap<String, String>;
+
+// Lifecycle of a stored transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    Settled,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    // Funds returned to the originator outside of a dispute
+    Reversed,
+    // Dispute left open on an account locked by a chargeback of another transaction, held until
+    // settled with `Engine::force_settle`, see `ChargebackDisputes::Freeze`
+    Frozen,
+}
+
+impl TxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxStatus::Settled => "settled",
+            TxStatus::Disputed => "disputed",
+            TxStatus::Resolved => "resolved",
+            TxStatus::ChargedBack => "chargedback",
+            TxStatus::Reversed => "reversed",
+            TxStatus::Frozen => "frozen",
+        }
+    }
+
+    // Whether the transaction's funds are held by a dispute.
+    pub fn is_open_dispute(self) -> bool {
+        matches!(self, TxStatus::Disputed | TxStatus::Frozen)
+    }
+
+    // Move to the next state, rejecting illegal transitions.
+    //
+    // * Settled -> Disputed | Reversed
+    // * Disputed -> Resolved | ChargedBack
+    // * Resolved -> Disputed, a resolved dispute can be raised again
+    // * Disputed -> Frozen -> Resolved | ChargedBack
+    // * ChargedBack and Reversed are final
+    pub fn transition(self, to: TxStatus) -> Result<TxStatus, AccountManagerError> {
+        use TxStatus::*;
+        match (self, to) {
+            (Settled, Disputed | Reversed)
+            | (Disputed, Resolved | ChargedBack | Frozen)
+            | (Frozen, Resolved | ChargedBack)
+            | (Resolved, Disputed) => Ok(to),
+            (ChargedBack, _) => Err(AccountManagerError::TransactionAlreadyChargedBack),
+            (Reversed, _) => Err(AccountManagerError::TransactionReversed),
+            (Disputed | Frozen, Disputed) => Err(AccountManagerError::TransactionDisputed),
+            (_, Resolved | ChargedBack) => Err(AccountManagerError::TransactionNotDisputed),
+            (from, to) => Err(AccountManagerError::IllegalTransition(from, to)),
+        }
+    }
+}
+
+impl FromStr for TxStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<TxStatus, ()> {
+        match s {
+            "settled" => Ok(TxStatus::Settled),
+            "disputed" => Ok(TxStatus::Disputed),
+            "resolved" => Ok(TxStatus::Resolved),
+            "chargedback" => Ok(TxStatus::ChargedBack),
+            "reversed" => Ok(TxStatus::Reversed),
+            "frozen" => Ok(TxStatus::Frozen),
+            _ => Err(()),
+        }
+    }
 }
 
-impl TransactionDetails {
-    pub fn new(amount: Decimal) -> TransactionDetails {
+pub struct TransactionDetails<A> {
+    pub amount: A,
+    pub status: TxStatus,
+    pub metadata: Metadata,
+    // Time of the last accepted dispute, from its timestamp column. Kept in snapshots since
+    // version 4.
+    pub disputed_at: Option<Timestamp>,
+}
+
+impl<A: Amount> TransactionDetails<A> {
+    pub fn new(amount: A) -> TransactionDetails<A> {
+        Self::with_metadata(amount, Metadata::new())
+    }
+
+    pub fn with_metadata(amount: A, metadata: Metadata) -> TransactionDetails<A> {
         TransactionDetails {
             amount,
-            disputed: false,
+            status: TxStatus::Settled,
+            metadata,
+            disputed_at: None,
         }
     }
 }
 
+// Transaction stored with an account, as returned by queries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredTransaction<A> {
+    pub transaction_id: TransactionId,
+    pub amount: A,
+    pub status: TxStatus,
+    pub metadata: Metadata,
+    // Time of the last accepted dispute, see `TransactionDetails::disputed_at`
+    pub disputed_at: Option<Timestamp>,
+}
+
+impl<A> StoredTransaction<A> {
+    // Time from the timestamp column, None if it's missing or invalid.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.metadata.get(TIMESTAMP_COLUMN)?.parse().ok()
+    }
+}
+
+// Page of a client's stored transactions in id order. `next_cursor` is set if there are more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionPage<A> {
+    pub transactions: Vec<StoredTransaction<A>>,
+    pub next_cursor: Option<TransactionId>,
+}
+
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum TransactionValidationError {
     #[error("Amount is missing")]
     AmountMissing,
     #[error("Amount is negative")]
     AmountNegative,
+    #[error("Amount is zero")]
+    AmountZero,
+    #[error("Amount is not representable")]
+    UnrepresentableAmount,
+    #[error("Note text is missing")]
+    NoteMissing,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct Transaction {
     pub r#type: TransactionType,
     #[serde(rename = "client")]
-    pub client_id: u16,
+    pub client_id: ClientId,
     #[serde(rename = "tx")]
-    pub transaction_id: u32,
+    pub transaction_id: TransactionId,
     pub amount: Option<Decimal>,
+    // Filled by the parsers from columns other than the above
+    #[serde(skip)]
+    pub metadata: Metadata,
 }
 
+// Metadata column with the time of a transaction, see `Timestamp` for the accepted formats.
+pub const TIMESTAMP_COLUMN: &str = "timestamp";
+
+// Point in time parsed from RFC 3339 (`2024-03-01T12:00:00Z`, the `Z` and `T` are optional), a date
+// (`2024-03-01`, midnight UTC) or seconds since the Unix epoch. Times after `Timestamp::MAX`
+// can't be formatted and are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub SystemTime);
+
+impl Timestamp {
+    // 9999-12-31T23:59:59Z, the last second RFC 3339 can represent.
+    pub const MAX_SECONDS: u64 = 253_402_300_799;
+
+    // Timestamp of `time`, None if it's after `MAX_SECONDS`.
+    pub fn new(time: SystemTime) -> Option<Timestamp> {
+        let max = UNIX_EPOCH.checked_add(Duration::from_secs(Timestamp::MAX_SECONDS))?;
+        (time <= max).then_some(Timestamp(time))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Timestamp, ()> {
+        if let Ok(seconds) = s.parse::<u64>() {
+            let time = UNIX_EPOCH
+                .checked_add(Duration::from_secs(seconds))
+                .ok_or(())?;
+            return Timestamp::new(time).ok_or(());
+        }
+        humantime::parse_rfc3339_weak(s)
+            .or_else(|_| humantime::parse_rfc3339_weak(&format!("{}T00:00:00", s)))
+            .map(Timestamp)
+            .map_err(drop)
+    }
+}
+
+// RFC 3339 in UTC, to the second.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime::format_rfc3339_seconds(self.0))
+    }
+}
+
+// Metadata column with a monotonically increasing sequence number of the transaction's source.
+pub const SEQUENCE_COLUMN: &str = "sequence";
+// Metadata column naming the feed a transaction comes from, each with its own sequence.
+pub const SOURCE_COLUMN: &str = "source";
+
 impl Transaction {
-    pub fn get_amount_or_error(&self) -> Result<Decimal, TransactionValidationError> {
+    // Time from the timestamp column, None if it's missing or invalid.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.metadata.get(TIMESTAMP_COLUMN)?.parse().ok()
+    }
+
+    // Number from the sequence column, None if it's missing or invalid.
+    pub fn sequence(&self) -> Option<u64> {
+        self.metadata.get(SEQUENCE_COLUMN)?.parse().ok()
+    }
+
+    // Stable hash of the record's type, client, id and amount, the same for every delivery of
+    // the record. Amounts are normalized, so 1.0 and 1.00 are the same record. FNV-1a, the hash
+    // is persisted in snapshots and must not change between builds.
+    pub fn fingerprint(&self) -> u64 {
+        let amount = self.amount.map(|amount| amount.normalize().to_string());
+        let record = format!(
+            "{},{},{},{}",
+            self.r#type.name(),
+            self.client_id,
+            self.transaction_id,
+            amount.unwrap_or_default()
+        );
+        record.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    pub fn get_amount_or_error<A: Amount>(&self) -> Result<A, TransactionValidationError> {
         match self.amount {
             Some(amount) => {
                 if amount.is_sign_negative() {
-                    return Err(TransactionValidationError::AmountNegative.into());
+                    return Err(TransactionValidationError::AmountNegative);
                 }
-                Ok(amount)
+                A::from_decimal(amount).ok_or(TransactionValidationError::UnrepresentableAmount)
             }
-            None => Err(TransactionValidationError::AmountMissing.into()),
+            None => Err(TransactionValidationError::AmountMissing),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
 pub enum TransactionType {
     #[serde(rename = "deposit")]
     Deposit,
@@ -59,4 +284,108 @@ pub enum TransactionType {
     Resolve,
     #[serde(rename = "chargeback")]
     Chargeback,
+    #[serde(rename = "close_account")]
+    CloseAccount,
+    // Note on the account, see `Note`
+    #[serde(rename = "note")]
+    Note,
+}
+
+impl TransactionType {
+    // Name of the type in the input
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdraw => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::CloseAccount => "close_account",
+            TransactionType::Note => "note",
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<TransactionType, ()> {
+        match s {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdraw),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "close_account" => Ok(TransactionType::CloseAccount),
+            "note" => Ok(TransactionType::Note),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_formats() {
+        let expected = Ok(Timestamp(UNIX_EPOCH + Duration::from_secs(1_709_251_200)));
+
+        assert_eq!("1709251200".parse(), expected);
+        assert_eq!("2024-03-01".parse(), expected);
+        assert_eq!("2024-03-01T00:00:00Z".parse(), expected);
+        assert_eq!("2024-03-01 00:00:00".parse(), expected);
+        assert_eq!("March 1st".parse::<Timestamp>(), Err(()));
+    }
+
+    #[test]
+    fn test_timestamp_out_of_range() {
+        let max = "253402300799".parse::<Timestamp>();
+
+        assert_eq!(max, "9999-12-31T23:59:59Z".parse());
+        assert_eq!("253402300800".parse::<Timestamp>(), Err(()));
+        assert_eq!("18446744073709551615".parse::<Timestamp>(), Err(()));
+    }
+
+    #[test]
+    fn test_legal_transitions() {
+        let status = TxStatus::Settled
+            .transition(TxStatus::Disputed)
+            .and_then(|s| s.transition(TxStatus::Resolved))
+            .and_then(|s| s.transition(TxStatus::Disputed))
+            .and_then(|s| s.transition(TxStatus::ChargedBack));
+
+        assert_eq!(status, Ok(TxStatus::ChargedBack));
+    }
+
+    #[test]
+    fn test_illegal_transitions() {
+        assert_eq!(
+            TxStatus::ChargedBack.transition(TxStatus::Disputed),
+            Err(AccountManagerError::TransactionAlreadyChargedBack)
+        );
+        assert_eq!(
+            TxStatus::ChargedBack.transition(TxStatus::ChargedBack),
+            Err(AccountManagerError::TransactionAlreadyChargedBack)
+        );
+        assert_eq!(
+            TxStatus::Disputed.transition(TxStatus::Disputed),
+            Err(AccountManagerError::TransactionDisputed)
+        );
+        assert_eq!(
+            TxStatus::Resolved.transition(TxStatus::Resolved),
+            Err(AccountManagerError::TransactionNotDisputed)
+        );
+        assert_eq!(
+            TxStatus::Reversed.transition(TxStatus::Disputed),
+            Err(AccountManagerError::TransactionReversed)
+        );
+        assert_eq!(
+            TxStatus::Disputed.transition(TxStatus::Settled),
+            Err(AccountManagerError::IllegalTransition(
+                TxStatus::Disputed,
+                TxStatus::Settled
+            ))
+        );
+    }
 }
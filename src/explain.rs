@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::engine::{Book, ClientId, Engine, SnapshotError, TransactionId, TxStatus};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExplainError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error("Malformed journal record on line {0}")]
+    Malformed(u64),
+    #[error("Client {0} not found")]
+    UnknownClient(ClientId),
+}
+
+// Header of the journal written with `EngineBuilder::journal`.
+const JOURNAL_HEADER: &str = "client,tx,debit,credit,amount";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Deposit,
+    // Also a reversal of a deposit, see `EngineBuilder::negative_deposits_as_reversals`
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Fee,
+    Reserve,
+    Release,
+    // Whatever a snapshot doesn't keep the transactions of: withdrawals, fees, reserves,
+    // compacted deposits and locks other than by chargebacks
+    Untracked,
+}
+
+impl Event {
+    // Event of a posting out of `debit` into `credit`, see `Book`.
+    fn of(debit: Book, credit: Book) -> Option<Event> {
+        match (debit, credit) {
+            (Book::Settlement, Book::ClientAvailable) => Some(Event::Deposit),
+            (Book::ClientAvailable, Book::Settlement) => Some(Event::Withdrawal),
+            (Book::ClientAvailable, Book::ClientHeld) => Some(Event::Dispute),
+            (Book::ClientHeld, Book::ClientAvailable) => Some(Event::Resolve),
+            (Book::ClientHeld, Book::ChargebackLoss) => Some(Event::Chargeback),
+            (Book::ClientAvailable, Book::FeeIncome) => Some(Event::Fee),
+            (Book::ClientAvailable, Book::ClientReserve) => Some(Event::Reserve),
+            (Book::ClientReserve, Book::ClientAvailable) => Some(Event::Release),
+            _ => None,
+        }
+    }
+
+    // Changes of available and held funds by `amount` of the event.
+    fn effect(self, amount: Decimal) -> (Decimal, Decimal) {
+        match self {
+            Event::Deposit | Event::Release => (amount, Decimal::ZERO),
+            Event::Withdrawal | Event::Fee | Event::Reserve => (-amount, Decimal::ZERO),
+            Event::Dispute => (-amount, amount),
+            Event::Resolve => (amount, -amount),
+            Event::Chargeback => (Decimal::ZERO, -amount),
+            Event::Untracked => (amount, Decimal::ZERO),
+        }
+    }
+}
+
+// Step of an explanation, with the client's funds after it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Step {
+    pub tx: Option<TransactionId>,
+    pub event: Event,
+    pub amount: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+// Minimal sequence of transactions and dispute events producing the final available and held
+// funds and lock of the client, from the journal or snapshot given as `state`, told apart by
+// how it starts.
+//
+// From a journal, postings of a transaction undoing each other, e.g. a dispute and its resolve,
+// are left out, and so are transactions without a net effect on the funds, except for the
+// chargeback locking the account. A journal doesn't record locks other than by chargebacks.
+// From a snapshot, stored deposits are explained by their status and what the snapshot doesn't
+// keep the transactions of is a single `Event::Untracked` step.
+pub fn explain(state: &[u8], client_id: ClientId) -> Result<Vec<Step>, ExplainError> {
+    if state.starts_with(JOURNAL_HEADER.as_bytes()) {
+        explain_journal(state, client_id)
+    } else {
+        explain_snapshot(state, client_id)
+    }
+}
+
+pub fn write_steps<W: io::Write>(steps: &[Step], writer: W) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for step in steps {
+        writer.serialize(step)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+struct Posting {
+    tx: TransactionId,
+    event: Event,
+    debit: Book,
+    credit: Book,
+    amount: Decimal,
+}
+
+impl Posting {
+    fn undoes(&self, other: &Posting) -> bool {
+        self.tx == other.tx
+            && self.debit == other.credit
+            && self.credit == other.debit
+            && self.amount == other.amount
+    }
+}
+
+fn explain_journal(journal: &[u8], client_id: ClientId) -> Result<Vec<Step>, ExplainError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(journal);
+    let mut postings = Vec::new();
+    for (line, record) in (2..).zip(reader.records()) {
+        let record = record?;
+        let field = |index: usize| record.get(index).ok_or(ExplainError::Malformed(line));
+        if field(0)?.parse::<ClientId>().ok() != Some(client_id) {
+            continue;
+        }
+        let parse = || {
+            let (debit, credit) = (field(2).ok()?.parse().ok()?, field(3).ok()?.parse().ok()?);
+            Some(Posting {
+                tx: field(1).ok()?.parse().ok()?,
+                event: Event::of(debit, credit)?,
+                debit,
+                credit,
+                amount: field(4).ok()?.parse().ok()?,
+            })
+        };
+        postings.push(parse().ok_or(ExplainError::Malformed(line))?);
+    }
+    if postings.is_empty() {
+        return Err(ExplainError::UnknownClient(client_id));
+    }
+
+    // Postings undoing an earlier posting of the same transaction cancel out
+    let mut kept = vec![true; postings.len()];
+    let mut by_tx: HashMap<TransactionId, Vec<usize>> = HashMap::new();
+    for (index, posting) in postings.iter().enumerate() {
+        by_tx.entry(posting.tx).or_default().push(index);
+    }
+    for index in 0..postings.len() {
+        if !kept[index] {
+            continue;
+        }
+        let undo = by_tx[&postings[index].tx].iter().copied().find(|&other| {
+            other > index && kept[other] && postings[other].undoes(&postings[index])
+        });
+        if let Some(other) = undo {
+            kept[index] = false;
+            kept[other] = false;
+        }
+    }
+
+    // Transactions left without a net effect don't explain the funds, one charged back explains
+    // the lock
+    let mut effects: HashMap<TransactionId, (Decimal, Decimal)> = HashMap::new();
+    for (posting, _) in postings.iter().zip(&kept).filter(|(_, kept)| **kept) {
+        let (available, held) = posting.event.effect(posting.amount);
+        let effect = effects.entry(posting.tx).or_default();
+        effect.0 += available;
+        effect.1 += held;
+    }
+    let charged_back = |tx: TransactionId| {
+        by_tx[&tx]
+            .iter()
+            .any(|&index| kept[index] && postings[index].event == Event::Chargeback)
+    };
+    let mut explaining: HashSet<TransactionId> = effects
+        .iter()
+        .filter(|(_, effect)| **effect != (Decimal::ZERO, Decimal::ZERO))
+        .map(|(tx, _)| *tx)
+        .collect();
+    if !explaining.iter().any(|tx| charged_back(*tx)) {
+        let locking = postings
+            .iter()
+            .enumerate()
+            .find(|(index, posting)| kept[*index] && posting.event == Event::Chargeback);
+        if let Some((_, posting)) = locking {
+            explaining.insert(posting.tx);
+        }
+    }
+
+    let mut steps = Vec::new();
+    let (mut available, mut held, mut locked) = (Decimal::ZERO, Decimal::ZERO, false);
+    for (index, posting) in postings.iter().enumerate() {
+        if !kept[index] || !explaining.contains(&posting.tx) {
+            continue;
+        }
+        let event = posting.event;
+        let (available_change, held_change) = event.effect(posting.amount);
+        available += available_change;
+        held += held_change;
+        locked |= event == Event::Chargeback;
+        steps.push(Step {
+            tx: Some(posting.tx),
+            event,
+            amount: posting.amount,
+            available,
+            held,
+            locked,
+        });
+    }
+    Ok(steps)
+}
+
+fn explain_snapshot(snapshot: &[u8], client_id: ClientId) -> Result<Vec<Step>, ExplainError> {
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(snapshot)?;
+    let account = engine
+        .account(client_id)
+        .ok_or(ExplainError::UnknownClient(client_id))?;
+    let transactions = engine
+        .transactions(client_id, usize::MAX, None)
+        .map(|page| page.transactions)
+        .unwrap_or_default();
+
+    let mut steps: Vec<Step> = Vec::new();
+    let (mut available, mut held, mut locked) = (Decimal::ZERO, Decimal::ZERO, false);
+    let mut push = |tx: Option<TransactionId>, event: Event, amount: Decimal| {
+        let (available_change, held_change) = event.effect(amount);
+        available += available_change;
+        held += held_change;
+        locked |= event == Event::Chargeback;
+        steps.push(Step {
+            tx,
+            event,
+            amount,
+            available,
+            held,
+            locked,
+        });
+    };
+    // Charged back deposits have no net effect on the funds, the first one explains the lock
+    let mut lock_explained = !account.locked;
+    for transaction in transactions {
+        let (tx, amount) = (Some(transaction.transaction_id), transaction.amount);
+        match transaction.status {
+            TxStatus::Settled | TxStatus::Resolved => push(tx, Event::Deposit, amount),
+            TxStatus::Disputed | TxStatus::Frozen => {
+                push(tx, Event::Deposit, amount);
+                push(tx, Event::Dispute, amount);
+            }
+            TxStatus::ChargedBack if !lock_explained => {
+                push(tx, Event::Deposit, amount);
+                push(tx, Event::Dispute, amount);
+                push(tx, Event::Chargeback, amount);
+                lock_explained = true;
+            }
+            TxStatus::ChargedBack | TxStatus::Reversed => {}
+        }
+    }
+    let untracked = account.available + account.held - available - held;
+    if untracked != Decimal::ZERO || account.held != held || account.locked != locked {
+        steps.push(Step {
+            tx: None,
+            event: Event::Untracked,
+            amount: untracked,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+        });
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(steps: &[Step]) -> Vec<(Option<TransactionId>, Event)> {
+        steps.iter().map(|step| (step.tx, step.event)).collect()
+    }
+
+    #[test]
+    fn test_explain() {
+        let journal = "client,tx,debit,credit,amount\n\
+                       1,1,settlement,client_available,5.0\n\
+                       2,2,settlement,client_available,9.0\n\
+                       1,3,settlement,client_available,2.0\n\
+                       1,1,client_available,client_held,5.0\n\
+                       1,1,client_held,client_available,5.0\n\
+                       1,4,client_available,settlement,1.0\n\
+                       1,3,client_available,client_held,2.0\n\
+                       1,3,client_held,chargeback_loss,2.0\n\
+                       1,5,settlement,client_available,4.0\n\
+                       1,5,client_available,client_held,4.0\n\
+                       1,5,client_held,chargeback_loss,4.0\n";
+        let client = "1".parse().unwrap();
+        let steps = explain(journal.as_bytes(), client).unwrap();
+        // The resolved dispute cancels out, the second chargeback isn't needed for the lock
+        assert_eq!(
+            events(&steps),
+            [
+                (Some(1), Event::Deposit),
+                (Some(3), Event::Deposit),
+                (Some(4), Event::Withdrawal),
+                (Some(3), Event::Dispute),
+                (Some(3), Event::Chargeback),
+            ]
+        );
+        let last = steps.last().unwrap();
+        assert_eq!(
+            (last.available, last.held, last.locked),
+            (4.into(), 0.into(), true)
+        );
+        assert!(matches!(
+            explain(journal.as_bytes(), "3".parse().unwrap()),
+            Err(ExplainError::UnknownClient(_))
+        ));
+
+        let engine: Engine = Engine::new();
+        engine
+            .restore_snapshot(
+                "snapshot,1\n\
+                 account,1,4.0,2.0,true,false,false\n\
+                 tx,1,5.0,settled\n\
+                 tx,2,2.0,disputed\n\
+                 tx,3,2.0,chargedback\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let steps = explain(&snapshot, client).unwrap();
+        assert_eq!(
+            events(&steps),
+            [
+                (Some(1), Event::Deposit),
+                (Some(2), Event::Deposit),
+                (Some(2), Event::Dispute),
+                (Some(3), Event::Deposit),
+                (Some(3), Event::Dispute),
+                (Some(3), Event::Chargeback),
+                (None, Event::Untracked),
+            ]
+        );
+        // A withdrawal the snapshot doesn't keep
+        assert_eq!(steps.last().unwrap().amount, Decimal::from(-1));
+    }
+}
@@ -0,0 +1,190 @@
+// C ABI for embedding the engine in non-Rust hosts, declared in include/payements_engine.h.
+//
+// Strings cross the boundary as NUL-terminated UTF-8 and identifiers as strings, so the ABI
+// doesn't change with the identifier width features. Strings returned by the library must be
+// released with `pe_string_free`. Panics don't unwind into the host, which is undefined behavior,
+// they're caught at the boundary and reported as `PE_PANICKED` or NULL.
+
+// The safety contract of each function is documented in the header
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use rust_decimal::Decimal;
+
+use crate::engine::{AmountFormat, Engine, Transaction};
+
+pub const PE_OK: c_int = 0;
+pub const PE_REJECTED: c_int = 1;
+pub const PE_INVALID_ARGUMENT: c_int = -1;
+pub const PE_PANICKED: c_int = -2;
+
+pub struct PeEngine(Engine<Decimal>);
+
+// Run `f`, returning `panicked` if it panics.
+fn guard<T>(panicked: T, f: impl FnOnce() -> T) -> T {
+    // The engine is abandoned by the host after a panic, see the header
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(panicked)
+}
+
+#[no_mangle]
+pub extern "C" fn pe_engine_new() -> *mut PeEngine {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(PeEngine(Engine::new())))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pe_engine_free(engine: *mut PeEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+// Process one transaction. `amount` may be NULL for disputes, resolves and chargebacks.
+//
+// Returns PE_OK, PE_REJECTED if the engine refused the transaction, e.g. for insufficient funds,
+// PE_INVALID_ARGUMENT for NULL or unparsable arguments or PE_PANICKED.
+#[no_mangle]
+pub unsafe extern "C" fn pe_engine_submit(
+    engine: *const PeEngine,
+    r#type: *const c_char,
+    client: *const c_char,
+    tx: *const c_char,
+    amount: *const c_char,
+) -> c_int {
+    guard(PE_PANICKED, || submit(engine, r#type, client, tx, amount))
+}
+
+unsafe fn submit(
+    engine: *const PeEngine,
+    r#type: *const c_char,
+    client: *const c_char,
+    tx: *const c_char,
+    amount: *const c_char,
+) -> c_int {
+    let Some(engine) = engine.as_ref() else {
+        return PE_INVALID_ARGUMENT;
+    };
+    let transaction = (|| {
+        Some(Transaction {
+            r#type: parse(r#type)?,
+            client_id: parse(client)?,
+            transaction_id: parse(tx)?,
+            amount: if amount.is_null() {
+                None
+            } else {
+                Some(parse(amount)?)
+            },
+            metadata: Default::default(),
+        })
+    })();
+
+    match transaction.map(|transaction| engine.0.process(transaction)) {
+        Some(Ok(())) => PE_OK,
+        Some(Err(_)) => PE_REJECTED,
+        None => PE_INVALID_ARGUMENT,
+    }
+}
+
+unsafe fn parse<T: std::str::FromStr>(value: *const c_char) -> Option<T> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok()?.parse().ok()
+}
+
+// Accounts sorted by client id as a JSON array, amounts as strings. NULL if `engine` is NULL or
+// on a panic.
+#[no_mangle]
+pub unsafe extern "C" fn pe_engine_accounts_json(engine: *const PeEngine) -> *mut c_char {
+    guard(ptr::null_mut(), || accounts_json(engine))
+}
+
+unsafe fn accounts_json(engine: *const PeEngine) -> *mut c_char {
+    let Some(Ok(mut accounts)) = engine.as_ref().map(|engine| engine.0.accounts()) else {
+        return ptr::null_mut();
+    };
+    accounts.sort_by_key(|account| account.client_id);
+    let accounts: Vec<_> = accounts
+        .iter()
+        .map(|account| account.formatted(AmountFormat::default()))
+        .collect();
+
+    serde_json::to_string(&accounts)
+        .ok()
+        .and_then(|json| CString::new(json).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pe_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submit(engine: *const PeEngine, fields: [&str; 4]) -> c_int {
+        let fields = fields.map(|field| CString::new(field).unwrap());
+        let amount = if fields[3].is_empty() {
+            ptr::null()
+        } else {
+            fields[3].as_ptr()
+        };
+        unsafe {
+            pe_engine_submit(
+                engine,
+                fields[0].as_ptr(),
+                fields[1].as_ptr(),
+                fields[2].as_ptr(),
+                amount,
+            )
+        }
+    }
+
+    #[test]
+    fn test_engine_lifecycle() {
+        let engine = pe_engine_new();
+
+        assert_eq!(submit(engine, ["deposit", "1", "1", "2.5"]), PE_OK);
+        assert_eq!(submit(engine, ["withdrawal", "1", "2", "5.0"]), PE_REJECTED);
+        assert_eq!(submit(engine, ["dispute", "1", "1", ""]), PE_OK);
+        assert_eq!(
+            submit(engine, ["transfer", "1", "3", "1.0"]),
+            PE_INVALID_ARGUMENT
+        );
+
+        unsafe {
+            let json = pe_engine_accounts_json(engine);
+            let accounts: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(accounts[0]["held"], "2.5");
+            pe_string_free(json);
+            pe_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_null_engine() {
+        assert_eq!(
+            submit(ptr::null(), ["deposit", "1", "1", "1.0"]),
+            PE_INVALID_ARGUMENT
+        );
+        assert!(unsafe { pe_engine_accounts_json(ptr::null()) }.is_null());
+    }
+
+    #[test]
+    fn test_panics_are_caught() {
+        assert_eq!(guard(PE_PANICKED, || PE_OK), PE_OK);
+        assert_eq!(
+            guard(PE_PANICKED, || -> c_int { panic!("engine bug") }),
+            PE_PANICKED
+        );
+    }
+}
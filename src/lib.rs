@@ -0,0 +1,55 @@
+#[cfg(not(target_family = "wasm"))]
+pub mod admin;
+pub mod bundle;
+pub mod checkpoint;
+pub mod chunked;
+pub mod compare;
+#[cfg(not(target_family = "wasm"))]
+pub mod config;
+pub mod crypto;
+pub mod directory;
+#[cfg(not(target_family = "wasm"))]
+pub mod distributed;
+pub mod engine;
+pub mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod manifest;
+pub mod migrate;
+#[cfg(unix)]
+pub mod mmap;
+pub mod parser;
+pub mod partition;
+pub mod pipeline;
+pub mod reorder;
+pub mod repl;
+pub mod replay;
+pub mod rollup;
+#[cfg(not(target_family = "wasm"))]
+pub mod routing;
+#[cfg(not(target_family = "wasm"))]
+mod run;
+pub mod sequence;
+#[cfg(not(target_family = "wasm"))]
+pub mod server;
+#[cfg(not(target_family = "wasm"))]
+pub mod shadow;
+#[cfg(unix)]
+pub mod signals;
+pub mod simulate;
+#[cfg(feature = "datafusion")]
+pub mod sql;
+pub mod statement;
+pub mod storage;
+pub mod stress;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(not(target_family = "wasm"))]
+pub mod throttle;
+pub mod upsert;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use engine::Engine;
+#[cfg(not(target_family = "wasm"))]
+pub use run::{run_file, Report};
@@ -1,36 +1,1547 @@
-use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
 
-mod engine;
-use engine::Engine;
+use payements_engine::admin::{self, AdminOptions, Ingestion};
+use payements_engine::bundle::{
+    Bundle, BundleEntry, SharedBuffer, JOURNAL_ENTRY, REJECTED_ENTRY, REPORT_ENTRY, SNAPSHOT_ENTRY,
+    STATS_ENTRY,
+};
+use payements_engine::chunked::{self, ChunkedParser};
+use payements_engine::compare;
+use payements_engine::config::{self, ConfigWatcher};
+use payements_engine::crypto::{to_hex, Sha256, SigningKey};
+use payements_engine::directory::{self, Merge};
+use payements_engine::distributed;
+use payements_engine::engine::amount::{Amount, MinorUnits};
+use payements_engine::engine::store::{
+    AccountStore, BTreeMapStore, DashMapStore, ShardedStore, SpillingStore,
+};
+use payements_engine::engine::{
+    diff_accounts, Account, AccountFilter, AmountFormat, ClientId, Engine, EngineBuilder, Ledgers,
+    MemoryStats, PaymentsEngine, ReferenceEngine, RollingReserve, SimulatedClock, Sweep,
+    SystemClock, Timestamp, TransactionError, TIMELINE_TARGET,
+};
+use payements_engine::explain;
+use payements_engine::manifest::{self as run_manifest, DigestWriter, Manifest, ReportDigest};
+use payements_engine::migrate;
+#[cfg(unix)]
+use payements_engine::mmap::MappedFile;
+use payements_engine::parser::{ByteRecordParser, SerdeParser};
+use payements_engine::partition;
+use payements_engine::pipeline::{self, DecompressStage, ParseStage};
+use payements_engine::reorder::Reorder;
+use payements_engine::repl;
+use payements_engine::replay::EventLogParser;
+use payements_engine::rollup::{Parents, Rollups};
+use payements_engine::routing::HashRing;
+use payements_engine::sequence::{GapPolicy, SequenceTracker};
+use payements_engine::server::{self, ServeOptions};
+use payements_engine::shadow::Shadow;
+#[cfg(unix)]
+use payements_engine::signals;
+use payements_engine::simulate;
+use payements_engine::statement::{Period, Statement, StatementFormat};
+use payements_engine::storage;
+use payements_engine::stress;
+use payements_engine::throttle::{Throttle, TokenBucket};
+use payements_engine::upsert::upsert_report;
+use rust_decimal::Decimal;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::new().default_filter_or("error")).init();
+mod cli;
 
-    let file_path = match env::args().nth(1) {
-        None => Err(anyhow::anyhow!("Expecting one argument")),
-        Some(file_path) => Ok(file_path),
-    }?;
+fn spawn_parse_stage(options: &cli::Options, file_path: &str) -> anyhow::Result<ParseStage> {
+    if Path::new(file_path).is_dir() {
+        let mut inputs = Vec::new();
+        for path in directory::input_files(file_path)? {
+            let reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_path(path)?;
+            let parser: Box<dyn Iterator<Item = _> + Send> = if options.fast_parse {
+                Box::new(ByteRecordParser::new(reader)?)
+            } else {
+                Box::new(SerdeParser::new(reader)?)
+            };
+            inputs.push(parser);
+        }
+        return Ok(ParseStage::spawn(
+            Merge::new(inputs, options.merge_order),
+            options.queue_capacity,
+        ));
+    }
+    // `--mmap` is rejected on other platforms
+    #[cfg(unix)]
+    let mapped = if options.mmap {
+        Some(MappedFile::open(file_path)?)
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    let mapped: Option<Vec<u8>> = None;
 
-    let reader = csv::ReaderBuilder::new()
+    let input: Box<dyn io::Read + Send> = match mapped {
+        Some(mapped) if options.parse_threads > 1 && !pipeline::is_zstd(&mapped) => {
+            let parser = ChunkedParser::spawn(
+                Arc::new(mapped),
+                options.parse_threads,
+                chunked::DEFAULT_CHUNK_SIZE,
+                options.fast_parse,
+            )?;
+            return Ok(ParseStage::spawn(parser, options.queue_capacity));
+        }
+        Some(mapped) => Box::new(io::Cursor::new(mapped)),
+        None => storage::open(Path::new(file_path))?,
+    };
+    // Compressed inputs are decompressed on a stage of their own
+    let mut input = io::BufReader::new(input);
+    let (mut input, mut decompress): (Box<dyn io::Read + Send>, _) =
+        if pipeline::is_zstd(input.fill_buf()?) {
+            let (stage, decompressed) =
+                DecompressStage::spawn(input, pipeline::DEFAULT_BLOCK_CAPACITY);
+            (Box::new(decompressed), Some(stage))
+        } else {
+            (Box::new(input), None)
+        };
+
+    let stage = if options.parse_threads > 1 {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        if let Some(decompress) = decompress.take() {
+            decompress.join()?;
+        }
+        let parser = ChunkedParser::spawn(
+            Arc::new(data),
+            options.parse_threads,
+            chunked::DEFAULT_CHUNK_SIZE,
+            options.fast_parse,
+        )?;
+        ParseStage::spawn(parser, options.queue_capacity)
+    } else {
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input);
+        if options.fast_parse {
+            ParseStage::spawn(ByteRecordParser::new(reader)?, options.queue_capacity)
+        } else {
+            ParseStage::spawn(SerdeParser::new(reader)?, options.queue_capacity)
+        }
+    };
+    Ok(match decompress {
+        Some(decompress) => stage.after(decompress),
+        None => stage,
+    })
+}
+
+// Client ids in the first column of a CSV file, with an optional `client` header.
+fn read_clients(path: &Path) -> anyhow::Result<Vec<ClientId>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
         .trim(csv::Trim::All)
         .flexible(true)
-        .from_path(file_path)?;
+        .from_path(path)?;
+    let mut clients = Vec::new();
+    for (line, record) in (1..).zip(reader.records()) {
+        let record = record?;
+        let field = record.get(0).unwrap_or_default();
+        if line == 1 && field == "client" {
+            continue;
+        }
+        clients.push(
+            field
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid client id in {}:{}", path.display(), line))?,
+        );
+    }
+    Ok(clients)
+}
+
+// Engine builder with the processing options applied.
+fn configure<A, S>(
+    mut builder: EngineBuilder<A, S>,
+    options: &cli::Options,
+) -> anyhow::Result<EngineBuilder<A, S>>
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    if let Some(expected_transactions) = options.duplicate_filter {
+        builder = builder.duplicate_filter(expected_transactions, options.duplicate_fp_rate);
+    }
+    if let Some(interval) = options.compaction_interval {
+        builder = builder.compaction_interval(interval);
+    }
+    if options.enforce_balance_invariants {
+        builder = builder.enforce_balance_invariants();
+    }
+    if options.idempotent {
+        builder = builder.idempotent();
+    }
+    if let Some(client_id) = options.trace_client {
+        builder = builder.trace_client(client_id);
+    }
+    if options.reject_zero_amounts {
+        builder = builder.reject_zero_amounts();
+    }
+    if options.negative_deposits_as_reversals {
+        builder = builder.negative_deposits_as_reversals();
+    }
+    if options.close_with_held_funds {
+        builder = builder.close_with_held_funds();
+    }
+    builder = builder.chargeback_disputes(options.chargeback_disputes);
+    if let Some(max_balance) = options.max_balance {
+        builder = builder.max_balance(max_balance);
+    }
+    if let Some(percent) = options.rolling_reserve {
+        builder = builder.rolling_reserve(RollingReserve {
+            rate: percent / Decimal::ONE_HUNDRED,
+            period: options.reserve_period,
+        });
+    }
+    if let Some(path) = &options.calendar {
+        builder = builder.calendars(config::load_calendars(path)?);
+    }
+    if let Some(policy) = options.dunning {
+        builder = builder.dunning(policy);
+    }
+    if let Some(policy) = options.structuring {
+        builder = builder.structuring(policy);
+    }
+    if options.aml_register.is_some() {
+        builder = builder.aml_register(options.aml_thresholds);
+    }
+    builder = builder.risk_thresholds(options.risk_thresholds);
+    if let Some(path) = &options.blocklist {
+        builder = builder.blocklist(read_clients(path)?);
+    }
+    if let Some(path) = &options.allowlist {
+        builder = builder.allowlist(read_clients(path)?);
+    }
+    if let Some(name) = &options.loss_account {
+        builder = builder.loss_account(name.clone());
+    }
+    if options.dispute_suspense.is_some() {
+        builder = builder.dispute_suspense();
+    }
+    if options.queue_locked.is_some() {
+        builder = builder.queue_locked();
+    }
+    if options.analytics.is_some() {
+        builder = builder.analytics();
+    }
+    if options.hot_accounts.is_some() {
+        builder = builder.hot_accounts();
+    }
+    if options.time_buckets.is_some() {
+        builder = builder.time_buckets(options.bucket_width);
+    }
+    // Disputes expire and age, reserves are released and dunning escalates relative to the time
+    // of the input, or the wall clock while serving
+    if matches!(options.command, cli::Command::Serve(_)) {
+        builder = builder.clock(Arc::new(SystemClock));
+    } else if options.dispute_window.is_some() || options.held_aging.is_some() {
+        builder = builder.clock(Arc::new(SimulatedClock::default()));
+    }
+
+    Ok(builder)
+}
+
+// Process the input into the configured ledgers and print the report of all of them.
+fn run_ledgers(
+    options: &cli::Options,
+    names: &[String],
+    parse_stage: Option<ParseStage>,
+) -> anyhow::Result<()> {
+    let mut engines = Vec::new();
+    for name in names {
+        engines.push((
+            name.clone(),
+            configure(EngineBuilder::new(), options)?.build(),
+        ));
+    }
+    let ledgers: Ledgers =
+        Ledgers::new(engines).ok_or_else(|| anyhow::anyhow!("No ledgers given"))?;
 
-    let mut engine = Engine::new();
-    if let Err(e) = engine.process_transactions(reader.into_deserialize()).await {
-        return Err(anyhow::anyhow!("Error processing transactions: {}", e));
+    if let Some(parse_stage) = parse_stage {
+        let records = Throttle::new(
+            Reorder::new(
+                parse_stage.records(),
+                options.reorder_by,
+                options.reorder_window,
+            ),
+            options.max_tps,
+        );
+        for transaction in records.flatten() {
+            if !options.input_filter.matches(&transaction) {
+                continue;
+            }
+            let transaction_id = transaction.transaction_id;
+            if let Err(e) = ledgers.process(transaction) {
+                log::warn!("Error processing transaction {}: {}", transaction_id, e);
+            }
+        }
+        parse_stage.join()?;
     }
 
-    let mut writer = csv::WriterBuilder::new()
-        .has_headers(true)
-        .delimiter(b',')
-        .flexible(false)
-        .from_writer(std::io::stdout());
+    let manifest = ManifestOutput::new(options)?;
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut rows = 0;
+    for (name, engine) in ledgers.iter() {
+        for account in engine.accounts()? {
+            if !options.report_filter.matches(&account) {
+                continue;
+            }
+            let mut account = account.formatted(options.output_format).with_ledger(name);
+            if options.closed_column {
+                account = account.with_closed_column();
+            }
+            if options.enforce_balance_invariants {
+                account = account.with_quarantined_column();
+            }
+            if options.dunning.is_some() {
+                account = account.with_dunning_column();
+            }
+            if options.report_filter.include_dormant {
+                account = account.with_dormant_column();
+            }
+            writer.serialize(account)?;
+            rows += 1;
+        }
+    }
+    let report = writer.into_inner()?;
+    let digest = match (&options.upsert, &options.report_out) {
+        (Some(path), _) => upsert(path, &report)?,
+        (None, Some(path)) => write_report(path, &report, rows)?,
+        (None, None) => {
+            let mut stdout = DigestWriter::new(io::stdout());
+            stdout.write_all(&report)?;
+            let (sha256, bytes) = stdout.digest();
+            ReportDigest {
+                path: None,
+                sha256,
+                bytes,
+                rows,
+            }
+        }
+    };
+    if let Some(manifest) = manifest {
+        manifest.write(digest)?;
+    }
+    Ok(())
+}
+
+// Write the report to the file or object, returning its digest.
+fn write_report(path: &Path, report: &[u8], rows: u64) -> anyhow::Result<ReportDigest> {
+    let mut output = storage::create(path)?;
+    output.write_all(report)?;
+    output.finish()?;
+    Ok(ReportDigest {
+        path: Some(path.display().to_string()),
+        sha256: to_hex(&Sha256::digest(report)),
+        bytes: report.len() as u64,
+        rows,
+    })
+}
 
+// Merge the report into the accounts file, returning the digest of the file.
+fn upsert(path: &Path, report: &[u8]) -> anyhow::Result<ReportDigest> {
+    let stats = upsert_report(path, report)
+        .map_err(|e| anyhow::anyhow!("Error upserting into {}: {}", path.display(), e))?;
+    log::info!(
+        "Upserted report into {}: {} rows replaced, {} added, {} kept",
+        path.display(),
+        stats.replaced,
+        stats.added,
+        stats.kept
+    );
+    let file = run_manifest::digest_file(path)?;
+    Ok(ReportDigest {
+        path: Some(file.path),
+        sha256: file.sha256,
+        bytes: file.bytes,
+        rows: (stats.replaced + stats.added + stats.kept) as u64,
+    })
+}
+
+// Files of the input, the file or the files of the directory.
+fn input_files(options: &cli::Options) -> anyhow::Result<Vec<PathBuf>> {
+    let Some(file_path) = &options.file_path else {
+        return Ok(Vec::new());
+    };
+    if Path::new(file_path).is_dir() {
+        Ok(directory::input_files(file_path)?)
+    } else {
+        Ok(vec![PathBuf::from(file_path)])
+    }
+}
+
+// Manifest of the report, see `--manifest`.
+struct ManifestOutput {
+    path: PathBuf,
+    key: Option<SigningKey>,
+    inputs: Vec<PathBuf>,
+}
+
+impl ManifestOutput {
+    // None without `--manifest`. The key is read up front, so a bad key fails the run before
+    // processing.
+    fn new(options: &cli::Options) -> anyhow::Result<Option<ManifestOutput>> {
+        let Some(path) = &options.manifest else {
+            return Ok(None);
+        };
+        let key = match &options.manifest_key {
+            Some(path) => Some(run_manifest::read_signing_key(path)?),
+            None => None,
+        };
+        let mut inputs = input_files(options)?;
+        let other_inputs = [
+            &options.restore,
+            &options.from_bundle,
+            &options.config,
+            &options.blocklist,
+            &options.allowlist,
+            &options.parents,
+        ];
+        inputs.extend(other_inputs.into_iter().flatten().cloned());
+        Ok(Some(ManifestOutput {
+            path: path.clone(),
+            key,
+            inputs,
+        }))
+    }
+
+    fn write(&self, report: ReportDigest) -> anyhow::Result<()> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|path| run_manifest::digest_file(path))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut manifest = Manifest::new(std::env::args().skip(1).collect(), inputs, report);
+        if let Some(key) = &self.key {
+            manifest.sign(key);
+        }
+        serde_json::to_writer_pretty(File::create(&self.path)?, &manifest)?;
+        Ok(())
+    }
+}
+
+fn run<A, S>(
+    options: &cli::Options,
+    store: S,
+    parse_stage: Option<ParseStage>,
+) -> anyhow::Result<()>
+where
+    A: Amount,
+    S: AccountStore<A> + 'static,
+{
+    let mut builder = configure(EngineBuilder::with_store(store), options)?;
+    if let Some(path) = &options.journal {
+        builder = builder.journal(File::create(path)?);
+    }
+    // Outputs of `export` collected for the bundle
+    let exporting = matches!(options.command, cli::Command::Export(_));
+    let journal = SharedBuffer::default();
+    if exporting {
+        builder = builder.journal(journal.clone());
+    }
+    let mut rejected = exporting.then(|| csv::Writer::from_writer(Vec::new()));
+    if let Some(writer) = &mut rejected {
+        writer.write_record(["record", "type", "client", "tx", "amount", "code", "error"])?;
+    }
+    if let Some(path) = &options.record {
+        builder = builder.record(File::create(path)?);
+    }
+    let report = Arc::new(Mutex::new(ReportWriter::new(options)?));
+    if let Some(horizon) = options.finalize_after {
+        let report = Arc::clone(&report);
+        builder = builder.finalize_idle_accounts(horizon, move |account| {
+            let client_id = account.client_id;
+            let mut report = report.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = report.write(account) {
+                log::warn!("Error writing finalized account {}: {}", client_id, e);
+            }
+        });
+    }
+    let mut engine = builder.build();
+    if let Some(path) = &options.restore {
+        engine.restore_snapshot(storage::open(path)?)?;
+    }
+    if let Some(path) = &options.from_bundle {
+        let bundle = read_bundle(path)?;
+        let snapshot = bundle
+            .entry(SNAPSHOT_ENTRY)
+            .ok_or_else(|| anyhow::anyhow!("No {} in bundle {}", SNAPSHOT_ENTRY, path.display()))?;
+        let accounts = engine.restore_snapshot(snapshot)?;
+        log::info!(
+            "Restored {} accounts from bundle {} created at {}",
+            accounts,
+            path.display(),
+            bundle.manifest.created_at
+        );
+    }
+    if let Some(path) = &options.config {
+        let mut watcher = ConfigWatcher::new(path, engine.policy_handle());
+        watcher.reload()?;
+        watcher.spawn(config::DEFAULT_POLL_INTERVAL);
+    }
+    if let Some(parse_stage) = parse_stage {
+        let reference = options.differential.then(ReferenceEngine::new);
+        let input_filter = &options.input_filter;
+        let records = parse_stage.records().filter(|record| {
+            record
+                .as_ref()
+                .map_or(true, |transaction| input_filter.matches(transaction))
+        });
+        let mut sequence_tracker = SequenceTracker::default();
+        let mut halted = None;
+        let ingestion = Arc::new(Ingestion::default());
+        #[cfg(unix)]
+        signals::control_ingestion(Arc::clone(&ingestion), || {})?;
+        let records = Reorder::new(records, options.reorder_by, options.reorder_window)
+            .inspect(|_| ingestion.wait_resumed());
+        let records = Throttle::new(records, options.max_tps)
+            .take_while(|record| {
+                let (Some(policy), Ok(transaction)) = (options.gap_policy, record) else {
+                    return true;
+                };
+                match sequence_tracker.check(transaction) {
+                    Some(anomaly) if policy == GapPolicy::Warn => {
+                        log::warn!("{}", anomaly);
+                        true
+                    }
+                    Some(anomaly) => {
+                        halted = Some(anomaly);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .inspect(|record| {
+                if let (Some(reference), Ok(transaction)) = (&reference, record) {
+                    let _ = reference.process(transaction.clone());
+                }
+            });
+        let on_error = |e: TransactionError| {
+            log::warn!("{}", e);
+            if let Some(writer) = &mut rejected {
+                let transaction = &e.transaction;
+                let row = [
+                    e.position.to_string(),
+                    transaction.r#type.name().to_string(),
+                    transaction.client_id.to_string(),
+                    transaction.transaction_id.to_string(),
+                    transaction
+                        .amount
+                        .map(|amount| amount.to_string())
+                        .unwrap_or_default(),
+                    e.error.code().as_str().to_string(),
+                    e.error.to_string(),
+                ];
+                if let Err(e) = writer.write_record(row) {
+                    log::warn!("Error writing rejected transaction: {}", e);
+                }
+            }
+        };
+        if let Err(e) = engine.process_transactions_with(records, on_error) {
+            return Err(anyhow::anyhow!("Error processing transactions: {}", e));
+        }
+        parse_stage.join()?;
+        match (halted, options.gap_policy) {
+            (Some(anomaly), Some(GapPolicy::Abort)) => {
+                return Err(anyhow::anyhow!("Aborted: {}", anomaly));
+            }
+            (Some(anomaly), _) => log::warn!("Paused: {}", anomaly),
+            _ => {}
+        }
+
+        if let Some(reference) = reference {
+            let clients = diff_accounts(&engine.accounts()?, &reference.accounts());
+            if let Some(client_id) = clients.first() {
+                return Err(anyhow::anyhow!(
+                    "Differential check failed: {} accounts differ from the reference engine, first is client {}",
+                    clients.len(),
+                    client_id
+                ));
+            }
+        }
+    }
+
+    let expiry = options
+        .dispute_window
+        .map(|window| (window, options.expire_disputes_as));
+    if let Some(sweep) = engine.sweep(expiry) {
+        log_sweep(&sweep, options);
+        if let Some(path) = &options.expired_disputes {
+            let mut writer = csv::Writer::from_path(path)?;
+            for dispute in sweep.expired {
+                writer.serialize(dispute)?;
+            }
+            writer.flush()?;
+        }
+    }
+    if let Some(path) = &options.held_aging {
+        let now = engine.now().unwrap_or_else(|| Timestamp(SystemTime::now()));
+        let mut writer = csv::Writer::from_path(path)?;
+        for aging in engine.held_aging(now) {
+            writer.serialize(aging)?;
+        }
+        writer.flush()?;
+    }
+
+    engine.flush_journal()?;
+    engine.flush_record()?;
+    if let Some(path) = &options.snapshot_out {
+        let mut output = storage::create(path)?;
+        engine.write_snapshot(&mut output)?;
+        output.finish()?;
+    }
+    if let Some(path) = &options.dispute_suspense {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["client", "tx"])?;
+        for (client_id, transaction_id) in engine.suspense() {
+            writer.write_record([client_id.to_string(), transaction_id.to_string()])?;
+        }
+        writer.flush()?;
+    }
+    if let Some(path) = &options.queue_locked {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["type", "client", "tx", "amount"])?;
+        for transaction in engine.queued() {
+            writer.write_record([
+                transaction.r#type.name().to_string(),
+                transaction.client_id.to_string(),
+                transaction.transaction_id.to_string(),
+                transaction
+                    .amount
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+            ])?;
+        }
+        writer.flush()?;
+    }
+    if let Some(path) = &options.extended_report {
+        let report = engine
+            .extended_report()
+            .ok_or_else(|| anyhow::anyhow!("Trial balance overflows the amount type"))?;
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+    }
+    if let Some(path) = &options.risk_report {
+        let mut writer = csv::Writer::from_path(path)?;
+        for entry in engine.risk_report(engine.policies().risk_thresholds) {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+    }
+    if let (Some(path), Some(register)) = (&options.aml_register, engine.aml_register()) {
+        let mut writer = csv::Writer::from_path(path)?;
+        for entry in register {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+    }
+    if let Some(path) = &options.structuring_evidence {
+        let mut writer = csv::Writer::from_path(path)?;
+        for evidence in engine.structuring_evidence() {
+            writer.serialize(evidence)?;
+        }
+        writer.flush()?;
+    }
+    if let (Some(path), Some(report)) = (
+        &options.analytics,
+        engine.analytics_report(options.top_clients),
+    ) {
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+    }
+    if let (Some(path), Some(hot_accounts)) = (
+        &options.hot_accounts,
+        engine.hot_accounts(options.top_clients),
+    ) {
+        let mut writer = csv::Writer::from_path(path)?;
+        for hot_account in hot_accounts {
+            writer.serialize(hot_account)?;
+        }
+        writer.flush()?;
+    }
+    if let (Some(path), Some(buckets)) = (&options.time_buckets, engine.time_buckets()) {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            serde_json::to_writer_pretty(File::create(path)?, &buckets)?;
+        } else {
+            let mut writer = csv::Writer::from_path(path)?;
+            for bucket in buckets {
+                writer.serialize(bucket)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    if let cli::Command::Serve(address) = options.command {
+        let ring = match &options.peers {
+            Some(peers) => {
+                let local = options.advertise.unwrap_or(address);
+                let ring = HashRing::new(peers.clone(), local).ok_or_else(|| {
+                    anyhow::anyhow!("Address {} of this instance is not among --peers", local)
+                })?;
+                Some(ring)
+            }
+            None => None,
+        };
+        let admin = match &options.admin_token_file {
+            Some(path) => {
+                let token = fs::read_to_string(path)?.trim().to_string();
+                if token.is_empty() {
+                    return Err(anyhow::anyhow!("Empty admin token in {}", path.display()));
+                }
+                let approvers = match &options.approvers_file {
+                    Some(path) => {
+                        admin::parse_approvers(&fs::read_to_string(path)?).ok_or_else(|| {
+                            anyhow::anyhow!("Approver without a token in {}", path.display())
+                        })?
+                    }
+                    None => Vec::new(),
+                };
+                Some(AdminOptions {
+                    token,
+                    snapshot_path: options.snapshot_out.clone(),
+                    approvers,
+                })
+            }
+            None => None,
+        };
+        if options.approval_threshold.is_some()
+            && admin
+                .as_ref()
+                .is_none_or(|admin| admin.approvers.is_empty())
+        {
+            return Err(anyhow::anyhow!(
+                "--approval-threshold needs --admin-token-file and --approvers-file to approve held transactions"
+            ));
+        }
+        let listener = TcpListener::bind(address)?;
+        log::info!("Serving on {}", listener.local_addr()?);
+        let engine = Arc::new(engine);
+        if let Some(interval) = options.memory_log_interval {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                log_memory_stats(&engine.memory_stats());
+            });
+        }
+        if options.dispute_window.is_some()
+            || options.rolling_reserve.is_some()
+            || options.dunning.is_some()
+        {
+            let engine = Arc::clone(&engine);
+            let interval = options.dispute_sweep_interval;
+            let expiry = options
+                .dispute_window
+                .map(|window| (window, options.expire_disputes_as));
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                if let Some(sweep) = engine.sweep(expiry) {
+                    if !sweep.expired.is_empty() {
+                        log::info!("Expired {} disputes", sweep.expired.len());
+                    }
+                }
+            });
+        }
+        let shadow = match &options.shadow_config {
+            Some(path) => {
+                let shadow: Engine = configure(EngineBuilder::new(), options)?.build();
+                let mut watcher = ConfigWatcher::new(path, shadow.policy_handle());
+                watcher.reload()?;
+                watcher.spawn(config::DEFAULT_POLL_INTERVAL);
+                // Start from the state served, after the input and restored snapshots
+                let mut state = Vec::new();
+                engine.write_snapshot(&mut state)?;
+                shadow.restore_snapshot(state.as_slice())?;
+                log::info!(
+                    "Shadowing transactions to an engine with the policies of {}",
+                    path.display()
+                );
+                Some(Arc::new(Shadow::new(Box::new(shadow))))
+            }
+            None => None,
+        };
+        let approval_threshold = options.approval_threshold;
+        let ingestion = Arc::new(Ingestion::default());
+        #[cfg(unix)]
+        {
+            let engine = Arc::clone(&engine);
+            let snapshot_path = options.snapshot_out.clone();
+            signals::control_ingestion(Arc::clone(&ingestion), move || {
+                let Some(path) = &snapshot_path else {
+                    return;
+                };
+                match admin::write_snapshot(&engine, path) {
+                    Ok(()) => log::info!(target: "audit", "Wrote snapshot to {}", path.display()),
+                    Err(e) => log::warn!("Error writing snapshot to {}: {}", path.display(), e),
+                }
+            })?;
+        }
+        server::serve_with(
+            engine,
+            listener,
+            ServeOptions {
+                ring,
+                admin,
+                approval_threshold,
+                shadow,
+                throttle: options.max_tps.map(TokenBucket::new),
+                ingestion,
+            },
+        )?;
+        return Ok(());
+    }
+
+    let mut report = report.lock().unwrap_or_else(|e| e.into_inner());
     for account in engine.accounts()? {
+        report.write(account)?;
+    }
+    report.flush()?;
+
+    if let cli::Command::Export(path) = &options.command {
+        let mut bundle = Bundle::new(std::env::args().skip(1).collect());
+        for path in input_files(options)? {
+            let digest = run_manifest::digest_file(&path)?;
+            bundle.manifest.inputs.push(BundleEntry {
+                name: digest.path,
+                bytes: digest.bytes,
+                sha256: digest.sha256,
+            });
+        }
+        bundle.add(REPORT_ENTRY, report.buffered().to_vec());
+        engine.flush_journal()?;
+        bundle.add(JOURNAL_ENTRY, journal.contents());
+        bundle.add(STATS_ENTRY, serde_json::to_vec_pretty(&engine.stats())?);
+        let rejected = match rejected {
+            Some(writer) => writer.into_inner()?,
+            None => Vec::new(),
+        };
+        bundle.add(REJECTED_ENTRY, rejected);
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot)?;
+        bundle.add(SNAPSHOT_ENTRY, snapshot);
+        bundle.write(File::create(path)?)?;
+        log::info!(
+            "Exported {} entries to {}",
+            bundle.manifest.entries.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+// Log what the sweep at the end of the input did, for the features enabled.
+fn log_sweep(sweep: &Sweep, options: &cli::Options) {
+    let now = sweep.now;
+    if options.rolling_reserve.is_some() {
+        log::info!("Released {} reserves due by {}", sweep.released, now);
+    }
+    if let Some(window) = options.dispute_window {
+        log::info!(
+            "Expired {} disputes open for more than {} at {}",
+            sweep.expired.len(),
+            humantime::format_duration(window),
+            now
+        );
+    }
+    if options.dunning.is_some() {
+        log::info!("{} accounts in dunning at {}", sweep.dunning, now);
+    }
+}
+
+fn log_memory_stats(stats: &MemoryStats) {
+    log::info!(
+        "Memory: {} accounts, {} stored transactions, {} metadata entries, ~{} bytes, {} shards of {} to {} accounts",
+        stats.accounts,
+        stats.transactions,
+        stats.metadata_entries,
+        stats.estimated_bytes,
+        stats.shards.len(),
+        stats.shards.iter().min().unwrap_or(&0),
+        stats.shards.iter().max().unwrap_or(&0)
+    );
+}
+
+fn print_report<A: Amount>(
+    options: &cli::Options,
+    accounts: Vec<Account<A>>,
+) -> anyhow::Result<()> {
+    let mut report = ReportWriter::new(options)?;
+    for account in accounts {
+        report.write(account)?;
+    }
+    report.flush()
+}
+
+// Account report on stdout. Shared with the sink of `--finalize-after`, so the accounts finalized
+// while processing and the remaining ones end up in one CSV under a single header, and in the
+// rollups by parent. With `--upsert` the report is collected and merged into the file on flush,
+// `export` collects it for the bundle.
+struct ReportWriter {
+    writer: csv::Writer<DigestWriter<io::Stdout>>,
+    rows: u64,
+    manifest: Option<ManifestOutput>,
+    buffer: Option<csv::Writer<Vec<u8>>>,
+    upsert: Option<PathBuf>,
+    report_out: Option<PathBuf>,
+    filter: AccountFilter,
+    format: AmountFormat,
+    closed_column: bool,
+    quarantined_column: bool,
+    dunning_column: bool,
+    rollups: Option<Rollups>,
+    parent_report: Option<PathBuf>,
+}
+
+impl ReportWriter {
+    fn new(options: &cli::Options) -> anyhow::Result<ReportWriter> {
+        let rollups = match &options.parents {
+            Some(path) => Some(Rollups::new(Parents::read(File::open(path)?).map_err(
+                |e| anyhow::anyhow!("Invalid parents file {}: {}", path.display(), e),
+            )?)),
+            None => None,
+        };
+        Ok(ReportWriter {
+            writer: csv::WriterBuilder::new()
+                .has_headers(true)
+                .delimiter(b',')
+                .flexible(false)
+                .from_writer(DigestWriter::new(io::stdout())),
+            rows: 0,
+            manifest: ManifestOutput::new(options)?,
+            filter: options.report_filter.clone(),
+            format: options.output_format,
+            closed_column: options.closed_column,
+            quarantined_column: options.enforce_balance_invariants,
+            dunning_column: options.dunning.is_some(),
+            buffer: (options.upsert.is_some()
+                || options.report_out.is_some()
+                || matches!(options.command, cli::Command::Export(_)))
+            .then(|| csv::Writer::from_writer(Vec::new())),
+            upsert: options.upsert.clone(),
+            report_out: options.report_out.clone(),
+            rollups,
+            parent_report: options.parent_report.clone(),
+        })
+    }
+
+    fn write<A: Amount>(&mut self, account: Account<A>) -> anyhow::Result<()> {
+        if !self.filter.matches(&account) {
+            return Ok(());
+        }
+        if let Some(rollups) = &mut self.rollups {
+            rollups.add(&account);
+        }
+        let mut formatted = account.formatted(self.format);
+        if self.closed_column {
+            formatted = formatted.with_closed_column();
+        }
+        if self.quarantined_column {
+            formatted = formatted.with_quarantined_column();
+        }
+        if self.dunning_column {
+            formatted = formatted.with_dunning_column();
+        }
+        if self.filter.include_dormant {
+            formatted = formatted.with_dormant_column();
+        }
+        if let Some(rollups) = &self.rollups {
+            formatted = formatted.with_parent(rollups.parents().parent(&account.client_id));
+        }
+        match &mut self.buffer {
+            Some(writer) => writer.serialize(formatted)?,
+            None => self.writer.serialize(formatted)?,
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    // Report collected instead of printed, empty if it was printed.
+    fn buffered(&self) -> &[u8] {
+        self.buffer.as_ref().map_or(&[], |writer| writer.get_ref())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        if let Some(writer) = &mut self.buffer {
+            writer.flush()?;
+        }
+        let digest = match (&self.buffer, &self.upsert, &self.report_out) {
+            (Some(writer), Some(path), _) => upsert(path, writer.get_ref())?,
+            (Some(writer), None, Some(path)) => write_report(path, writer.get_ref(), self.rows)?,
+            (Some(writer), None, None) => ReportDigest {
+                path: None,
+                sha256: to_hex(&Sha256::digest(writer.get_ref())),
+                bytes: writer.get_ref().len() as u64,
+                rows: self.rows,
+            },
+            (None, ..) => {
+                let (sha256, bytes) = self.writer.get_ref().digest();
+                ReportDigest {
+                    path: None,
+                    sha256,
+                    bytes,
+                    rows: self.rows,
+                }
+            }
+        };
+        if let Some(manifest) = &self.manifest {
+            manifest.write(digest)?;
+        }
+        if let (Some(rollups), Some(path)) = (&self.rollups, &self.parent_report) {
+            let mut writer = csv::Writer::from_path(path)?;
+            writer.write_record(["parent", "clients", "available", "held", "total", "locked"])?;
+            for rollup in rollups.rollups() {
+                writer.write_record([
+                    rollup.parent.clone(),
+                    rollup.clients.to_string(),
+                    self.format.format(rollup.available),
+                    self.format.format(rollup.held),
+                    self.format.format(rollup.total),
+                    rollup.locked.to_string(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Shared secret of workers and coordinators, see `distributed::run_job`.
+fn read_secret(options: &cli::Options) -> anyhow::Result<Option<String>> {
+    let Some(path) = &options.secret_file else {
+        return Ok(None);
+    };
+    let secret = fs::read_to_string(path)?.trim().to_string();
+    if secret.is_empty() || secret.contains('\n') {
+        return Err(anyhow::anyhow!(
+            "The secret in {} has to be one non-empty line",
+            path.display()
+        ));
+    }
+    Ok(Some(secret))
+}
+
+// Process jobs of coordinators one at a time, each on a fresh engine.
+fn worker(options: &cli::Options, address: SocketAddr) -> anyhow::Result<()> {
+    let secret = read_secret(options)?;
+    let listener = TcpListener::bind(address)?;
+    log::info!("Worker listening on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let (stream, peer) = match stream.and_then(|stream| Ok((stream.peer_addr()?, stream))) {
+            Ok((peer, stream)) => (stream, peer),
+            Err(e) => {
+                log::warn!("Error accepting job: {}", e);
+                continue;
+            }
+        };
+        let mut engine: Engine = configure(EngineBuilder::new(), options)?.build();
+        if let Some(path) = &options.config {
+            ConfigWatcher::new(path, engine.policy_handle()).reload()?;
+        }
+        match distributed::run_job(&mut engine, stream, secret.as_deref()) {
+            Ok(()) => {
+                log::info!("Finished job of {}: {:?}", peer, engine.stats());
+                log_memory_stats(&engine.memory_stats());
+            }
+            Err(e) => log::warn!("Error processing job of {}: {}", peer, e),
+        }
+    }
+    Ok(())
+}
+
+fn coordinate(
+    options: &cli::Options,
+    input_path: &str,
+    workers: &[SocketAddr],
+) -> anyhow::Result<()> {
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(input_path)?;
+    let engine: Engine = Engine::new();
+    let secret = read_secret(options)?;
+    let accounts = distributed::coordinate(reader, workers, &engine, secret.as_deref())?;
+    log::info!(
+        "Collected {} accounts from {} workers",
+        accounts,
+        workers.len()
+    );
+
+    let mut accounts = engine.accounts()?;
+    accounts.sort_by_key(|account| account.client_id);
+    print_report(options, accounts)
+}
+
+// Apply the batch to the state of the base snapshot and print the accounts it would change. The
+// snapshot isn't touched.
+fn simulate(options: &cli::Options, base: &Path, batch: &Path) -> anyhow::Result<()> {
+    let mut engine: Engine = configure(EngineBuilder::new(), options)?.build();
+    engine.restore_snapshot(File::open(base)?)?;
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(batch)?;
+    let changes = simulate::simulate(&mut engine, ByteRecordParser::new(reader)?)?;
+    simulate::write_changes(io::stdout(), &changes, options.output_format)?;
+    Ok(())
+}
+
+// Check the engine against the reference engine on the generated inputs of `rounds` seeds,
+// printing the input of the first diverging seed.
+fn stress(seed: u64, rounds: u64, length: usize) -> anyhow::Result<()> {
+    let workers = thread::available_parallelism().map_or(4, usize::from);
+    for seed in seed..seed.saturating_add(rounds) {
+        if let Some(divergence) = stress::check(seed, length, workers) {
+            stress::write_transactions(io::stdout(), &divergence.transactions)?;
+            return Err(anyhow::anyhow!(
+                "Engine diverged from the reference at seed {}: first differing transaction {:?}, differing clients {:?}",
+                seed,
+                divergence.transaction,
+                divergence.clients
+            ));
+        }
+        log::info!("Seed {} agrees with the reference", seed);
+    }
+    eprintln!(
+        "{} seeds of {} transactions agree with the reference",
+        rounds, length
+    );
+    Ok(())
+}
+
+// Run commands from stdin against a copy of the snapshot, printing the result of each. Nothing is
+// written back.
+fn repl(options: &cli::Options, snapshot_path: Option<&str>) -> anyhow::Result<()> {
+    let engine: Engine = configure(EngineBuilder::new(), options)?.build();
+    if let Some(path) = snapshot_path {
+        let accounts = engine.restore_snapshot(File::open(path)?)?;
+        println!("Restored {} accounts from {}", accounts, path);
+    }
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("{}", repl::HELP);
+    }
+
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            write!(stdout, "> ")?;
+            stdout.flush()?;
+        }
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(repl::Command::Quit) => return Ok(()),
+            Ok(command) => writeln!(stdout, "{}", repl::execute(&engine, command))?,
+            Err(e) => writeln!(stdout, "{}", e)?,
+        }
+    }
+}
+
+fn purge(snapshot_path: &str, client_id: ClientId) -> anyhow::Result<()> {
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(File::open(snapshot_path)?)?;
+    if engine.purge_client(client_id).is_none() {
+        return Err(anyhow::anyhow!(
+            "Client {} not found in the snapshot",
+            client_id
+        ));
+    }
+    engine.write_snapshot(io::stdout())?;
+    Ok(())
+}
+
+// Print the fields of the accounts differing between the runs of the bundles, failing if any do.
+fn compare_runs(old_path: &Path, new_path: &Path, input: &Path) -> anyhow::Result<()> {
+    let old = read_bundle(old_path)?;
+    let new = read_bundle(new_path)?;
+    let changes = compare::compare_runs(&old, &new, &run_manifest::digest_file(input)?)
+        .map_err(|e| anyhow::anyhow!("Error comparing runs: {}", e))?;
+    compare::write_changes(&changes, io::stdout().lock())?;
+    let mut clients: Vec<_> = changes.iter().map(|change| change.client).collect();
+    clients.dedup();
+    if !clients.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} accounts differ between {} and {}",
+            clients.len(),
+            old_path.display(),
+            new_path.display()
+        ));
+    }
+    log::info!(
+        "All accounts match between {} and {}",
+        old_path.display(),
+        new_path.display()
+    );
+    Ok(())
+}
+
+// Print the stored transactions of the client, or of all clients, of the snapshot in client and
+// transaction id order.
+fn export_transactions(
+    options: &cli::Options,
+    snapshot_path: &str,
+    client_id: Option<ClientId>,
+) -> anyhow::Result<()> {
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(File::open(snapshot_path)?)?;
+    let mut clients = match client_id {
+        Some(client_id) => vec![client_id],
+        None => engine
+            .accounts()?
+            .iter()
+            .map(|account| account.client_id)
+            .collect(),
+    };
+    clients.sort();
+    let time = |timestamp: Option<Timestamp>| timestamp.map(|t| t.to_string()).unwrap_or_default();
+    let mut writer = csv::Writer::from_writer(io::stdout().lock());
+    writer.write_record([
+        "client",
+        "tx",
+        "amount",
+        "status",
+        "timestamp",
+        "disputed_at",
+    ])?;
+    for client_id in clients {
+        let page = engine
+            .transactions(client_id, usize::MAX, None)
+            .ok_or_else(|| anyhow::anyhow!("Client {} not found in the snapshot", client_id))?;
+        for transaction in page.transactions {
+            writer.write_record([
+                client_id.to_string(),
+                transaction.transaction_id.to_string(),
+                options.output_format.format(transaction.amount),
+                transaction.status.as_str().to_string(),
+                time(transaction.timestamp()),
+                time(transaction.disputed_at),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Print the accounts of the snapshot matching the report filter with its query.
+fn query(options: &cli::Options, snapshot_path: &str) -> anyhow::Result<()> {
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(File::open(snapshot_path)?)?;
+    let mut accounts = engine.accounts()?;
+    accounts.retain(|account| options.report_filter.matches(account));
+    accounts.sort_by_key(|account| account.client_id);
+    let mut writer = csv::Writer::from_writer(io::stdout().lock());
+    for account in accounts {
+        let mut account = account.formatted(options.output_format);
+        if options.closed_column {
+            account = account.with_closed_column();
+        }
+        if options.report_filter.include_dormant {
+            account = account.with_dormant_column();
+        }
         writer.serialize(account)?;
     }
+    writer.flush()?;
+    Ok(())
+}
+
+// Print the result of the statement over the snapshot, and the journal with `--journal`.
+#[cfg(feature = "datafusion")]
+fn sql(options: &cli::Options, snapshot_path: &str, statement: &str) -> anyhow::Result<()> {
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(File::open(snapshot_path)?)?;
+    let journal = options.journal.as_ref().map(fs::read).transpose()?;
+    let session = payements_engine::sql::session(&engine, journal.as_deref())?;
+    tokio::runtime::Builder::new_current_thread()
+        .build()?
+        .block_on(payements_engine::sql::execute(
+            &session,
+            statement,
+            io::stdout().lock(),
+        ))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "datafusion"))]
+fn sql(_: &cli::Options, _: &str, _: &str) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "sql needs a build with the datafusion feature"
+    ))
+}
+
+fn explain(state_path: &str, client_id: ClientId) -> anyhow::Result<()> {
+    let steps = explain::explain(&fs::read(state_path)?, client_id)
+        .map_err(|e| anyhow::anyhow!("Error explaining {}: {}", state_path, e))?;
+    explain::write_steps(&steps, io::stdout().lock())?;
+    Ok(())
+}
+
+fn migrate(state_path: &str) -> anyhow::Result<()> {
+    let state = fs::read(state_path)?;
+    let migration = migrate::migrate(&state, io::stdout().lock())
+        .map_err(|e| anyhow::anyhow!("Error migrating {}: {}", state_path, e))?;
+    log::info!(
+        "Migrated {:?} {} from snapshot version {} to {}",
+        migration.kind,
+        state_path,
+        migration.from,
+        migration.to
+    );
+    Ok(())
+}
+
+fn partition(input_path: &str, shards: usize, out: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(out)?;
+    let mut writers = Vec::with_capacity(shards);
+    for shard in 0..shards {
+        writers.push(
+            csv::WriterBuilder::new()
+                .flexible(true)
+                .from_path(out.join(format!("shard-{:04}.csv", shard)))?,
+        );
+    }
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(input_path)?;
+    let counts = partition::partition(reader, &mut writers)?;
+    log::info!("Records per shard: {:?}", counts);
+    Ok(())
+}
 
+fn close_period(
+    options: &cli::Options,
+    snapshot_path: &str,
+    archive_path: &Path,
+) -> anyhow::Result<()> {
+    let mut builder = EngineBuilder::new();
+    if let Some(fees) = &options.maintenance_fees {
+        builder = builder.maintenance_fees(fees.clone());
+    }
+    if let Some(periods) = options.dormancy {
+        builder = builder.dormancy(periods);
+    }
+    let engine: Engine = builder.build();
+    engine.restore_snapshot(File::open(snapshot_path)?)?;
+    let close = engine.close_period(File::create(archive_path)?)?;
+    log::info!("{:?}", close);
+    if let Some(path) = &options.dormant_archive {
+        let archived = engine.archive_dormant(File::create(path)?)?;
+        log::info!(
+            "Archived {} dormant accounts to {}",
+            archived,
+            path.display()
+        );
+    }
+    engine.write_snapshot(io::stdout())?;
     Ok(())
 }
+
+fn statement(
+    history_path: &str,
+    client_id: ClientId,
+    period: Period,
+    format: StatementFormat,
+) -> anyhow::Result<()> {
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(history_path)?;
+    // Invalid records would be rejected by the engine anyway
+    let history = ByteRecordParser::new(reader)?.filter_map(Result::ok);
+    let statement = Statement::build(client_id, period, history);
+    print!("{}", statement.render(format));
+    Ok(())
+}
+
+fn read_bundle(path: &Path) -> anyhow::Result<Bundle> {
+    Bundle::read(File::open(path)?)
+        .map_err(|e| anyhow::anyhow!("Invalid bundle {}: {}", path.display(), e))
+}
+
+fn inspect(bundle_path: &str, entry: Option<&str>) -> anyhow::Result<()> {
+    let bundle = read_bundle(Path::new(bundle_path))?;
+    let mut stdout = io::stdout().lock();
+    match entry {
+        Some(name) => {
+            let data = bundle
+                .entry(name)
+                .ok_or_else(|| anyhow::anyhow!("No entry {} in the bundle", name))?;
+            stdout.write_all(data)?;
+        }
+        None => {
+            let manifest = &bundle.manifest;
+            writeln!(stdout, "version: {}", manifest.version)?;
+            writeln!(stdout, "engine version: {}", manifest.engine_version)?;
+            writeln!(stdout, "created at: {}", manifest.created_at)?;
+            writeln!(stdout, "parameters: {}", manifest.parameters.join(" "))?;
+            for entry in &manifest.entries {
+                writeln!(stdout, "{} {} {}", entry.sha256, entry.bytes, entry.name)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn verify_manifest(manifest_path: &str, trusted_key: &Path) -> anyhow::Result<()> {
+    let manifest: Manifest = serde_json::from_reader(File::open(manifest_path)?)?;
+    manifest.verify(&run_manifest::read_public_key(trusted_key)?)?;
+    manifest.verify_files()?;
+    writeln!(io::stdout(), "Manifest verified")?;
+    Ok(())
+}
+
+fn run_with_store<A: Amount>(
+    options: &cli::Options,
+    parse_stage: Option<ParseStage>,
+) -> anyhow::Result<()> {
+    match options.store {
+        cli::StoreKind::DashMap => {
+            let store = match options.store_shards {
+                Some(shards) => {
+                    DashMapStore::<A>::with_capacity_and_shards(options.expected_clients, shards)
+                }
+                None => DashMapStore::<A>::with_capacity(options.expected_clients),
+            };
+            run(options, store, parse_stage)
+        }
+        cli::StoreKind::Sharded => {
+            let shards = options
+                .store_shards
+                .unwrap_or_else(ShardedStore::<A>::default_shards);
+            let store = ShardedStore::<A>::with_capacity(shards, options.expected_clients);
+            run(options, store, parse_stage)
+        }
+        cli::StoreKind::BTreeMap => run(options, BTreeMapStore::<A>::default(), parse_stage),
+        cli::StoreKind::Spilling => {
+            let store = SpillingStore::<A>::new(&options.spill_dir, options.max_resident_accounts)?
+                .with_retry_policy(options.store_retry);
+            run(options, store, parse_stage)
+        }
+    }
+}
+
+// Errors only, apart from the `audit` target, which records admin actions and other changes
+// of state made outside the input and has to be kept without setting `RUST_LOG`.
+const DEFAULT_LOG_FILTER: &str = "error,audit=info";
+
+fn main() -> anyhow::Result<()> {
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::new().default_filter_or(DEFAULT_LOG_FILTER));
+    let options = cli::Options::from_args();
+    if matches!(&options, Ok(options) if options.trace_client.is_some()) {
+        logger.filter_module(TIMELINE_TARGET, log::LevelFilter::Info);
+    }
+    logger.init();
+    let options = options?;
+    if let (cli::Command::CompareRuns(new_bundle, input), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return compare_runs(Path::new(file_path), new_bundle, input);
+    }
+    if let (cli::Command::ExportTransactions(client_id), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return export_transactions(&options, file_path, *client_id);
+    }
+    if let (cli::Command::Purge(client_id), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return purge(file_path, *client_id);
+    }
+    if let (cli::Command::Partition(shards, out), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return partition(file_path, *shards, out);
+    }
+    if let cli::Command::Worker(address) = options.command {
+        return worker(&options, address);
+    }
+    if let cli::Command::Simulate(base, batch) = &options.command {
+        return simulate(&options, base, batch);
+    }
+    if let cli::Command::Stress(seed, rounds, length) = options.command {
+        return stress(seed, rounds, length);
+    }
+    if let cli::Command::Repl = options.command {
+        return repl(&options, options.file_path.as_deref());
+    }
+    if let (cli::Command::Coordinate(workers), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return coordinate(&options, file_path, workers);
+    }
+    if let (cli::Command::ClosePeriod(archive_path), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return close_period(&options, file_path, archive_path);
+    }
+    if let (cli::Command::Statement(client_id, period, format), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return statement(file_path, *client_id, *period, *format);
+    }
+    if let (cli::Command::Migrate, Some(file_path)) = (&options.command, &options.file_path) {
+        return migrate(file_path);
+    }
+    if let (cli::Command::Sql(statement), Some(file_path)) = (&options.command, &options.file_path)
+    {
+        return sql(&options, file_path, statement);
+    }
+    if let (cli::Command::Query, Some(file_path)) = (&options.command, &options.file_path) {
+        return query(&options, file_path);
+    }
+    if let (cli::Command::Explain(client_id), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return explain(file_path, *client_id);
+    }
+    if let (cli::Command::Inspect(entry), Some(file_path)) = (&options.command, &options.file_path)
+    {
+        return inspect(file_path, entry.as_deref());
+    }
+    if let (cli::Command::VerifyManifest(trusted_key), Some(file_path)) =
+        (&options.command, &options.file_path)
+    {
+        return verify_manifest(file_path, trusted_key);
+    }
+    let parse_stage = match (&options.command, &options.file_path) {
+        (cli::Command::Replay, Some(file_path)) => {
+            let reader = csv::ReaderBuilder::new()
+                .flexible(true)
+                .from_path(file_path)?;
+            Some(ParseStage::spawn(
+                EventLogParser::new(reader)?,
+                options.queue_capacity,
+            ))
+        }
+        (_, Some(file_path)) => Some(spawn_parse_stage(&options, file_path)?),
+        (_, None) => None,
+    };
+
+    if let Some(names) = &options.ledgers {
+        return run_ledgers(&options, names, parse_stage);
+    }
+    match options.amount {
+        cli::AmountKind::Decimal => run_with_store::<Decimal>(&options, parse_stage),
+        cli::AmountKind::MinorUnits => run_with_store::<MinorUnits>(&options, parse_stage),
+        cli::AmountKind::Float => run_with_store::<f64>(&options, parse_stage),
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, from_hex, to_hex, Sha256, SigningKey};
+use crate::storage;
+
+// 2: paths and parameters in the signed message are length-prefixed
+// 3: the signature is over the JSON of the whole manifest
+const VERSION: &str = "3";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ManifestError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid signing key in {0}, expected a 32 byte Ed25519 seed in hex")]
+    InvalidKey(String),
+    #[error("Invalid public key in {0}, expected a 32 byte Ed25519 public key in hex")]
+    InvalidPublicKey(String),
+    #[error("Manifest version {0} can't be verified, expected {VERSION}")]
+    UnsupportedVersion(String),
+    #[error("Manifest isn't signed")]
+    Unsigned,
+    #[error("Manifest signature is invalid or not by the trusted key")]
+    InvalidSignature,
+    #[error("{0} doesn't match its digest in the manifest")]
+    Mismatch(String),
+}
+
+// SHA-256 and size of a file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+// Digest of the report, `path` None if it was written to stdout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportDigest {
+    pub path: Option<String>,
+    pub sha256: String,
+    pub bytes: u64,
+    // Records without the header
+    pub rows: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+    pub algorithm: String,
+    pub public_key: String,
+    // Over `Manifest::signed_message`
+    pub value: String,
+}
+
+// What a run produced from what, for consumers of the report to check they got the complete and
+// unchanged output of a specific run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    pub engine_version: String,
+    // RFC 3339 in UTC
+    pub created_at: String,
+    // Command line arguments of the run
+    pub parameters: Vec<String>,
+    pub inputs: Vec<FileDigest>,
+    pub report: ReportDigest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+}
+
+impl Manifest {
+    pub fn new(parameters: Vec<String>, inputs: Vec<FileDigest>, report: ReportDigest) -> Manifest {
+        Manifest {
+            version: VERSION.to_string(),
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            parameters,
+            inputs,
+            report,
+            signature: None,
+        }
+    }
+
+    // What the signature is over: the manifest without the signature as compact JSON, with the
+    // fields in declaration order. Verifiers parse the manifest and serialize it the same way.
+    pub fn signed_message(&self) -> Vec<u8> {
+        let unsigned = Manifest {
+            signature: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).expect("manifest serializes")
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = Some(Signature {
+            algorithm: "ed25519".to_string(),
+            public_key: to_hex(&key.public_key()),
+            value: to_hex(&key.sign(&self.signed_message())),
+        });
+    }
+
+    // Check the manifest is signed by the trusted key and unchanged since.
+    pub fn verify(&self, trusted_key: &[u8; 32]) -> Result<(), ManifestError> {
+        if self.version != VERSION {
+            return Err(ManifestError::UnsupportedVersion(self.version.clone()));
+        }
+        let signature = self.signature.as_ref().ok_or(ManifestError::Unsigned)?;
+        let value = from_hex(&signature.value)
+            .and_then(|value| <[u8; 64]>::try_from(value).ok())
+            .ok_or(ManifestError::InvalidSignature)?;
+        if signature.algorithm != "ed25519"
+            || signature.public_key != to_hex(trusted_key)
+            || !crypto::verify(trusted_key, &self.signed_message(), &value)
+        {
+            return Err(ManifestError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    // Check the report, if written to a file, and the inputs against their digests.
+    pub fn verify_files(&self) -> Result<(), ManifestError> {
+        let report = self.report.path.as_ref().map(|path| FileDigest {
+            path: path.clone(),
+            sha256: self.report.sha256.clone(),
+            bytes: self.report.bytes,
+        });
+        for expected in report.iter().chain(&self.inputs) {
+            if digest_file(Path::new(&expected.path))? != *expected {
+                return Err(ManifestError::Mismatch(expected.path.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Ed25519 signing key from a file with the 32 byte seed in hex.
+pub fn read_signing_key(path: &Path) -> Result<SigningKey, ManifestError> {
+    let invalid = || ManifestError::InvalidKey(path.display().to_string());
+    let seed = from_hex(fs::read_to_string(path)?.trim()).ok_or_else(invalid)?;
+    let seed: [u8; 32] = seed.try_into().map_err(|_| invalid())?;
+    Ok(SigningKey::from_seed(seed))
+}
+
+// Ed25519 public key from a file with the 32 bytes in hex, e.g. to verify manifests with.
+pub fn read_public_key(path: &Path) -> Result<[u8; 32], ManifestError> {
+    let invalid = || ManifestError::InvalidPublicKey(path.display().to_string());
+    let key = from_hex(fs::read_to_string(path)?.trim()).ok_or_else(invalid)?;
+    key.try_into().map_err(|_| invalid())
+}
+
+// Digest of a file, or an object with the `object-store` feature, see `storage`.
+pub fn digest_file(path: &Path) -> io::Result<FileDigest> {
+    let mut file = storage::open(path)?;
+    let mut sha = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        sha.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+    Ok(FileDigest {
+        path: path.display().to_string(),
+        sha256: to_hex(&sha.finish()),
+        bytes,
+    })
+}
+
+// Writer hashing what passes through, e.g. a report written to stdout.
+pub struct DigestWriter<W> {
+    inner: W,
+    sha: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W) -> DigestWriter<W> {
+        DigestWriter {
+            inner,
+            sha: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    // SHA-256 in hex and size of what was written so far.
+    pub fn digest(&self) -> (String, u64) {
+        (to_hex(&self.sha.clone().finish()), self.bytes)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let mut writer = DigestWriter::new(Vec::new());
+        writer.write_all(b"client,available\n1,1.0\n").unwrap();
+        let (sha256, bytes) = writer.digest();
+        assert_eq!(
+            sha256,
+            to_hex(&Sha256::digest(b"client,available\n1,1.0\n"))
+        );
+
+        let mut manifest = Manifest::new(
+            vec!["input.csv".to_string(), "--manifest".to_string()],
+            vec![FileDigest {
+                path: "input.csv".to_string(),
+                sha256: "00".to_string(),
+                bytes: 1,
+            }],
+            ReportDigest {
+                path: None,
+                sha256,
+                bytes,
+                rows: 1,
+            },
+        );
+        let key = SigningKey::from_seed([7; 32]);
+        assert!(matches!(
+            manifest.verify(&key.public_key()),
+            Err(ManifestError::Unsigned)
+        ));
+        manifest.sign(&key);
+        manifest.verify(&key.public_key()).unwrap();
+
+        // Verified as read back
+        let json = serde_json::to_string_pretty(&manifest).unwrap();
+        let read: Manifest = serde_json::from_str(&json).unwrap();
+        read.verify(&key.public_key()).unwrap();
+
+        // Every field is covered
+        let mut changed = read.clone();
+        changed.report.bytes += 1;
+        assert!(changed.verify(&key.public_key()).is_err());
+        let mut changed = read.clone();
+        changed.inputs[0].bytes = 2;
+        assert!(changed.verify(&key.public_key()).is_err());
+        let mut changed = read.clone();
+        changed.engine_version = "0.0.0".to_string();
+        assert!(changed.verify(&key.public_key()).is_err());
+
+        // Re-signed by another key
+        let mut changed = read.clone();
+        changed.sign(&SigningKey::from_seed([8; 32]));
+        assert!(matches!(
+            changed.verify(&key.public_key()),
+            Err(ManifestError::InvalidSignature)
+        ));
+    }
+}
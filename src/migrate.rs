@@ -0,0 +1,156 @@
+use std::io::{self, Write};
+
+use crate::bundle::{self, Bundle, BundleError, SNAPSHOT_ENTRY};
+use crate::checkpoint::{self, CheckpointError};
+use crate::engine::{snapshot_version, Engine, SnapshotError, SNAPSHOT_VERSION};
+
+#[derive(thiserror::Error, Debug)]
+pub enum MigrateError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+    #[error("No {} in the bundle", SNAPSHOT_ENTRY)]
+    MissingSnapshot,
+}
+
+// Files holding engine state, told apart by how they start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateKind {
+    Snapshot,
+    // Offsets followed by a snapshot, see `checkpoint::Checkpointer`
+    Checkpoint,
+    // Bundle of `export`, see `bundle::Bundle`
+    Bundle,
+}
+
+impl StateKind {
+    pub fn of(state: &[u8]) -> StateKind {
+        if bundle::is_bundle(state) {
+            StateKind::Bundle
+        } else if state.starts_with(b"offset,") {
+            StateKind::Checkpoint
+        } else {
+            StateKind::Snapshot
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub kind: StateKind,
+    // Snapshot versions before and after
+    pub from: u32,
+    pub to: u32,
+}
+
+// Rewrite a snapshot, checkpoint or bundle with the snapshot in the current layout, so state of
+// earlier releases keeps loading once reading their versions is dropped. The snapshot is loaded
+// into an engine, which reads every earlier version, and written again. Offsets of a checkpoint
+// and the other entries of a bundle are kept as they are.
+pub fn migrate<W: Write>(state: &[u8], mut writer: W) -> Result<Migration, MigrateError> {
+    let kind = StateKind::of(state);
+    let from = match kind {
+        StateKind::Snapshot => migrate_snapshot(state, writer)?,
+        StateKind::Checkpoint => {
+            let (offsets, snapshot) = checkpoint::read_checkpoint(state)?;
+            checkpoint::write_offsets(&mut writer, &offsets)?;
+            migrate_snapshot(snapshot, writer)?
+        }
+        StateKind::Bundle => {
+            let mut bundle = Bundle::read(state)?;
+            let snapshot = bundle
+                .entry(SNAPSHOT_ENTRY)
+                .ok_or(MigrateError::MissingSnapshot)?;
+            let mut migrated = Vec::new();
+            let from = migrate_snapshot(snapshot, &mut migrated)?;
+            bundle.set(SNAPSHOT_ENTRY, migrated);
+            bundle.write(writer)?;
+            from
+        }
+    };
+    Ok(Migration {
+        kind,
+        from,
+        to: SNAPSHOT_VERSION,
+    })
+}
+
+// Amounts are read as decimals, which every amount representation converts from losslessly.
+fn migrate_snapshot<W: Write>(snapshot: &[u8], writer: W) -> Result<u32, MigrateError> {
+    let from = snapshot_version(snapshot)?;
+    let engine: Engine = Engine::new();
+    engine.restore_snapshot(snapshot)?;
+    engine.write_snapshot(writer)?;
+    Ok(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Version 1: one account without books, one with books before the rolling reserve
+    const VERSION_1: &str = "snapshot,1\n\
+                             account,1,3.0,2.0,false,false,false\n\
+                             tx,1,5.0,disputed\n\
+                             account,2,1.0,0.0,false,false,false\n\
+                             books,-1.0,0,0\n";
+
+    #[test]
+    fn test_migrate() {
+        let mut migrated = Vec::new();
+        let migration = migrate(VERSION_1.as_bytes(), &mut migrated).unwrap();
+        assert_eq!(
+            migration,
+            Migration {
+                kind: StateKind::Snapshot,
+                from: 1,
+                to: SNAPSHOT_VERSION
+            }
+        );
+        let migrated = String::from_utf8(migrated).unwrap();
+        assert!(migrated.starts_with(&format!("snapshot,{}\n", SNAPSHOT_VERSION)));
+        assert!(migrated.contains("books,-5.0,0,0,0\n"));
+        assert!(migrated.contains("tx,1,5.0,disputed,\n"));
+
+        let original: Engine = Engine::new();
+        original.restore_snapshot(VERSION_1.as_bytes()).unwrap();
+        let restored: Engine = Engine::new();
+        restored.restore_snapshot(migrated.as_bytes()).unwrap();
+        let mut accounts = restored.accounts().unwrap();
+        accounts.sort_by_key(|account| account.client_id);
+        let mut expected = original.accounts().unwrap();
+        expected.sort_by_key(|account| account.client_id);
+        assert_eq!(accounts, expected);
+
+        // Version 2 requires the client reserve
+        assert!(matches!(
+            restored.restore_snapshot(format!("snapshot,2\n{}", &VERSION_1[11..]).as_bytes()),
+            Err(SnapshotError::Malformed(5))
+        ));
+
+        // Version 3 metadata follows the status, version 4 the time of the dispute
+        let version_3 = "snapshot,3\n\
+                         account,1,0.0,5.0,false,false,false,0,false\n\
+                         books,-5.0,0,0,0\n\
+                         tx,1,5.0,disputed,memo,rent\n";
+        let mut migrated = Vec::new();
+        migrate(version_3.as_bytes(), &mut migrated).unwrap();
+        let migrated = String::from_utf8(migrated).unwrap();
+        assert!(migrated.contains("tx,1,5.0,disputed,,memo,rent\n"));
+        // Version 4 and earlier don't know when accounts were last active
+        assert!(migrated.contains("account,1,0.0,5.0,false,false,false,0,false,\n"));
+
+        let checkpoint = format!("offset,payments/0,42\n{}", VERSION_1);
+        let mut migrated = Vec::new();
+        let migration = migrate(checkpoint.as_bytes(), &mut migrated).unwrap();
+        assert_eq!(migration.kind, StateKind::Checkpoint);
+        let (offsets, snapshot) = checkpoint::read_checkpoint(&migrated).unwrap();
+        assert_eq!(offsets.get("payments/0"), Some(&42));
+        assert_eq!(snapshot_version(snapshot).unwrap(), SNAPSHOT_VERSION);
+    }
+}
@@ -0,0 +1,117 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::{ptr, slice};
+
+// Read-only memory mapping of a whole file.
+//
+// Records are parsed straight from the mapped pages, which avoids a read syscall (and a copy into
+// a kernel-to-user buffer) per buffer refill on very large inputs.
+//
+// The file must not be truncated while mapped: reading the pages past its new end raises SIGBUS
+// and kills the process. Callers map only files nothing else writes to in place, e.g. a finished
+// input; replacing the file by a rename leaves the mapping of the old one intact.
+pub struct MappedFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only and never mutated after creation.
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedFile> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "File too large to map"))?;
+
+        // mmap rejects zero-length mappings
+        if len == 0 {
+            return Ok(MappedFile {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Input is consumed front to back, let the kernel read ahead aggressively
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+
+        Ok(MappedFile { ptr, len })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_mapped_file() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!("mmap-test-{}-{}", std::process::id(), name))
+        };
+        let (input, moved, empty) = (path("input.csv"), path("moved.csv"), path("empty.csv"));
+        let content = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        fs::write(&input, content).unwrap();
+        let mapped = MappedFile::open(&input).unwrap();
+        assert_eq!(&*mapped, content.as_bytes());
+
+        // Replacing the file rather than truncating it leaves the mapping as it was
+        fs::rename(&input, &moved).unwrap();
+        fs::write(&input, "type").unwrap();
+        assert_eq!(&*mapped, content.as_bytes());
+        drop(mapped);
+
+        // Zero-length mappings aren't made, the file reads as empty
+        fs::write(&empty, "").unwrap();
+        assert!(MappedFile::open(&empty).unwrap().is_empty());
+        assert!(MappedFile::open(path("missing.csv")).is_err());
+
+        for path in [input, moved, empty] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
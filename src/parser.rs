@@ -0,0 +1,283 @@
+use std::io;
+use std::str::{self, FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::engine::{Metadata, Transaction, TransactionType};
+
+const TRANSACTION_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParseError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("Missing column: {0}")]
+    MissingColumn(&'static str),
+    #[error("Unknown transaction type")]
+    UnknownTransactionType,
+    #[error("Invalid value in column: {0}")]
+    InvalidValue(&'static str),
+    // Records after it were lost, unlike the other errors, which only affect their record
+    #[error("Parser thread panicked")]
+    ParserPanicked,
+}
+
+// Columns which are not part of the transaction format, passed through as metadata.
+struct MetadataColumns(Vec<(usize, String)>);
+
+impl MetadataColumns {
+    fn from_headers(headers: &csv::ByteRecord) -> MetadataColumns {
+        MetadataColumns(
+            headers
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| !TRANSACTION_COLUMNS.iter().any(|c| c.as_bytes() == *name))
+                .map(|(index, name)| (index, String::from_utf8_lossy(name).into_owned()))
+                .collect(),
+        )
+    }
+
+    // Allocates only if the input actually has extra columns
+    fn capture(&self, record: &csv::ByteRecord) -> Metadata {
+        self.0
+            .iter()
+            .filter_map(|(index, name)| {
+                let value = record.get(*index).filter(|value| !value.is_empty())?;
+                Some((name.clone(), String::from_utf8_lossy(value).into_owned()))
+            })
+            .collect()
+    }
+}
+
+struct Columns {
+    r#type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    metadata: MetadataColumns,
+}
+
+impl Columns {
+    fn from_headers(headers: &csv::ByteRecord) -> Result<Columns, ParseError> {
+        let position = |name: &str| headers.iter().position(|h| h == name.as_bytes());
+        Ok(Columns {
+            r#type: position("type").ok_or(ParseError::MissingColumn("type"))?,
+            client: position("client").ok_or(ParseError::MissingColumn("client"))?,
+            tx: position("tx").ok_or(ParseError::MissingColumn("tx"))?,
+            amount: position("amount"),
+            metadata: MetadataColumns::from_headers(headers),
+        })
+    }
+}
+
+// Fast path parser building transactions straight from `csv::ByteRecord`s.
+//
+// A single record buffer is reused for the whole input and fields are parsed in place, so no
+// intermediate Strings are allocated and serde is bypassed entirely.
+pub struct ByteRecordParser<R> {
+    reader: csv::Reader<R>,
+    columns: Columns,
+    record: csv::ByteRecord,
+}
+
+impl<R: io::Read> ByteRecordParser<R> {
+    pub fn new(mut reader: csv::Reader<R>) -> Result<ByteRecordParser<R>, ParseError> {
+        let headers = reader.byte_headers()?.clone();
+        Self::with_headers(reader, &headers)
+    }
+
+    // Create a parser over a reader positioned past the headers, e.g. a chunk of a larger input.
+    pub fn with_headers(
+        reader: csv::Reader<R>,
+        headers: &csv::ByteRecord,
+    ) -> Result<ByteRecordParser<R>, ParseError> {
+        let columns = Columns::from_headers(headers)?;
+        Ok(ByteRecordParser {
+            reader,
+            columns,
+            record: csv::ByteRecord::new(),
+        })
+    }
+
+    fn parse_record(&self) -> Result<Transaction, ParseError> {
+        let field = |index: usize| self.record.get(index).unwrap_or_default();
+
+        let r#type = match field(self.columns.r#type) {
+            b"deposit" => TransactionType::Deposit,
+            b"withdrawal" => TransactionType::Withdraw,
+            b"dispute" => TransactionType::Dispute,
+            b"resolve" => TransactionType::Resolve,
+            b"chargeback" => TransactionType::Chargeback,
+            b"close_account" => TransactionType::CloseAccount,
+            b"note" => TransactionType::Note,
+            _ => return Err(ParseError::UnknownTransactionType),
+        };
+        let amount = match self.columns.amount.map(field) {
+            None | Some(b"") => None,
+            Some(amount) => Some(parse_field::<Decimal>(amount, "amount")?),
+        };
+
+        Ok(Transaction {
+            r#type,
+            client_id: parse_field(field(self.columns.client), "client")?,
+            transaction_id: parse_field(field(self.columns.tx), "tx")?,
+            amount,
+            metadata: self.columns.metadata.capture(&self.record),
+        })
+    }
+}
+
+fn parse_field<T: FromStr>(bytes: &[u8], column: &'static str) -> Result<T, ParseError> {
+    str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(ParseError::InvalidValue(column))
+}
+
+impl<R: io::Read> Iterator for ByteRecordParser<R> {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => Some(self.parse_record()),
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+// Parser deserializing transactions with serde.
+//
+// Extra columns are captured as transaction metadata, which serde can't do by itself for CSV
+// (`#[serde(flatten)]` loses the column types).
+pub struct SerdeParser<R> {
+    reader: csv::Reader<R>,
+    headers: csv::ByteRecord,
+    metadata: MetadataColumns,
+    record: csv::ByteRecord,
+}
+
+impl<R: io::Read> SerdeParser<R> {
+    pub fn new(mut reader: csv::Reader<R>) -> Result<SerdeParser<R>, ParseError> {
+        let headers = reader.byte_headers()?.clone();
+        Ok(Self::with_headers(reader, &headers))
+    }
+
+    // Create a parser over a reader positioned past the headers, e.g. a chunk of a larger input.
+    pub fn with_headers(reader: csv::Reader<R>, headers: &csv::ByteRecord) -> SerdeParser<R> {
+        SerdeParser {
+            reader,
+            headers: headers.clone(),
+            metadata: MetadataColumns::from_headers(headers),
+            record: csv::ByteRecord::new(),
+        }
+    }
+
+    fn parse_record(&self) -> Result<Transaction, ParseError> {
+        let mut transaction: Transaction = self.record.deserialize(Some(&self.headers))?;
+        transaction.metadata = self.metadata.capture(&self.record);
+        Ok(transaction)
+    }
+}
+
+impl<R: io::Read> Iterator for SerdeParser<R> {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => Some(self.parse_record()),
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(input: &str) -> csv::Reader<&[u8]> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes())
+    }
+
+    #[test]
+    fn test_matches_serde_parsing() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.5\n\
+                     withdrawal, 2, 2, 0.0001\n\
+                     dispute, 1, 1,\n\
+                     resolve, 1, 1\n\
+                     chargeback, 65535, 4294967295,\n";
+
+        let fast: Vec<Transaction> = ByteRecordParser::new(reader(input))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let serde: Vec<Transaction> = reader(input)
+            .into_deserialize()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(fast, serde);
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let input = "type,client,tx,amount\n\
+                     transfer,1,1,1.0\n\
+                     deposit,1.5,2,1.0\n\
+                     deposit,1,3,abc\n";
+
+        let results: Vec<_> = ByteRecordParser::new(reader(input)).unwrap().collect();
+
+        assert!(matches!(
+            results[0],
+            Err(ParseError::UnknownTransactionType)
+        ));
+        assert!(matches!(
+            results[1],
+            Err(ParseError::InvalidValue("client"))
+        ));
+        assert!(matches!(
+            results[2],
+            Err(ParseError::InvalidValue("amount"))
+        ));
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let result = ByteRecordParser::new(reader("type,client,amount\n"));
+
+        assert!(matches!(result, Err(ParseError::MissingColumn("tx"))));
+    }
+
+    #[test]
+    fn test_metadata_columns() {
+        let input = "memo,type,client,tx,amount,ref\n\
+                     rent,deposit,1,1,1.0,INV-1\n\
+                     ,withdrawal,1,2,1.0,\n";
+
+        for results in [
+            ByteRecordParser::new(reader(input))
+                .unwrap()
+                .collect::<Vec<_>>(),
+            SerdeParser::new(reader(input)).unwrap().collect(),
+        ] {
+            let metadata: Vec<Metadata> =
+                results.into_iter().map(|t| t.unwrap().metadata).collect();
+            assert_eq!(
+                metadata,
+                [
+                    Metadata::from([
+                        ("memo".to_string(), "rent".to_string()),
+                        ("ref".to_string(), "INV-1".to_string()),
+                    ]),
+                    Metadata::new(),
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+
+use crate::engine::ClientId;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PartitionError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("Missing column: client")]
+    MissingClientColumn,
+    #[error("At least one shard is needed")]
+    NoShards,
+}
+
+// FNV-1a, stable across runs, machines and builds unlike `std::hash`. Finished with the
+// MurmurHash3 finalizer, FNV alone barely changes the high bits for inputs differing in the last
+// bytes, e.g. consecutive ids.
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+// Shard of a client out of `shards`, stable so that shards produced on different machines line
+// up.
+pub fn shard_of(client_id: &ClientId, shards: usize) -> usize {
+    (stable_hash(client_id.to_string().as_bytes()) % shards.max(1) as u64) as usize
+}
+
+// Split the input records by the shard of their client, writing each record unchanged with the
+// input headers to the writer of its shard. Writers should be flexible like the reader, records
+// without an amount have fewer fields. All transactions of a client end up in the same
+// shard in input order, so the shards can be processed independently and their accounts
+// combined, e.g. with `Engine::merge`. Records with an invalid client go to the first shard,
+// where processing rejects them. Returns the number of records per shard.
+pub fn partition<R: io::Read, W: io::Write>(
+    reader: csv::Reader<R>,
+    writers: &mut [csv::Writer<W>],
+) -> Result<Vec<usize>, PartitionError> {
+    let shards = writers.len();
+    partition_by(reader, writers, |client_id| {
+        client_id.map_or(0, |client_id| shard_of(client_id, shards))
+    })
+}
+
+// Split the input records like `partition`, by the writer index `shard` picks for the client of a
+// record, None if the client is invalid.
+pub fn partition_by<R, W, F>(
+    mut reader: csv::Reader<R>,
+    writers: &mut [csv::Writer<W>],
+    mut shard: F,
+) -> Result<Vec<usize>, PartitionError>
+where
+    R: io::Read,
+    W: io::Write,
+    F: FnMut(Option<&ClientId>) -> usize,
+{
+    if writers.is_empty() {
+        return Err(PartitionError::NoShards);
+    }
+    let headers = reader.byte_headers()?.clone();
+    let client = headers
+        .iter()
+        .position(|name| name == b"client")
+        .ok_or(PartitionError::MissingClientColumn)?;
+    for writer in writers.iter_mut() {
+        writer.write_byte_record(&headers)?;
+    }
+
+    let mut counts = vec![0; writers.len()];
+    let mut record = csv::ByteRecord::new();
+    while reader.read_byte_record(&mut record)? {
+        let client_id = std::str::from_utf8(record.get(client).unwrap_or_default())
+            .ok()
+            .and_then(|client_id| client_id.parse::<ClientId>().ok());
+        let shard = shard(client_id.as_ref());
+        writers[shard].write_byte_record(&record)?;
+        counts[shard] += 1;
+    }
+    for writer in writers.iter_mut() {
+        writer.flush().map_err(csv::Error::from)?;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Transaction;
+    use crate::Engine;
+
+    #[test]
+    fn test_partitioned_processing_matches() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,2,2,3.0\n\
+                     deposit,3,3,1.0\n\
+                     dispute,1,1,\n\
+                     withdrawal,2,4,1.0\n\
+                     chargeback,1,1,\n\
+                     deposit,4,5,2.0\n";
+        let process = |engine: &mut Engine, input: &[u8]| {
+            let reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+            engine
+                .process_transactions_blocking(reader.into_deserialize::<Transaction>())
+                .unwrap();
+        };
+
+        let mut writers: Vec<_> = (0..3)
+            .map(|_| csv::Writer::from_writer(Vec::new()))
+            .collect();
+        let counts = partition(csv::Reader::from_reader(input.as_bytes()), &mut writers).unwrap();
+        assert_eq!(counts.iter().sum::<usize>(), 7);
+
+        let merged: Engine = Engine::new();
+        for writer in writers {
+            let mut shard: Engine = Engine::new();
+            process(&mut shard, &writer.into_inner().unwrap());
+            merged.merge(&shard).unwrap();
+        }
+        let mut whole: Engine = Engine::new();
+        process(&mut whole, input.as_bytes());
+
+        let sorted = |engine: &Engine| {
+            let mut accounts = engine.accounts().unwrap();
+            accounts.sort_by_key(|a| a.client_id);
+            accounts
+        };
+        assert_eq!(sorted(&merged), sorted(&whole));
+    }
+}
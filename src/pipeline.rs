@@ -0,0 +1,318 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::engine::Transaction;
+use crate::parser::ParseError;
+
+// Number of parsed records buffered between the parse stage and the engine.
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 4096;
+// Number of decompressed blocks buffered between the decompress and the parse stage, and their
+// size.
+pub const DEFAULT_BLOCK_CAPACITY: usize = 16;
+pub const DECOMPRESSED_BLOCK: usize = 128 * 1024;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+// Occupancy of the channel between the parse stage and the engine.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QueueMetrics {
+    pub capacity: usize,
+    // Records parsed but not taken by the engine yet
+    pub depth: u64,
+    pub max_depth: u64,
+    // Records the parse stage had to wait with because the channel was full
+    pub full: u64,
+    // Time the parse stage spent waiting for room
+    pub blocked: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    received: AtomicU64,
+    max_depth: AtomicU64,
+    full: AtomicU64,
+    blocked_nanos: AtomicU64,
+}
+
+impl Counters {
+    fn depth(&self) -> u64 {
+        let received = self.received.load(Ordering::Relaxed);
+        self.sent.load(Ordering::Relaxed).saturating_sub(received)
+    }
+}
+
+// Parse stage of the processing pipeline.
+//
+// CSV parsing runs on a dedicated thread and hands records over to the engine through a bounded
+// channel, so parsing of record *i + 1* overlaps with applying record *i*. The channel preserves
+// input order, and its capacity bounds the memory used when the engine falls behind the reader:
+// the parse stage then waits for room, which `metrics` counts as backpressure.
+pub struct ParseStage {
+    receiver: Receiver<Result<Transaction, ParseError>>,
+    handle: JoinHandle<Result<(), ParseError>>,
+    capacity: usize,
+    counters: Arc<Counters>,
+    upstream: Option<DecompressStage>,
+}
+
+impl ParseStage {
+    pub fn spawn<I, E>(records: I, capacity: usize) -> ParseStage
+    where
+        I: Iterator<Item = Result<Transaction, E>> + Send + 'static,
+        E: Into<ParseError>,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let counters = Arc::new(Counters::default());
+        let handle = thread::spawn({
+            let counters = Arc::clone(&counters);
+            move || {
+                for record in records {
+                    let record = record.map_err(Into::into);
+                    // Nothing reliable follows, fail the stage instead of skipping the record
+                    if let Err(ParseError::ParserPanicked) = record {
+                        return Err(ParseError::ParserPanicked);
+                    }
+                    let sent = match sender.try_send(record) {
+                        Ok(()) => Ok(()),
+                        Err(TrySendError::Full(record)) => {
+                            counters.full.fetch_add(1, Ordering::Relaxed);
+                            let start = Instant::now();
+                            let sent = sender.send(record).map_err(drop);
+                            counters
+                                .blocked_nanos
+                                .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                            sent
+                        }
+                        Err(TrySendError::Disconnected(_)) => Err(()),
+                    };
+                    // Engine side hung up, nothing left to do
+                    if sent.is_err() {
+                        break;
+                    }
+                    counters.sent.fetch_add(1, Ordering::Relaxed);
+                    counters
+                        .max_depth
+                        .fetch_max(counters.depth(), Ordering::Relaxed);
+                }
+                Ok(())
+            }
+        });
+
+        ParseStage {
+            receiver,
+            handle,
+            capacity,
+            counters,
+            upstream: None,
+        }
+    }
+
+    // Parse stage reading the output of the decompress stage, joined with it.
+    pub fn after(mut self, upstream: DecompressStage) -> ParseStage {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = Result<Transaction, ParseError>> + '_ {
+        self.receiver.iter().inspect(|_| {
+            self.counters.received.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            capacity: self.capacity,
+            depth: self.counters.depth(),
+            max_depth: self.counters.max_depth.load(Ordering::Relaxed),
+            full: self.counters.full.load(Ordering::Relaxed),
+            blocked: Duration::from_nanos(self.counters.blocked_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn join(self) -> anyhow::Result<()> {
+        info!("Parse queue: {:?}", self.metrics());
+        drop(self.receiver);
+        self.handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Parse stage panicked"))?
+            .map_err(anyhow::Error::from)?;
+        match self.upstream {
+            Some(upstream) => upstream.join(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Whether the input starts like zstd-compressed data.
+pub fn is_zstd(data: &[u8]) -> bool {
+    data.starts_with(&ZSTD_MAGIC)
+}
+
+// Decompress stage in front of the parse stage, for zstd-compressed inputs.
+//
+// Decoding runs on a thread of its own and hands blocks of the decompressed input to the parse
+// stage through a bounded channel, so decoding, parsing and applying transactions overlap. The
+// stage fails on a corrupt or truncated input; the parse stage then sees the input end where the
+// decoding stopped, so the failure shows when the parse stage is joined.
+pub struct DecompressStage {
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl DecompressStage {
+    pub fn spawn<R>(compressed: R, capacity: usize) -> (DecompressStage, Decompressed)
+    where
+        R: Read + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let handle = thread::spawn(move || decompress(compressed, sender));
+        let decompressed = Decompressed {
+            receiver,
+            block: Vec::new(),
+            position: 0,
+        };
+        (DecompressStage { handle }, decompressed)
+    }
+
+    pub fn join(self) -> anyhow::Result<()> {
+        self.handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Decompress stage panicked"))?
+            .map_err(|e| anyhow::anyhow!("Failed to decompress the input: {}", e))
+    }
+}
+
+// Frames one after another, as `zstd` writes them for concatenated inputs.
+fn decompress<R: Read>(compressed: R, sender: SyncSender<Vec<u8>>) -> io::Result<()> {
+    let mut compressed = io::BufReader::new(compressed);
+    loop {
+        if io::BufRead::fill_buf(&mut compressed)?.is_empty() {
+            return Ok(());
+        }
+        let mut decoder = ruzstd::decoding::StreamingDecoder::new(&mut compressed)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        loop {
+            let mut block = Vec::with_capacity(DECOMPRESSED_BLOCK);
+            (&mut decoder)
+                .take(DECOMPRESSED_BLOCK as u64)
+                .read_to_end(&mut block)?;
+            if block.is_empty() {
+                break;
+            }
+            // Parse stage hung up, nothing left to do
+            if sender.send(block).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Decompressed input as the decompress stage produces it.
+pub struct Decompressed {
+    receiver: Receiver<Vec<u8>>,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            match self.receiver.recv() {
+                Ok(block) => {
+                    self.block = block;
+                    self.position = 0;
+                }
+                // Decompress stage done, or failed which its join reports
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.block.len() - self.position);
+        buf[..count].copy_from_slice(&self.block[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::SerdeParser;
+
+    #[test]
+    fn test_backpressure() {
+        let input: String = (1..=20)
+            .map(|tx| format!("deposit,1,{},1.0\n", tx))
+            .collect();
+        let reader = csv::Reader::from_reader(std::io::Cursor::new(format!(
+            "type,client,tx,amount\n{}",
+            input
+        )));
+        let stage = ParseStage::spawn(SerdeParser::new(reader).unwrap(), 4);
+
+        // The engine falls behind, the parse stage fills the channel and waits
+        while stage.metrics().full == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let metrics = stage.metrics();
+        assert_eq!(
+            (metrics.capacity, metrics.depth, metrics.max_depth),
+            (4, 4, 4)
+        );
+
+        assert_eq!(stage.records().flatten().count(), 20);
+        assert_eq!(stage.metrics().depth, 0);
+        stage.join().unwrap();
+    }
+
+    #[test]
+    fn test_panicked_parser_fails_the_stage() {
+        let records: Vec<Result<Transaction, ParseError>> = vec![
+            Err(ParseError::InvalidValue("amount")),
+            Err(ParseError::ParserPanicked),
+        ];
+        let stage = ParseStage::spawn(records.into_iter(), 4);
+
+        assert_eq!(stage.records().count(), 1);
+        assert_eq!(
+            stage.join().unwrap_err().to_string(),
+            "Parser thread panicked"
+        );
+    }
+
+    #[test]
+    fn test_decompress_stage() {
+        let input: String = std::iter::once("type,client,tx,amount\n".to_string())
+            .chain((1..=50_000).map(|tx| format!("deposit,1,{},1.0\n", tx)))
+            .collect();
+        let compressed = ruzstd::encoding::compress_to_vec(
+            input.as_bytes(),
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        assert!(is_zstd(&compressed));
+
+        let (decompress, decompressed) =
+            DecompressStage::spawn(std::io::Cursor::new(compressed.clone()), 2);
+        let reader = csv::Reader::from_reader(decompressed);
+        let stage = ParseStage::spawn(SerdeParser::new(reader).unwrap(), 4).after(decompress);
+        assert_eq!(stage.records().flatten().count(), 50_000);
+        stage.join().unwrap();
+
+        // Truncated input fails the stages once joined
+        let truncated = compressed[..compressed.len() / 2].to_vec();
+        let (decompress, decompressed) = DecompressStage::spawn(std::io::Cursor::new(truncated), 2);
+        let reader = csv::Reader::from_reader(decompressed);
+        let stage = ParseStage::spawn(SerdeParser::new(reader).unwrap(), 4).after(decompress);
+        assert!(stage.records().flatten().count() < 50_000);
+        assert!(stage
+            .join()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Failed to decompress the input"));
+    }
+}
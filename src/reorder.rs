@@ -0,0 +1,169 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use crate::engine::{Timestamp, Transaction};
+
+// Column transactions are reordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReorderKey {
+    Timestamp,
+    Sequence,
+}
+
+impl ReorderKey {
+    pub(crate) fn of(self, transaction: &Transaction) -> Option<u128> {
+        match self {
+            ReorderKey::Timestamp => {
+                let Timestamp(time) = transaction.timestamp()?;
+                Some(time.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+            }
+            ReorderKey::Sequence => transaction.sequence().map(u128::from),
+        }
+    }
+}
+
+impl FromStr for ReorderKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ReorderKey, ()> {
+        match s {
+            "timestamp" => Ok(ReorderKey::Timestamp),
+            "sequence" => Ok(ReorderKey::Sequence),
+            _ => Err(()),
+        }
+    }
+}
+
+struct Buffered {
+    key: u128,
+    // Input position, keeps the input order of equal keys
+    index: u64,
+    transaction: Transaction,
+}
+
+impl PartialEq for Buffered {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Buffered {}
+
+impl PartialOrd for Buffered {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Buffered {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.key, self.index).cmp(&(other.key, other.index))
+    }
+}
+
+// Reordering buffer for approximately ordered input, e.g. merged feeds.
+//
+// Up to `window` transactions are held back and the one with the smallest key is released first,
+// so a transaction arriving at most `window` records late is still applied in order. A
+// transaction without a key takes the key of the one before it. Errors aren't buffered.
+pub struct Reorder<I> {
+    records: I,
+    key: ReorderKey,
+    window: usize,
+    buffer: BinaryHeap<Reverse<Buffered>>,
+    last_key: u128,
+    index: u64,
+}
+
+impl<I> Reorder<I> {
+    pub fn new(records: I, key: ReorderKey, window: usize) -> Reorder<I> {
+        Reorder {
+            records,
+            key,
+            window,
+            buffer: BinaryHeap::with_capacity(window + 1),
+            last_key: 0,
+            index: 0,
+        }
+    }
+}
+
+impl<I, E> Iterator for Reorder<I>
+where
+    I: Iterator<Item = Result<Transaction, E>>,
+{
+    type Item = Result<Transaction, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.len() <= self.window {
+            match self.records.next() {
+                Some(Ok(transaction)) => {
+                    if let Some(key) = self.key.of(&transaction) {
+                        self.last_key = key;
+                    }
+                    self.buffer.push(Reverse(Buffered {
+                        key: self.last_key,
+                        index: self.index,
+                        transaction,
+                    }));
+                    self.index += 1;
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+        }
+        self.buffer
+            .pop()
+            .map(|Reverse(buffered)| Ok(buffered.transaction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ByteRecordParser, ParseError};
+
+    fn reorder(input: &str, key: ReorderKey, window: usize) -> Vec<String> {
+        let records = ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap();
+        Reorder::new(records, key, window)
+            .map(|record: Result<Transaction, ParseError>| {
+                record.unwrap().transaction_id.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reorder_within_window() {
+        let input = "type,client,tx,amount,sequence\n\
+                     dispute,1,1,,2\n\
+                     deposit,1,1,1.0,1\n\
+                     deposit,1,2,1.0,\n\
+                     deposit,1,3,1.0,5\n\
+                     deposit,1,4,1.0,6\n\
+                     deposit,1,5,1.0,7\n\
+                     deposit,1,6,1.0,3\n";
+
+        // tx 2 has no sequence number and stays behind the deposit before it
+        assert_eq!(
+            reorder(input, ReorderKey::Sequence, 2),
+            ["1", "2", "1", "3", "6", "4", "5"]
+        );
+        // Too late for a window of 2
+        assert_eq!(reorder(input, ReorderKey::Sequence, 1)[4], "4");
+        assert_eq!(
+            reorder(input, ReorderKey::Sequence, 0),
+            ["1", "1", "2", "3", "4", "5", "6"]
+        );
+    }
+
+    #[test]
+    fn test_reorder_by_timestamp() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,1.0,2024-03-01T00:00:02Z\n\
+                     deposit,1,2,1.0,2024-03-01T00:00:01Z\n";
+
+        assert_eq!(reorder(input, ReorderKey::Timestamp, 8), ["2", "1"]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use rust_decimal::Decimal;
+
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{
+    AmountFormat, ClientId, Engine, Note, Timestamp, Transaction, TransactionId, TransactionType,
+};
+
+// Stored transactions fetched per page by `history`.
+const HISTORY_PAGE: usize = 1000;
+
+pub const HELP: &str = "\
+<type> <client> <tx> [<amount>]  process a transaction, e.g. `deposit 1 100 5.0` or `dispute 1 100`
+show <client>                    balances of the client
+history <client>                 stored transactions and notes of the client
+note <client> [#<case>] <text>   leave a note on the account, e.g. `note 1 #C-42 called the bank`
+help                             this help
+quit                             leave, nothing is written";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ReplError {
+    #[error("Unknown command: {0}, see `help`")]
+    UnknownCommand(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("Invalid client id: {0}")]
+    InvalidClient(String),
+    #[error("Invalid transaction id: {0}")]
+    InvalidTransaction(String),
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(String),
+}
+
+// A line of the interactive mode.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Process(Transaction),
+    Show(ClientId),
+    History(ClientId),
+    Note(ClientId, Note),
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = ReplError;
+
+    fn from_str(line: &str) -> Result<Command, ReplError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["help"] => Ok(Command::Help),
+            ["quit" | "exit"] => Ok(Command::Quit),
+            ["show", client_id] => Ok(Command::Show(client(client_id)?)),
+            ["show", ..] => Err(ReplError::Usage("show <client>")),
+            ["history", client_id] => Ok(Command::History(client(client_id)?)),
+            ["history", ..] => Err(ReplError::Usage("history <client>")),
+            ["note", client_id, words @ ..] => {
+                let (case, words) = match words {
+                    [case, words @ ..] if case.len() > 1 && case.starts_with('#') => {
+                        (Some(case[1..].to_string()), words)
+                    }
+                    _ => (None, words),
+                };
+                if words.is_empty() {
+                    return Err(ReplError::Usage("note <client> [#<case>] <text>"));
+                }
+                Ok(Command::Note(
+                    client(client_id)?,
+                    Note {
+                        tx: None,
+                        case,
+                        text: words.join(" "),
+                        at: None,
+                    },
+                ))
+            }
+            ["note", ..] => Err(ReplError::Usage("note <client> [#<case>] <text>")),
+            [name, args @ ..] => {
+                let r#type: TransactionType = name
+                    .parse()
+                    .map_err(|_| ReplError::UnknownCommand(name.to_string()))?;
+                let (client_id, transaction_id, amount) = match args {
+                    [client_id, transaction_id] => (client_id, transaction_id, None),
+                    [client_id, transaction_id, amount] => {
+                        let amount = amount
+                            .parse::<Decimal>()
+                            .map_err(|_| ReplError::InvalidAmount(amount.to_string()))?;
+                        (client_id, transaction_id, Some(amount))
+                    }
+                    _ => return Err(ReplError::Usage("<type> <client> <tx> [<amount>]")),
+                };
+                Ok(Command::Process(Transaction {
+                    r#type,
+                    client_id: client(client_id)?,
+                    transaction_id: transaction_id
+                        .parse::<TransactionId>()
+                        .map_err(|_| ReplError::InvalidTransaction(transaction_id.to_string()))?,
+                    amount,
+                    metadata: Default::default(),
+                }))
+            }
+            [] => Err(ReplError::Usage("help")),
+        }
+    }
+}
+
+fn client(client_id: &str) -> Result<ClientId, ReplError> {
+    client_id
+        .parse()
+        .map_err(|_| ReplError::InvalidClient(client_id.to_string()))
+}
+
+// Run a command against the engine, returning what to print. `Command::Quit` is up to the caller.
+pub fn execute<A, S>(engine: &Engine<A, S>, command: Command) -> String
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    match command {
+        Command::Process(transaction) => match engine.process(transaction) {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("rejected: {} ({})", e, e.code()),
+        },
+        Command::Show(client_id) => match engine.account(client_id) {
+            Some(account) => {
+                let format = AmountFormat::default();
+                let flags: String = [
+                    (account.locked, "locked"),
+                    (account.quarantined, "quarantined"),
+                    (account.closed, "closed"),
+                ]
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| format!(", {}", flag))
+                .collect();
+                format!(
+                    "client {}: available {}, held {}, total {}{}",
+                    client_id,
+                    format.format(account.available),
+                    format.format(account.held),
+                    format.format(account.calculate_total()),
+                    flags
+                )
+            }
+            None => format!("client {} has no account", client_id),
+        },
+        Command::History(client_id) => {
+            let mut lines = Vec::new();
+            let mut cursor = None;
+            loop {
+                let Some(page) = engine.transactions(client_id, HISTORY_PAGE, cursor) else {
+                    return format!("client {} has no account", client_id);
+                };
+                for transaction in &page.transactions {
+                    lines.push(format!(
+                        "tx {}: {} {}",
+                        transaction.transaction_id,
+                        AmountFormat::default().format(transaction.amount),
+                        transaction.status.as_str()
+                    ));
+                }
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+            for note in engine.notes(client_id).unwrap_or_default() {
+                lines.push(describe_note(&note));
+            }
+            if lines.is_empty() {
+                return format!("client {} has no stored transactions", client_id);
+            }
+            lines.join("\n")
+        }
+        Command::Note(client_id, note) => {
+            let note = Note {
+                at: Some(Timestamp(SystemTime::now())),
+                ..note
+            };
+            if engine.add_note(client_id, note) {
+                "ok".to_string()
+            } else {
+                format!("client {} has no account", client_id)
+            }
+        }
+        Command::Help => HELP.to_string(),
+        Command::Quit => String::new(),
+    }
+}
+
+// `note [<at>] [case <case>] [tx <tx>]: <text>`
+fn describe_note(note: &Note) -> String {
+    let mut line = "note".to_string();
+    if let Some(at) = note.at {
+        line.push_str(&format!(" {}", at));
+    }
+    if let Some(case) = &note.case {
+        line.push_str(&format!(" case {}", case));
+    }
+    if let Some(tx) = note.tx {
+        line.push_str(&format!(" tx {}", tx));
+    }
+    format!("{}: {}", line, note.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(engine: &Engine, line: &str) -> String {
+        execute(engine, line.parse().unwrap())
+    }
+
+    #[test]
+    fn test_session() {
+        let engine: Engine = Engine::new();
+
+        assert_eq!(run(&engine, "deposit 1 100 5.0"), "ok");
+        assert_eq!(run(&engine, "deposit 1 101 2.5"), "ok");
+        assert_eq!(run(&engine, "dispute 1 100"), "ok");
+        assert_eq!(
+            run(&engine, "withdrawal 1 102 10"),
+            "rejected: AccountManager error: Insufficient funds (E213 insufficient_funds)"
+        );
+        assert_eq!(run(&engine, "chargeback 1 100"), "ok");
+        assert_eq!(
+            run(&engine, "show 1"),
+            "client 1: available 2.5, held 0.0, total 2.5, locked"
+        );
+        assert_eq!(
+            run(&engine, "history 1"),
+            "tx 100: 5.0 chargedback\ntx 101: 2.5 settled"
+        );
+        assert_eq!(run(&engine, "show 2"), "client 2 has no account");
+        assert_eq!(run(&engine, "note 1 #C-42 called the bank"), "ok");
+        assert!(run(&engine, "history 1").ends_with(" case C-42: called the bank"));
+        assert_eq!(run(&engine, "note 2 no account"), "client 2 has no account");
+        assert_eq!(
+            "note 1 #C-42".parse::<Command>(),
+            Err(ReplError::Usage("note <client> [#<case>] <text>"))
+        );
+        assert_eq!(" quit ".parse(), Ok(Command::Quit));
+        assert_eq!(
+            "refund 1 1".parse::<Command>(),
+            Err(ReplError::UnknownCommand("refund".to_string()))
+        );
+        assert_eq!(
+            "deposit 1 abc 1.0".parse::<Command>(),
+            Err(ReplError::InvalidTransaction("abc".to_string()))
+        );
+    }
+}
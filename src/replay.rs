@@ -0,0 +1,130 @@
+use std::io;
+
+use crate::engine::{Metadata, Transaction, EVENT_LOG_HEADERS};
+use crate::parser::ParseError;
+
+// Transactions of an event log written by `EngineBuilder::record`, in the recorded order.
+//
+// Unlike the input parsers this takes the columns by position and the metadata from its JSON
+// column, so every event is restored exactly as the engine saw it.
+pub struct EventLogParser<R> {
+    records: csv::StringRecordsIntoIter<R>,
+}
+
+impl<R: io::Read> EventLogParser<R> {
+    pub fn new(mut reader: csv::Reader<R>) -> Result<EventLogParser<R>, ParseError> {
+        let headers = reader.headers()?;
+        // An engine which saw no transaction leaves an empty log
+        if !headers.is_empty() {
+            for (index, name) in EVENT_LOG_HEADERS.iter().enumerate() {
+                if headers.get(index) != Some(*name) {
+                    return Err(ParseError::MissingColumn(name));
+                }
+            }
+        }
+        Ok(EventLogParser {
+            records: reader.into_records(),
+        })
+    }
+}
+
+fn event(record: &csv::StringRecord) -> Result<Transaction, ParseError> {
+    let field = |index: usize| record.get(index).unwrap_or_default();
+    let optional = |index: usize| Some(field(index)).filter(|value| !value.is_empty());
+    Ok(Transaction {
+        r#type: field(0)
+            .parse()
+            .map_err(|_| ParseError::UnknownTransactionType)?,
+        client_id: field(1)
+            .parse()
+            .map_err(|_| ParseError::InvalidValue("client"))?,
+        transaction_id: field(2)
+            .parse()
+            .map_err(|_| ParseError::InvalidValue("tx"))?,
+        amount: optional(3)
+            .map(str::parse)
+            .transpose()
+            .map_err(|_| ParseError::InvalidValue("amount"))?,
+        metadata: optional(4)
+            .map(serde_json::from_str::<Metadata>)
+            .transpose()
+            .map_err(|_| ParseError::InvalidValue("metadata"))?
+            .unwrap_or_default(),
+    })
+}
+
+impl<R: io::Read> Iterator for EventLogParser<R> {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(event(&record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::engine::Engine;
+    use crate::parser::ByteRecordParser;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_replay_reproduces_state() {
+        let input = "type,client,tx,amount,note\n\
+                     deposit,1,1,5.00,\"first, with comma\"\n\
+                     deposit,2,2,3.0,\n\
+                     withdrawal,1,3,9.0,\n\
+                     dispute,1,1,,\n\
+                     deposit,x,4,1.0,\n\
+                     chargeback,1,1,,\n";
+        let log = SharedBuffer::default();
+        let mut recorded: Engine = Engine::builder().record(log.clone()).build();
+        recorded
+            .process_transactions_blocking(
+                ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes())).unwrap(),
+            )
+            .unwrap();
+        recorded.flush_record().unwrap();
+
+        let log = log.0.lock().unwrap().clone();
+        let mut replayed: Engine = Engine::new();
+        replayed
+            .process_transactions_blocking(
+                EventLogParser::new(csv::Reader::from_reader(log.as_slice())).unwrap(),
+            )
+            .unwrap();
+
+        let sorted = |engine: &Engine| {
+            let mut accounts = engine.accounts().unwrap();
+            accounts.sort_by_key(|account| account.client_id);
+            accounts
+        };
+        assert_eq!(sorted(&replayed), sorted(&recorded));
+        assert_eq!(replayed.stats(), recorded.stats());
+        assert_eq!(
+            replayed.transaction_metadata(1.to_string().parse().unwrap(), 1),
+            Some(Metadata::from([(
+                "note".to_string(),
+                "first, with comma".to_string()
+            )]))
+        );
+    }
+}
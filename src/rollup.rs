@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use rust_decimal::Decimal;
+
+use crate::engine::amount::Amount;
+use crate::engine::{Account, ClientId};
+
+#[derive(thiserror::Error, Debug)]
+pub enum RollupError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("Invalid client id on line {0}")]
+    InvalidClient(u64),
+    #[error("Missing parent on line {0}")]
+    MissingParent(u64),
+    #[error("Client {0} has more than one parent")]
+    ConflictingParent(ClientId),
+}
+
+// Parent entities of clients, e.g. a merchant with many sub-accounts. Read from CSV records of
+// `<client>,<parent>` with an optional `client,parent` header.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Parents {
+    parents: HashMap<ClientId, String>,
+}
+
+impl Parents {
+    pub fn read<R: io::Read>(reader: R) -> Result<Parents, RollupError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+        let mut parents = HashMap::new();
+        for (line, record) in (1..).zip(reader.records()) {
+            let record = record?;
+            let client = record.get(0).unwrap_or_default();
+            if line == 1 && client == "client" {
+                continue;
+            }
+            let client_id: ClientId = client
+                .parse()
+                .map_err(|_| RollupError::InvalidClient(line))?;
+            let parent = match record.get(1) {
+                Some(parent) if !parent.is_empty() => parent.to_string(),
+                _ => return Err(RollupError::MissingParent(line)),
+            };
+            if parents
+                .get(&client_id)
+                .is_some_and(|previous| *previous != parent)
+            {
+                return Err(RollupError::ConflictingParent(client_id));
+            }
+            parents.insert(client_id, parent);
+        }
+        Ok(Parents { parents })
+    }
+
+    pub fn parent(&self, client_id: &ClientId) -> Option<&str> {
+        self.parents.get(client_id).map(String::as_str)
+    }
+}
+
+// Balances of the sub-accounts of a parent added up.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ParentRollup {
+    pub parent: String,
+    // Sub-accounts in the report
+    pub clients: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    // Locked sub-accounts
+    pub locked: usize,
+}
+
+// Rollups of the accounts of a report by parent, collected as the accounts are written. Accounts
+// of clients without a parent aren't rolled up.
+#[derive(Debug, Default)]
+pub struct Rollups {
+    parents: Parents,
+    rollups: BTreeMap<String, ParentRollup>,
+}
+
+impl Rollups {
+    pub fn new(parents: Parents) -> Rollups {
+        Rollups {
+            parents,
+            rollups: BTreeMap::new(),
+        }
+    }
+
+    pub fn parents(&self) -> &Parents {
+        &self.parents
+    }
+
+    // Add the account to its parent's rollup. Sums are kept as decimals, so the rollup of amounts
+    // close to the limits of the amount type doesn't overflow.
+    pub fn add<A: Amount>(&mut self, account: &Account<A>) {
+        let Some(parent) = self.parents.parent(&account.client_id) else {
+            return;
+        };
+        let rollup = self
+            .rollups
+            .entry(parent.to_string())
+            .or_insert_with(|| ParentRollup {
+                parent: parent.to_string(),
+                ..ParentRollup::default()
+            });
+        let (available, held) = (account.available.to_decimal(), account.held.to_decimal());
+        rollup.clients += 1;
+        rollup.available += available;
+        rollup.held += held;
+        rollup.total += available + held;
+        rollup.locked += usize::from(account.locked);
+    }
+
+    // Rollups in parent order.
+    pub fn rollups(&self) -> impl Iterator<Item = &ParentRollup> {
+        self.rollups.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollups() {
+        let parents =
+            Parents::read("client,parent\n1,acme\n2,acme\n3,globex\n".as_bytes()).unwrap();
+        let mut rollups = Rollups::new(parents);
+        let account = |id: &str, available: i64, held: i64, locked: bool| Account {
+            available: Decimal::from(available),
+            held: Decimal::from(held),
+            locked,
+            ..Account::new(id.parse().unwrap())
+        };
+        rollups.add(&account("1", 10, 5, false));
+        rollups.add(&account("2", 3, 0, true));
+        rollups.add(&account("3", 1, 0, false));
+        rollups.add(&account("4", 100, 0, false));
+
+        let acme = rollups.rollups().next().unwrap();
+        assert_eq!(
+            acme,
+            &ParentRollup {
+                parent: "acme".to_string(),
+                clients: 2,
+                available: Decimal::from(13),
+                held: Decimal::from(5),
+                total: Decimal::from(18),
+                locked: 1,
+            }
+        );
+        assert_eq!(rollups.rollups().count(), 2);
+
+        assert!(matches!(
+            Parents::read("1,acme\n1,globex\n".as_bytes()),
+            Err(RollupError::ConflictingParent(_))
+        ));
+        assert!(matches!(
+            Parents::read("1,\n".as_bytes()),
+            Err(RollupError::MissingParent(1))
+        ));
+    }
+}
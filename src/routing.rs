@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{ClientId, ErrorCode};
+use crate::partition::stable_hash;
+
+// Points per instance on the ring, evening out the share of clients each instance owns.
+const VIRTUAL_NODES: usize = 64;
+
+// Header marking a batch forwarded by another instance, processed where it arrives. Only
+// honoured on connections from the address of a peer, see `HashRing::is_peer`, batches of
+// anyone else are routed as usual.
+pub const FORWARDED_HEADER: &str = "X-Forwarded-By";
+// Connect, read and write timeout of forwarded batches, so a peer which hangs fails the batch
+// instead of holding the connection it was posted on.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Consistent hashing of clients over a static list of `serve` instances.
+//
+// Every instance is placed on a ring of hashes at several points and a client belongs to the
+// instance at the first point after the client's hash. All instances with the same peer list
+// agree on the owners, and adding or removing an instance only moves the clients of its points.
+pub struct HashRing {
+    peers: Vec<SocketAddr>,
+    local: usize,
+    // (point, index into peers), sorted by point
+    ring: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    // Ring over the peers, one of which is this instance. None if `local` isn't a peer.
+    pub fn new(peers: Vec<SocketAddr>, local: SocketAddr) -> Option<HashRing> {
+        let local = peers.iter().position(|peer| *peer == local)?;
+        let mut ring: Vec<(u64, usize)> = peers
+            .iter()
+            .enumerate()
+            .flat_map(|(index, peer)| {
+                (0..VIRTUAL_NODES)
+                    .map(move |node| (stable_hash(format!("{}#{}", peer, node).as_bytes()), index))
+            })
+            .collect();
+        ring.sort_unstable();
+        Some(HashRing { peers, local, ring })
+    }
+
+    pub fn peers(&self) -> &[SocketAddr] {
+        &self.peers
+    }
+
+    pub fn local(&self) -> usize {
+        self.local
+    }
+
+    // Whether a connection from the address may come from a peer. Peers connect from ephemeral
+    // ports, so only the address is compared.
+    pub fn is_peer(&self, address: IpAddr) -> bool {
+        self.peers.iter().any(|peer| peer.ip() == address)
+    }
+
+    // Index of the peer owning the client.
+    pub fn owner(&self, client_id: &ClientId) -> usize {
+        let hash = stable_hash(client_id.to_string().as_bytes());
+        let point = self.ring.partition_point(|(point, _)| *point < hash);
+        self.ring[point % self.ring.len()].1
+    }
+}
+
+// Outcome of a batch, as answered by `POST /transactions`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchOutcome {
+    pub processed: u64,
+    pub rejected: u64,
+    // Rejections by the engine by reason, records which couldn't be parsed only count as rejected
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rejections: BTreeMap<ErrorCode, u64>,
+    // Held for approval, counted as processed but not rejected, see
+    // `ServeOptions::approval_threshold`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub held: u64,
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+// Forward a CSV batch to the `POST /transactions` endpoint of its owner, with the
+// `Authorization` header it was posted with.
+pub fn forward(
+    peer: SocketAddr,
+    local: SocketAddr,
+    authorization: Option<&str>,
+    batch: &[u8],
+) -> io::Result<BatchOutcome> {
+    let mut stream = TcpStream::connect_timeout(&peer, FORWARD_TIMEOUT)?;
+    stream.set_read_timeout(Some(FORWARD_TIMEOUT))?;
+    stream.set_write_timeout(Some(FORWARD_TIMEOUT))?;
+    write!(
+        stream,
+        "POST /transactions HTTP/1.1\r\nHost: {}\r\nContent-Type: text/csv\r\nContent-Length: {}\r\n{}: {}\r\n",
+        peer,
+        batch.len(),
+        FORWARDED_HEADER,
+        local
+    )?;
+    if let Some(authorization) = authorization {
+        write!(stream, "Authorization: {}\r\n", authorization)?;
+    }
+    write!(stream, "Connection: close\r\n\r\n")?;
+    stream.write_all(batch)?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed response"))?;
+    if !head.starts_with("HTTP/1.1 200") {
+        return Err(io::Error::other(format!(
+            "Peer {} answered {}",
+            peer,
+            head.lines().next().unwrap_or_default()
+        )));
+    }
+    serde_json::from_str(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(count: u16) -> Vec<SocketAddr> {
+        (0..count)
+            .map(|port| SocketAddr::from(([10, 0, 0, 1], 8000 + port)))
+            .collect()
+    }
+
+    #[test]
+    fn test_owners_move_only_from_removed_peer() {
+        let all = HashRing::new(peers(4), peers(4)[0]).unwrap();
+        let fewer = HashRing::new(peers(3), peers(3)[0]).unwrap();
+        let clients: Vec<ClientId> = (0..1000).map(|c| c.to_string().parse().unwrap()).collect();
+
+        let mut owned = [0; 4];
+        for client_id in &clients {
+            let owner = all.owner(client_id);
+            owned[owner] += 1;
+            if owner != 3 {
+                assert_eq!(fewer.owner(client_id), owner);
+            }
+        }
+        assert!(owned.iter().all(|&count| count > 150), "{:?}", owned);
+        assert!(HashRing::new(peers(2), peers(3)[2]).is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+
+use crate::engine::{Account, Engine, EngineStats};
+use crate::parser::SerdeParser;
+
+// Outcome of processing a whole input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    // Sorted by client id
+    pub accounts: Vec<Account<Decimal>>,
+    pub stats: EngineStats,
+}
+
+// Process a transactions file with the default engine options, like the CLI without flags.
+pub fn run_file<P: AsRef<Path>>(path: P) -> Result<Report> {
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(File::open(path)?);
+
+    let mut engine: Engine = Engine::new();
+    engine.process_transactions_blocking(SerdeParser::new(reader)?)?;
+
+    let mut accounts = engine.accounts()?;
+    accounts.sort_by_key(|account| account.client_id);
+    Ok(Report {
+        accounts,
+        stats: engine.stats(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_run_file() {
+        let path = std::env::temp_dir().join(format!("run-file-test-{}.csv", std::process::id()));
+        fs::write(
+            &path,
+            "type, client, tx, amount\n\
+             deposit, 2, 1, 2.0\n\
+             deposit, 1, 2, 1.5\n\
+             withdrawal, 1, 3, 5.0\n",
+        )
+        .unwrap();
+
+        let report = run_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let balances: Vec<_> = report
+            .accounts
+            .iter()
+            .map(|account| (account.client_id.to_string(), account.available))
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                ("1".to_string(), Decimal::new(15, 1)),
+                ("2".to_string(), Decimal::new(2, 0)),
+            ]
+        );
+        assert_eq!(report.stats.processed, 3);
+        assert_eq!(report.stats.rejected, 1);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        assert!(run_file("does-not-exist.csv").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::engine::{Transaction, SOURCE_COLUMN};
+
+// What to do when a source's sequence numbers skip or go back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapPolicy {
+    // Log the anomaly and keep processing
+    Warn,
+    // Stop processing at the anomaly, keeping what was processed so far
+    Pause,
+    // Stop processing and fail
+    Abort,
+}
+
+impl FromStr for GapPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<GapPolicy, ()> {
+        match s {
+            "warn" => Ok(GapPolicy::Warn),
+            "pause" => Ok(GapPolicy::Pause),
+            "abort" => Ok(GapPolicy::Abort),
+            _ => Err(()),
+        }
+    }
+}
+
+// Sequence number out of line with the previous one of the same source, a possible sign of
+// records lost or replayed upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceAnomaly {
+    Gap {
+        source: String,
+        expected: u64,
+        found: u64,
+    },
+    Regression {
+        source: String,
+        last: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for SequenceAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceAnomaly::Gap {
+                source,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Sequence gap{}: expected {}, found {}",
+                in_source(source),
+                expected,
+                found
+            ),
+            SequenceAnomaly::Regression {
+                source,
+                last,
+                found,
+            } => write!(
+                f,
+                "Sequence regression{}: {} after {}",
+                in_source(source),
+                found,
+                last
+            ),
+        }
+    }
+}
+
+fn in_source(source: &str) -> String {
+    if source.is_empty() {
+        String::new()
+    } else {
+        format!(" in source '{}'", source)
+    }
+}
+
+// Tracks the `sequence` column of every `source` (a missing source is the unnamed one).
+// Transactions without a sequence number aren't checked.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last: HashMap<String, u64>,
+}
+
+impl SequenceTracker {
+    pub fn check(&mut self, transaction: &Transaction) -> Option<SequenceAnomaly> {
+        let found = transaction.sequence()?;
+        let source = transaction
+            .metadata
+            .get(SOURCE_COLUMN)
+            .cloned()
+            .unwrap_or_default();
+        let last = self.last.insert(source.clone(), found)?;
+
+        if found <= last {
+            // Keep expecting numbers after the highest one seen
+            self.last.insert(source.clone(), last);
+            Some(SequenceAnomaly::Regression {
+                source,
+                last,
+                found,
+            })
+        } else if found != last + 1 {
+            Some(SequenceAnomaly::Gap {
+                source,
+                expected: last + 1,
+                found,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ByteRecordParser;
+
+    #[test]
+    fn test_anomalies() {
+        let input = "type,client,tx,amount,source,sequence\n\
+                     deposit,1,1,1.0,a,1\n\
+                     deposit,1,2,1.0,b,7\n\
+                     deposit,1,3,1.0,a,2\n\
+                     deposit,1,4,1.0,,\n\
+                     deposit,1,5,1.0,a,4\n\
+                     deposit,1,6,1.0,a,3\n\
+                     deposit,1,7,1.0,a,5\n";
+        let mut tracker = SequenceTracker::default();
+
+        let anomalies: Vec<_> = ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .filter_map(|transaction| tracker.check(&transaction.unwrap()))
+            .collect();
+
+        assert_eq!(
+            anomalies,
+            [
+                SequenceAnomaly::Gap {
+                    source: "a".to_string(),
+                    expected: 3,
+                    found: 4,
+                },
+                SequenceAnomaly::Regression {
+                    source: "a".to_string(),
+                    last: 4,
+                    found: 3,
+                },
+            ]
+        );
+    }
+}
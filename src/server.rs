@@ -0,0 +1,1178 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::admin::{self, AdminOptions, Ingestion};
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{
+    AccountFilter, AmountFormat, ClientId, Engine, EngineError, Transaction, TransactionId,
+    SUBMITTER_COLUMN,
+};
+use crate::parser::{ByteRecordParser, ParseError};
+use crate::partition;
+use crate::routing::{self, BatchOutcome, HashRing, FORWARDED_HEADER};
+use crate::shadow::Shadow;
+use crate::throttle::TokenBucket;
+
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+pub const MAX_PAGE_LIMIT: usize = 1000;
+// Larger batches should be split by the client
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+// Request line and headers together
+const MAX_HEADER_BYTES: u64 = 64 * 1024;
+// Connections served at once, more are answered with 503 right away
+const MAX_CONNECTIONS: usize = 256;
+// Slow or stalled clients are dropped after waiting this long for a read or write
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Serve the engine over HTTP, one thread per connection, at most `MAX_CONNECTIONS` at once.
+//
+// * POST /transactions - process a CSV batch in the input file format
+// * GET /accounts?clients=&only_locked&min_balance=&include_dormant&query= - report in the output
+//   file format, optionally filtered like with the CLI report options
+// * GET /accounts/{id}/balance - single account as JSON
+// * GET /accounts/{id}/transactions?limit=&cursor= - page of stored transactions as JSON, pass
+//   `next_cursor` of a page as `cursor` to get the next one
+// * GET /shadow - divergences of the shadow engine as JSON, if enabled, see `shadow::Shadow`
+// * /admin/... - operational actions, if enabled, see `admin::AdminOptions`
+pub fn serve<A, S>(engine: Arc<Engine<A, S>>, listener: TcpListener) -> io::Result<()>
+where
+    A: Amount,
+    S: AccountStore<A> + 'static,
+{
+    serve_with(engine, listener, ServeOptions::default())
+}
+
+// Serve the engine as one of several instances, each owning the clients the ring assigns it.
+// Transactions of other clients posted to this instance are forwarded to their owners, see
+// `routing::HashRing`.
+pub fn serve_routed<A, S>(
+    engine: Arc<Engine<A, S>>,
+    listener: TcpListener,
+    ring: Option<HashRing>,
+) -> io::Result<()>
+where
+    A: Amount,
+    S: AccountStore<A> + 'static,
+{
+    serve_with(
+        engine,
+        listener,
+        ServeOptions {
+            ring,
+            ..ServeOptions::default()
+        },
+    )
+}
+
+#[derive(Default)]
+pub struct ServeOptions {
+    // Share the clients with other instances, see `serve_routed`
+    pub ring: Option<HashRing>,
+    // Enable the admin endpoints, see `admin::AdminOptions`
+    pub admin: Option<AdminOptions>,
+    // Hold posted transactions with a higher amount until approved or rejected through the admin
+    // endpoints, instead of applying them, see `Engine::hold`. Transactions posted with the token
+    // of an approver can only be approved by another one, see `admin::Approver`
+    pub approval_threshold: Option<Decimal>,
+    // Also apply processed transactions to the shadow engine and compare the outcomes
+    pub shadow: Option<Arc<Shadow>>,
+    // Limit the rate posted transactions are applied at across connections, requests wait for
+    // their turn
+    pub throttle: Option<TokenBucket>,
+    // Admission of posted transactions, to also pause it from outside the admin endpoints, e.g.
+    // by signals
+    pub ingestion: Arc<Ingestion>,
+}
+
+// Serve the engine with all optional features of the server.
+pub fn serve_with<A, S>(
+    engine: Arc<Engine<A, S>>,
+    listener: TcpListener,
+    options: ServeOptions,
+) -> io::Result<()>
+where
+    A: Amount,
+    S: AccountStore<A> + 'static,
+{
+    let server = Arc::new(Server::new(engine, options));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        {
+            warn!("Error setting connection timeouts: {}", e);
+            continue;
+        }
+        let connection = Connection::open(&connections);
+        if connections.load(Ordering::SeqCst) > MAX_CONNECTIONS {
+            warn!("Too many connections, refusing one");
+            let _ = write_response(stream, &Response::error(503, "Too many connections"));
+            continue;
+        }
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(&server, stream) {
+                debug!("Error handling connection: {}", e);
+            }
+            drop(connection);
+        });
+    }
+    Ok(())
+}
+
+// A connection counted as served until dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn open(connections: &Arc<AtomicUsize>) -> Connection {
+        connections.fetch_add(1, Ordering::SeqCst);
+        Connection(Arc::clone(connections))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Server<A, S> {
+    engine: Arc<Engine<A, S>>,
+    ring: Option<HashRing>,
+    admin: Option<AdminOptions>,
+    ingestion: Arc<Ingestion>,
+    approval_threshold: Option<Decimal>,
+    shadow: Option<Arc<Shadow>>,
+    throttle: Option<TokenBucket>,
+}
+
+impl<A, S> Server<A, S> {
+    fn new(engine: Arc<Engine<A, S>>, options: ServeOptions) -> Server<A, S> {
+        Server {
+            engine,
+            ring: options.ring,
+            admin: options.admin,
+            ingestion: options.ingestion,
+            approval_threshold: options.approval_threshold,
+            shadow: options.shadow,
+            throttle: options.throttle,
+        }
+    }
+}
+
+pub(crate) struct Request {
+    pub(crate) method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    // Forwarded by another instance, see `routing::FORWARDED_HEADER`
+    forwarded: bool,
+    // Address of the connection the request came on
+    source: Option<IpAddr>,
+    authorization: Option<String>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    pub(crate) fn bearer_token(&self) -> Option<&str> {
+        self.authorization.as_deref()?.strip_prefix("Bearer ")
+    }
+
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(crate) struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub(crate) fn json<T: Serialize>(status: u16, body: &T) -> Response {
+        match serde_json::to_string(body) {
+            Ok(body) => Response {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    pub(crate) fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+fn handle_connection<A, S>(server: &Server<A, S>, stream: TcpStream) -> io::Result<()>
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader)? {
+        Some(mut request) => {
+            request.source = stream.peer_addr().ok().map(|address| address.ip());
+            route(server, &request)
+        }
+        None => Response::error(400, "Malformed request"),
+    };
+    write_response(stream, &response)
+}
+
+// Query value with `+` as a space and `%XX` escapes decoded, e.g. of `query=total+%3E+5`.
+// Malformed escapes are kept as they are.
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| std::str::from_utf8(tail.get(..2)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (_, Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+                continue;
+            }
+            (b'+', None) => bytes.push(b' '),
+            (byte, None) => bytes.push(byte),
+        }
+        rest = tail;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// None if the request is malformed or its head is longer than `MAX_HEADER_BYTES`.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut head = reader.take(MAX_HEADER_BYTES);
+    let mut line = String::new();
+    if !read_head_line(&mut head, &mut line)? {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let method = method.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = path.to_string();
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), percent_decode(value))
+        })
+        .collect();
+
+    let mut content_length = 0;
+    let mut forwarded = false;
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if !read_head_line(&mut head, &mut line)? {
+            return Ok(None);
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                match value.trim().parse() {
+                    Ok(length) if length <= MAX_BODY_BYTES => content_length = length,
+                    _ => return Ok(None),
+                }
+            }
+            if name.trim().eq_ignore_ascii_case(FORWARDED_HEADER) {
+                forwarded = true;
+            }
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    // Grown as the body arrives rather than allocated up front for the announced length
+    let mut body = Vec::new();
+    head.into_inner()
+        .take(content_length as u64)
+        .read_to_end(&mut body)?;
+    if body.len() < content_length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        forwarded,
+        source: None,
+        authorization,
+        body,
+    }))
+}
+
+// Read one line of the request head, false if the head is too long. The end of the stream ends
+// the head like an empty line.
+fn read_head_line<R: BufRead>(head: &mut io::Take<R>, line: &mut String) -> io::Result<bool> {
+    let read = head.read_line(line)?;
+    Ok(head.limit() > 0 || (read > 0 && line.ends_with('\n')))
+}
+
+fn write_response<W: Write>(mut writer: W, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    writer.flush()
+}
+
+fn route<A, S>(server: &Server<A, S>, request: &Request) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let engine = server.engine.as_ref();
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["transactions"]) => {
+            let Some(_admitted) = server.ingestion.admit() else {
+                return Response::error(503, "Ingestion paused");
+            };
+            match &server.ring {
+                Some(ring) if !is_forwarded(ring, request) => process_routed(server, ring, request),
+                _ => match process(server, &request.body, submitter(server, request)) {
+                    Ok(outcome) => Response::json(200, &outcome),
+                    Err(e) => Response::error(400, &e.to_string()),
+                },
+            }
+        }
+        (_, ["admin", segments @ ..]) => match &server.admin {
+            Some(options) => admin::route(
+                engine,
+                options,
+                &server.ingestion,
+                &|transaction| apply(server, transaction),
+                request,
+                segments,
+            ),
+            None => Response::error(404, "Not found"),
+        },
+        ("GET", ["shadow"]) => match &server.shadow {
+            Some(shadow) => {
+                Response::json(200, &shadow.report(&engine.accounts().unwrap_or_default()))
+            }
+            None => Response::error(404, "Not found"),
+        },
+        ("GET", ["accounts"]) => report(engine, request),
+        ("GET", ["accounts", client_id, "balance"]) => balance(engine, client_id),
+        ("GET", ["accounts", client_id, "transactions"]) => {
+            transactions(engine, client_id, request)
+        }
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+// Whether the request was forwarded by a peer, the header of anyone else is ignored.
+fn is_forwarded(ring: &HashRing, request: &Request) -> bool {
+    if !request.forwarded {
+        return false;
+    }
+    let from_peer = request.source.is_some_and(|source| ring.is_peer(source));
+    if !from_peer {
+        warn!(
+            "Ignoring {} header of {:?}, not a peer",
+            FORWARDED_HEADER, request.source
+        );
+    }
+    from_peer
+}
+
+// Approver who posted the request, see `admin::Approver`.
+fn submitter<'a, A, S>(server: &'a Server<A, S>, request: &Request) -> Option<&'a str> {
+    server.admin.as_ref()?.approver(request)
+}
+
+// Apply an admitted transaction, at the throttled rate and also to the shadow engine.
+fn apply<A, S>(server: &Server<A, S>, transaction: Transaction) -> Result<(), EngineError>
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    if let Some(throttle) = &server.throttle {
+        throttle.acquire();
+    }
+    match &server.shadow {
+        Some(shadow) => {
+            let result = server.engine.process(transaction.clone());
+            shadow.apply(transaction, &result);
+            result
+        }
+        None => server.engine.process(transaction),
+    }
+}
+
+fn process<A, S>(
+    server: &Server<A, S>,
+    body: &[u8],
+    submitter: Option<&str>,
+) -> Result<BatchOutcome, ParseError>
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let engine = server.engine.as_ref();
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(body);
+
+    let mut outcome = BatchOutcome::default();
+    for record in ByteRecordParser::new(reader)? {
+        outcome.processed += 1;
+        let record = match record {
+            Ok(mut transaction)
+                if server.approval_threshold.is_some_and(|threshold| {
+                    transaction.amount.is_some_and(|amount| amount > threshold)
+                }) =>
+            {
+                // Only ever from the token of the request
+                match submitter {
+                    Some(submitter) => {
+                        let submitter = submitter.to_string();
+                        transaction
+                            .metadata
+                            .insert(SUBMITTER_COLUMN.to_string(), submitter);
+                    }
+                    None => {
+                        transaction.metadata.remove(SUBMITTER_COLUMN);
+                    }
+                }
+                engine.hold(transaction);
+                outcome.held += 1;
+                continue;
+            }
+            record => record,
+        };
+        let record = record.map(|transaction| apply(server, transaction));
+        match record {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                outcome.rejected += 1;
+                *outcome.rejections.entry(e.code()).or_default() += 1;
+            }
+            Err(_) => outcome.rejected += 1,
+        }
+    }
+    // Keep the event log complete up to the answered batches
+    if let Err(e) = engine.flush_record() {
+        warn!("Error flushing event log: {}", e);
+    }
+    Ok(outcome)
+}
+
+// Process the transactions of the batch owned by this instance and forward the rest to their
+// owners. Transactions which can't be forwarded count as rejected.
+fn process_routed<A, S>(server: &Server<A, S>, ring: &HashRing, request: &Request) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(request.body.as_slice());
+    let mut batches: Vec<_> = ring
+        .peers()
+        .iter()
+        .map(|_| {
+            csv::WriterBuilder::new()
+                .flexible(true)
+                .from_writer(Vec::new())
+        })
+        .collect();
+    // Invalid clients stay here to be rejected
+    let counts = match partition::partition_by(reader, &mut batches, |client_id| {
+        client_id.map_or(ring.local(), |client_id| ring.owner(client_id))
+    }) {
+        Ok(counts) => counts,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+
+    let local = ring.peers()[ring.local()];
+    let mut outcome = BatchOutcome::default();
+    for (index, (batch, count)) in batches.into_iter().zip(counts).enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let Ok(batch) = batch.into_inner() else {
+            return Response::error(500, "Error splitting batch");
+        };
+        let result = if index == ring.local() {
+            process(server, &batch, submitter(server, request)).map_err(|e| e.to_string())
+        } else {
+            // With the poster's token, to hold the transactions as submitted by them
+            let authorization = request.authorization.as_deref();
+            routing::forward(ring.peers()[index], local, authorization, &batch)
+                .map_err(|e| e.to_string())
+        };
+        match result {
+            Ok(forwarded) => {
+                outcome.processed += forwarded.processed;
+                outcome.rejected += forwarded.rejected;
+                outcome.held += forwarded.held;
+                for (code, count) in forwarded.rejections {
+                    *outcome.rejections.entry(code).or_default() += count;
+                }
+            }
+            Err(e) => {
+                warn!("Error processing batch at {}: {}", ring.peers()[index], e);
+                outcome.processed += count as u64;
+                outcome.rejected += count as u64;
+            }
+        }
+    }
+    Response::json(200, &outcome)
+}
+
+fn report<A, S>(engine: &Engine<A, S>, request: &Request) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let filter = match report_filter(request) {
+        Ok(filter) => filter,
+        Err(message) => return Response::error(400, message),
+    };
+    let mut accounts = match engine.accounts() {
+        Ok(accounts) => accounts,
+        Err(e) => return Response::error(500, &e.to_string()),
+    };
+    accounts.retain(|account| filter.matches(account));
+    accounts.sort_by_key(|account| account.client_id);
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for account in &accounts {
+        if let Err(e) = writer.serialize(account) {
+            return Response::error(500, &e.to_string());
+        }
+    }
+    match writer.into_inner().map(String::from_utf8) {
+        Ok(Ok(body)) => Response {
+            status: 200,
+            content_type: "text/csv",
+            body,
+        },
+        _ => Response::error(500, "Error writing report"),
+    }
+}
+
+// Report filter from the `clients`, `only_locked`, `min_balance`, `include_dormant` and `query`
+// query parameters, with the same meaning as the CLI options.
+fn report_filter(request: &Request) -> Result<AccountFilter, &'static str> {
+    let flag = |name: &str, error: &'static str| match request.query(name) {
+        None | Some("false") => Ok(false),
+        Some("" | "true") => Ok(true),
+        Some(_) => Err(error),
+    };
+    Ok(AccountFilter {
+        clients: request
+            .query("clients")
+            .map(|clients| clients.parse().map_err(|_| "Invalid clients"))
+            .transpose()?,
+        only_locked: flag("only_locked", "Invalid only_locked")?,
+        min_balance: request
+            .query("min_balance")
+            .map(|min_balance| min_balance.parse().map_err(|_| "Invalid min_balance"))
+            .transpose()?,
+        include_dormant: flag("include_dormant", "Invalid include_dormant")?,
+        query: request
+            .query("query")
+            .map(|query| query.parse().map_err(|_| "Invalid query"))
+            .transpose()?,
+    })
+}
+
+fn balance<A, S>(engine: &Engine<A, S>, client_id: &str) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let Ok(client_id) = client_id.parse::<ClientId>() else {
+        return Response::error(400, "Invalid client id");
+    };
+    match engine.account(client_id) {
+        Some(account) => Response::json(200, &account.formatted(AmountFormat::default())),
+        None => Response::error(404, "Account not found"),
+    }
+}
+
+fn transactions<A, S>(engine: &Engine<A, S>, client_id: &str, request: &Request) -> Response
+where
+    A: Amount,
+    S: AccountStore<A>,
+{
+    let Ok(client_id) = client_id.parse::<ClientId>() else {
+        return Response::error(400, "Invalid client id");
+    };
+    let limit = match request.query("limit").map(str::parse::<usize>) {
+        None => DEFAULT_PAGE_LIMIT,
+        Some(Ok(limit)) if (1..=MAX_PAGE_LIMIT).contains(&limit) => limit,
+        Some(_) => return Response::error(400, "Invalid limit"),
+    };
+    let cursor = match request.query("cursor").map(str::parse::<TransactionId>) {
+        None => None,
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(_)) => return Response::error(400, "Invalid cursor"),
+    };
+
+    let Some(page) = engine.transactions(client_id, limit, cursor) else {
+        return Response::error(404, "Account not found");
+    };
+    let transactions: Vec<_> = page
+        .transactions
+        .iter()
+        .map(|transaction| {
+            json!({
+                "tx": transaction.transaction_id,
+                "amount": AmountFormat::default().format(transaction.amount),
+                "status": transaction.status.as_str(),
+                "metadata": transaction.metadata,
+            })
+        })
+        .collect();
+    Response::json(
+        200,
+        &json!({ "transactions": transactions, "next_cursor": page.next_cursor }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::Shutdown;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::admin::Approver;
+    use crate::engine::store::DashMapStore;
+
+    fn server(options: ServeOptions) -> Server<Decimal, DashMapStore<Decimal>> {
+        Server::new(Arc::new(Engine::new()), options)
+    }
+
+    fn send(server: &Server<Decimal, DashMapStore<Decimal>>, raw: &str) -> (u16, String) {
+        let request = read_request(&mut raw.as_bytes()).unwrap().unwrap();
+        let response = route(server, &request);
+        (response.status, response.body)
+    }
+
+    fn post(server: &Server<Decimal, DashMapStore<Decimal>>, body: &str) -> (u16, String) {
+        send(
+            server,
+            &format!(
+                "POST /transactions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        )
+    }
+
+    #[test]
+    fn test_account_queries() {
+        let server = server(ServeOptions::default());
+        let (status, body) = post(
+            &server,
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,1,2,2.5\n\
+             deposit,1,3,4.0\n\
+             dispute,1,2,\n\
+             withdrawal,1,4,100.0\n",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"processed":5,"rejected":1,"rejections":{"insufficient_funds":1}}"#
+        );
+
+        post(&server, "type,client,tx,amount\ndeposit,2,5,1.0\n");
+        let (_, body) = send(&server, "GET /accounts?min_balance=2 HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            body,
+            "client,available,held,total,locked\n1,5.0,2.5,7.5,false\n"
+        );
+        let (_, body) = send(&server, "GET /accounts?only_locked HTTP/1.1\r\n\r\n");
+        assert_eq!(body, "");
+        let (_, body) = send(
+            &server,
+            "GET /accounts?query=held+%3E+0+AND+client+%3D+1 HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(
+            body,
+            "client,available,held,total,locked\n1,5.0,2.5,7.5,false\n"
+        );
+
+        let (status, body) = send(&server, "GET /accounts/1/balance HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 200);
+        let account: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(account["available"], "5.0");
+        assert_eq!(account["held"], "2.5");
+
+        let (status, body) = send(
+            &server,
+            "GET /accounts/1/transactions?limit=2 HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(status, 200);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(page["transactions"][1]["status"], "disputed");
+        assert_eq!(page["next_cursor"], 2);
+
+        let (_, body) = send(
+            &server,
+            "GET /accounts/1/transactions?limit=2&cursor=2 HTTP/1.1\r\n\r\n",
+        );
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(page["transactions"][0]["tx"], 3);
+        assert_eq!(page["next_cursor"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let server = server(ServeOptions::default());
+        let status = |raw: &str| send(&server, raw).0;
+
+        assert_eq!(status("GET /accounts/1/balance HTTP/1.1\r\n\r\n"), 404);
+        // Too long for any client id type
+        let client_id = "x".repeat(64);
+        assert_eq!(
+            status(&format!(
+                "GET /accounts/{}/balance HTTP/1.1\r\n\r\n",
+                client_id
+            )),
+            400
+        );
+        assert_eq!(
+            status("GET /accounts/1/transactions?limit=0 HTTP/1.1\r\n\r\n"),
+            400
+        );
+        assert_eq!(status("GET /unknown HTTP/1.1\r\n\r\n"), 404);
+        // Admin endpoints are disabled without a token
+        assert_eq!(status("POST /admin/compact HTTP/1.1\r\n\r\n"), 404);
+        assert_eq!(
+            status("GET /accounts?query=total+%3E HTTP/1.1\r\n\r\n"),
+            400
+        );
+        assert_eq!(
+            status(&format!(
+                "GET /accounts?query={} HTTP/1.1\r\n\r\n",
+                "!".repeat(60_000)
+            )),
+            400
+        );
+        assert_eq!(
+            status("GET /accounts?min_balance=abc HTTP/1.1\r\n\r\n"),
+            400
+        );
+        assert_eq!(post(&server, "type,client,amount\n").0, 400);
+
+        let header = format!("X-Padding: {}\r\n", "x".repeat(1024));
+        let raw = format!("GET /accounts HTTP/1.1\r\n{}\r\n", header.repeat(64));
+        assert!(read_request(&mut raw.as_bytes()).unwrap().is_none());
+        let raw = format!("GET /accounts HTTP/1.1\r\n{}\r\n", header.repeat(32));
+        assert!(read_request(&mut raw.as_bytes()).unwrap().is_some());
+        // Body shorter than announced
+        let raw = "POST /transactions HTTP/1.1\r\nContent-Length: 1000000\r\n\r\ntype\n";
+        assert!(matches!(
+            read_request(&mut raw.as_bytes()),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_admin() {
+        let snapshot_path =
+            std::env::temp_dir().join(format!("admin-test-{}.csv", std::process::id()));
+        let server = server(ServeOptions {
+            admin: Some(AdminOptions {
+                token: "secret".to_string(),
+                snapshot_path: Some(snapshot_path.clone()),
+                approvers: Vec::new(),
+            }),
+            ..ServeOptions::default()
+        });
+        let admin = |method: &str, path: &str| {
+            send(
+                &server,
+                &format!(
+                    "{} /admin/{} HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+                    method, path
+                ),
+            )
+        };
+        post(
+            &server,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,1,2,3.0\n\
+             dispute,1,1,\n\
+             dispute,1,2,\n\
+             chargeback,1,2,\n",
+        );
+
+        assert_eq!(
+            send(
+                &server,
+                "POST /admin/compact HTTP/1.1\r\nAuthorization: Bearer guess\r\n\r\n"
+            )
+            .0,
+            401
+        );
+        assert_eq!(admin("POST", "accounts/1/transactions/1/resolve").0, 200);
+        assert_eq!(
+            admin("POST", "accounts/1/transactions/2/resolve"),
+            (
+                409,
+                r#"{"code":"transaction_charged_back","error":"Transaction already charged back"}"#
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            admin("POST", "accounts/1/unlock"),
+            (200, r#"{"was_locked":true}"#.to_string())
+        );
+        let account = server
+            .engine
+            .account(1.to_string().parse().unwrap())
+            .unwrap();
+        assert_eq!(
+            (account.available, account.locked),
+            (Decimal::from(5), false)
+        );
+
+        let body = r#"{"text":"chargeback confirmed by the issuer","case":"C-42","tx":2}"#;
+        let (status, _) = send(
+            &server,
+            &format!(
+                "POST /admin/accounts/1/notes HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        );
+        assert_eq!(status, 200);
+        let (_, body) = admin("GET", "accounts/1/notes");
+        let notes: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(notes["notes"][0]["case"], "C-42");
+        assert_eq!(admin("GET", "accounts/9/notes").0, 404);
+
+        assert_eq!(admin("POST", "snapshot").0, 200);
+        let restored: Engine = Engine::new();
+        restored
+            .restore_snapshot(std::fs::File::open(&snapshot_path).unwrap())
+            .unwrap();
+        std::fs::remove_file(&snapshot_path).unwrap();
+        assert_eq!(
+            restored.accounts().unwrap(),
+            server.engine.accounts().unwrap()
+        );
+
+        assert_eq!(
+            admin("POST", "ingestion/drain"),
+            (200, r#"{"in_flight":0,"paused":true}"#.to_string())
+        );
+        assert_eq!(
+            post(&server, "type,client,tx,amount\ndeposit,1,3,1.0\n").0,
+            503
+        );
+        admin("POST", "ingestion/resume");
+
+        // A drain can leave a snapshot of the drained state for maintenance
+        let body = r#"{"snapshot":true}"#;
+        let (status, _) = send(
+            &server,
+            &format!(
+                "POST /admin/ingestion/drain HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        );
+        assert_eq!(status, 200);
+        std::fs::remove_file(&snapshot_path).unwrap();
+        admin("POST", "ingestion/resume");
+        assert_eq!(
+            post(&server, "type,client,tx,amount\ndeposit,1,3,1.0\n").0,
+            200
+        );
+    }
+
+    #[test]
+    fn test_approvals() {
+        let approver = |name: &str| Approver {
+            name: name.to_string(),
+            token: format!("{}-token", name),
+        };
+        let server = server(ServeOptions {
+            admin: Some(AdminOptions {
+                token: "secret".to_string(),
+                snapshot_path: None,
+                approvers: vec![approver("alice"), approver("bob")],
+            }),
+            approval_threshold: Some(Decimal::from(1000)),
+            ..ServeOptions::default()
+        });
+        let admin = |method: &str, path: &str, token: &str| {
+            send(
+                &server,
+                &format!(
+                    "{} /admin/{} HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+                    method, path, token
+                ),
+            )
+        };
+        let body = "type,client,tx,amount,submitted_by\n\
+                    deposit,1,1,500.0,\n\
+                    deposit,1,2,5000.0,bob\n\
+                    withdrawal,1,3,2000.0,\n";
+        let (_, body) = send(
+            &server,
+            &format!(
+                "POST /transactions HTTP/1.1\r\nAuthorization: Bearer alice-token\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        );
+        assert_eq!(body, r#"{"processed":3,"rejected":0,"held":2}"#);
+        let (_, body) = admin("GET", "approvals", "bob-token");
+        let approvals: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(approvals["approvals"][1]["tx"], 3);
+        // Whoever posted it, not what the batch claims
+        assert_eq!(
+            approvals["approvals"][0]["metadata"][SUBMITTER_COLUMN],
+            "alice"
+        );
+
+        // Four eyes: neither the submitter nor the shared admin token can approve
+        assert_eq!(admin("POST", "approvals/1/approve", "alice-token").0, 403);
+        assert_eq!(admin("POST", "approvals/1/approve", "secret").0, 403);
+        assert_eq!(admin("POST", "compact", "bob-token").0, 401);
+
+        // Approvals are ingested like posted transactions, not while ingestion is paused
+        server.ingestion.pause(true);
+        assert_eq!(admin("POST", "approvals/1/approve", "bob-token").0, 503);
+        server.ingestion.pause(false);
+        assert_eq!(
+            admin("POST", "approvals/1/approve", "bob-token"),
+            (200, r#"{"status":"applied"}"#.to_string())
+        );
+        assert_eq!(
+            admin("POST", "approvals/2/reject", "alice-token"),
+            (200, r#"{"status":"rejected"}"#.to_string())
+        );
+        assert_eq!(admin("POST", "approvals/2/approve", "bob-token").0, 404);
+        let account = server.engine.account("1".parse().unwrap()).unwrap();
+        assert_eq!(account.available, Decimal::from(5500));
+    }
+
+    #[test]
+    fn test_shadow() {
+        assert_eq!(
+            send(
+                &server(ServeOptions::default()),
+                "GET /shadow HTTP/1.1\r\n\r\n"
+            )
+            .0,
+            404
+        );
+        let shadow: Engine = Engine::builder().max_balance(Decimal::from(100)).build();
+        let server = server(ServeOptions {
+            shadow: Some(Arc::new(Shadow::new(Box::new(shadow)))),
+            ..ServeOptions::default()
+        });
+        // The shadow's outcome isn't returned
+        let (_, body) = post(
+            &server,
+            "type,client,tx,amount\n\
+             deposit,1,1,50.0\n\
+             deposit,1,2,80.0\n",
+        );
+        assert_eq!(body, r#"{"processed":2,"rejected":0}"#);
+        let (status, body) = send(&server, "GET /shadow HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 200);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            (report["compared"].as_u64(), report["diverged"].as_u64()),
+            (Some(2), Some(1))
+        );
+        assert_eq!(report["recent"][0]["tx"], 2);
+        assert_eq!(
+            report["accounts"],
+            json!(["1".parse::<ClientId>().unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let engine: Arc<Engine> = Arc::new(Engine::new());
+        thread::spawn(move || serve(engine, listener));
+
+        let body = "type,client,tx,amount\ndeposit,7,1,1.5\n";
+        let mut responses = Vec::new();
+        for raw in [
+            format!(
+                "POST /transactions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+            "GET /accounts HTTP/1.1\r\n\r\n".to_string(),
+        ] {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            responses.push(response);
+        }
+
+        assert!(responses[0].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(responses[1].ends_with("client,available,held,total,locked\n7,1.5,0.0,1.5,false\n"));
+    }
+
+    #[test]
+    fn test_routing_to_owners() {
+        let listeners: Vec<_> = (0..2)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let peers: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        let engines: Vec<Arc<Engine>> = (0..2).map(|_| Arc::new(Engine::new())).collect();
+        for (listener, engine) in listeners.into_iter().zip(&engines) {
+            let ring = HashRing::new(peers.clone(), listener.local_addr().unwrap());
+            let engine = Arc::clone(engine);
+            thread::spawn(move || serve_routed(engine, listener, ring));
+        }
+
+        let body = "type,client,tx,amount\n".to_string()
+            + &(1..=20)
+                .map(|client| format!("deposit,{},{},1.0\n", client, client))
+                .collect::<String>()
+            // Invalid with any client id type
+            + &format!("deposit,{},21,1.0\n", "x".repeat(64));
+        let mut stream = TcpStream::connect(peers[0]).unwrap();
+        write!(
+            stream,
+            "POST /transactions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with(r#"{"processed":21,"rejected":1}"#));
+
+        let ring = HashRing::new(peers.clone(), peers[0]).unwrap();
+        let accounts: Vec<_> = engines
+            .iter()
+            .map(|engine| engine.accounts().unwrap())
+            .collect();
+        assert_eq!(accounts[0].len() + accounts[1].len(), 20);
+        for (index, accounts) in accounts.iter().enumerate() {
+            assert!(!accounts.is_empty());
+            assert!(accounts
+                .iter()
+                .all(|account| ring.owner(&account.client_id) == index));
+        }
+    }
+
+    #[test]
+    fn test_forwarded_only_from_peers() {
+        // The other peer refuses connections, so batches routed to it are rejected
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let local = "127.0.0.2:8000".parse().unwrap();
+        let ring = HashRing::new(vec![local, closed], local).unwrap();
+        let client_id = (1..)
+            .map(|client: u32| client.to_string().parse::<ClientId>().unwrap())
+            .find(|client_id| ring.owner(client_id) == 1)
+            .unwrap();
+        let server = server(ServeOptions {
+            ring: Some(ring),
+            ..ServeOptions::default()
+        });
+        let body = format!("type,client,tx,amount\ndeposit,{},1,1.0\n", client_id);
+        let raw = format!(
+            "POST /transactions HTTP/1.1\r\n{}: {}\r\nContent-Length: {}\r\n\r\n{}",
+            FORWARDED_HEADER,
+            closed,
+            body.len(),
+            body
+        );
+        let send_from = |source: &str| {
+            let mut request = read_request(&mut raw.as_bytes()).unwrap().unwrap();
+            request.source = Some(source.parse().unwrap());
+            let response = route(&server, &request);
+            (response.status, response.body)
+        };
+
+        assert_eq!(
+            send_from("192.0.2.1"),
+            (200, r#"{"processed":1,"rejected":1}"#.to_string())
+        );
+        assert!(server.engine.account(client_id).is_none());
+        assert_eq!(
+            send_from("127.0.0.1"),
+            (200, r#"{"processed":1,"rejected":0}"#.to_string())
+        );
+        assert!(server.engine.account(client_id).is_some());
+    }
+}
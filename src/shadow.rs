@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::engine::amount::Amount;
+use crate::engine::{
+    diff_accounts, Account, ClientId, EngineError, PaymentsEngine, Transaction, TransactionId,
+};
+
+// Log target of divergences between the primary and the shadow engine.
+pub const SHADOW_TARGET: &str = "shadow";
+// Divergences kept for `ShadowReport::recent`
+const RECENT_DIVERGENCES: usize = 100;
+
+// Transaction with a different outcome in the shadow engine, `accepted` or the error code of the
+// rejection in each.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Divergence {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub primary: String,
+    pub shadow: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShadowReport {
+    // Transactions applied to both engines
+    pub compared: u64,
+    pub diverged: u64,
+    // Latest divergences, oldest first
+    pub recent: Vec<Divergence>,
+    // Clients whose accounts differ between the engines now, see `diff_accounts`
+    pub accounts: Vec<ClientId>,
+}
+
+#[derive(Default)]
+struct Divergences {
+    compared: u64,
+    diverged: u64,
+    recent: VecDeque<Divergence>,
+}
+
+// Second engine the transactions of the served one are also applied to, e.g. built with trial
+// policies or another implementation of `PaymentsEngine`, to compare their outcomes on live
+// traffic without affecting the served state. It should start from the state of the served
+// engine. Its outcomes aren't returned to callers, divergences are logged to `SHADOW_TARGET`
+// and kept for `report`.
+pub struct Shadow {
+    engine: Box<dyn PaymentsEngine>,
+    divergences: Mutex<Divergences>,
+}
+
+impl Shadow {
+    pub fn new(engine: Box<dyn PaymentsEngine>) -> Shadow {
+        Shadow {
+            engine,
+            divergences: Mutex::new(Divergences::default()),
+        }
+    }
+
+    // Apply the transaction the served engine processed with the `primary` outcome.
+    pub fn apply(&self, transaction: Transaction, primary: &Result<(), EngineError>) {
+        let (client, tx) = (transaction.client_id, transaction.transaction_id);
+        let shadow = self.engine.process(transaction);
+        let mut divergences = self.divergences.lock().unwrap_or_else(|e| e.into_inner());
+        divergences.compared += 1;
+        let (primary, shadow) = (outcome(primary), outcome(&shadow));
+        if primary == shadow {
+            return;
+        }
+        warn!(
+            target: SHADOW_TARGET,
+            "Transaction {} of client {} diverged: {} by the primary engine, {} by the shadow",
+            tx,
+            client,
+            primary,
+            shadow
+        );
+        divergences.diverged += 1;
+        if divergences.recent.len() == RECENT_DIVERGENCES {
+            divergences.recent.pop_front();
+        }
+        divergences.recent.push_back(Divergence {
+            client,
+            tx,
+            primary,
+            shadow,
+        });
+    }
+
+    // Divergences so far and the clients whose accounts differ from the `primary` ones.
+    pub fn report<A: Amount>(&self, primary: &[Account<A>]) -> ShadowReport {
+        let accounts = diff_accounts(primary, &self.engine.accounts());
+        let divergences = self.divergences.lock().unwrap_or_else(|e| e.into_inner());
+        ShadowReport {
+            compared: divergences.compared,
+            diverged: divergences.diverged,
+            recent: divergences.recent.iter().cloned().collect(),
+            accounts,
+        }
+    }
+}
+
+fn outcome(result: &Result<(), EngineError>) -> String {
+    match result {
+        Ok(()) => "accepted".to_string(),
+        Err(e) => e.code().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_shadow() {
+        let primary: Engine = Engine::new();
+        let shadow = Shadow::new(Box::new(
+            Engine::<Decimal>::builder()
+                .max_balance(Decimal::from(10))
+                .build(),
+        ));
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,8.0\n\
+                     deposit,2,3,1.0\n";
+        for transaction in csv::Reader::from_reader(input.as_bytes()).deserialize() {
+            let transaction: Transaction = transaction.unwrap();
+            let result = primary.process(transaction.clone());
+            shadow.apply(transaction, &result);
+        }
+
+        let report = shadow.report(&primary.accounts().unwrap());
+        assert_eq!((report.compared, report.diverged), (3, 1));
+        assert_eq!(report.recent.len(), 1);
+        assert_eq!(report.recent[0].tx, 2);
+        assert_eq!(report.recent[0].primary, "accepted");
+        assert_ne!(report.recent[0].shadow, "accepted");
+        assert_eq!(report.accounts, ["1".parse::<ClientId>().unwrap()]);
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::info;
+
+use crate::admin::Ingestion;
+
+// How often the signal stored by the handlers is checked.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Latest signal received since the last check, stored by the handlers, which can't do more than
+// that safely. Signals arriving within one interval leave the state the last one asks for.
+static LATEST: AtomicU8 = AtomicU8::new(NONE);
+const NONE: u8 = 0;
+const PAUSE: u8 = 1;
+const RESUME: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestionSignal {
+    // SIGUSR1
+    Pause,
+    // SIGUSR2
+    Resume,
+}
+
+impl IngestionSignal {
+    fn as_str(self) -> &'static str {
+        match self {
+            IngestionSignal::Pause => "paused",
+            IngestionSignal::Resume => "resumed",
+        }
+    }
+}
+
+// Handle SIGUSR1 and SIGUSR2 of the process, received as `IngestionSignal`s. Install once per
+// process, the signals go to a single receiver.
+pub fn ingestion_signals() -> io::Result<Receiver<IngestionSignal>> {
+    let handlers: [(libc::c_int, extern "C" fn(libc::c_int)); 2] =
+        [(libc::SIGUSR1, on_pause), (libc::SIGUSR2, on_resume)];
+    for (signal, handler) in handlers {
+        // SAFETY: the handlers only store to atomics, which is async-signal-safe
+        if unsafe { libc::signal(signal, handler as libc::sighandler_t) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        thread::sleep(SIGNAL_POLL_INTERVAL);
+        let signal = match LATEST.swap(NONE, Ordering::SeqCst) {
+            PAUSE => IngestionSignal::Pause,
+            RESUME => IngestionSignal::Resume,
+            _ => continue,
+        };
+        if sender.send(signal).is_err() {
+            return;
+        }
+    });
+    Ok(receiver)
+}
+
+// Pause `ingestion` on SIGUSR1 and resume it on SIGUSR2, for maintenance windows without the admin
+// endpoints. A pause drains the work in flight and then calls `on_drained`, e.g. to write a
+// snapshot of the drained state.
+pub fn control_ingestion<F>(ingestion: Arc<Ingestion>, on_drained: F) -> io::Result<()>
+where
+    F: Fn() + Send + 'static,
+{
+    let signals = ingestion_signals()?;
+    thread::spawn(move || {
+        for signal in signals {
+            match signal {
+                IngestionSignal::Pause => ingestion.drain(),
+                IngestionSignal::Resume => ingestion.pause(false),
+            }
+            info!(target: "audit", "Ingestion {} by signal", signal.as_str());
+            if signal == IngestionSignal::Pause {
+                on_drained();
+            }
+        }
+    });
+    Ok(())
+}
+
+extern "C" fn on_pause(_: libc::c_int) {
+    LATEST.store(PAUSE, Ordering::SeqCst);
+}
+
+extern "C" fn on_resume(_: libc::c_int) {
+    LATEST.store(RESUME, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Instant;
+
+    use super::*;
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_control_ingestion() {
+        let ingestion = Arc::new(Ingestion::default());
+        let drained = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&drained);
+        control_ingestion(Arc::clone(&ingestion), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        unsafe { libc::raise(libc::SIGUSR1) };
+        wait_until(|| drained.load(Ordering::SeqCst) == 1);
+        assert!(ingestion.is_paused());
+        unsafe { libc::raise(libc::SIGUSR2) };
+        wait_until(|| !ingestion.is_paused());
+        assert_eq!(drained.load(Ordering::SeqCst), 1);
+
+        // The later of two signals in a row wins
+        unsafe {
+            libc::raise(libc::SIGUSR2);
+            libc::raise(libc::SIGUSR1);
+        }
+        wait_until(|| drained.load(Ordering::SeqCst) == 2);
+        assert!(ingestion.is_paused());
+    }
+}
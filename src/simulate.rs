@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{Account, AmountFormat, ClientId, Engine, Transaction};
+
+// Balances of a client before and after a simulated batch.
+#[derive(Debug, PartialEq)]
+pub struct BalanceChange<A> {
+    pub client_id: ClientId,
+    // None if the batch opened the account
+    pub before: Option<Account<A>>,
+    pub after: Account<A>,
+}
+
+// Apply a hypothetical batch to the engine, e.g. one restored from a snapshot, and return the
+// accounts it changed, in client order. The engine keeps the applied batch, simulate on a copy
+// of the state which isn't written back.
+pub fn simulate<A, S, I, E>(
+    engine: &mut Engine<A, S>,
+    batch: I,
+) -> anyhow::Result<Vec<BalanceChange<A>>>
+where
+    A: Amount,
+    S: AccountStore<A>,
+    I: Iterator<Item = Result<Transaction, E>>,
+    E: Error + Sync + Send + 'static,
+{
+    let before = engine.accounts()?;
+    engine.process_transactions_blocking(batch)?;
+    Ok(balance_changes(before, engine.accounts()?))
+}
+
+// Accounts differing between the two lists, in client order. Accounts never disappear, so only
+// accounts of `after` are compared.
+pub fn balance_changes<A: Amount>(
+    before: Vec<Account<A>>,
+    after: Vec<Account<A>>,
+) -> Vec<BalanceChange<A>> {
+    let mut before: HashMap<ClientId, Account<A>> = before
+        .into_iter()
+        .map(|account| (account.client_id, account))
+        .collect();
+    let mut changes: Vec<BalanceChange<A>> = after
+        .into_iter()
+        .filter_map(|after| {
+            let before = before.remove(&after.client_id);
+            (before.as_ref() != Some(&after)).then_some(BalanceChange {
+                client_id: after.client_id,
+                before,
+                after,
+            })
+        })
+        .collect();
+    changes.sort_by_key(|change| change.client_id);
+    changes
+}
+
+// Write the changes as CSV, balances before and after and the change of the total. Accounts
+// opened by the batch start from zero balances.
+pub fn write_changes<A, W>(
+    writer: W,
+    changes: &[BalanceChange<A>],
+    format: AmountFormat,
+) -> Result<(), csv::Error>
+where
+    A: Amount,
+    W: io::Write,
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "client",
+        "available_before",
+        "available_after",
+        "held_before",
+        "held_after",
+        "total_before",
+        "total_after",
+        "total_change",
+        "locked_before",
+        "locked_after",
+    ])?;
+    for change in changes {
+        let before = change
+            .before
+            .clone()
+            .unwrap_or_else(|| Account::new(change.client_id));
+        let after = &change.after;
+        let total_change =
+            after.calculate_total().to_decimal() - before.calculate_total().to_decimal();
+        writer.write_record([
+            change.client_id.to_string(),
+            format.format(before.available),
+            format.format(after.available),
+            format.format(before.held),
+            format.format(after.held),
+            format.format(before.calculate_total()),
+            format.format(after.calculate_total()),
+            format.format(total_change),
+            before.locked.to_string(),
+            after.locked.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> csv::DeserializeRecordsIntoIter<&[u8], Transaction> {
+        csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+    }
+
+    #[test]
+    fn test_pending_chargebacks() {
+        let mut engine: Engine = Engine::new();
+        engine
+            .process_transactions_blocking(parse(
+                "type,client,tx,amount\n\
+                 deposit,1,1,5.0\n\
+                 deposit,2,2,3.0\n\
+                 dispute,1,1,\n",
+            ))
+            .unwrap();
+
+        let changes = simulate(
+            &mut engine,
+            parse("type,client,tx,amount\nchargeback,1,1,\ndeposit,3,3,1.0\n"),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        write_changes(&mut output, &changes, AmountFormat::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available_before,available_after,held_before,held_after,total_before,total_after,total_change,locked_before,locked_after\n\
+             1,0.0,0.0,5.0,0.0,5.0,0.0,-5.0,false,true\n\
+             3,0.0,1.0,0.0,0.0,0.0,1.0,1.0,false,false\n"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+// SQL over a completed run with DataFusion. A snapshot gives the `accounts` and `transactions`
+// tables, a journal the `journal` table, e.g.
+// `SELECT client, sum(amount) FROM transactions WHERE status = 'chargeback' GROUP BY client`.
+//
+// Amounts are `DECIMAL(38, 4)`, exact as they have at most 4 decimal places. Times are RFC 3339
+// strings, which order like the times.
+
+use std::io;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{ArrayRef, BooleanArray, Decimal128Array, StringArray, UInt64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::datasource::MemTable;
+use datafusion::error::DataFusionError;
+use datafusion::prelude::SessionContext;
+use rust_decimal::Decimal;
+
+use crate::engine::amount::Amount;
+use crate::engine::store::AccountStore;
+use crate::engine::{Book, ClientId, Engine, Timestamp, TransactionId};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SqlError {
+    #[error(transparent)]
+    Engine(#[from] anyhow::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+    #[error(transparent)]
+    DataFusion(#[from] DataFusionError),
+    #[error("Malformed journal record on line {0}")]
+    Malformed(u64),
+}
+
+const AMOUNT_PRECISION: u8 = 38;
+const AMOUNT_SCALE: u32 = 4;
+
+// Session with the tables of the engine, and of the journal if given.
+pub fn session<A: Amount, S: AccountStore<A>>(
+    engine: &Engine<A, S>,
+    journal: Option<&[u8]>,
+) -> Result<SessionContext, SqlError> {
+    let session = SessionContext::new();
+    let mut accounts = engine.accounts()?;
+    accounts.sort_by_key(|account| account.client_id);
+
+    let batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            client_field(),
+            amount_field("available"),
+            amount_field("held"),
+            amount_field("reserve"),
+            amount_field("total"),
+            Field::new("locked", DataType::Boolean, false),
+            Field::new("quarantined", DataType::Boolean, false),
+            Field::new("closed", DataType::Boolean, false),
+            Field::new("dormant", DataType::Boolean, false),
+            Field::new("dunning", DataType::Utf8, false),
+        ])),
+        vec![
+            client_column(accounts.iter().map(|account| &account.client_id)),
+            amount_column(accounts.iter().map(|account| account.available))?,
+            amount_column(accounts.iter().map(|account| account.held))?,
+            amount_column(accounts.iter().map(|account| account.reserve))?,
+            amount_column(accounts.iter().map(|account| account.calculate_total()))?,
+            bool_column(accounts.iter().map(|account| account.locked)),
+            bool_column(accounts.iter().map(|account| account.quarantined)),
+            bool_column(accounts.iter().map(|account| account.closed)),
+            bool_column(accounts.iter().map(|account| account.dormant)),
+            Arc::new(StringArray::from_iter_values(
+                accounts.iter().map(|account| account.dunning.as_str()),
+            )),
+        ],
+    )?;
+    register(&session, "accounts", batch)?;
+
+    let mut clients = Vec::new();
+    let mut transactions = Vec::new();
+    for account in &accounts {
+        let Some(page) = engine.transactions(account.client_id, usize::MAX, None) else {
+            continue;
+        };
+        clients.extend(page.transactions.iter().map(|_| account.client_id));
+        transactions.extend(page.transactions);
+    }
+    let time = |timestamp: Option<Timestamp>| timestamp.map(|t| t.to_string());
+    let batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            client_field(),
+            Field::new("tx", DataType::UInt64, false),
+            amount_field("amount"),
+            Field::new("status", DataType::Utf8, false),
+            Field::new("timestamp", DataType::Utf8, true),
+            Field::new("disputed_at", DataType::Utf8, true),
+        ])),
+        vec![
+            client_column(clients.iter()),
+            tx_column(transactions.iter().map(|t| t.transaction_id)),
+            amount_column(transactions.iter().map(|t| t.amount))?,
+            Arc::new(StringArray::from_iter_values(
+                transactions.iter().map(|t| t.status.as_str()),
+            )),
+            Arc::new(StringArray::from_iter(
+                transactions.iter().map(|t| time(t.timestamp())),
+            )),
+            Arc::new(StringArray::from_iter(
+                transactions.iter().map(|t| time(t.disputed_at)),
+            )),
+        ],
+    )?;
+    register(&session, "transactions", batch)?;
+
+    if let Some(journal) = journal {
+        register(&session, "journal", journal_batch(journal)?)?;
+    }
+    Ok(session)
+}
+
+// Postings of a journal written with `EngineBuilder::journal`.
+fn journal_batch(journal: &[u8]) -> Result<RecordBatch, SqlError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(journal);
+    let (mut clients, mut txs, mut debits, mut credits, mut amounts) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (line, record) in (2..).zip(reader.records()) {
+        let record = record?;
+        let parse = || {
+            let client: ClientId = record.get(0)?.parse().ok()?;
+            let tx = record.get(1)?.parse().ok()?;
+            let debit: Book = record.get(2)?.parse().ok()?;
+            let credit: Book = record.get(3)?.parse().ok()?;
+            let amount: Decimal = record.get(4)?.parse().ok()?;
+            Some((client, tx, debit, credit, amount))
+        };
+        let (client, tx, debit, credit, amount) = parse().ok_or(SqlError::Malformed(line))?;
+        clients.push(client);
+        txs.push(tx);
+        debits.push(debit.as_str());
+        credits.push(credit.as_str());
+        amounts.push(amount);
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            client_field(),
+            Field::new("tx", DataType::UInt64, false),
+            Field::new("debit", DataType::Utf8, false),
+            Field::new("credit", DataType::Utf8, false),
+            amount_field("amount"),
+        ])),
+        vec![
+            client_column(clients.iter()),
+            tx_column(txs),
+            Arc::new(StringArray::from_iter_values(debits)),
+            Arc::new(StringArray::from_iter_values(credits)),
+            amount_column(amounts)?,
+        ],
+    )?)
+}
+
+// Run the statement, writing its result as CSV with a header.
+pub async fn execute<W: io::Write>(
+    session: &SessionContext,
+    statement: &str,
+    writer: W,
+) -> Result<(), SqlError> {
+    let frame = session.sql(statement).await?;
+    let schema = frame.schema().as_arrow().clone();
+    let batches = frame.collect().await?;
+
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(schema.fields().iter().map(|field| field.name()))?;
+    let options = FormatOptions::default();
+    for batch in batches {
+        let formatters = batch
+            .columns()
+            .iter()
+            .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+            .collect::<Result<Vec<_>, _>>()?;
+        for row in 0..batch.num_rows() {
+            writer.write_record(
+                formatters
+                    .iter()
+                    .map(|formatter| formatter.value(row).to_string()),
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn register(session: &SessionContext, name: &str, batch: RecordBatch) -> Result<(), SqlError> {
+    let table = MemTable::try_new(batch.schema(), vec![vec![batch]])?;
+    session.register_table(name, Arc::new(table))?;
+    Ok(())
+}
+
+fn amount_field(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::Decimal128(AMOUNT_PRECISION, AMOUNT_SCALE as i8),
+        false,
+    )
+}
+
+fn amount_column<A: Amount>(amounts: impl IntoIterator<Item = A>) -> Result<ArrayRef, SqlError> {
+    let values = amounts.into_iter().map(|amount| {
+        let mut amount = amount.to_decimal();
+        amount.rescale(AMOUNT_SCALE);
+        amount.mantissa()
+    });
+    Ok(Arc::new(
+        Decimal128Array::from_iter_values(values)
+            .with_precision_and_scale(AMOUNT_PRECISION, AMOUNT_SCALE as i8)?,
+    ))
+}
+
+fn bool_column(values: impl Iterator<Item = bool>) -> ArrayRef {
+    Arc::new(values.map(Some).collect::<BooleanArray>())
+}
+
+// TransactionId is already u64 with the `tx-id-u64` feature
+#[allow(clippy::useless_conversion)]
+fn tx_column(txs: impl IntoIterator<Item = TransactionId>) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter_values(
+        txs.into_iter().map(u64::from),
+    ))
+}
+
+// Numeric client ids are integers, alphanumeric ones strings.
+#[cfg(not(feature = "client-id-string"))]
+fn client_field() -> Field {
+    Field::new("client", DataType::UInt64, false)
+}
+
+#[cfg(not(feature = "client-id-string"))]
+#[allow(clippy::useless_conversion)]
+fn client_column<'a>(clients: impl Iterator<Item = &'a ClientId>) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter_values(
+        clients.map(|client| u64::from(*client)),
+    ))
+}
+
+#[cfg(feature = "client-id-string")]
+fn client_field() -> Field {
+    Field::new("client", DataType::Utf8, false)
+}
+
+#[cfg(feature = "client-id-string")]
+fn client_column<'a>(clients: impl Iterator<Item = &'a ClientId>) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(
+        clients.map(|client| client.as_str()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sql() {
+        let mut engine: Engine = Engine::new();
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1.5\n\
+            deposit,1,2,2.25\n\
+            deposit,2,3,10\n\
+            dispute,2,3,\n\
+            chargeback,2,3,\n";
+        engine
+            .process_transactions(
+                csv::ReaderBuilder::new()
+                    .flexible(true)
+                    .from_reader(input.as_bytes())
+                    .into_deserialize(),
+            )
+            .await
+            .unwrap();
+        let journal = "client,tx,debit,credit,amount\n\
+            1,1,settlement,client_available,1.5\n\
+            1,2,settlement,client_available,2.25\n";
+        let session = session(&engine, Some(journal.as_bytes())).unwrap();
+
+        let run = |statement: &str| {
+            let session = session.clone();
+            let statement = statement.to_string();
+            async move {
+                let mut output = Vec::new();
+                execute(&session, &statement, &mut output).await.unwrap();
+                String::from_utf8(output).unwrap()
+            }
+        };
+        assert_eq!(
+            run("SELECT client, sum(amount) AS deposited FROM transactions GROUP BY client ORDER BY client").await,
+            "client,deposited\n1,3.7500\n2,10.0000\n"
+        );
+        assert_eq!(
+            run("SELECT client, total, locked FROM accounts WHERE locked").await,
+            "client,total,locked\n2,0.0000,true\n"
+        );
+        assert_eq!(
+            run("SELECT count(*) AS postings, sum(amount) AS amount FROM journal").await,
+            "postings,amount\n2,3.7500\n"
+        );
+        assert_eq!(
+            run("SELECT tx FROM transactions WHERE tx > 10").await,
+            "tx\n"
+        );
+        assert!(session.sql("SELECT * FROM missing").await.is_err());
+    }
+}
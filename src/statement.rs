@@ -0,0 +1,375 @@
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use rust_decimal::Decimal;
+
+use crate::engine::{ClientId, Engine, Note, Timestamp, Transaction, TransactionType};
+
+// Calendar month, as `2024-03`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub year: u16,
+    pub month: u8,
+}
+
+impl Period {
+    fn start(self) -> SystemTime {
+        month_start(self.year, self.month)
+    }
+
+    fn end(self) -> SystemTime {
+        if self.month == 12 {
+            month_start(self.year + 1, 1)
+        } else {
+            month_start(self.year, self.month + 1)
+        }
+    }
+}
+
+fn month_start(year: u16, month: u8) -> SystemTime {
+    let Ok(Timestamp(time)) = format!("{:04}-{:02}-01", year, month).parse() else {
+        unreachable!("year and month are validated when parsing the period")
+    };
+    time
+}
+
+impl FromStr for Period {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Period, ()> {
+        let (year, month) = s.split_once('-').ok_or(())?;
+        let period = Period {
+            year: year.parse().map_err(drop)?,
+            month: month.parse().map_err(drop)?,
+        };
+        if year.len() != 4 || !(1..=12).contains(&period.month) {
+            return Err(());
+        }
+        Ok(period)
+    }
+}
+
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementFormat {
+    Markdown,
+    Html,
+}
+
+impl FromStr for StatementFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<StatementFormat, ()> {
+        match s {
+            "markdown" => Ok(StatementFormat::Markdown),
+            "html" => Ok(StatementFormat::Html),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Balance {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+// Accepted transaction with the client's balance after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    pub timestamp: String,
+    pub transaction: Transaction,
+    pub balance: Balance,
+}
+
+// Note on the account from a `note` row of the history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementNote {
+    pub timestamp: String,
+    pub note: Note,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub client_id: ClientId,
+    pub period: Period,
+    pub opening: Balance,
+    pub lines: Vec<StatementLine>,
+    pub closing: Balance,
+    pub notes: Vec<StatementNote>,
+}
+
+impl Statement {
+    // Statement of the client for the period from the transaction history, e.g. the engine
+    // input. The history is replayed with the default engine rules, so rejected transactions
+    // aren't listed. Notes of the period are listed after the transactions.
+    //
+    // The history is expected in chronological order. A transaction without a timestamp is taken
+    // to happen at the time of the transaction before it.
+    pub fn build<I>(client_id: ClientId, period: Period, history: I) -> Statement
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let (start, end) = (period.start(), period.end());
+        let engine: Engine = Engine::new();
+        let balance = || {
+            engine
+                .account(client_id)
+                .map_or_else(Balance::default, |account| Balance {
+                    available: account.available,
+                    held: account.held,
+                })
+        };
+
+        let mut opening = Balance::default();
+        let mut lines = Vec::new();
+        let mut notes = Vec::new();
+        let mut time = None;
+        for transaction in history {
+            if transaction.client_id != client_id {
+                continue;
+            }
+            if let Some(Timestamp(timestamp)) = transaction.timestamp() {
+                time = Some(timestamp);
+            }
+            match time {
+                Some(time) if time >= end => break,
+                Some(time) if time >= start && transaction.r#type == TransactionType::Note => {
+                    if let Some(note) = Note::from_transaction(&transaction) {
+                        notes.push(StatementNote {
+                            timestamp: humantime::format_rfc3339_seconds(time).to_string(),
+                            note,
+                        });
+                    }
+                }
+                Some(time) if time >= start => {
+                    if engine.process(transaction.clone()).is_ok() {
+                        lines.push(StatementLine {
+                            timestamp: humantime::format_rfc3339_seconds(time).to_string(),
+                            transaction,
+                            balance: balance(),
+                        });
+                    }
+                }
+                _ => {
+                    let _ = engine.process(transaction);
+                    opening = balance();
+                }
+            }
+        }
+
+        Statement {
+            client_id,
+            period,
+            opening,
+            closing: lines.last().map_or(opening, |line| line.balance),
+            lines,
+            notes,
+        }
+    }
+
+    pub fn render(&self, format: StatementFormat) -> String {
+        match format {
+            StatementFormat::Markdown => self.render_markdown(),
+            StatementFormat::Html => self.render_html(),
+        }
+    }
+
+    fn render_markdown(&self) -> String {
+        let mut output = String::new();
+        let _ = writeln!(
+            output,
+            "# Statement of client {} for {}\n",
+            self.client_id, self.period
+        );
+        let _ = writeln!(output, "Opening balance: {}\n", describe(self.opening));
+        let _ = writeln!(
+            output,
+            "| Time | Type | Transaction | Amount | Available | Held |"
+        );
+        let _ = writeln!(output, "|---|---|---|---:|---:|---:|");
+        for line in &self.lines {
+            let [time, r#type, tx, amount, available, held] = line.cells();
+            let _ = writeln!(
+                output,
+                "| {} | {} | {} | {} | {} | {} |",
+                time, r#type, tx, amount, available, held
+            );
+        }
+        let _ = writeln!(output, "\nClosing balance: {}", describe(self.closing));
+        if !self.notes.is_empty() {
+            let _ = writeln!(output, "\n## Notes\n");
+            for note in &self.notes {
+                let _ = writeln!(output, "- {}", note.describe());
+            }
+        }
+        output
+    }
+
+    fn render_html(&self) -> String {
+        let mut output = String::new();
+        let _ = writeln!(
+            output,
+            "<h1>Statement of client {} for {}</h1>",
+            self.client_id, self.period
+        );
+        let _ = writeln!(output, "<p>Opening balance: {}</p>", describe(self.opening));
+        let _ = writeln!(output, "<table>");
+        let _ = writeln!(
+            output,
+            "<tr><th>Time</th><th>Type</th><th>Transaction</th><th>Amount</th><th>Available</th><th>Held</th></tr>"
+        );
+        for line in &self.lines {
+            let cells: String = line
+                .cells()
+                .iter()
+                .map(|cell| format!("<td>{}</td>", cell))
+                .collect();
+            let _ = writeln!(output, "<tr>{}</tr>", cells);
+        }
+        let _ = writeln!(output, "</table>");
+        let _ = writeln!(output, "<p>Closing balance: {}</p>", describe(self.closing));
+        if !self.notes.is_empty() {
+            let _ = writeln!(output, "<h2>Notes</h2>");
+            let _ = writeln!(output, "<ul>");
+            for note in &self.notes {
+                let _ = writeln!(output, "<li>{}</li>", escape_html(&note.describe()));
+            }
+            let _ = writeln!(output, "</ul>");
+        }
+        output
+    }
+}
+
+impl StatementLine {
+    fn cells(&self) -> [String; 6] {
+        let transaction = &self.transaction;
+        [
+            self.timestamp.clone(),
+            transaction.r#type.name().to_string(),
+            transaction.transaction_id.to_string(),
+            transaction
+                .amount
+                .map_or_else(String::new, |amount| amount.normalize().to_string()),
+            self.balance.available.normalize().to_string(),
+            self.balance.held.normalize().to_string(),
+        ]
+    }
+}
+
+impl StatementNote {
+    // `<time> [case <case>] [tx <tx>]: <text>`
+    fn describe(&self) -> String {
+        let mut line = self.timestamp.clone();
+        if let Some(case) = &self.note.case {
+            line.push_str(&format!(" case {}", case));
+        }
+        if let Some(tx) = self.note.tx {
+            line.push_str(&format!(" tx {}", tx));
+        }
+        format!("{}: {}", line, self.note.text)
+    }
+}
+
+// Notes are free text, unlike the other cells.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn describe(balance: Balance) -> String {
+    format!(
+        "{} available, {} held",
+        balance.available.normalize(),
+        balance.held.normalize()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ByteRecordParser;
+
+    fn history(input: &str) -> Vec<Transaction> {
+        ByteRecordParser::new(csv::Reader::from_reader(input.as_bytes()))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_period() {
+        assert_eq!(
+            "2024-03".parse(),
+            Ok(Period {
+                year: 2024,
+                month: 3
+            })
+        );
+        assert_eq!("2024-13".parse::<Period>(), Err(()));
+        assert_eq!("24-03".parse::<Period>(), Err(()));
+        assert_eq!(
+            "2024-12".parse::<Period>().unwrap().end(),
+            month_start(2025, 1)
+        );
+    }
+
+    #[test]
+    fn test_statement() {
+        let history = history(
+            "type,client,tx,amount,timestamp,note,case\n\
+             deposit,1,1,10.0,2024-02-28,,\n\
+             deposit,2,2,5.0,2024-03-01,,\n\
+             deposit,1,3,2.5,2024-03-02,,\n\
+             withdrawal,1,4,100.0,2024-03-03,,\n\
+             dispute,1,1,,,,\n\
+             note,1,1,,2024-03-04,card <stolen>,C-42\n\
+             deposit,1,5,1.0,2024-04-01,,\n",
+        );
+
+        let statement = Statement::build("1".parse().unwrap(), "2024-03".parse().unwrap(), history);
+
+        assert_eq!(
+            statement.opening,
+            Balance {
+                available: Decimal::from(10),
+                held: Decimal::ZERO,
+            }
+        );
+        // The rejected withdrawal isn't listed, the dispute happens at the time before it
+        let lines: Vec<_> = statement
+            .lines
+            .iter()
+            .map(|line| (line.timestamp.as_str(), line.transaction.transaction_id))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("2024-03-02T00:00:00Z", 3u16.into()),
+                ("2024-03-03T00:00:00Z", 1u16.into()),
+            ]
+        );
+        assert_eq!(
+            statement.closing,
+            Balance {
+                available: Decimal::new(25, 1),
+                held: Decimal::from(10),
+            }
+        );
+
+        let markdown = statement.render(StatementFormat::Markdown);
+        assert!(markdown.contains("| 2024-03-02T00:00:00Z | deposit | 3 | 2.5 | 12.5 | 0 |"));
+        assert!(markdown.contains("Closing balance: 2.5 available, 10 held"));
+        assert!(markdown.contains("- 2024-03-04T00:00:00Z case C-42 tx 1: card <stolen>"));
+        let html = statement.render(StatementFormat::Html);
+        assert!(html.contains("<tr><td>2024-03-03T00:00:00Z</td><td>dispute</td>"));
+        assert!(html.contains("<li>2024-03-04T00:00:00Z case C-42 tx 1: card &lt;stolen&gt;</li>"));
+    }
+}
@@ -0,0 +1,220 @@
+// Files which may also be objects in object storage, given as URIs like `s3://bucket/key`.
+//
+// Objects need the `object-store` feature. They're streamed: reads come in as they're consumed
+// and writes are uploaded in parts, nothing is staged on local disk. Credentials and regions come
+// from the environment as with the cloud CLIs, e.g. `AWS_ACCESS_KEY_ID` and `AWS_REGION`.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Schemes of object storage URIs, never taken for relative paths.
+pub const OBJECT_STORE_SCHEMES: [&str; 3] = ["s3", "gs", "az"];
+
+// Whether the path is an object storage URI.
+pub fn is_object_uri(path: &Path) -> bool {
+    path.to_str()
+        .and_then(|path| path.split_once("://"))
+        .is_some_and(|(scheme, _)| OBJECT_STORE_SCHEMES.contains(&scheme))
+}
+
+// Open a file or object for reading.
+pub fn open(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    if is_object_uri(path) {
+        return object::open(path.to_str().unwrap_or_default());
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+// Create a file or object for writing. An object is complete only once `Output::finish`
+// returns, one that isn't finished is never written, so a failed run doesn't leave a truncated
+// object behind.
+pub fn create(path: &Path) -> io::Result<Output> {
+    if is_object_uri(path) {
+        return object::create(path.to_str().unwrap_or_default());
+    }
+    Ok(Output::File(File::create(path)?))
+}
+
+pub enum Output {
+    File(File),
+    #[cfg(feature = "object-store")]
+    Object(Box<object::Upload>),
+}
+
+impl Output {
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::File(mut file) => file.flush(),
+            #[cfg(feature = "object-store")]
+            Output::Object(mut upload) => upload.shutdown(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(file) => file.write(buf),
+            #[cfg(feature = "object-store")]
+            Output::Object(upload) => upload.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(file) => file.flush(),
+            #[cfg(feature = "object-store")]
+            Output::Object(upload) => upload.flush(),
+        }
+    }
+}
+
+#[cfg(feature = "object-store")]
+mod object {
+    use std::io::{self, Read};
+    use std::sync::{Arc, OnceLock};
+
+    use object_store::buffered::BufWriter;
+    use object_store::{ObjectStore, ObjectStoreExt};
+    use tokio::runtime::Runtime;
+    use tokio_util::io::{StreamReader, SyncIoBridge};
+
+    use super::Output;
+
+    pub type Upload = SyncIoBridge<BufWriter>;
+
+    // Requests run on a runtime of their own, blocking the calling thread.
+    fn runtime() -> io::Result<&'static Runtime> {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        if let Some(runtime) = RUNTIME.get() {
+            return Ok(runtime);
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("object-store")
+            .enable_all()
+            .build()?;
+        Ok(RUNTIME.get_or_init(|| runtime))
+    }
+
+    fn store(uri: &str) -> io::Result<(Arc<dyn ObjectStore>, object_store::path::Path)> {
+        let url = url::Url::parse(uri)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", uri, e)))?;
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, path) = object_store::parse_url_opts(&url, options)?;
+        Ok((Arc::from(store), path))
+    }
+
+    pub fn open(uri: &str) -> io::Result<Box<dyn Read + Send>> {
+        let (store, path) = store(uri)?;
+        read(store, path)
+    }
+
+    pub fn create(uri: &str) -> io::Result<Output> {
+        let (store, path) = store(uri)?;
+        Ok(Output::Object(Box::new(upload(store, path)?)))
+    }
+
+    fn read(
+        store: Arc<dyn ObjectStore>,
+        path: object_store::path::Path,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        let runtime = runtime()?;
+        let object = runtime.block_on(store.get(&path))?;
+        Ok(Box::new(SyncIoBridge::new_with_handle(
+            StreamReader::new(object.into_stream()),
+            runtime.handle().clone(),
+        )))
+    }
+
+    fn upload(store: Arc<dyn ObjectStore>, path: object_store::path::Path) -> io::Result<Upload> {
+        Ok(SyncIoBridge::new_with_handle(
+            BufWriter::new(store, path),
+            runtime()?.handle().clone(),
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::Write;
+
+        use object_store::memory::InMemory;
+
+        use super::*;
+
+        #[test]
+        fn test_objects() {
+            let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+            let path = object_store::path::Path::from("runs/report.csv");
+            let content = "client,available\n1,1.0\n".repeat(100_000);
+
+            let mut output = upload(store.clone(), path.clone()).unwrap();
+            output.write_all(content.as_bytes()).unwrap();
+            // Not there before it's finished
+            assert!(read(store.clone(), path.clone()).is_err());
+            output.shutdown().unwrap();
+
+            let mut read_back = String::new();
+            read(store, path)
+                .unwrap()
+                .read_to_string(&mut read_back)
+                .unwrap();
+            assert_eq!(read_back, content);
+        }
+    }
+}
+
+#[cfg(not(feature = "object-store"))]
+mod object {
+    use std::io::{self, Read};
+
+    use super::Output;
+
+    fn unsupported(uri: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Object storage URIs need a build with the object-store feature: {}",
+                uri
+            ),
+        )
+    }
+
+    pub fn open(uri: &str) -> io::Result<Box<dyn Read + Send>> {
+        Err(unsupported(uri))
+    }
+
+    pub fn create(uri: &str) -> io::Result<Output> {
+        Err(unsupported(uri))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files() {
+        let path = std::env::temp_dir().join(format!("storage-{}.csv", std::process::id()));
+        let mut output = create(&path).unwrap();
+        output.write_all(b"client,available\n1,1.0\n").unwrap();
+        output.finish().unwrap();
+
+        let mut content = String::new();
+        open(&path).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "client,available\n1,1.0\n");
+        std::fs::remove_file(path).unwrap();
+
+        assert!(is_object_uri(Path::new("s3://bucket/input.csv")));
+        assert!(!is_object_uri(Path::new("bucket/input.csv")));
+        #[cfg(not(feature = "object-store"))]
+        assert_eq!(
+            open(Path::new("s3://bucket/input.csv"))
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::panic;
+use std::thread;
+
+use rust_decimal::Decimal;
+
+use crate::engine::{
+    diff_accounts, ClientId, Engine, ErrorCode, PaymentsEngine, ReferenceEngine, Transaction,
+    TransactionId, TransactionType,
+};
+
+// Few clients and transaction ids, so that generated records keep running into each other.
+const CLIENTS: u64 = 4;
+const TRANSACTION_IDS: u64 = 24;
+
+// Where the engine and the reference engine disagreed on a generated input.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub seed: u64,
+    // First transaction the two engines accepted or rejected differently, by input index
+    pub transaction: Option<usize>,
+    // Clients whose final accounts differ
+    pub clients: Vec<ClientId>,
+    pub transactions: Vec<Transaction>,
+}
+
+// Adversarial dispute sequences from a fixed seed: disputes, resolves and chargebacks in any
+// order around the deposits they reference, redeliveries of earlier records and references to
+// transactions of other clients. The same seed always generates the same input.
+pub fn generate(seed: u64, length: usize) -> Vec<Transaction> {
+    let mut rng = XorShift::new(seed);
+    let mut transactions: Vec<Transaction> = Vec::with_capacity(length);
+    while transactions.len() < length {
+        let client_id = client(rng.below(CLIENTS));
+        let transaction_id = TransactionId::from(rng.below(TRANSACTION_IDS) as u32);
+        let transaction = match rng.below(10) {
+            0..=2 => record(
+                TransactionType::Deposit,
+                client_id,
+                transaction_id,
+                Some(Decimal::new(rng.below(1000) as i64 + 1, 2)),
+            ),
+            3 => record(
+                TransactionType::Withdraw,
+                client_id,
+                transaction_id,
+                Some(Decimal::new(rng.below(500) as i64 + 1, 2)),
+            ),
+            4 | 5 => record(TransactionType::Dispute, client_id, transaction_id, None),
+            6 => record(TransactionType::Resolve, client_id, transaction_id, None),
+            7 => record(TransactionType::Chargeback, client_id, transaction_id, None),
+            // Redelivery of an earlier record
+            8 if !transactions.is_empty() => {
+                transactions[rng.below(transactions.len() as u64) as usize].clone()
+            }
+            // A dispute step on another client's transaction
+            _ => {
+                let r#type = [
+                    TransactionType::Dispute,
+                    TransactionType::Resolve,
+                    TransactionType::Chargeback,
+                ][rng.below(3) as usize];
+                let other = transactions
+                    .iter()
+                    .rev()
+                    .find(|other| other.client_id != client_id)
+                    .map_or(transaction_id, |other| other.transaction_id);
+                record(r#type, client_id, other, None)
+            }
+        };
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+// Run the input of `seed` through the reference engine sequentially and through `Engine` on
+// `workers` threads, with the clients partitioned across the threads. None if both agree on
+// every transaction and on the final accounts.
+pub fn check(seed: u64, length: usize, workers: usize) -> Option<Divergence> {
+    let transactions = generate(seed, length);
+
+    let reference = ReferenceEngine::new();
+    let expected: Vec<Option<ErrorCode>> = transactions
+        .iter()
+        .map(|transaction| reference.process(transaction.clone()).err())
+        .map(|error| error.map(|e| e.code()))
+        .collect();
+
+    let workers = workers.max(1);
+    let mut partitions: Vec<Vec<usize>> = vec![Vec::new(); workers];
+    for (index, transaction) in transactions.iter().enumerate() {
+        let mut hasher = DefaultHasher::new();
+        transaction.client_id.hash(&mut hasher);
+        partitions[hasher.finish() as usize % workers].push(index);
+    }
+    let engine: Engine = Engine::new();
+    let mut actual = vec![None; transactions.len()];
+    thread::scope(|scope| {
+        let handles: Vec<_> = partitions
+            .iter()
+            .map(|partition| {
+                let (engine, transactions) = (&engine, &transactions);
+                scope.spawn(move || {
+                    partition
+                        .iter()
+                        .map(|&index| {
+                            let result = engine.process(transactions[index].clone());
+                            (index, result.err().map(|e| e.code()))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for handle in handles {
+            for (index, code) in handle.join().unwrap_or_else(|e| panic::resume_unwind(e)) {
+                actual[index] = code;
+            }
+        }
+    });
+
+    let transaction = (0..transactions.len()).find(|&index| actual[index] != expected[index]);
+    let clients = diff_accounts(&PaymentsEngine::accounts(&engine), &reference.accounts());
+    (transaction.is_some() || !clients.is_empty()).then_some(Divergence {
+        seed,
+        transaction,
+        clients,
+        transactions,
+    })
+}
+
+// Write generated transactions as an input file, e.g. to reproduce a divergence.
+pub fn write_transactions<W: io::Write>(
+    writer: W,
+    transactions: &[Transaction],
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["type", "client", "tx", "amount"])?;
+    for transaction in transactions {
+        writer.write_record([
+            transaction.r#type.name().to_string(),
+            transaction.client_id.to_string(),
+            transaction.transaction_id.to_string(),
+            transaction
+                .amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn client(index: u64) -> ClientId {
+    match (index + 1).to_string().parse() {
+        Ok(client_id) => client_id,
+        Err(_) => unreachable!("small client ids parse with every id type"),
+    }
+}
+
+fn record(
+    r#type: TransactionType,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Option<Decimal>,
+) -> Transaction {
+    Transaction {
+        r#type,
+        client_id,
+        transaction_id,
+        amount,
+        metadata: Default::default(),
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    // Spread the seed over all bits, xorshift state must not be zero
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_input() {
+        assert_eq!(generate(7, 200), generate(7, 200));
+        assert_ne!(generate(7, 200), generate(8, 200));
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        for seed in 0..16 {
+            assert_eq!(check(seed, 500, 3), None, "seed {}", seed);
+        }
+    }
+}
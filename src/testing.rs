@@ -0,0 +1,172 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::engine::amount::Amount;
+use crate::engine::{EngineError, PaymentsEngine, Transaction};
+
+// Interleaving of a scheduled run, input indexes of the transactions in execution order.
+#[derive(Debug, PartialEq)]
+pub struct Schedule {
+    pub order: Vec<usize>,
+    pub results: Vec<Result<(), EngineError>>,
+}
+
+// Deterministic concurrency test driver.
+//
+// Transactions are partitioned by client across worker threads, like in a client-partitioned
+// pipeline, but workers only run one transaction at a time and the next worker is picked from
+// the seed. The same seed always produces the same interleaving, so a failing run can be
+// replayed from the seed alone while still executing on different threads.
+pub struct SeededScheduler {
+    seed: u64,
+    workers: usize,
+}
+
+impl SeededScheduler {
+    pub fn new(seed: u64, workers: usize) -> SeededScheduler {
+        SeededScheduler {
+            seed,
+            workers: workers.max(1),
+        }
+    }
+
+    pub fn run<A, E>(&self, engine: &E, transactions: Vec<Transaction>) -> Schedule
+    where
+        A: Amount,
+        E: PaymentsEngine<A>,
+    {
+        let mut queues: Vec<Vec<(usize, Transaction)>> = vec![Vec::new(); self.workers];
+        for (index, transaction) in transactions.into_iter().enumerate() {
+            let mut hasher = DefaultHasher::new();
+            transaction.client_id.hash(&mut hasher);
+            queues[hasher.finish() as usize % self.workers].push((index, transaction));
+        }
+        let mut remaining: Vec<usize> = queues.iter().map(Vec::len).collect();
+
+        let mut results: Vec<Option<Result<(), EngineError>>> =
+            (0..remaining.iter().sum()).map(|_| None).collect();
+        let mut order = Vec::with_capacity(results.len());
+        let mut rng = XorShift::new(self.seed);
+
+        thread::scope(|scope| {
+            let (done_sender, done) = mpsc::channel();
+            let turns: Vec<mpsc::Sender<()>> = queues
+                .into_iter()
+                .map(|queue| {
+                    let (turn, turn_receiver) = mpsc::channel();
+                    let done_sender = done_sender.clone();
+                    scope.spawn(move || {
+                        for (index, transaction) in queue {
+                            if turn_receiver.recv().is_err() {
+                                return;
+                            }
+                            let _ = done_sender.send((index, engine.process(transaction)));
+                        }
+                    });
+                    turn
+                })
+                .collect();
+
+            loop {
+                let ready: Vec<usize> = (0..remaining.len())
+                    .filter(|&worker| remaining[worker] > 0)
+                    .collect();
+                if ready.is_empty() {
+                    break;
+                }
+                let worker = ready[rng.next() as usize % ready.len()];
+                remaining[worker] -= 1;
+
+                turns[worker]
+                    .send(())
+                    .expect("worker is waiting for its turn");
+                let (index, result) = done.recv().expect("worker reports its result");
+                order.push(index);
+                results[index] = Some(result);
+            }
+        });
+
+        Schedule {
+            order,
+            results: results.into_iter().flatten().collect(),
+        }
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    // Spread the seed over all bits, xorshift state must not be zero
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::{diff_accounts, ClientId, ReferenceEngine, TransactionType};
+    use crate::Engine;
+
+    fn transactions() -> Vec<Transaction> {
+        let transaction =
+            |r#type, client_id: u16, transaction_id: u16, amount: Option<i64>| Transaction {
+                r#type,
+                client_id: client_id.to_string().parse().unwrap(),
+                transaction_id: transaction_id.into(),
+                amount: amount.map(Decimal::from),
+                metadata: Default::default(),
+            };
+        (0..200)
+            .flat_map(|i| {
+                let client_id = i % 7;
+                [
+                    transaction(TransactionType::Deposit, client_id, i * 3, Some(10)),
+                    transaction(TransactionType::Withdraw, client_id, i * 3 + 1, Some(3)),
+                    transaction(TransactionType::Dispute, client_id, i * 3, None),
+                    transaction(TransactionType::Resolve, client_id, i * 3, None),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_schedule() {
+        let run =
+            |seed| SeededScheduler::new(seed, 4).run(&Engine::<Decimal>::new(), transactions());
+
+        assert_eq!(run(42).order, run(42).order);
+        assert_ne!(run(42).order, run(43).order);
+    }
+
+    #[test]
+    fn test_any_interleaving_matches_reference() {
+        let reference = ReferenceEngine::new();
+        transactions()
+            .into_iter()
+            .for_each(|transaction| drop(reference.process(transaction)));
+
+        for seed in 0..8 {
+            let engine: Engine = Engine::new();
+            SeededScheduler::new(seed, 3).run(&engine, transactions());
+
+            assert_eq!(
+                diff_accounts(&engine.accounts().unwrap(), &reference.accounts()),
+                [] as [ClientId; 0],
+                "seed {}",
+                seed
+            );
+        }
+    }
+}
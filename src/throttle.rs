@@ -0,0 +1,102 @@
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Token bucket admitting up to `per_second` transactions a second on average, with bursts of up
+// to a second's worth after idle time. Takers beyond the rate wait, so a backfill doesn't take
+// the CPU from other workloads of the host.
+pub struct TokenBucket {
+    rate: f64,
+    // Tokens left, negative when taken ahead of the refill
+    tokens: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(per_second: NonZeroU32) -> TokenBucket {
+        let rate = f64::from(per_second.get());
+        TokenBucket {
+            rate,
+            tokens: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    // Take a token, waiting until it's due. Callers on other threads wait their turn.
+    pub fn acquire(&self) {
+        let wait = self.take(Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    // Take a token at `now`, returning how long until it's due.
+    fn take(&self, now: Instant) -> Duration {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        let (available, refilled_at) = &mut *tokens;
+        let elapsed = now.saturating_duration_since(*refilled_at).as_secs_f64();
+        *available = (*available + elapsed * self.rate).min(self.rate) - 1.0;
+        *refilled_at = now.max(*refilled_at);
+        if *available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*available / self.rate)
+        }
+    }
+}
+
+// Iterator passing the items of another one at most at the rate of the bucket, see `--max-tps`.
+pub struct Throttle<I> {
+    inner: I,
+    bucket: Option<TokenBucket>,
+}
+
+impl<I> Throttle<I> {
+    // Without a rate items pass as they come.
+    pub fn new(inner: I, per_second: Option<NonZeroU32>) -> Throttle<I> {
+        Throttle {
+            inner,
+            bucket: per_second.map(TokenBucket::new),
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for Throttle<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let item = self.inner.next()?;
+        if let Some(bucket) = &self.bucket {
+            bucket.acquire();
+        }
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(NonZeroU32::new(10).unwrap());
+        let start = *bucket.tokens.lock().unwrap();
+        let at = |millis: u64| start.1 + Duration::from_millis(millis);
+
+        // A second's worth passes right away, then one every 100ms
+        for _ in 0..10 {
+            assert_eq!(bucket.take(at(0)), Duration::ZERO);
+        }
+        assert_eq!(bucket.take(at(0)), Duration::from_millis(100));
+        assert_eq!(bucket.take(at(0)), Duration::from_millis(200));
+        assert_eq!(bucket.take(at(250)), Duration::from_millis(50));
+        // Idle time refills up to the burst only
+        assert_eq!(bucket.take(at(10_000)), Duration::ZERO);
+        for _ in 0..9 {
+            assert_eq!(bucket.take(at(10_000)), Duration::ZERO);
+        }
+        assert_eq!(bucket.take(at(10_000)), Duration::from_millis(100));
+
+        let items: Vec<_> = Throttle::new(0..3, None).collect();
+        assert_eq!(items, [0, 1, 2]);
+    }
+}
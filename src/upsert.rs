@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UpsertError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Columns of the report ({report}) differ from the file's ({existing})")]
+    ColumnsDiffer { existing: String, report: String },
+    #[error("No client column")]
+    MissingClientColumn,
+}
+
+// Rows of the file after an upsert.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UpsertStats {
+    // Rows of clients in the report, replaced in place
+    pub replaced: usize,
+    // Rows of clients new to the file, appended
+    pub added: usize,
+    // Rows of clients not in the report, left as they were
+    pub kept: usize,
+}
+
+// Merge a CSV account report into the accounts file at `path`, replacing the rows of the clients
+// in the report and appending the ones of new clients, so repeated runs over new input maintain
+// one file with a row per client. Rows are keyed by the `client` column, and the `ledger`
+// column if there is one. The file is created if it doesn't exist and replaced atomically,
+// written next to it and moved in place. An empty report leaves the file as it is.
+pub fn upsert_report(path: &Path, report: &[u8]) -> Result<UpsertStats, UpsertError> {
+    let mut reader = csv::Reader::from_reader(report);
+    let header = reader.headers()?.clone();
+    let rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+    if rows.is_empty() {
+        return Ok(UpsertStats::default());
+    }
+    let key_columns: Vec<usize> = ["ledger", "client"]
+        .iter()
+        .filter_map(|name| header.iter().position(|column| column == *name))
+        .collect();
+    if !header.iter().any(|column| column == "client") {
+        return Err(UpsertError::MissingClientColumn);
+    }
+    let key = |row: &csv::StringRecord| -> Vec<String> {
+        key_columns
+            .iter()
+            .map(|&index| row.get(index).unwrap_or_default().to_string())
+            .collect()
+    };
+
+    let existing = match File::open(path) {
+        Ok(file) => {
+            let mut reader = csv::Reader::from_reader(file);
+            let existing_header = reader.headers()?.clone();
+            if existing_header != header {
+                return Err(UpsertError::ColumnsDiffer {
+                    existing: existing_header.iter().collect::<Vec<_>>().join(","),
+                    report: header.iter().collect::<Vec<_>>().join(","),
+                });
+            }
+            reader.records().collect::<Result<Vec<_>, _>>()?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut updates: HashMap<Vec<String>, csv::StringRecord> =
+        rows.iter().map(|row| (key(row), row.clone())).collect();
+    let mut stats = UpsertStats::default();
+    let temporary = path.with_extension("tmp");
+    let mut writer = csv::Writer::from_path(&temporary)?;
+    writer.write_record(&header)?;
+    for row in &existing {
+        match updates.remove(&key(row)) {
+            Some(update) => {
+                writer.write_record(&update)?;
+                stats.replaced += 1;
+            }
+            None => {
+                writer.write_record(row)?;
+                stats.kept += 1;
+            }
+        }
+    }
+    // New clients in report order
+    for row in &rows {
+        if let Some(update) = updates.remove(&key(row)) {
+            writer.write_record(&update)?;
+            stats.added += 1;
+        }
+    }
+    let mut file = writer
+        .into_inner()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsert_report() {
+        let path = std::env::temp_dir().join(format!("upsert-test-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let header = "client,available,held,total,locked\n";
+
+        let stats = upsert_report(
+            &path,
+            format!("{}1,1.0,0.0,1.0,false\n2,2.0,0.0,2.0,false\n", header).as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(stats.added, 2);
+        let stats = upsert_report(
+            &path,
+            format!("{}3,3.0,0.0,3.0,false\n1,5.0,1.0,6.0,true\n", header).as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            UpsertStats {
+                replaced: 1,
+                added: 1,
+                kept: 1
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "{}1,5.0,1.0,6.0,true\n2,2.0,0.0,2.0,false\n3,3.0,0.0,3.0,false\n",
+                header
+            )
+        );
+
+        let result = upsert_report(
+            &path,
+            b"client,available,held,total,locked,closed\n1,5.0,1.0,6.0,true,false\n",
+        );
+        assert!(matches!(result, Err(UpsertError::ColumnsDiffer { .. })));
+        fs::remove_file(&path).unwrap();
+    }
+}
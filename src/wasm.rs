@@ -0,0 +1,77 @@
+use rust_decimal::Decimal;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::engine::{AmountFormat, Engine};
+use crate::parser::ByteRecordParser;
+
+// Process CSV transactions held in memory and return the accounts as a JSON array, for embedding
+// in a browser where there are no files or threads.
+//
+// Amounts are JSON strings so JavaScript doesn't round them to floats, errors are thrown as plain
+// messages, e.g. `JSON.parse(process_csv(text))` after `wasm-bindgen --target web`.
+#[wasm_bindgen]
+pub fn process_csv(text: &str) -> Result<String, String> {
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let parser = ByteRecordParser::new(reader).map_err(|e| e.to_string())?;
+
+    let mut engine: Engine<Decimal> = Engine::new();
+    engine
+        .process_transactions_blocking(parser)
+        .map_err(|e| e.to_string())?;
+
+    let mut accounts = engine.accounts().map_err(|e| e.to_string())?;
+    accounts.sort_by_key(|account| account.client_id);
+    let accounts: Vec<_> = accounts
+        .iter()
+        .map(|account| account.formatted(AmountFormat::default()))
+        .collect();
+    serde_json::to_string(&accounts).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_csv() {
+        let json = process_csv(
+            "type,client,tx,amount\n\
+             deposit,2,1,2.0\n\
+             deposit,1,2,1.5\n\
+             dispute,1,2,\n",
+        )
+        .unwrap();
+
+        let accounts: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let balances: Vec<_> = accounts
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|account| {
+                (
+                    account["client"].to_string().trim_matches('"').to_string(),
+                    account["available"].clone(),
+                    account["held"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                ("1".to_string(), "0.0".into(), "1.5".into()),
+                ("2".to_string(), "2.0".into(), "0.0".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_column() {
+        assert_eq!(
+            process_csv("type,client,amount\n"),
+            Err("Missing column: tx".to_string())
+        );
+    }
+}